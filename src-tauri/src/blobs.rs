@@ -0,0 +1,177 @@
+//! Large base64 payloads (images, files) moved out of `messages.data` into files
+//! under `app_data_dir/blobs`, leaving a `valedesk-blob:<file>` reference in the JSON.
+
+use serde_json::Value;
+use std::collections::HashSet;
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+
+pub const BLOB_REF_PREFIX: &str = "valedesk-blob:";
+/// Strings shorter than this stay inline
+pub const BLOB_MIN_BYTES: usize = 64 * 1024;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct ExternalizeStats {
+    pub blobs: usize,
+    pub bytes_saved: usize,
+}
+
+/// Data URLs with a base64 body, or strings made only of base64 characters
+pub fn is_base64_blob(text: &str, min_len: usize) -> bool {
+    if text.len() < min_len {
+        return false;
+    }
+    let body = match text.strip_prefix("data:") {
+        Some(rest) => match rest.find(";base64,") {
+            Some(idx) => &rest[idx + ";base64,".len()..],
+            None => return false,
+        },
+        None => text,
+    };
+    body.bytes()
+        .all(|b| b.is_ascii_alphanumeric() || matches!(b, b'+' | b'/' | b'=' | b'-' | b'_' | b'\n' | b'\r'))
+}
+
+/// Write every blob-like string in `value` to `dir` and replace it with a reference.
+/// The file keeps the exact original string so rehydration is lossless.
+pub fn externalize_value(value: &mut Value, dir: &Path, min_len: usize) -> std::io::Result<ExternalizeStats> {
+    let mut stats = ExternalizeStats::default();
+    externalize_into(value, dir, min_len, &mut stats)?;
+    Ok(stats)
+}
+
+fn externalize_into(value: &mut Value, dir: &Path, min_len: usize, stats: &mut ExternalizeStats) -> std::io::Result<()> {
+    match value {
+        Value::String(s) if is_base64_blob(s, min_len) => {
+            fs::create_dir_all(dir)?;
+            let file_name = format!("{}.b64", uuid::Uuid::new_v4());
+            fs::write(dir.join(&file_name), s.as_bytes())?;
+            let reference = format!("{}{}", BLOB_REF_PREFIX, file_name);
+            stats.blobs += 1;
+            stats.bytes_saved += s.len().saturating_sub(reference.len());
+            *s = reference;
+        }
+        Value::Array(items) => {
+            for item in items {
+                externalize_into(item, dir, min_len, stats)?;
+            }
+        }
+        Value::Object(map) => {
+            for item in map.values_mut() {
+                externalize_into(item, dir, min_len, stats)?;
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Replace blob references with the stored content. Missing files leave the reference in place.
+/// Returns how many references were resolved.
+pub fn rehydrate_value(value: &mut Value, dir: &Path) -> usize {
+    match value {
+        Value::String(s) => {
+            let Some(file_name) = s.strip_prefix(BLOB_REF_PREFIX) else { return 0 };
+            // References are bare file names; never let one point outside the blobs dir
+            if file_name.is_empty() || file_name.contains(['/', '\\']) || file_name.starts_with('.') {
+                return 0;
+            }
+            match fs::read_to_string(dir.join(file_name)) {
+                Ok(content) => {
+                    *s = content;
+                    1
+                }
+                Err(e) => {
+                    eprintln!("[blobs] Cannot read {}: {}", file_name, e);
+                    0
+                }
+            }
+        }
+        Value::Array(items) => items.iter_mut().map(|v| rehydrate_value(v, dir)).sum(),
+        Value::Object(map) => map.values_mut().map(|v| rehydrate_value(v, dir)).sum(),
+        _ => 0,
+    }
+}
+
+/// Add the file name of every blob reference in `value` to `refs`
+pub fn collect_refs(value: &Value, refs: &mut HashSet<String>) {
+    match value {
+        Value::String(s) => {
+            if let Some(file_name) = s.strip_prefix(BLOB_REF_PREFIX) {
+                refs.insert(file_name.to_string());
+            }
+        }
+        Value::Array(items) => items.iter().for_each(|v| collect_refs(v, refs)),
+        Value::Object(map) => map.values().for_each(|v| collect_refs(v, refs)),
+        _ => {}
+    }
+}
+
+/// Delete blob files in `dir` that aren't in `referenced`. Files modified within `grace` are kept:
+/// they may belong to an externalization whose message update hasn't committed yet.
+/// Returns how many files were removed.
+pub fn remove_unreferenced(dir: &Path, referenced: &HashSet<String>, grace: Duration) -> std::io::Result<usize> {
+    let mut removed = 0;
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let file_name = entry.file_name().to_string_lossy().to_string();
+        if !file_name.ends_with(".b64") || referenced.contains(&file_name) {
+            continue;
+        }
+        let age = entry.metadata()?.modified()?.elapsed().unwrap_or_default();
+        if age < grace {
+            continue;
+        }
+        fs::remove_file(entry.path())?;
+        removed += 1;
+    }
+    Ok(removed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn externalize_and_rehydrate_roundtrip() {
+        let dir = std::env::temp_dir().join(format!("blobs_test_{}", uuid::Uuid::new_v4()));
+        let image = format!("data:image/png;base64,{}", "iVBORw0KGgo".repeat(20));
+        let raw = "QUJD".repeat(50);
+        let original = json!({
+            "type": "user",
+            "message": { "content": [
+                { "type": "image", "source": { "data": image } },
+                { "type": "tool_result", "content": raw },
+                { "type": "text", "text": "not base64, but long enough: ".repeat(10) }
+            ] }
+        });
+
+        let mut value = original.clone();
+        let stats = externalize_value(&mut value, &dir, 100).unwrap();
+        assert_eq!(stats.blobs, 2);
+        assert!(stats.bytes_saved > 200);
+        let data = value.pointer("/message/content/0/source/data").unwrap().as_str().unwrap();
+        assert!(data.starts_with(BLOB_REF_PREFIX));
+        assert_eq!(value["message"]["content"][2], original["message"]["content"][2]);
+
+        assert_eq!(rehydrate_value(&mut value, &dir), 2);
+        assert_eq!(value, original);
+
+        let mut escape = json!(format!("{}../secret", BLOB_REF_PREFIX));
+        assert_eq!(rehydrate_value(&mut escape, &dir), 0);
+
+        // Keep the image's file referenced; the raw string's file is orphaned
+        let mut value = original.clone();
+        externalize_value(&mut value, &dir, 100).unwrap();
+        let mut refs = HashSet::new();
+        collect_refs(&value["message"]["content"][0], &mut refs);
+        assert_eq!(refs.len(), 1);
+        assert_eq!(remove_unreferenced(&dir, &refs, Duration::from_secs(60)).unwrap(), 0);
+        assert_eq!(remove_unreferenced(&dir, &refs, Duration::ZERO).unwrap(), 3);
+        assert_eq!(rehydrate_value(&mut value, &dir), 1);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
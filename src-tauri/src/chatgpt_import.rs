@@ -0,0 +1,173 @@
+//! Parser for the OpenAI ChatGPT data export (`conversations.json`).
+//!
+//! Each conversation stores its messages as a tree (`mapping`) because edits and
+//! regenerations create branches. We follow the branch the user last saw
+//! (`current_node` up to the root) and drop the rest.
+
+use crate::db::ImportedSession;
+use serde_json::{json, Value};
+use std::collections::HashSet;
+
+/// Parse a ChatGPT `conversations.json` document into sessions ready for `Database::import_sessions`.
+pub fn parse_conversations(export: &Value) -> Result<Vec<ImportedSession>, String> {
+    let conversations = export
+        .as_array()
+        .ok_or_else(|| "Expected a JSON array of conversations".to_string())?;
+
+    Ok(conversations.iter().filter_map(parse_conversation).collect())
+}
+
+fn parse_conversation(conversation: &Value) -> Option<ImportedSession> {
+    let mapping = conversation.get("mapping")?.as_object()?;
+    let created_at = seconds_to_ms(conversation.get("create_time")).unwrap_or(0);
+    let updated_at = seconds_to_ms(conversation.get("update_time")).unwrap_or(created_at);
+
+    let mut messages: Vec<(Value, i64)> = Vec::new();
+    let mut last_at = created_at;
+    for node_id in main_path(conversation, mapping) {
+        let Some(message) = mapping.get(&node_id).and_then(|n| n.get("message")) else { continue };
+        let Some(text) = message_text(message) else { continue };
+
+        let data = match message.pointer("/author/role").and_then(|v| v.as_str()) {
+            Some("user") => json!({ "type": "user_prompt", "prompt": text }),
+            Some("assistant") => json!({ "type": "text", "text": text }),
+            _ => continue,
+        };
+        // Keep messages strictly ordered even when timestamps are missing or equal
+        let at = seconds_to_ms(message.get("create_time")).unwrap_or(last_at).max(last_at + 1);
+        last_at = at;
+        messages.push((data, at));
+    }
+
+    if messages.is_empty() {
+        return None;
+    }
+
+    let title = conversation
+        .get("title")
+        .and_then(|v| v.as_str())
+        .map(str::trim)
+        .filter(|t| !t.is_empty())
+        .unwrap_or("Imported chat")
+        .to_string();
+
+    Some(ImportedSession {
+        title,
+        created_at,
+        updated_at: updated_at.max(last_at),
+        messages,
+    })
+}
+
+/// Node ids from the root to the conversation's current node (or, if absent, the last child at each step).
+fn main_path(conversation: &Value, mapping: &serde_json::Map<String, Value>) -> Vec<String> {
+    let mut path = Vec::new();
+    let mut seen = HashSet::new();
+
+    if let Some(current) = conversation.get("current_node").and_then(|v| v.as_str()) {
+        let mut node_id = Some(current.to_string());
+        while let Some(id) = node_id {
+            if !seen.insert(id.clone()) {
+                break;
+            }
+            node_id = mapping
+                .get(&id)
+                .and_then(|n| n.get("parent"))
+                .and_then(|v| v.as_str())
+                .map(String::from);
+            path.push(id);
+        }
+        path.reverse();
+        return path;
+    }
+
+    let mut node_id = mapping
+        .iter()
+        .find(|(_, node)| node.get("parent").and_then(|p| p.as_str()).is_none())
+        .map(|(id, _)| id.clone());
+    while let Some(id) = node_id {
+        if !seen.insert(id.clone()) {
+            break;
+        }
+        node_id = mapping
+            .get(&id)
+            .and_then(|n| n.get("children"))
+            .and_then(|v| v.as_array())
+            .and_then(|children| children.last())
+            .and_then(|v| v.as_str())
+            .map(String::from);
+        path.push(id);
+    }
+    path
+}
+
+fn message_text(message: &Value) -> Option<String> {
+    let parts = message.pointer("/content/parts")?.as_array()?;
+    let text = parts
+        .iter()
+        .filter_map(|p| p.as_str())
+        .collect::<Vec<_>>()
+        .join("\n");
+    if text.trim().is_empty() {
+        None
+    } else {
+        Some(text)
+    }
+}
+
+fn seconds_to_ms(value: Option<&Value>) -> Option<i64> {
+    value.and_then(|v| v.as_f64()).map(|secs| (secs * 1000.0) as i64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn node(id: &str, parent: Option<&str>, children: &[&str], role: Option<&str>, text: &str, t: f64) -> (String, Value) {
+        let message = role.map(|role| json!({
+            "id": id,
+            "author": { "role": role },
+            "create_time": t,
+            "content": { "content_type": "text", "parts": [text] }
+        }));
+        (id.to_string(), json!({ "id": id, "parent": parent, "children": children, "message": message }))
+    }
+
+    #[test]
+    fn follows_current_branch_and_skips_system_messages() {
+        let mapping: serde_json::Map<String, Value> = [
+            node("root", None, &["sys"], None, "", 0.0),
+            node("sys", Some("root"), &["u1"], Some("system"), "You are ChatGPT", 1.0),
+            node("u1", Some("sys"), &["a_old", "a_new"], Some("user"), "Hi", 2.0),
+            node("a_old", Some("u1"), &[], Some("assistant"), "Old answer", 3.0),
+            node("a_new", Some("u1"), &[], Some("assistant"), "New answer", 4.0),
+        ].into_iter().collect();
+        let export = json!([{
+            "title": "Greeting",
+            "create_time": 1.0,
+            "update_time": 4.0,
+            "current_node": "a_new",
+            "mapping": mapping
+        }, {
+            "title": "Empty",
+            "mapping": {}
+        }]);
+
+        let sessions = parse_conversations(&export).unwrap();
+        assert_eq!(sessions.len(), 1);
+        let session = &sessions[0];
+        assert_eq!(session.title, "Greeting");
+        let texts: Vec<&Value> = session.messages.iter().map(|(m, _)| m).collect();
+        assert_eq!(texts, vec![
+            &json!({ "type": "user_prompt", "prompt": "Hi" }),
+            &json!({ "type": "text", "text": "New answer" }),
+        ]);
+        assert!(session.messages[0].1 < session.messages[1].1);
+
+        // Without current_node the last child wins at each branch
+        let mut export = export;
+        export[0].as_object_mut().unwrap().remove("current_node");
+        let sessions = parse_conversations(&export).unwrap();
+        assert_eq!(sessions[0].messages[1].0["text"], "New answer");
+    }
+}
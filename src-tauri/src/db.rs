@@ -1,25 +1,75 @@
-use rusqlite::{Connection, params, Result as SqliteResult};
+use r2d2_sqlite::SqliteConnectionManager;
+use rusqlite::{Connection, params, Result as SqliteResult, TransactionBehavior};
 use serde::{Deserialize, Serialize};
 use serde_json::Value as JsonValue;
 use std::path::Path;
-use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Temperature range accepted by OpenAI-compatible APIs, used when the model
+/// config doesn't declare its own `minTemperature`/`maxTemperature`.
+pub const DEFAULT_TEMPERATURE_RANGE: (f64, f64) = (0.0, 2.0);
+
+/// Clamp a temperature into `range`. Non-finite values are dropped (`None`)
+/// so they never reach the provider.
+pub fn clamp_temperature(value: f64, range: (f64, f64)) -> Option<f64> {
+    if !value.is_finite() {
+        return None;
+    }
+    Some(value.clamp(range.0, range.1))
+}
+
+/// A requested temperature after `clamp_temperature`; `value` is None for non-finite input
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TemperatureCheck {
+    pub value: Option<f64>,
+    pub clamped: bool,
+    pub range: (f64, f64),
+}
 
 pub struct Database {
-    conn: Mutex<Connection>,
+    pool: r2d2::Pool<SqliteConnectionManager>,
+    /// Applied on every checkout so `set_busy_timeout_ms` reaches all pooled connections
+    busy_timeout_ms: AtomicU64,
+}
+
+/// Connections kept open for file databases; readers no longer queue behind a slow writer
+pub const DB_POOL_SIZE: u32 = 4;
+
+fn pool_error(e: r2d2::Error) -> rusqlite::Error {
+    rusqlite::Error::SqliteFailure(
+        rusqlite::ffi::Error::new(rusqlite::ffi::SQLITE_BUSY),
+        Some(format!("no database connection available: {}", e)),
+    )
 }
 
 impl Database {
     pub fn new(path: &Path) -> SqliteResult<Self> {
-        let conn = Connection::open(path)?;
-        let db = Self { conn: Mutex::new(conn) };
+        // Every in-memory connection is its own empty database, so those get a pool of one
+        let (manager, size) = if path == Path::new(":memory:") {
+            (SqliteConnectionManager::memory(), 1)
+        } else {
+            (SqliteConnectionManager::file(path), DB_POOL_SIZE)
+        };
+        let pool = r2d2::Pool::builder()
+            .max_size(size)
+            .build(manager)
+            .map_err(pool_error)?;
+        let db = Self { pool, busy_timeout_ms: AtomicU64::new(DEFAULT_BUSY_TIMEOUT_MS) };
         db.initialize()?;
         Ok(db)
     }
 
-    fn initialize(&self) -> SqliteResult<()> {
-        let conn = self.conn.lock().unwrap();
-        
+    /// Check out a pooled connection with WAL and the current busy timeout applied
+    fn conn(&self) -> SqliteResult<r2d2::PooledConnection<SqliteConnectionManager>> {
+        let conn = self.pool.get().map_err(pool_error)?;
         conn.execute_batch("PRAGMA journal_mode = WAL;")?;
+        // Wait for other writers (sidecar, a second window) instead of failing with SQLITE_BUSY
+        conn.busy_timeout(std::time::Duration::from_millis(self.busy_timeout_ms.load(Ordering::Relaxed)))?;
+        Ok(conn)
+    }
+
+    fn initialize(&self) -> SqliteResult<()> {
+        let conn = self.conn()?;
         
         conn.execute_batch(r#"
             CREATE TABLE IF NOT EXISTS sessions (
@@ -66,6 +116,88 @@ impl Database {
             CREATE INDEX IF NOT EXISTS scheduled_tasks_next_run ON scheduled_tasks(next_run);
             CREATE INDEX IF NOT EXISTS scheduled_tasks_enabled ON scheduled_tasks(enabled);
 
+            -- Agent sessions started by scheduled tasks (status: running/completed/error/idle/cancelled)
+            CREATE TABLE IF NOT EXISTS task_runs (
+                id TEXT PRIMARY KEY,
+                task_id TEXT NOT NULL,
+                session_id TEXT NOT NULL,
+                status TEXT NOT NULL,
+                started_at INTEGER NOT NULL,
+                finished_at INTEGER
+            );
+            CREATE INDEX IF NOT EXISTS task_runs_task_id ON task_runs(task_id);
+            CREATE INDEX IF NOT EXISTS task_runs_session_id ON task_runs(session_id);
+
+            -- In-flight assistant text checkpointed while streaming (survives crashes)
+            CREATE TABLE IF NOT EXISTS stream_drafts (
+                session_id TEXT PRIMARY KEY,
+                content TEXT NOT NULL,
+                updated_at INTEGER NOT NULL
+            );
+
+            -- Free-form per-session tags (ticket ids, categories) set by users/integrations
+            CREATE TABLE IF NOT EXISTS session_metadata (
+                session_id TEXT NOT NULL,
+                key TEXT NOT NULL,
+                value TEXT NOT NULL,
+                updated_at INTEGER NOT NULL,
+                PRIMARY KEY (session_id, key),
+                FOREIGN KEY (session_id) REFERENCES sessions(id)
+            );
+
+            -- Approximate transcription usage per dictation session (kept after session delete for spend tracking)
+            CREATE TABLE IF NOT EXISTS voice_usage (
+                session_id TEXT PRIMARY KEY,
+                audio_seconds REAL NOT NULL DEFAULT 0,
+                requests INTEGER NOT NULL DEFAULT 0,
+                updated_at INTEGER NOT NULL
+            );
+
+            -- One row per finished dictation: latency to the first partial and to the final transcript
+            CREATE TABLE IF NOT EXISTS voice_dictation_metrics (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                session_id TEXT NOT NULL,
+                model TEXT,
+                transport TEXT NOT NULL,
+                first_partial_ms INTEGER,
+                final_latency_ms INTEGER NOT NULL,
+                created_at INTEGER NOT NULL
+            );
+            CREATE INDEX IF NOT EXISTS voice_dictation_metrics_created_at ON voice_dictation_metrics(created_at);
+
+            -- Per-model token increments, appended on every token update (kept after session delete for spend tracking).
+            -- cost_usd is priced when the row is written; NULL only on rows recorded before it existed
+            CREATE TABLE IF NOT EXISTS usage (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                session_id TEXT NOT NULL,
+                model TEXT,
+                input_tokens INTEGER NOT NULL DEFAULT 0,
+                output_tokens INTEGER NOT NULL DEFAULT 0,
+                ts INTEGER NOT NULL,
+                cost_usd REAL
+            );
+            CREATE INDEX IF NOT EXISTS usage_ts ON usage(ts);
+
+            -- Semantic search vectors (little-endian f32) per message and embedding model; only
+            -- filled while semantic search is configured
+            CREATE TABLE IF NOT EXISTS message_embeddings (
+                message_id TEXT NOT NULL,
+                session_id TEXT NOT NULL,
+                model TEXT NOT NULL,
+                vector BLOB NOT NULL,
+                created_at INTEGER NOT NULL,
+                PRIMARY KEY (message_id, model)
+            );
+            CREATE INDEX IF NOT EXISTS message_embeddings_session_id ON message_embeddings(session_id);
+
+            -- On-demand copies of settings + providers for "what changed" debugging
+            CREATE TABLE IF NOT EXISTS settings_snapshots (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                label TEXT,
+                data TEXT NOT NULL,
+                created_at INTEGER NOT NULL
+            );
+
             -- Settings key-value store
             CREATE TABLE IF NOT EXISTS settings (
                 key TEXT PRIMARY KEY,
@@ -116,28 +248,225 @@ impl Database {
             "ALTER TABLE sessions ADD COLUMN temperature REAL",
             [],
         ); // Ignore error if column already exists
+        let _ = conn.execute(
+            "ALTER TABLE sessions ADD COLUMN is_locked INTEGER DEFAULT 0",
+            [],
+        );
+        let _ = conn.execute(
+            "ALTER TABLE sessions ADD COLUMN max_output_tokens INTEGER",
+            [],
+        );
+        let _ = conn.execute(
+            "ALTER TABLE sessions ADD COLUMN permission_mode TEXT",
+            [],
+        );
+        let _ = conn.execute(
+            "ALTER TABLE scheduled_tasks ADD COLUMN catch_up_policy TEXT NOT NULL DEFAULT 'coalesce'",
+            [],
+        );
+        let _ = conn.execute(
+            "ALTER TABLE sessions ADD COLUMN archived INTEGER NOT NULL DEFAULT 0",
+            [],
+        );
+        let _ = conn.execute(
+            "ALTER TABLE usage ADD COLUMN cost_usd REAL",
+            [],
+        );
+
+        // Search still works without it, through the LIKE fallback
+        if let Err(e) = init_message_fts(&conn) {
+            eprintln!("[db] full-text search unavailable, falling back to LIKE: {}", e);
+        }
+
+        let configured_timeout: Option<String> = conn
+            .query_row("SELECT value FROM settings WHERE key = ?1", [BUSY_TIMEOUT_SETTING], |row| row.get(0))
+            .ok();
+        if let Some(ms) = configured_timeout.and_then(|v| v.parse::<u64>().ok()) {
+            self.busy_timeout_ms.store(ms, Ordering::Relaxed);
+        }
 
         Ok(())
     }
 
+    /// Accepted temperature range for a model, read from its config
+    /// (`minTemperature`/`maxTemperature`), falling back to `DEFAULT_TEMPERATURE_RANGE`.
+    pub fn temperature_range_for_model(&self, model_id: Option<&str>) -> SqliteResult<(f64, f64)> {
+        let (default_min, default_max) = DEFAULT_TEMPERATURE_RANGE;
+        let model_id = match model_id {
+            Some(id) if !id.trim().is_empty() => id,
+            _ => return Ok(DEFAULT_TEMPERATURE_RANGE),
+        };
+
+        let conn = self.conn()?;
+        let mut stmt = conn.prepare("SELECT config FROM models WHERE id = ?1")?;
+        let mut rows = stmt.query([model_id])?;
+        let config: Option<JsonValue> = match rows.next()? {
+            Some(row) => row.get::<_, Option<String>>(0)?.and_then(|s| serde_json::from_str(&s).ok()),
+            None => None,
+        };
+
+        let min = config.as_ref().and_then(|c| c.get("minTemperature")).and_then(|v| v.as_f64()).unwrap_or(default_min);
+        let max = config.as_ref().and_then(|c| c.get("maxTemperature")).and_then(|v| v.as_f64()).unwrap_or(default_max);
+        if min > max {
+            return Ok(DEFAULT_TEMPERATURE_RANGE);
+        }
+        Ok((min, max))
+    }
+
+    /// Fit `temperature` to the model's range, flagging when it had to change
+    pub fn check_temperature(&self, temperature: f64, model_id: Option<&str>) -> SqliteResult<TemperatureCheck> {
+        let range = self.temperature_range_for_model(model_id)?;
+        let value = clamp_temperature(temperature, range);
+        Ok(TemperatureCheck { value, clamped: value != Some(temperature), range })
+    }
+
+    fn validated_temperature(&self, temperature: Option<f64>, model_id: Option<&str>) -> SqliteResult<Option<f64>> {
+        let Some(requested) = temperature else { return Ok(None) };
+        let check = self.check_temperature(requested, model_id)?;
+        if check.clamped {
+            eprintln!(
+                "[db] temperature {} outside {}..={} for model {:?}, using {:?}",
+                requested, check.range.0, check.range.1, model_id, check.value
+            );
+        }
+        Ok(check.value)
+    }
+
+    /// Rejects non-positive caps and caps above the model's `maxOutputTokens` when that's known
+    fn validated_max_output_tokens(&self, max_output_tokens: Option<i64>, model_id: Option<&str>) -> SqliteResult<Option<i64>> {
+        let Some(value) = max_output_tokens else { return Ok(None) };
+        if value <= 0 {
+            return Err(rusqlite::Error::ToSqlConversionFailure(
+                format!("maxOutputTokens must be positive, got {}", value).into(),
+            ));
+        }
+        let limit = match model_id.filter(|id| !id.trim().is_empty()) {
+            Some(id) => self.get_model(id)?.and_then(|m| ModelConfig::from_config(m.config.as_ref()).max_output_tokens),
+            None => None,
+        };
+        if let Some(limit) = limit.filter(|limit| value > *limit) {
+            return Err(rusqlite::Error::ToSqlConversionFailure(
+                format!("maxOutputTokens {} exceeds the model's limit of {}", value, limit).into(),
+            ));
+        }
+        Ok(Some(value))
+    }
+
+    /// Resolve a session's model reference to a `models.id` so later lookups never have to
+    /// guess: known ids pass through, a unique display name (or `provider::name`) is corrected
+    /// to its id, anything else is rejected. Blank values and DBs with no models are left as-is.
+    fn resolved_model(&self, model: Option<&str>) -> SqliteResult<Option<String>> {
+        let Some(model) = model else { return Ok(None) };
+        let trimmed = model.trim();
+        if trimmed.is_empty() {
+            return Ok(Some(model.to_string()));
+        }
+
+        let conn = self.conn()?;
+        let model_count: i64 = conn.query_row("SELECT COUNT(*) FROM models", [], |row| row.get(0))?;
+        if model_count == 0 {
+            return Ok(Some(model.to_string()));
+        }
+
+        let exact: Option<String> = conn
+            .query_row("SELECT id FROM models WHERE id = ?1", [trimmed], |row| row.get(0))
+            .ok();
+        if exact.is_some() {
+            return Ok(exact);
+        }
+
+        let mut candidates: Vec<String> = conn
+            .prepare("SELECT id FROM models WHERE name = ?1")?
+            .query_map([trimmed], |row| row.get(0))?
+            .collect::<SqliteResult<_>>()?;
+        if candidates.is_empty() {
+            if let Some((provider_id, name)) = trimmed.split_once("::") {
+                candidates = conn
+                    .prepare("SELECT id FROM models WHERE provider_id = ?1 AND name = ?2")?
+                    .query_map([provider_id, name], |row| row.get(0))?
+                    .collect::<SqliteResult<_>>()?;
+            }
+        }
+
+        match candidates.as_slice() {
+            [id] => {
+                eprintln!("[db] model '{}' resolved to id '{}'", trimmed, id);
+                Ok(Some(id.clone()))
+            }
+            [] => Err(rusqlite::Error::ToSqlConversionFailure(
+                format!("Unknown model '{}': not a configured model id", trimmed).into(),
+            )),
+            _ => Err(rusqlite::Error::ToSqlConversionFailure(
+                format!("Ambiguous model '{}': matches {}", trimmed, candidates.join(", ")).into(),
+            )),
+        }
+    }
+
+    /// Per-field validation for `session.sync`, which must not lose a whole session to one bad
+    /// value: an invalid model, max output tokens or permission mode is logged and dropped, and
+    /// temperature is clamped. The result passes `create_session`'s checks.
+    pub fn sanitize_create_params(&self, params: &CreateSessionParams) -> CreateSessionParams {
+        let session_id = params.id.as_deref().unwrap_or("new");
+        let mut clean = params.clone();
+        clean.model = self.resolved_model(params.model.as_deref())
+            .unwrap_or_else(|e| dropped_field(session_id, "model", e));
+        clean.temperature = self.validated_temperature(params.temperature, clean.model.as_deref())
+            .unwrap_or_else(|e| dropped_field(session_id, "temperature", e));
+        clean.max_output_tokens = self.validated_max_output_tokens(params.max_output_tokens, clean.model.as_deref())
+            .unwrap_or_else(|e| dropped_field(session_id, "maxOutputTokens", e));
+        clean.permission_mode = params.permission_mode.clone()
+            .filter(|mode| validate_permission_mode(mode).map_err(|e| dropped_field::<()>(session_id, "permissionMode", e)).is_ok());
+        clean
+    }
+
+    /// `sanitize_create_params` for updates; dropped fields are left unchanged in the DB
+    pub fn sanitize_update_params(&self, id: &str, params: &UpdateSessionParams) -> UpdateSessionParams {
+        let mut clean = params.clone();
+        clean.model = self.resolved_model(params.model.as_deref())
+            .unwrap_or_else(|e| dropped_field(id, "model", e));
+        let model = match clean.model.clone() {
+            Some(model) => Some(model),
+            None => self.get_session(id).ok().flatten().and_then(|s| s.model),
+        };
+        clean.temperature = self.validated_temperature(params.temperature, model.as_deref())
+            .unwrap_or_else(|e| dropped_field(id, "temperature", e));
+        clean.max_output_tokens = match params.max_output_tokens {
+            Some(Some(value)) => self.validated_max_output_tokens(Some(value), model.as_deref())
+                .unwrap_or_else(|e| dropped_field(id, "maxOutputTokens", e))
+                .map(Some),
+            other => other,
+        };
+        clean.permission_mode = params.permission_mode.clone()
+            .filter(|mode| validate_permission_mode(mode).map_err(|e| dropped_field::<()>(id, "permissionMode", e)).is_ok());
+        clean
+    }
+
     pub fn create_session(&self, params: &CreateSessionParams) -> SqliteResult<Session> {
-        let conn = self.conn.lock().unwrap();
+        let model = self.resolved_model(params.model.as_deref())?;
+        let temperature = self.validated_temperature(params.temperature, model.as_deref())?;
+        let max_output_tokens = self.validated_max_output_tokens(params.max_output_tokens, model.as_deref())?;
+        if let Some(ref mode) = params.permission_mode {
+            validate_permission_mode(mode)?;
+        }
+        let conn = self.conn()?;
         let id = params.id.clone().unwrap_or_else(|| uuid::Uuid::new_v4().to_string());
         let now = chrono::Utc::now().timestamp_millis();
 
         conn.execute(
             r#"INSERT INTO sessions 
-               (id, title, status, cwd, allowed_tools, last_prompt, model, thread_id, temperature, created_at, updated_at)
-               VALUES (?1, ?2, 'idle', ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)"#,
+               (id, title, status, cwd, allowed_tools, last_prompt, model, thread_id, temperature, max_output_tokens, permission_mode, created_at, updated_at)
+               VALUES (?1, ?2, 'idle', ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12)"#,
             params![
                 &id,
                 &params.title,
                 &params.cwd,
                 &params.allowed_tools,
                 &params.prompt,
-                &params.model,
+                &model,
                 &params.thread_id,
-                &params.temperature,
+                &temperature,
+                &max_output_tokens,
+                &params.permission_mode,
                 now,
                 now
             ],
@@ -151,10 +480,14 @@ impl Database {
             cwd: params.cwd.clone(),
             allowed_tools: params.allowed_tools.clone(),
             last_prompt: params.prompt.clone(),
-            model: params.model.clone(),
+            model,
             thread_id: params.thread_id.clone(),
-            temperature: params.temperature,
+            temperature,
+            max_output_tokens,
+            permission_mode: params.permission_mode.clone(),
             is_pinned: false,
+            is_locked: false,
+            is_archived: false,
             input_tokens: 0,
             output_tokens: 0,
             created_at: now,
@@ -162,15 +495,150 @@ impl Database {
         })
     }
 
+    /// Insert externally sourced sessions with fresh ids in one transaction.
+    /// Returns the number of messages written.
+    pub fn import_sessions(&self, sessions: &[ImportedSession]) -> SqliteResult<usize> {
+        let mut conn = self.conn()?;
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        let mut message_count = 0;
+
+        for session in sessions {
+            let session_id = uuid::Uuid::new_v4().to_string();
+            tx.execute(
+                r#"INSERT INTO sessions (id, title, status, created_at, updated_at)
+                   VALUES (?1, ?2, 'idle', ?3, ?4)"#,
+                params![&session_id, &session.title, session.created_at, session.updated_at],
+            )?;
+            for (message, created_at) in &session.messages {
+                let data = serde_json::to_string(message).unwrap_or_default();
+                tx.execute(
+                    "INSERT INTO messages (id, session_id, data, created_at) VALUES (?1, ?2, ?3, ?4)",
+                    params![uuid::Uuid::new_v4().to_string(), &session_id, &data, created_at],
+                )?;
+                message_count += 1;
+            }
+        }
+
+        tx.commit()?;
+        Ok(message_count)
+    }
+
+    /// The session with its messages, todos, file changes and metadata as one versioned JSON object.
+    /// Externalized blobs are read back in from `blobs_dir`, so the bundle stands on its own;
+    /// a message row that isn't valid JSON fails the export instead of being dropped.
+    pub fn export_session(&self, id: &str, blobs_dir: &Path) -> SqliteResult<JsonValue> {
+        let session = self.get_session(id)?.ok_or(rusqlite::Error::QueryReturnedNoRows)?;
+        let messages = {
+            let conn = self.conn()?;
+            let mut stmt = conn.prepare(
+                "SELECT id, data, created_at FROM messages WHERE session_id = ?1 ORDER BY created_at ASC"
+            )?;
+            let rows = stmt.query_map([id], |row| {
+                let message_id: String = row.get(0)?;
+                let data: String = row.get(1)?;
+                let mut data: JsonValue = serde_json::from_str(&data).map_err(|e| {
+                    rusqlite::Error::FromSqlConversionFailure(
+                        1,
+                        rusqlite::types::Type::Text,
+                        format!("Message {} is not valid JSON: {}", message_id, e).into(),
+                    )
+                })?;
+                crate::blobs::rehydrate_value(&mut data, blobs_dir);
+                Ok(BundledMessage { data, created_at: row.get(2)? })
+            })?;
+            rows.collect::<SqliteResult<Vec<_>>>()?
+        };
+
+        let bundle = SessionBundle {
+            version: SESSION_BUNDLE_VERSION,
+            session,
+            messages,
+            todos: self.get_todos(id)?,
+            file_changes: self.get_file_changes(id)?,
+            metadata: self.list_session_meta(id)?,
+        };
+        serde_json::to_value(&bundle).map_err(|e| rusqlite::Error::ToSqlConversionFailure(Box::new(e)))
+    }
+
+    /// Insert an `export_session` bundle as a new session (fresh session and message ids).
+    /// A model that isn't configured here is dropped rather than failing the import.
+    pub fn import_session(&self, bundle: &JsonValue) -> SqliteResult<String> {
+        match bundle.get("version").and_then(|v| v.as_u64()) {
+            Some(SESSION_BUNDLE_VERSION) => {}
+            Some(version) => {
+                return Err(rusqlite::Error::ToSqlConversionFailure(
+                    format!("Unsupported session bundle version {} (expected {})", version, SESSION_BUNDLE_VERSION).into(),
+                ));
+            }
+            None => {
+                return Err(rusqlite::Error::ToSqlConversionFailure("Not a session bundle: missing version".into()));
+            }
+        }
+        let bundle: SessionBundle = serde_json::from_value(bundle.clone())
+            .map_err(|e| rusqlite::Error::ToSqlConversionFailure(format!("Invalid session bundle: {}", e).into()))?;
+        let session = &bundle.session;
+        if let Some(ref mode) = session.permission_mode {
+            validate_permission_mode(mode)?;
+        }
+        let model = self.resolved_model(session.model.as_deref()).unwrap_or(None);
+
+        let mut conn = self.conn()?;
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        let session_id = uuid::Uuid::new_v4().to_string();
+        tx.execute(
+            r#"INSERT INTO sessions
+               (id, title, status, cwd, allowed_tools, last_prompt, model, thread_id, temperature, max_output_tokens,
+                permission_mode, input_tokens, output_tokens, todos, file_changes, created_at, updated_at)
+               VALUES (?1, ?2, 'idle', ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16)"#,
+            params![
+                &session_id,
+                &session.title,
+                &session.cwd,
+                &session.allowed_tools,
+                &session.last_prompt,
+                &model,
+                &session.thread_id,
+                &session.temperature,
+                &session.max_output_tokens,
+                &session.permission_mode,
+                session.input_tokens,
+                session.output_tokens,
+                serde_json::to_string(&bundle.todos).unwrap_or_default(),
+                serde_json::to_string(&bundle.file_changes).unwrap_or_default(),
+                session.created_at,
+                session.updated_at
+            ],
+        )?;
+        for message in &bundle.messages {
+            tx.execute(
+                "INSERT INTO messages (id, session_id, data, created_at) VALUES (?1, ?2, ?3, ?4)",
+                params![uuid::Uuid::new_v4().to_string(), &session_id, message.data.to_string(), message.created_at],
+            )?;
+        }
+        for (key, value) in &bundle.metadata {
+            tx.execute(
+                "INSERT INTO session_metadata (session_id, key, value, updated_at) VALUES (?1, ?2, ?3, ?4)",
+                params![&session_id, key, value, session.updated_at],
+            )?;
+        }
+        tx.commit()?;
+        Ok(session_id)
+    }
+
+    /// Sessions newest first, without archived ones
     pub fn list_sessions(&self) -> SqliteResult<Vec<Session>> {
-        let conn = self.conn.lock().unwrap();
+        self.list_sessions_filtered(false)
+    }
+
+    pub fn list_sessions_filtered(&self, include_archived: bool) -> SqliteResult<Vec<Session>> {
+        let conn = self.conn()?;
         let mut stmt = conn.prepare(
             r#"SELECT id, title, claude_session_id, status, cwd, allowed_tools, last_prompt, 
-                      model, thread_id, temperature, is_pinned, input_tokens, output_tokens, created_at, updated_at
-               FROM sessions ORDER BY updated_at DESC"#
+                      model, thread_id, temperature, is_pinned, input_tokens, output_tokens, created_at, updated_at, is_locked, max_output_tokens, permission_mode, archived
+               FROM sessions WHERE ?1 OR COALESCE(archived, 0) = 0 ORDER BY updated_at DESC"#
         )?;
 
-        let rows = stmt.query_map([], |row| {
+        let rows = stmt.query_map([include_archived], |row| {
             Ok(Session {
                 id: row.get(0)?,
                 title: row.get(1)?,
@@ -187,17 +655,48 @@ impl Database {
                 output_tokens: row.get(12)?,
                 created_at: row.get(13)?,
                 updated_at: row.get(14)?,
+                is_locked: row.get::<_, Option<i32>>(15)?.unwrap_or(0) != 0,
+                max_output_tokens: row.get(16)?,
+                permission_mode: row.get(17)?,
+                is_archived: row.get::<_, Option<i32>>(18)?.unwrap_or(0) != 0,
             })
         })?;
 
         rows.collect()
     }
 
+    /// Sessions grouped by working directory. Groups are ordered by their most recent
+    /// session; sessions without a cwd go to a trailing `UNFILED_PROJECT` bucket.
+    pub fn list_sessions_grouped_by_cwd(&self) -> SqliteResult<Vec<ProjectSessions>> {
+        let mut groups: Vec<ProjectSessions> = Vec::new();
+        let mut unfiled: Vec<Session> = Vec::new();
+
+        // list_sessions is already newest-first, so first appearance fixes group order
+        for session in self.list_sessions()? {
+            let cwd = match session.cwd.as_deref().map(str::trim) {
+                Some(cwd) if !cwd.is_empty() => cwd.to_string(),
+                _ => {
+                    unfiled.push(session);
+                    continue;
+                }
+            };
+            match groups.iter_mut().find(|g| g.cwd == cwd) {
+                Some(group) => group.sessions.push(session),
+                None => groups.push(ProjectSessions { cwd, sessions: vec![session] }),
+            }
+        }
+
+        if !unfiled.is_empty() {
+            groups.push(ProjectSessions { cwd: UNFILED_PROJECT.to_string(), sessions: unfiled });
+        }
+        Ok(groups)
+    }
+
     pub fn get_session(&self, id: &str) -> SqliteResult<Option<Session>> {
-        let conn = self.conn.lock().unwrap();
+        let conn = self.conn()?;
         let mut stmt = conn.prepare(
             r#"SELECT id, title, claude_session_id, status, cwd, allowed_tools, last_prompt, 
-                      model, thread_id, temperature, is_pinned, input_tokens, output_tokens, created_at, updated_at
+                      model, thread_id, temperature, is_pinned, input_tokens, output_tokens, created_at, updated_at, is_locked, max_output_tokens, permission_mode, archived
                FROM sessions WHERE id = ?1"#
         )?;
 
@@ -218,6 +717,10 @@ impl Database {
                 output_tokens: row.get(12)?,
                 created_at: row.get(13)?,
                 updated_at: row.get(14)?,
+                is_locked: row.get::<_, Option<i32>>(15)?.unwrap_or(0) != 0,
+                max_output_tokens: row.get(16)?,
+                permission_mode: row.get(17)?,
+                is_archived: row.get::<_, Option<i32>>(18)?.unwrap_or(0) != 0,
             })
         })?;
 
@@ -228,8 +731,47 @@ impl Database {
     }
 
     pub fn update_session(&self, id: &str, params: &UpdateSessionParams) -> SqliteResult<bool> {
-        let conn = self.conn.lock().unwrap();
+        let resolved_model = self.resolved_model(params.model.as_deref())?;
+        let temperature = match params.temperature {
+            Some(_) => {
+                let model = match resolved_model.clone() {
+                    Some(m) => Some(m),
+                    None => self.get_session(id)?.and_then(|s| s.model),
+                };
+                self.validated_temperature(params.temperature, model.as_deref())?
+            }
+            None => None,
+        };
+        // Some(None) clears the cap
+        let max_output_tokens = match params.max_output_tokens {
+            Some(Some(value)) => {
+                let model = match resolved_model.clone() {
+                    Some(m) => Some(m),
+                    None => self.get_session(id)?.and_then(|s| s.model),
+                };
+                Some(self.validated_max_output_tokens(Some(value), model.as_deref())?)
+            }
+            Some(None) => Some(None),
+            None => None,
+        };
+        if let Some(ref mode) = params.permission_mode {
+            validate_permission_mode(mode)?;
+        }
+        let conn = self.conn()?;
         let now = chrono::Utc::now().timestamp_millis();
+        // session.sync sends running totals; usage rows store the increase
+        let previous_tokens: Option<(i64, i64)> = if params.input_tokens.is_some() || params.output_tokens.is_some() {
+            conn.query_row(
+                "SELECT COALESCE(input_tokens, 0), COALESCE(output_tokens, 0) FROM sessions WHERE id = ?1",
+                [id],
+                |row| Ok(Some((row.get(0)?, row.get(1)?))),
+            ).or_else(|e| match e {
+                rusqlite::Error::QueryReturnedNoRows => Ok(None),
+                e => Err(e),
+            })?
+        } else {
+            None
+        };
 
         let mut updates = vec!["updated_at = ?1".to_string()];
         let mut values: Vec<Box<dyn rusqlite::ToSql>> = vec![Box::new(now)];
@@ -250,7 +792,7 @@ impl Database {
             values.push(Box::new(cwd.clone()));
             idx += 1;
         }
-        if let Some(ref model) = params.model {
+        if let Some(ref model) = resolved_model {
             updates.push(format!("model = ?{}", idx));
             values.push(Box::new(model.clone()));
             idx += 1;
@@ -275,6 +817,21 @@ impl Database {
             values.push(Box::new(output_tokens));
             idx += 1;
         }
+        if let Some(temperature) = temperature {
+            updates.push(format!("temperature = ?{}", idx));
+            values.push(Box::new(temperature));
+            idx += 1;
+        }
+        if let Some(max_output_tokens) = max_output_tokens {
+            updates.push(format!("max_output_tokens = ?{}", idx));
+            values.push(Box::new(max_output_tokens));
+            idx += 1;
+        }
+        if let Some(ref permission_mode) = params.permission_mode {
+            updates.push(format!("permission_mode = ?{}", idx));
+            values.push(Box::new(permission_mode.clone()));
+            idx += 1;
+        }
 
         let sql = format!(
             "UPDATE sessions SET {} WHERE id = ?{}",
@@ -285,18 +842,142 @@ impl Database {
 
         let params_refs: Vec<&dyn rusqlite::ToSql> = values.iter().map(|v| v.as_ref()).collect();
         let changed = conn.execute(&sql, params_refs.as_slice())?;
+        if let Some((input_before, output_before)) = previous_tokens {
+            append_usage(
+                &conn,
+                id,
+                params.input_tokens.map_or(0, |t| t - input_before),
+                params.output_tokens.map_or(0, |t| t - output_before),
+                now,
+            )?;
+        }
         Ok(changed > 0)
     }
 
     pub fn delete_session(&self, id: &str) -> SqliteResult<bool> {
-        let conn = self.conn.lock().unwrap();
-        conn.execute("DELETE FROM messages WHERE session_id = ?1", [id])?;
-        let changed = conn.execute("DELETE FROM sessions WHERE id = ?1", [id])?;
-        Ok(changed > 0)
+        let conn = self.conn()?;
+        ensure_unlocked(&conn, id)?;
+        delete_session_rows(&conn, id)
+    }
+
+    pub fn get_max_sessions(&self) -> SqliteResult<Option<usize>> {
+        Ok(self.get_setting(MAX_SESSIONS_SETTING)?.and_then(|v| v.parse::<usize>().ok()).filter(|n| *n > 0))
+    }
+
+    /// `None` (or 0) means unlimited
+    pub fn set_max_sessions(&self, max: Option<usize>) -> SqliteResult<()> {
+        match max.filter(|n| *n > 0) {
+            Some(n) => self.set_setting(MAX_SESSIONS_SETTING, &n.to_string()),
+            None => {
+                let conn = self.conn()?;
+                conn.execute("DELETE FROM settings WHERE key = ?1", [MAX_SESSIONS_SETTING])?;
+                Ok(())
+            }
+        }
+    }
+
+    /// Archive least recently updated sessions until at most `max` non-archived sessions remain.
+    /// Nothing is deleted: evicted sessions stay restorable via `set_archived`. Already archived
+    /// sessions don't count toward the cap. Pinned, locked and running sessions (and `keep`) are
+    /// never evicted, so the cap is best-effort. Returns the archived ids.
+    pub fn evict_oldest_sessions(&self, max: usize, keep: Option<&str>) -> SqliteResult<Vec<String>> {
+        let mut conn = self.conn()?;
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        let total: i64 = tx.query_row(
+            "SELECT COUNT(*) FROM sessions WHERE COALESCE(archived, 0) = 0",
+            [],
+            |row| row.get(0),
+        )?;
+        let excess = total - max as i64;
+        if excess <= 0 {
+            return Ok(Vec::new());
+        }
+        let ids: Vec<String> = {
+            let mut stmt = tx.prepare(
+                r#"SELECT id FROM sessions
+                   WHERE is_pinned = 0 AND COALESCE(is_locked, 0) = 0 AND COALESCE(archived, 0) = 0
+                     AND status != 'running' AND id != ?1
+                   ORDER BY updated_at ASC
+                   LIMIT ?2"#
+            )?;
+            let rows = stmt.query_map(params![keep.unwrap_or(""), excess], |row| row.get(0))?;
+            rows.collect::<SqliteResult<_>>()?
+        };
+        for id in &ids {
+            tx.execute("UPDATE sessions SET archived = 1 WHERE id = ?1", [id])?;
+        }
+        tx.commit()?;
+        Ok(ids)
+    }
+
+    /// Sessions whose last prompts match after case/whitespace normalization, in clusters of 2+.
+    /// Members are newest first; clusters are ordered by their newest member.
+    pub fn find_duplicate_sessions(&self) -> SqliteResult<Vec<DuplicateCluster>> {
+        let conn = self.conn()?;
+        let mut stmt = conn.prepare(
+            r#"SELECT id, title, last_prompt, updated_at FROM sessions
+               WHERE last_prompt IS NOT NULL AND TRIM(last_prompt) != ''
+               ORDER BY updated_at DESC"#
+        )?;
+        let rows = stmt.query_map([], |row| {
+            let prompt: String = row.get(2)?;
+            Ok((prompt, DuplicateMember { id: row.get(0)?, title: row.get(1)?, updated_at: row.get(3)? }))
+        })?;
+
+        // Rows arrive newest first, so cluster order and member order both follow recency
+        let mut clusters: Vec<DuplicateCluster> = Vec::new();
+        let mut index: std::collections::HashMap<String, usize> = std::collections::HashMap::new();
+        for row in rows {
+            let (prompt, member) = row?;
+            let key = normalize_prompt(&prompt);
+            match index.get(&key) {
+                Some(&i) => clusters[i].sessions.push(member),
+                None => {
+                    index.insert(key.clone(), clusters.len());
+                    clusters.push(DuplicateCluster { prompt: key, sessions: vec![member] });
+                }
+            }
+        }
+        clusters.retain(|c| c.sessions.len() > 1);
+        Ok(clusters)
+    }
+
+    /// Give sessions sharing a title (trimmed, case-insensitive) a " (2)", " (3)"… suffix.
+    /// The oldest keeps its title; suffixes skip titles already in use. `updated_at` is left alone
+    /// so the list order holds. Returns how many sessions were renamed.
+    pub fn dedupe_session_titles(&self) -> SqliteResult<usize> {
+        let mut conn = self.conn()?;
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        let sessions: Vec<(String, String)> = {
+            let mut stmt = tx.prepare("SELECT id, title FROM sessions ORDER BY created_at ASC, rowid ASC")?;
+            let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?;
+            rows.collect::<SqliteResult<_>>()?
+        };
+
+        let key = |title: &str| title.trim().to_lowercase();
+        let mut taken: std::collections::HashSet<String> = sessions.iter().map(|(_, title)| key(title)).collect();
+        let mut seen: std::collections::HashSet<String> = std::collections::HashSet::new();
+        let mut renamed = 0;
+        for (id, title) in &sessions {
+            if seen.insert(key(title)) {
+                continue;
+            }
+            let base = title.trim();
+            let new_title = (2..)
+                .map(|n| format!("{} ({})", base, n))
+                .find(|candidate| !taken.contains(&key(candidate)))
+                .expect("unbounded range");
+            taken.insert(key(&new_title));
+            seen.insert(key(&new_title));
+            tx.execute("UPDATE sessions SET title = ?1 WHERE id = ?2", params![new_title, id])?;
+            renamed += 1;
+        }
+        tx.commit()?;
+        Ok(renamed)
     }
 
     pub fn set_pinned(&self, id: &str, is_pinned: bool) -> SqliteResult<()> {
-        let conn = self.conn.lock().unwrap();
+        let conn = self.conn()?;
         let now = chrono::Utc::now().timestamp_millis();
         conn.execute(
             "UPDATE sessions SET is_pinned = ?1, updated_at = ?2 WHERE id = ?3",
@@ -305,10 +986,53 @@ impl Database {
         Ok(())
     }
 
+    /// Hide a session from `list_sessions` without deleting it. Returns false for unknown ids.
+    pub fn set_archived(&self, id: &str, archived: bool) -> SqliteResult<bool> {
+        let conn = self.conn()?;
+        let changed = conn.execute(
+            "UPDATE sessions SET archived = ?1 WHERE id = ?2",
+            params![if archived { 1 } else { 0 }, id],
+        )?;
+        Ok(changed > 0)
+    }
+
+    /// Locked sessions stay readable but refuse deletes, truncation and new messages
+    pub fn set_locked(&self, id: &str, is_locked: bool) -> SqliteResult<bool> {
+        let conn = self.conn()?;
+        let changed = conn.execute(
+            "UPDATE sessions SET is_locked = ?1 WHERE id = ?2",
+            params![if is_locked { 1 } else { 0 }, id],
+        )?;
+        Ok(changed > 0)
+    }
+
+    pub fn is_session_locked(&self, id: &str) -> SqliteResult<bool> {
+        let conn = self.conn()?;
+        session_locked(&conn, id)
+    }
+
+    /// Force one session's status (e.g. stuck as "running" after the sidecar lost its completion).
+    /// Returns false when the session doesn't exist.
+    pub fn reset_session_status(&self, id: &str, to_status: &str) -> SqliteResult<bool> {
+        if !SESSION_STATUSES.contains(&to_status) {
+            return Err(rusqlite::Error::ToSqlConversionFailure(
+                format!("Unknown session status '{}' (expected one of: {})", to_status, SESSION_STATUSES.join(", ")).into(),
+            ));
+        }
+        let conn = self.conn()?;
+        let now = chrono::Utc::now().timestamp_millis();
+        let changed = conn.execute(
+            "UPDATE sessions SET status = ?1, updated_at = ?2 WHERE id = ?3",
+            params![to_status, now, id],
+        )?;
+        Ok(changed > 0)
+    }
+
     /// Reset all sessions with status "running" to "idle"
     /// Should be called on app startup to clean up stale running sessions
+    /// Archived sessions included: a session archived mid-run must not stay "running"
     pub fn reset_running_sessions(&self) -> SqliteResult<usize> {
-        let conn = self.conn.lock().unwrap();
+        let conn = self.conn()?;
         let now = chrono::Utc::now().timestamp_millis();
         let changed = conn.execute(
             "UPDATE sessions SET status = 'idle', updated_at = ?1 WHERE status = 'running'",
@@ -317,8 +1041,65 @@ impl Database {
         Ok(changed)
     }
 
+    /// Point every session using `from_model_id` at `to_model_id`, returning the ids that changed.
+    /// Callers are expected to have checked the target model (see `get_model`).
+    pub fn reassign_model(&self, from_model_id: &str, to_model_id: &str) -> SqliteResult<Vec<String>> {
+        let mut conn = self.conn()?;
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        let ids = {
+            let mut stmt = tx.prepare("SELECT id FROM sessions WHERE model = ?1")?;
+            let rows = stmt.query_map([from_model_id], |row| row.get::<_, String>(0))?;
+            rows.collect::<SqliteResult<Vec<_>>>()?
+        };
+        tx.execute(
+            "UPDATE sessions SET model = ?1 WHERE model = ?2",
+            params![to_model_id, from_model_id],
+        )?;
+        tx.commit()?;
+        Ok(ids)
+    }
+
+    /// Set the temperature of the given sessions (`None` = every session), returning how many changed.
+    /// Values outside `DEFAULT_TEMPERATURE_RANGE` are rejected; within it, each session is clamped to
+    /// its model's own range like `update_session`. `updated_at` is left alone so the list order holds.
+    pub fn set_temperature_bulk(&self, ids: Option<&[String]>, temperature: f64) -> SqliteResult<usize> {
+        let (min, max) = DEFAULT_TEMPERATURE_RANGE;
+        if !temperature.is_finite() || temperature < min || temperature > max {
+            return Err(rusqlite::Error::ToSqlConversionFailure(
+                format!("temperature must be between {} and {}, got {}", min, max, temperature).into(),
+            ));
+        }
+
+        let targets: Vec<(String, Option<String>)> = {
+            let conn = self.conn()?;
+            let mut stmt = conn.prepare("SELECT id, model FROM sessions")?;
+            let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?;
+            rows.filter(|row| match (row, ids) {
+                (Ok((id, _)), Some(ids)) => ids.contains(id),
+                _ => true,
+            })
+            .collect::<SqliteResult<_>>()?
+        };
+        let mut ranges: std::collections::HashMap<Option<String>, (f64, f64)> = std::collections::HashMap::new();
+        for (_, model) in &targets {
+            if !ranges.contains_key(model) {
+                ranges.insert(model.clone(), self.temperature_range_for_model(model.as_deref())?);
+            }
+        }
+
+        let mut conn = self.conn()?;
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        let mut changed = 0;
+        for (id, model) in &targets {
+            let value = clamp_temperature(temperature, ranges[model]);
+            changed += tx.execute("UPDATE sessions SET temperature = ?1 WHERE id = ?2", params![value, id])?;
+        }
+        tx.commit()?;
+        Ok(changed)
+    }
+
     pub fn update_tokens(&self, id: &str, input_tokens: i64, output_tokens: i64) -> SqliteResult<()> {
-        let conn = self.conn.lock().unwrap();
+        let conn = self.conn()?;
         let now = chrono::Utc::now().timestamp_millis();
         conn.execute(
             r#"UPDATE sessions SET 
@@ -328,11 +1109,12 @@ impl Database {
                WHERE id = ?4"#,
             params![input_tokens, output_tokens, now, id],
         )?;
-        Ok(())
+        append_usage(&conn, id, input_tokens, output_tokens, now)
     }
 
     pub fn record_message(&self, session_id: &str, message: &serde_json::Value) -> SqliteResult<()> {
-        let conn = self.conn.lock().unwrap();
+        let conn = self.conn()?;
+        ensure_unlocked(&conn, session_id)?;
         let id = message
             .get("uuid")
             .and_then(|v| v.as_str())
@@ -348,42 +1130,544 @@ impl Database {
         Ok(())
     }
 
-    pub fn get_session_messages(&self, session_id: &str) -> SqliteResult<Vec<serde_json::Value>> {
-        let conn = self.conn.lock().unwrap();
-        let mut stmt = conn.prepare(
-            "SELECT data FROM messages WHERE session_id = ?1 ORDER BY created_at ASC"
-        )?;
+    /// Semantic search configuration; None while the feature is off
+    pub fn get_embedding_settings(&self) -> SqliteResult<Option<EmbeddingSettings>> {
+        Ok(self.get_setting(EMBEDDING_SETTINGS_SETTING)?.and_then(|raw| serde_json::from_str(&raw).ok()))
+    }
 
-        let rows = stmt.query_map([session_id], |row| {
-            let data: String = row.get(0)?;
-            Ok(serde_json::from_str(&data).unwrap_or(serde_json::Value::Null))
-        })?;
+    pub fn set_embedding_settings(&self, settings: Option<&EmbeddingSettings>) -> SqliteResult<()> {
+        match settings {
+            Some(settings) => {
+                let json = serde_json::to_string(settings)
+                    .map_err(|e| rusqlite::Error::ToSqlConversionFailure(Box::new(e)))?;
+                self.set_setting(EMBEDDING_SETTINGS_SETTING, &json)
+            }
+            None => {
+                let conn = self.conn()?;
+                conn.execute("DELETE FROM settings WHERE key = ?1", [EMBEDDING_SETTINGS_SETTING])?;
+                Ok(())
+            }
+        }
+    }
 
+    /// Prompts and replies with no vector for `model` yet, oldest first: (message id, session id, text)
+    pub fn messages_missing_embeddings(&self, model: &str, limit: u32) -> SqliteResult<Vec<(String, String, String)>> {
+        let conn = self.conn()?;
+        let mut stmt = conn.prepare(&format!(
+            r#"SELECT m.id, m.session_id, {} AS body
+               FROM messages m
+               WHERE json_valid(m.data) AND body IS NOT NULL AND trim(body) != ''
+                 AND NOT EXISTS (SELECT 1 FROM message_embeddings e WHERE e.message_id = m.id AND e.model = ?1)
+               ORDER BY m.created_at ASC
+               LIMIT ?2"#,
+            MESSAGE_TEXT_SQL.replace("{data}", "m.data")
+        ))?;
+        let rows = stmt.query_map(params![model, limit], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?;
         rows.collect()
     }
 
-    pub fn get_session_history(&self, id: &str) -> SqliteResult<Option<SessionHistory>> {
-        let session = match self.get_session(id)? {
-            Some(s) => s,
-            None => return Ok(None),
-        };
-
-        let messages = self.get_session_messages(id)?;
-        
-        // Get todos from session
-        let todos = self.get_todos(id)?;
-        let file_changes = self.get_file_changes(id)?;
+    pub fn save_message_embedding(&self, message_id: &str, session_id: &str, model: &str, vector: &[f32]) -> SqliteResult<()> {
+        let conn = self.conn()?;
+        let blob: Vec<u8> = vector.iter().flat_map(|v| v.to_le_bytes()).collect();
+        conn.execute(
+            "INSERT OR REPLACE INTO message_embeddings (message_id, session_id, model, vector, created_at) VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![message_id, session_id, model, blob, chrono::Utc::now().timestamp_millis()],
+        )?;
+        Ok(())
+    }
+
+    /// The `top_k` messages embedded with `model` closest to `query` by cosine similarity, best first
+    pub fn semantic_search(&self, model: &str, query: &[f32], top_k: usize) -> SqliteResult<Vec<SemanticHit>> {
+        let conn = self.conn()?;
+        let mut stmt = conn.prepare(
+            r#"SELECT e.message_id, e.session_id, s.title, e.vector, m.data, m.created_at
+               FROM message_embeddings e
+               JOIN messages m ON m.id = e.message_id
+               JOIN sessions s ON s.id = e.session_id
+               WHERE e.model = ?1"#
+        )?;
+        let mut rows = stmt.query([model])?;
+        let mut hits: Vec<SemanticHit> = Vec::new();
+        while let Some(row) = rows.next()? {
+            let blob: Vec<u8> = row.get(3)?;
+            let vector: Vec<f32> = blob.chunks_exact(4).map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]])).collect();
+            let Some(score) = cosine_similarity(query, &vector) else { continue };
+            let data: String = row.get(4)?;
+            hits.push(SemanticHit {
+                message_id: row.get(0)?,
+                session_id: row.get(1)?,
+                session_title: row.get(2)?,
+                snippet: message_snippet(&serde_json::from_str(&data).unwrap_or(JsonValue::Null)),
+                created_at: row.get(5)?,
+                score,
+            });
+        }
+        hits.sort_by(|a, b| b.score.total_cmp(&a.score));
+        hits.truncate(top_k);
+        Ok(hits)
+    }
+
+    /// Messages whose prompt/reply text matches `query`, best match first. Uses the FTS5 index
+    /// when SQLite has it, otherwise a case-insensitive `LIKE` scan (newest first).
+    pub fn search_messages(&self, query: &str, limit: u32) -> SqliteResult<Vec<MessageHit>> {
+        let terms: Vec<&str> = query.split_whitespace().collect();
+        if terms.is_empty() {
+            return Ok(Vec::new());
+        }
+        let conn = self.conn()?;
+        let map_hit = |row: &rusqlite::Row| {
+            Ok(MessageHit {
+                session_id: row.get(0)?,
+                session_title: row.get(1)?,
+                message_id: row.get(2)?,
+                snippet: row.get::<_, Option<String>>(3)?.unwrap_or_default(),
+                created_at: row.get(4)?,
+            })
+        };
+
+        if has_message_fts(&conn)? {
+            let map_hit = |row: &rusqlite::Row| {
+                map_hit(row).map(|mut hit| {
+                    hit.snippet = fts_snippet_html(&hit.snippet);
+                    hit
+                })
+            };
+            // Quote every term so user input can't hit FTS5 query syntax
+            let fts_query = terms
+                .iter()
+                .map(|t| format!("\"{}\"", t.replace('"', "\"\"")))
+                .collect::<Vec<_>>()
+                .join(" ");
+            let mut stmt = conn.prepare(
+                r#"SELECT m.session_id, s.title, m.id,
+                          snippet(messages_fts, 0, char(1), char(2), '…', 12), m.created_at
+                   FROM messages_fts f
+                   JOIN messages m ON m.id = f.message_id
+                   JOIN sessions s ON s.id = m.session_id
+                   WHERE messages_fts MATCH ?1
+                   ORDER BY rank
+                   LIMIT ?2"#
+            )?;
+            let rows = stmt.query_map(params![fts_query, limit], map_hit)?;
+            return rows.collect();
+        }
+
+        let pattern = format!("%{}%", query.trim().replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_"));
+        let mut stmt = conn.prepare(&format!(
+            r#"SELECT m.session_id, s.title, m.id, {} AS body, m.created_at
+               FROM messages m
+               JOIN sessions s ON s.id = m.session_id
+               WHERE json_valid(m.data) AND body LIKE ?1 ESCAPE '\'
+               ORDER BY m.created_at DESC
+               LIMIT ?2"#,
+            MESSAGE_TEXT_SQL.replace("{data}", "m.data")
+        ))?;
+        let rows = stmt.query_map(params![pattern, limit], map_hit)?;
+        rows.map(|hit| {
+            hit.map(|mut hit| {
+                hit.snippet = highlight_snippet(&hit.snippet, query.trim());
+                hit
+            })
+        })
+        .collect()
+    }
+
+    /// Move large base64 strings in this session's messages into `blobs_dir`, leaving
+    /// references (see `blobs::rehydrate_value`). Returns what was moved and the bytes saved.
+    pub fn externalize_blobs(&self, session_id: &str, blobs_dir: &Path) -> SqliteResult<BlobExternalization> {
+        let mut conn = self.conn()?;
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        ensure_unlocked(&tx, session_id)?;
+        let messages: Vec<(String, String)> = tx
+            .prepare("SELECT id, data FROM messages WHERE session_id = ?1")?
+            .query_map([session_id], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<SqliteResult<_>>()?;
+
+        let mut result = BlobExternalization::default();
+        for (id, data) in messages {
+            // Cheap pre-check: nothing to do unless the raw JSON is at least one blob long
+            if data.len() < crate::blobs::BLOB_MIN_BYTES {
+                continue;
+            }
+            let Ok(mut value) = serde_json::from_str::<JsonValue>(&data) else { continue };
+            let stats = crate::blobs::externalize_value(&mut value, blobs_dir, crate::blobs::BLOB_MIN_BYTES)
+                .map_err(|e| rusqlite::Error::ToSqlConversionFailure(Box::new(e)))?;
+            if stats.blobs == 0 {
+                continue;
+            }
+            let data = serde_json::to_string(&value).unwrap_or_default();
+            tx.execute("UPDATE messages SET data = ?1 WHERE id = ?2", params![data, id])?;
+            result.messages_updated += 1;
+            result.blobs += stats.blobs;
+            result.bytes_saved += stats.bytes_saved as i64;
+        }
+        tx.commit()?;
+        Ok(result)
+    }
+
+    /// File names of every blob still referenced from stored messages (see `blobs::collect_refs`)
+    pub fn referenced_blobs(&self) -> SqliteResult<std::collections::HashSet<String>> {
+        let conn = self.conn()?;
+        let mut stmt = conn.prepare("SELECT data FROM messages WHERE instr(data, ?1) > 0")?;
+        let rows = stmt.query_map([crate::blobs::BLOB_REF_PREFIX], |row| row.get::<_, String>(0))?;
+        let mut refs = std::collections::HashSet::new();
+        for data in rows {
+            if let Ok(value) = serde_json::from_str::<JsonValue>(&data?) {
+                crate::blobs::collect_refs(&value, &mut refs);
+            }
+        }
+        Ok(refs)
+    }
+
+    pub fn get_session_messages(&self, session_id: &str) -> SqliteResult<Vec<serde_json::Value>> {
+        let conn = self.conn()?;
+        let mut stmt = conn.prepare(
+            "SELECT data FROM messages WHERE session_id = ?1 ORDER BY created_at ASC"
+        )?;
+
+        let rows = stmt.query_map([session_id], |row| {
+            let data: String = row.get(0)?;
+            Ok(serde_json::from_str(&data).unwrap_or(serde_json::Value::Null))
+        })?;
+
+        rows.collect()
+    }
+
+    pub fn get_session_history(&self, id: &str) -> SqliteResult<Option<SessionHistory>> {
+        let session = match self.get_session(id)? {
+            Some(s) => s,
+            None => return Ok(None),
+        };
+
+        let mut messages = self.get_session_messages(id)?;
+        // A leftover draft means the app died mid-stream; show it as the last partial message
+        if let Some(draft) = self.get_stream_draft(id)? {
+            messages.push(serde_json::json!({
+                "type": "text",
+                "text": draft,
+                "uuid": format!("draft_{}", id),
+                "partial": true
+            }));
+        }
+        
+        // Get todos from session
+        let todos = self.get_todos(id)?;
+        let file_changes = self.get_file_changes(id)?;
+        let metadata = self.list_session_meta(id)?;
 
         Ok(Some(SessionHistory {
             session,
             messages,
             todos,
             file_changes,
+            metadata,
         }))
     }
 
+    /// Aggregate counts for a session without loading message bodies
+    pub fn get_session_summary(&self, id: &str) -> SqliteResult<Option<SessionSummary>> {
+        let conn = self.conn()?;
+        let mut stmt = conn.prepare(
+            r#"SELECT s.id, s.model, s.input_tokens, s.output_tokens, s.created_at, s.updated_at,
+                      (SELECT COUNT(*) FROM messages m WHERE m.session_id = s.id),
+                      (SELECT MIN(created_at) FROM messages m WHERE m.session_id = s.id),
+                      (SELECT MAX(created_at) FROM messages m WHERE m.session_id = s.id),
+                      CASE WHEN json_valid(s.file_changes) THEN json_array_length(s.file_changes) ELSE 0 END,
+                      mo.config
+               FROM sessions s
+               LEFT JOIN models mo ON mo.id = s.model
+               WHERE s.id = ?1"#
+        )?;
+
+        let mut rows = stmt.query_map([id], |row| {
+            let input_tokens: i64 = row.get::<_, Option<i64>>(2)?.unwrap_or(0);
+            let output_tokens: i64 = row.get::<_, Option<i64>>(3)?.unwrap_or(0);
+            let created_at: i64 = row.get(4)?;
+            let updated_at: i64 = row.get(5)?;
+            let config: Option<JsonValue> = row.get::<_, Option<String>>(10)?
+                .and_then(|s| serde_json::from_str(&s).ok());
+            let estimated_cost = ModelConfig::from_config(config.as_ref()).estimate_cost(input_tokens, output_tokens);
+
+            Ok(SessionSummary {
+                session_id: row.get(0)?,
+                model: row.get(1)?,
+                message_count: row.get(6)?,
+                input_tokens,
+                output_tokens,
+                estimated_cost,
+                first_activity_at: row.get::<_, Option<i64>>(7)?.unwrap_or(created_at),
+                last_activity_at: row.get::<_, Option<i64>>(8)?.unwrap_or(updated_at).max(updated_at),
+                file_change_count: row.get(9)?,
+            })
+        })?;
+
+        rows.next().transpose()
+    }
+
+    pub fn get_session_text_stats(&self, id: &str) -> SqliteResult<Option<SessionTextStats>> {
+        let conn = self.conn()?;
+        let exists: bool = conn.query_row("SELECT EXISTS(SELECT 1 FROM sessions WHERE id = ?1)", [id], |row| row.get(0))?;
+        if !exists {
+            return Ok(None);
+        }
+
+        // Only the text fields leave SQLite, not the whole message JSON
+        let mut stmt = conn.prepare(
+            r#"SELECT json_extract(data, '$.type'),
+                      CASE json_extract(data, '$.type') WHEN 'user_prompt' THEN json_extract(data, '$.prompt') ELSE json_extract(data, '$.text') END
+               FROM messages
+               WHERE session_id = ?1 AND json_valid(data) AND json_extract(data, '$.type') IN ('user_prompt', 'text')"#
+        )?;
+        let mut stats = SessionTextStats {
+            session_id: id.to_string(),
+            user_messages: 0,
+            assistant_messages: 0,
+            words: 0,
+            characters: 0,
+            reading_minutes: 0,
+        };
+        let rows = stmt.query_map([id], |row| Ok((row.get::<_, String>(0)?, row.get::<_, Option<String>>(1)?)))?;
+        for row in rows {
+            let (kind, text) = row?;
+            let Some(text) = text else { continue };
+            if kind == "user_prompt" {
+                stats.user_messages += 1;
+            } else {
+                stats.assistant_messages += 1;
+            }
+            stats.words += text.split_whitespace().count() as i64;
+            stats.characters += text.chars().count() as i64;
+        }
+        stats.reading_minutes = (stats.words + READING_WORDS_PER_MINUTE - 1) / READING_WORDS_PER_MINUTE;
+        Ok(Some(stats))
+    }
+
+    /// Tool calls recorded in a session's messages: per-tool counts (most used first) and the
+    /// calls in order. `None` if the session doesn't exist.
+    pub fn list_tool_invocations(&self, session_id: &str) -> SqliteResult<Option<ToolUsageReport>> {
+        let conn = self.conn()?;
+        let exists: bool = conn.query_row("SELECT EXISTS(SELECT 1 FROM sessions WHERE id = ?1)", [session_id], |row| row.get(0))?;
+        if !exists {
+            return Ok(None);
+        }
+
+        let mut stmt = conn.prepare(
+            r#"SELECT json_extract(data, '$.name'), json_extract(data, '$.id'), created_at
+               FROM messages
+               WHERE session_id = ?1 AND json_valid(data) AND json_extract(data, '$.type') = 'tool_use'
+               ORDER BY created_at ASC"#
+        )?;
+        let timeline: Vec<ToolInvocation> = stmt
+            .query_map([session_id], |row| {
+                Ok(ToolInvocation {
+                    name: row.get::<_, Option<String>>(0)?.unwrap_or_else(|| "unknown".to_string()),
+                    tool_use_id: row.get(1)?,
+                    created_at: row.get(2)?,
+                })
+            })?
+            .collect::<SqliteResult<_>>()?;
+
+        let mut counts: Vec<ToolUsageCount> = Vec::new();
+        for call in &timeline {
+            match counts.iter_mut().find(|c| c.name == call.name) {
+                Some(count) => count.count += 1,
+                None => counts.push(ToolUsageCount { name: call.name.clone(), count: 1 }),
+            }
+        }
+        // Stable, so ties keep first-use order
+        counts.sort_by_key(|c| std::cmp::Reverse(c.count));
+
+        Ok(Some(ToolUsageReport { session_id: session_id.to_string(), counts, timeline }))
+    }
+
+    /// Messages a prune would remove, oldest first, without deleting anything.
+    /// The newest `keep_last_n` are always kept; with a `token_budget`, older messages are kept
+    /// newest-first until the estimated total would exceed it, and everything before that goes.
+    pub fn preview_prune(&self, session_id: &str, keep_last_n: usize, token_budget: Option<i64>) -> SqliteResult<Vec<PruneCandidate>> {
+        let conn = self.conn()?;
+        let mut stmt = conn.prepare(
+            "SELECT id, data, created_at FROM messages WHERE session_id = ?1 ORDER BY created_at ASC"
+        )?;
+        let messages: Vec<(String, JsonValue, i64)> = stmt
+            .query_map([session_id], |row| {
+                let data: String = row.get(1)?;
+                Ok((row.get(0)?, serde_json::from_str(&data).unwrap_or(JsonValue::Null), row.get(2)?))
+            })?
+            .collect::<SqliteResult<_>>()?;
+
+        let tokens: Vec<i64> = messages.iter().map(|(_, parsed, _)| estimate_message_tokens(parsed)).collect();
+        let cut = prune_cut(&tokens, keep_last_n, token_budget);
+
+        Ok(messages[..cut]
+            .iter()
+            .zip(&tokens)
+            .map(|((id, parsed, created_at), tokens)| PruneCandidate {
+                id: id.clone(),
+                message_type: parsed.get("type").and_then(|v| v.as_str()).unwrap_or("unknown").to_string(),
+                snippet: message_snippet(parsed),
+                estimated_tokens: *tokens,
+                created_at: *created_at,
+            })
+            .collect())
+    }
+
+    /// Token budget for history sent on `session.continue`; None (the default) sends it all
+    pub fn get_max_context_tokens(&self) -> SqliteResult<Option<i64>> {
+        Ok(self
+            .get_setting(MAX_CONTEXT_TOKENS_SETTING)?
+            .and_then(|v| v.trim().parse::<i64>().ok())
+            .filter(|n| *n > 0))
+    }
+
+    pub fn save_stream_draft(&self, session_id: &str, content: &str) -> SqliteResult<()> {
+        let conn = self.conn()?;
+        let now = chrono::Utc::now().timestamp_millis();
+        conn.execute(
+            "INSERT OR REPLACE INTO stream_drafts (session_id, content, updated_at) VALUES (?1, ?2, ?3)",
+            params![session_id, content, now],
+        )?;
+        Ok(())
+    }
+
+    pub fn get_stream_draft(&self, session_id: &str) -> SqliteResult<Option<String>> {
+        let conn = self.conn()?;
+        let mut stmt = conn.prepare("SELECT content FROM stream_drafts WHERE session_id = ?1")?;
+        let mut rows = stmt.query([session_id])?;
+        match rows.next()? {
+            Some(row) => Ok(Some(row.get(0)?)),
+            None => Ok(None),
+        }
+    }
+
+    pub fn clear_stream_draft(&self, session_id: &str) -> SqliteResult<()> {
+        let conn = self.conn()?;
+        conn.execute("DELETE FROM stream_drafts WHERE session_id = ?1", [session_id])?;
+        Ok(())
+    }
+
+    /// Deletes drafts last written before `cutoff_ms`; returns how many went
+    pub fn expire_stream_drafts(&self, cutoff_ms: i64) -> SqliteResult<usize> {
+        let conn = self.conn()?;
+        conn.execute("DELETE FROM stream_drafts WHERE updated_at < ?1", [cutoff_ms])
+    }
+
+    // --- Session metadata ---
+
+    /// Returns false when the session doesn't exist
+    pub fn set_session_meta(&self, session_id: &str, key: &str, value: &str) -> SqliteResult<bool> {
+        let key = key.trim();
+        if key.is_empty() {
+            return Err(rusqlite::Error::ToSqlConversionFailure("Metadata key must not be empty".into()));
+        }
+        let conn = self.conn()?;
+        let exists: bool = conn.query_row("SELECT EXISTS(SELECT 1 FROM sessions WHERE id = ?1)", [session_id], |row| row.get(0))?;
+        if !exists {
+            return Ok(false);
+        }
+        let now = chrono::Utc::now().timestamp_millis();
+        conn.execute(
+            "INSERT OR REPLACE INTO session_metadata (session_id, key, value, updated_at) VALUES (?1, ?2, ?3, ?4)",
+            params![session_id, key, value, now],
+        )?;
+        Ok(true)
+    }
+
+    pub fn get_session_meta(&self, session_id: &str, key: &str) -> SqliteResult<Option<String>> {
+        let conn = self.conn()?;
+        let mut stmt = conn.prepare("SELECT value FROM session_metadata WHERE session_id = ?1 AND key = ?2")?;
+        let mut rows = stmt.query(params![session_id, key.trim()])?;
+        match rows.next()? {
+            Some(row) => Ok(Some(row.get(0)?)),
+            None => Ok(None),
+        }
+    }
+
+    pub fn list_session_meta(&self, session_id: &str) -> SqliteResult<std::collections::BTreeMap<String, String>> {
+        let conn = self.conn()?;
+        let mut stmt = conn.prepare("SELECT key, value FROM session_metadata WHERE session_id = ?1")?;
+        let rows = stmt.query_map([session_id], |row| Ok((row.get(0)?, row.get(1)?)))?;
+        rows.collect()
+    }
+
+    pub fn delete_session_meta(&self, session_id: &str, key: &str) -> SqliteResult<bool> {
+        let conn = self.conn()?;
+        let changed = conn.execute(
+            "DELETE FROM session_metadata WHERE session_id = ?1 AND key = ?2",
+            params![session_id, key.trim()],
+        )?;
+        Ok(changed > 0)
+    }
+
+    /// Add to the running transcription totals for a session
+    pub fn add_voice_usage(&self, session_id: &str, audio_seconds: f64, requests: i64) -> SqliteResult<()> {
+        let conn = self.conn()?;
+        let now = chrono::Utc::now().timestamp_millis();
+        conn.execute(
+            "INSERT INTO voice_usage (session_id, audio_seconds, requests, updated_at) VALUES (?1, ?2, ?3, ?4)
+             ON CONFLICT(session_id) DO UPDATE SET
+               audio_seconds = audio_seconds + excluded.audio_seconds,
+               requests = requests + excluded.requests,
+               updated_at = excluded.updated_at",
+            params![session_id, audio_seconds, requests, now],
+        )?;
+        Ok(())
+    }
+
+    pub fn add_dictation_metrics(&self, metrics: &DictationMetrics) -> SqliteResult<()> {
+        let conn = self.conn()?;
+        let now = chrono::Utc::now().timestamp_millis();
+        conn.execute(
+            "INSERT INTO voice_dictation_metrics (session_id, model, transport, first_partial_ms, final_latency_ms, created_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![
+                metrics.session_id,
+                metrics.model,
+                metrics.transport,
+                metrics.first_partial_ms,
+                metrics.final_latency_ms,
+                now
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// Dictation latency averaged per model and transport, for dictations finished at or after `since`
+    pub fn dictation_metrics_summary(&self, since: i64) -> SqliteResult<Vec<DictationMetricsSummary>> {
+        let conn = self.conn()?;
+        let mut stmt = conn.prepare(
+            r#"SELECT model, transport, COUNT(*), AVG(first_partial_ms), AVG(final_latency_ms), MAX(final_latency_ms)
+               FROM voice_dictation_metrics
+               WHERE created_at >= ?1
+               GROUP BY model, transport
+               ORDER BY COUNT(*) DESC"#
+        )?;
+        let rows = stmt.query_map([since], |row| {
+            Ok(DictationMetricsSummary {
+                model: row.get(0)?,
+                transport: row.get(1)?,
+                dictations: row.get(2)?,
+                avg_first_partial_ms: row.get(3)?,
+                avg_final_latency_ms: row.get(4)?,
+                max_final_latency_ms: row.get(5)?,
+            })
+        })?;
+        rows.collect()
+    }
+
+    /// Persisted transcription totals; zeros when the session never used voice
+    pub fn get_voice_usage(&self, session_id: &str) -> SqliteResult<VoiceUsage> {
+        let conn = self.conn()?;
+        let mut stmt = conn.prepare("SELECT audio_seconds, requests FROM voice_usage WHERE session_id = ?1")?;
+        let mut rows = stmt.query([session_id])?;
+        let (audio_seconds, requests) = match rows.next()? {
+            Some(row) => (row.get(0)?, row.get(1)?),
+            None => (0.0, 0),
+        };
+        Ok(VoiceUsage { session_id: session_id.to_string(), audio_seconds, requests })
+    }
+
     pub fn get_todos(&self, session_id: &str) -> SqliteResult<Vec<TodoItem>> {
-        let conn = self.conn.lock().unwrap();
+        let conn = self.conn()?;
         let mut stmt = conn.prepare("SELECT todos FROM sessions WHERE id = ?1")?;
         let mut rows = stmt.query([session_id])?;
         
@@ -399,7 +1683,7 @@ impl Database {
     }
 
     pub fn save_todos(&self, session_id: &str, todos: &[TodoItem]) -> SqliteResult<()> {
-        let conn = self.conn.lock().unwrap();
+        let conn = self.conn()?;
         let now = chrono::Utc::now().timestamp_millis();
         let todos_json = serde_json::to_string(todos).unwrap_or_default();
         conn.execute(
@@ -410,7 +1694,7 @@ impl Database {
     }
 
     pub fn get_file_changes(&self, session_id: &str) -> SqliteResult<Vec<FileChange>> {
-        let conn = self.conn.lock().unwrap();
+        let conn = self.conn()?;
         let mut stmt = conn.prepare("SELECT file_changes FROM sessions WHERE id = ?1")?;
         let mut rows = stmt.query([session_id])?;
         
@@ -426,7 +1710,7 @@ impl Database {
     }
 
     pub fn save_file_changes(&self, session_id: &str, changes: &[FileChange]) -> SqliteResult<()> {
-        let conn = self.conn.lock().unwrap();
+        let conn = self.conn()?;
         let now = chrono::Utc::now().timestamp_millis();
         let changes_json = serde_json::to_string(changes).unwrap_or_default();
         conn.execute(
@@ -437,11 +1721,11 @@ impl Database {
     }
 
     pub fn list_recent_cwds(&self, limit: u32) -> SqliteResult<Vec<String>> {
-        let conn = self.conn.lock().unwrap();
+        let conn = self.conn()?;
         let mut stmt = conn.prepare(
             r#"SELECT cwd, MAX(updated_at) as latest
                FROM sessions
-               WHERE cwd IS NOT NULL AND TRIM(cwd) != ''
+               WHERE cwd IS NOT NULL AND TRIM(cwd) != '' AND COALESCE(archived, 0) = 0
                GROUP BY cwd
                ORDER BY latest DESC
                LIMIT ?1"#
@@ -454,8 +1738,73 @@ impl Database {
         rows.collect()
     }
 
+    /// Sessions whose cwd is `path` (or, with `include_subfolders`, anywhere below it), newest first
+    pub fn list_sessions_for_cwd(&self, path: &str, include_subfolders: bool) -> SqliteResult<Vec<Session>> {
+        let target = normalize_cwd(path);
+        if target.is_empty() {
+            return Ok(Vec::new());
+        }
+        let prefix = if target.ends_with('/') { target.clone() } else { format!("{}/", target) };
+
+        // Archived sessions still depend on the folder
+        Ok(self.list_sessions_filtered(true)?
+            .into_iter()
+            .filter(|session| {
+                let Some(cwd) = session.cwd.as_deref().map(normalize_cwd) else { return false };
+                cwd == target || (include_subfolders && cwd.starts_with(&prefix))
+            })
+            .collect())
+    }
+
+    /// The stored `messages.data` string, untouched (blob refs stay externalized).
+    /// Indexes count in history order, like `truncate_history_after`.
+    pub fn get_message_raw(&self, session_id: &str, message: &MessageRef) -> SqliteResult<Option<String>> {
+        let conn = self.conn()?;
+        let found = match message {
+            MessageRef::Uuid(id) => conn.query_row(
+                "SELECT data FROM messages WHERE session_id = ?1 AND id = ?2",
+                params![session_id, id],
+                |row| row.get(0),
+            ),
+            MessageRef::Index(index) => conn.query_row(
+                "SELECT data FROM messages WHERE session_id = ?1 ORDER BY created_at ASC LIMIT 1 OFFSET ?2",
+                params![session_id, *index as i64],
+                |row| row.get(0),
+            ),
+        };
+        found.map(Some).or_else(|e| match e {
+            rusqlite::Error::QueryReturnedNoRows => Ok(None),
+            e => Err(e),
+        })
+    }
+
+    /// SHA-256 (hex) over the session row plus its message ids/timestamps, in history order.
+    /// Stable while nothing changes, so a sync layer can skip sessions with a matching hash.
+    pub fn session_content_hash(&self, session_id: &str) -> SqliteResult<Option<String>> {
+        use sha2::{Digest, Sha256};
+
+        let Some(session) = self.get_session(session_id)? else {
+            return Ok(None);
+        };
+        let conn = self.conn()?;
+        let mut stmt = conn.prepare(
+            "SELECT id, created_at FROM messages WHERE session_id = ?1 ORDER BY created_at ASC, id ASC"
+        )?;
+        let messages = stmt.query_map([session_id], |row| Ok((row.get::<_, String>(0)?, row.get::<_, i64>(1)?)))?;
+
+        let mut hasher = Sha256::new();
+        hasher.update(serde_json::to_string(&session).unwrap_or_default());
+        for message in messages {
+            let (id, created_at) = message?;
+            hasher.update(format!("\n{}\0{}", id, created_at));
+        }
+        let digest = hasher.finalize();
+        Ok(Some(digest.iter().map(|b| format!("{:02x}", b)).collect()))
+    }
+
     pub fn truncate_history_after(&self, session_id: &str, message_index: usize) -> SqliteResult<()> {
-        let conn = self.conn.lock().unwrap();
+        let conn = self.conn()?;
+        ensure_unlocked(&conn, session_id)?;
         
         // Get all message IDs for this session
         let mut stmt = conn.prepare(
@@ -488,6 +1837,119 @@ impl Database {
     }
 }
 
+/// Rough token count of a stored message: the text in its string fields, leaving out JSON
+/// syntax and attachments (inline base64 and blob references), which aren't sent as text tokens
+pub fn estimate_message_tokens(message: &JsonValue) -> i64 {
+    fn text_chars(value: &JsonValue) -> i64 {
+        match value {
+            JsonValue::String(text) => {
+                if text.starts_with(crate::blobs::BLOB_REF_PREFIX)
+                    || crate::blobs::is_base64_blob(text, ATTACHMENT_MIN_CHARS)
+                {
+                    0
+                } else {
+                    text.chars().count() as i64
+                }
+            }
+            JsonValue::Array(items) => items.iter().map(text_chars).sum(),
+            JsonValue::Object(fields) => fields.values().map(text_chars).sum(),
+            _ => 0,
+        }
+    }
+    (text_chars(message) + ESTIMATED_CHARS_PER_TOKEN - 1) / ESTIMATED_CHARS_PER_TOKEN
+}
+
+/// Drops the oldest messages from a history about to be sent until the estimate fits
+/// `max_tokens`. Stored rows are untouched. The first message (the original task/context) and
+/// the newest `AUTO_PRUNE_KEEP_LAST` always stay. Returns how many were left out.
+pub fn trim_history_to_budget(messages: &mut Vec<JsonValue>, max_tokens: i64) -> usize {
+    if messages.len() < 2 {
+        return 0;
+    }
+    let first_tokens = estimate_message_tokens(&messages[0]);
+    let tokens: Vec<i64> = messages[1..].iter().map(estimate_message_tokens).collect();
+    let cut = prune_cut(&tokens, AUTO_PRUNE_KEEP_LAST, Some(max_tokens - first_tokens));
+    messages.drain(1..=cut);
+    cut
+}
+
+/// How many of the oldest messages (given their token estimates, oldest first) a prune removes.
+/// The newest `keep_last_n` are always kept; with a budget, older ones are kept newest-first
+/// until the total would exceed it.
+fn prune_cut(tokens: &[i64], keep_last_n: usize, token_budget: Option<i64>) -> usize {
+    let protected_from = tokens.len().saturating_sub(keep_last_n);
+    let Some(budget) = token_budget else {
+        return protected_from;
+    };
+    let mut kept_tokens: i64 = tokens[protected_from..].iter().sum();
+    let mut cut = protected_from;
+    while cut > 0 {
+        if kept_tokens + tokens[cut - 1] > budget {
+            break;
+        }
+        kept_tokens += tokens[cut - 1];
+        cut -= 1;
+    }
+    cut
+}
+
+/// Whether a stored message, taken as the last one in a session, leaves the assistant turn
+/// unfinished: an unanswered prompt, a pending tool call/result, or a partial stream.
+/// A finished turn ends with a `result` (or final text) message.
+fn is_incomplete_turn(message: &JsonValue) -> bool {
+    let has_block = |kind: &str| {
+        message
+            .pointer("/message/content")
+            .and_then(|v| v.as_array())
+            .is_some_and(|blocks| blocks.iter().any(|b| b.get("type").and_then(|t| t.as_str()) == Some(kind)))
+    };
+    match message.get("type").and_then(|v| v.as_str()) {
+        Some("user_prompt") | Some("tool_use") | Some("tool_result") => true,
+        Some("user") => has_block("tool_result"),
+        Some("assistant") => has_block("tool_use"),
+        Some("text") => message.get("partial").and_then(|v| v.as_bool()) == Some(true),
+        _ => false,
+    }
+}
+
+/// First line-ish of a stored message's visible text, for previews
+fn message_snippet(message: &JsonValue) -> String {
+    let text = ["prompt", "text"]
+        .iter()
+        .find_map(|key| message.get(*key).and_then(|v| v.as_str()))
+        .or_else(|| {
+            message
+                .pointer("/message/content")
+                .and_then(|v| v.as_array())
+                .and_then(|blocks| blocks.iter().find_map(|b| b.get("text").and_then(|v| v.as_str()).or_else(|| b.get("name").and_then(|v| v.as_str()))))
+        })
+        .unwrap_or("");
+    let collapsed = text.split_whitespace().collect::<Vec<_>>().join(" ");
+    match collapsed.char_indices().nth(PRUNE_SNIPPET_CHARS) {
+        Some((idx, _)) => format!("{}…", &collapsed[..idx]),
+        None => collapsed,
+    }
+}
+
+fn session_locked(conn: &Connection, id: &str) -> SqliteResult<bool> {
+    let locked: Option<i64> = conn
+        .query_row("SELECT is_locked FROM sessions WHERE id = ?1", [id], |row| row.get(0))
+        .or_else(|e| match e {
+            rusqlite::Error::QueryReturnedNoRows => Ok(None),
+            e => Err(e),
+        })?;
+    Ok(locked.unwrap_or(0) != 0)
+}
+
+fn ensure_unlocked(conn: &Connection, id: &str) -> SqliteResult<()> {
+    if session_locked(conn, id)? {
+        return Err(rusqlite::Error::ToSqlConversionFailure(
+            format!("Session {} is locked; unlock it first", id).into(),
+        ));
+    }
+    Ok(())
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct Session {
@@ -508,9 +1970,20 @@ pub struct Session {
     pub thread_id: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub temperature: Option<f64>,
+    /// Cap on generated tokens per reply, forwarded to the sidecar
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_output_tokens: Option<i64>,
+    /// Overrides the global `ApiSettings.permission_mode` for this session
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub permission_mode: Option<String>,
     #[serde(default)]
     pub is_pinned: bool,
     #[serde(default)]
+    pub is_locked: bool,
+    /// Hidden from `list_sessions`, never evicted
+    #[serde(default)]
+    pub is_archived: bool,
+    #[serde(default)]
     pub input_tokens: i64,
     #[serde(default)]
     pub output_tokens: i64,
@@ -518,7 +1991,7 @@ pub struct Session {
     pub updated_at: i64,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct CreateSessionParams {
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -536,6 +2009,10 @@ pub struct CreateSessionParams {
     pub thread_id: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub temperature: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_output_tokens: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub permission_mode: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
@@ -557,6 +2034,22 @@ pub struct UpdateSessionParams {
     pub input_tokens: Option<i64>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub output_tokens: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f64>,
+    /// `Some(None)` (an explicit `null`) removes the cap
+    #[serde(default, skip_serializing_if = "Option::is_none", deserialize_with = "deserialize_nullable")]
+    pub max_output_tokens: Option<Option<i64>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub permission_mode: Option<String>,
+}
+
+/// Keeps an explicit `null` as `Some(None)` instead of folding it into "field absent"
+fn deserialize_nullable<'de, D, T>(deserializer: D) -> Result<Option<Option<T>>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    Option::<T>::deserialize(deserializer).map(Some)
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -588,657 +2081,3751 @@ pub struct SessionHistory {
     pub messages: Vec<serde_json::Value>,
     pub todos: Vec<TodoItem>,
     pub file_changes: Vec<FileChange>,
+    #[serde(default)]
+    pub metadata: std::collections::BTreeMap<String, String>,
 }
 
-// ============ LLM Providers ============
-
-#[derive(Debug, Clone, Serialize, Deserialize)]
-#[serde(rename_all = "camelCase")]
-pub struct LLMProvider {
-    pub id: String,
-    pub name: String,
-    #[serde(rename = "type")]
-    pub provider_type: String,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub base_url: Option<String>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub api_key: Option<String>,
-    #[serde(default = "default_true")]
-    pub enabled: bool,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub config: Option<serde_json::Value>,
-    #[serde(default = "default_timestamp")]
+/// A conversation coming from another app, ready to be stored as a session
+#[derive(Debug, Clone)]
+pub struct ImportedSession {
+    pub title: String,
     pub created_at: i64,
-    #[serde(default = "default_timestamp")]
     pub updated_at: i64,
+    /// Message payloads (same shape as `record_message`) with their timestamps, in order
+    pub messages: Vec<(JsonValue, i64)>,
 }
 
-fn default_timestamp() -> i64 {
-    chrono::Utc::now().timestamp_millis()
-}
-
-fn default_true() -> bool { true }
+/// Schema version written by `export_session`; `import_session` rejects anything else
+pub const SESSION_BUNDLE_VERSION: u64 = 1;
 
+/// Portable copy of one session, as produced by `export_session`
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
-pub struct LLMModel {
-    pub id: String,
-    pub provider_id: String,
-    pub name: String,
-    #[serde(default = "default_true")]
-    pub enabled: bool,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub config: Option<serde_json::Value>,
+pub struct SessionBundle {
+    pub version: u64,
+    pub session: Session,
+    pub messages: Vec<BundledMessage>,
+    #[serde(default)]
+    pub todos: Vec<TodoItem>,
+    #[serde(default)]
+    pub file_changes: Vec<FileChange>,
+    #[serde(default)]
+    pub metadata: std::collections::BTreeMap<String, String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
-pub struct LLMProviderSettings {
-    pub providers: Vec<LLMProvider>,
-    pub models: Vec<LLMModel>,
+pub struct BundledMessage {
+    pub data: JsonValue,
+    pub created_at: i64,
 }
 
-// ============ Settings ============
+/// Bucket name for sessions that have no working directory
+pub const UNFILED_PROJECT: &str = "Unfiled";
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
-pub struct ApiSettings {
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub base_url: Option<String>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub api_key: Option<String>,
+pub struct ProjectSessions {
+    pub cwd: String,
+    pub sessions: Vec<Session>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SessionSummary {
+    pub session_id: String,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub model: Option<String>,
+    pub message_count: i64,
+    pub input_tokens: i64,
+    pub output_tokens: i64,
+    /// USD, only when the model's pricing is known
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub temperature: Option<f64>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub permission_mode: Option<String>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub web_search_provider: Option<String>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub tavily_api_key: Option<String>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub enable_tavily_search: Option<bool>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub zai_api_key: Option<String>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub zai_api_url: Option<String>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub enable_memory: Option<bool>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub enable_zai_reader: Option<bool>,
+    pub estimated_cost: Option<f64>,
+    pub first_activity_at: i64,
+    pub last_activity_at: i64,
+    pub file_change_count: i64,
+}
+
+/// Searchable text of a stored message (`{data}` is the JSON column): prompts and replies only
+const MESSAGE_TEXT_SQL: &str = "CASE json_extract({data}, '$.type') WHEN 'user_prompt' THEN json_extract({data}, '$.prompt') WHEN 'text' THEN json_extract({data}, '$.text') END";
+
+/// Create the FTS5 index over message text, with triggers that keep it in step with `messages`.
+/// Existing messages are indexed the first time the table is created.
+fn init_message_fts(conn: &Connection) -> SqliteResult<()> {
+    if has_message_fts(conn)? {
+        return Ok(());
+    }
+    let text = |alias: &str| MESSAGE_TEXT_SQL.replace("{data}", &format!("{}.data", alias));
+    conn.execute_batch(&format!(
+        r#"BEGIN;
+           CREATE VIRTUAL TABLE messages_fts USING fts5(body, message_id UNINDEXED);
+           CREATE TRIGGER messages_fts_insert AFTER INSERT ON messages WHEN json_valid(new.data) AND {new_text} IS NOT NULL BEGIN
+               INSERT INTO messages_fts (body, message_id) VALUES ({new_text}, new.id);
+           END;
+           CREATE TRIGGER messages_fts_delete AFTER DELETE ON messages BEGIN
+               DELETE FROM messages_fts WHERE message_id = old.id;
+           END;
+           CREATE TRIGGER messages_fts_update AFTER UPDATE OF data ON messages BEGIN
+               DELETE FROM messages_fts WHERE message_id = old.id;
+               INSERT INTO messages_fts (body, message_id)
+                   SELECT {new_text}, new.id WHERE json_valid(new.data) AND {new_text} IS NOT NULL;
+           END;
+           INSERT INTO messages_fts (body, message_id)
+               SELECT {m_text}, m.id FROM messages m WHERE json_valid(m.data) AND {m_text} IS NOT NULL;
+           COMMIT;"#,
+        new_text = text("new"),
+        m_text = text("m"),
+    ))
+    .inspect_err(|_| {
+        let _ = conn.execute_batch("ROLLBACK");
+    })
+}
+
+fn has_message_fts(conn: &Connection) -> SqliteResult<bool> {
+    conn.query_row(
+        "SELECT EXISTS(SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = 'messages_fts')",
+        [],
+        |row| row.get(0),
+    )
+}
+
+/// FTS5 snippet (matches between \x01 and \x02) as escaped HTML with the matches in `<mark>` tags.
+/// Message text is user content, so it's escaped before any markup is added.
+fn fts_snippet_html(snippet: &str) -> String {
+    crate::transcript::escape_html(snippet)
+        .replace('\u{1}', "<mark>")
+        .replace('\u{2}', "</mark>")
+}
+
+/// Window of `text` around the first case-insensitive match of `query` as escaped HTML, with
+/// the match in `<mark>` tags (same shape as `fts_snippet_html`)
+fn highlight_snippet(text: &str, query: &str) -> String {
+    use crate::transcript::escape_html;
+    const CONTEXT_CHARS: usize = 60;
+    let lower = text.to_lowercase();
+    // Lowercasing can change byte lengths; only highlight when offsets still line up
+    let found = (lower.len() == text.len())
+        .then(|| lower.find(&query.to_lowercase()))
+        .flatten();
+    let Some(start) = found else {
+        return escape_html(&text.chars().take(CONTEXT_CHARS * 2).collect::<String>());
+    };
+    let end = start + query.len();
+    let before: String = text[..start].chars().rev().take(CONTEXT_CHARS).collect::<Vec<_>>().into_iter().rev().collect();
+    let after: String = text[end..].chars().take(CONTEXT_CHARS).collect();
+    format!(
+        "{}{}<mark>{}</mark>{}{}",
+        if before.len() < start { "…" } else { "" },
+        escape_html(&before),
+        escape_html(&text[start..end]),
+        escape_html(&after),
+        if end + after.len() < text.len() { "…" } else { "" },
+    )
+}
+
+/// None when the vectors differ in length or either is all zeros
+pub fn cosine_similarity(a: &[f32], b: &[f32]) -> Option<f32> {
+    if a.len() != b.len() || a.is_empty() {
+        return None;
+    }
+    let (mut dot, mut norm_a, mut norm_b) = (0.0f32, 0.0f32, 0.0f32);
+    for (x, y) in a.iter().zip(b) {
+        dot += x * y;
+        norm_a += x * x;
+        norm_b += y * y;
+    }
+    if norm_a == 0.0 || norm_b == 0.0 {
+        return None;
+    }
+    Some(dot / (norm_a.sqrt() * norm_b.sqrt()))
+}
+
+/// Remove a session and everything stored under it (voice usage is kept for spend tracking)
+fn delete_session_rows(conn: &Connection, id: &str) -> SqliteResult<bool> {
+    conn.execute("DELETE FROM messages WHERE session_id = ?1", [id])?;
+    conn.execute("DELETE FROM stream_drafts WHERE session_id = ?1", [id])?;
+    conn.execute("DELETE FROM session_metadata WHERE session_id = ?1", [id])?;
+    conn.execute("DELETE FROM message_embeddings WHERE session_id = ?1", [id])?;
+    let changed = conn.execute("DELETE FROM sessions WHERE id = ?1", [id])?;
+    Ok(changed > 0)
+}
+
+/// Record a token increase against the session's current model, priced at that model's current
+/// rates; decreases (resets) are ignored. Unpriced models record a cost of 0.
+fn append_usage(conn: &Connection, session_id: &str, input_tokens: i64, output_tokens: i64, ts: i64) -> SqliteResult<()> {
+    let (input_tokens, output_tokens) = (input_tokens.max(0), output_tokens.max(0));
+    if input_tokens == 0 && output_tokens == 0 {
+        return Ok(());
+    }
+    let found = conn.query_row(
+        "SELECT s.model, mo.config FROM sessions s LEFT JOIN models mo ON mo.id = s.model WHERE s.id = ?1",
+        [session_id],
+        |row| Ok((row.get::<_, Option<String>>(0)?, row.get::<_, Option<String>>(1)?)),
+    );
+    let (model, config) = match found {
+        Ok(found) => found,
+        Err(rusqlite::Error::QueryReturnedNoRows) => return Ok(()),
+        Err(e) => return Err(e),
+    };
+    let config: Option<JsonValue> = config.and_then(|s| serde_json::from_str(&s).ok());
+    let cost_usd = ModelConfig::from_config(config.as_ref()).estimate_cost(input_tokens, output_tokens).unwrap_or(0.0);
+    conn.execute(
+        "INSERT INTO usage (session_id, model, input_tokens, output_tokens, ts, cost_usd) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+        params![session_id, model, input_tokens, output_tokens, ts, cost_usd],
+    )?;
+    Ok(())
+}
+
+/// Quote a CSV field when it contains a delimiter, quote or newline. Text a spreadsheet would
+/// read as a formula (leading `=`, `+`, `-`, `@`, tab or CR) gets a `'` prefix so it stays text.
+fn csv_field(value: &str) -> String {
+    let value = if value.starts_with(['=', '+', '-', '@', '\t', '\r']) {
+        std::borrow::Cow::Owned(format!("'{}", value))
+    } else {
+        std::borrow::Cow::Borrowed(value)
+    };
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.into_owned()
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct DuplicateMember {
+    pub id: String,
+    pub title: String,
+    pub updated_at: i64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct MessageHit {
+    pub session_id: String,
+    pub session_title: String,
+    pub message_id: String,
+    /// Matched text as escaped HTML, with `<mark>` around the hits
+    pub snippet: String,
+    pub created_at: i64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct SemanticHit {
+    pub session_id: String,
+    pub session_title: String,
+    pub message_id: String,
+    pub snippet: String,
+    pub created_at: i64,
+    /// Cosine similarity to the query, 1.0 being identical
+    pub score: f32,
+}
+
+/// Setting key holding `EmbeddingSettings` as JSON; unset means semantic search is off
+pub const EMBEDDING_SETTINGS_SETTING: &str = "embedding_settings";
+
+/// Provider and model used to embed messages and queries for semantic search
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct EmbeddingSettings {
+    pub provider_id: String,
+    pub model: String,
+}
+
+/// "default" auto-executes tools, "ask" requires confirmation
+pub const PERMISSION_MODES: &[&str] = &["default", "ask"];
+
+/// Log a session field that failed validation and is being skipped
+fn dropped_field<T>(session_id: &str, field: &str, error: rusqlite::Error) -> Option<T> {
+    eprintln!("[db] session {}: dropping invalid {}: {}", session_id, field, error);
+    None
+}
+
+fn validate_permission_mode(mode: &str) -> SqliteResult<()> {
+    if PERMISSION_MODES.contains(&mode) {
+        return Ok(());
+    }
+    Err(rusqlite::Error::ToSqlConversionFailure(
+        format!("Unknown permission mode '{}'; expected one of {}", mode, PERMISSION_MODES.join(", ")).into(),
+    ))
+}
+
+/// Sessions sharing a normalized last prompt
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct DuplicateCluster {
+    pub prompt: String,
+    pub sessions: Vec<DuplicateMember>,
+}
+
+/// Comparable form of a cwd: `/` separators, no trailing separator, and lowercase where
+/// the filesystem is usually case-insensitive (Windows, macOS)
+fn normalize_cwd(path: &str) -> String {
+    let path = path.trim().replace('\\', "/");
+    let trimmed = path.trim_end_matches('/');
+    let path = if trimmed.is_empty() && !path.is_empty() { "/" } else { trimmed };
+    if cfg!(any(windows, target_os = "macos")) {
+        path.to_lowercase()
+    } else {
+        path.to_string()
+    }
+}
+
+/// Lowercased with whitespace runs collapsed, so reruns with stray spacing still match
+fn normalize_prompt(prompt: &str) -> String {
+    prompt.split_whitespace().collect::<Vec<_>>().join(" ").to_lowercase()
+}
+
+/// A message addressed by its stored id (the message `uuid`) or its position in the history
+#[derive(Debug, Clone, PartialEq)]
+pub enum MessageRef {
+    Uuid(String),
+    Index(usize),
+}
+
+/// Result of `sync_provider_models`: full model ids added/removed, and how many were kept as-is
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct ModelSyncReport {
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+    pub kept: usize,
+}
+
+pub const LAST_ACTIVE_SESSION_SETTING: &str = "last_active_session";
+
+/// Setting key; "true" suggests resuming the last unfinished session on launch
+pub const RESUME_UNFINISHED_SETTING: &str = "resume_unfinished_on_launch";
+
+/// Setting key; unset means unlimited
+pub const MAX_SESSIONS_SETTING: &str = "max_sessions";
+
+pub const SPENDING_CAP_SETTING: &str = "spending_cap_usd";
+/// Session metadata key; "true" lets the session run past the spending cap
+pub const BUDGET_OVERRIDE_META_KEY: &str = "budget_override";
+/// Session metadata key; seconds without streaming activity before a running session is stopped
+pub const IDLE_TIMEOUT_META_KEY: &str = "idle_timeout_secs";
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct BudgetStatus {
+    pub cap_usd: Option<f64>,
+    pub spent_usd: f64,
+    pub exceeded: bool,
+    pub overridden: bool,
+}
+
+impl BudgetStatus {
+    /// New work should be refused
+    pub fn blocks(&self) -> bool {
+        self.exceeded && !self.overridden
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct VoiceUsage {
+    pub session_id: String,
+    /// Estimated from uploaded audio bytes, not reported by the server
+    pub audio_seconds: f64,
+    pub requests: i64,
+}
+
+/// Tokens billed to one model over a period (see `Database::usage_summary`)
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct ModelUsage {
+    pub model: Option<String>,
+    pub input_tokens: i64,
+    pub output_tokens: i64,
+    /// Number of token updates recorded
+    pub updates: i64,
+    /// USD, only when the model's pricing is known
+    pub estimated_cost: Option<f64>,
+}
+
+/// Latency of one dictation, from its first audio chunk (see `Database::add_dictation_metrics`)
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct DictationMetrics {
+    pub session_id: String,
+    pub model: Option<String>,
+    /// "websocket" or "multipart"
+    pub transport: String,
+    /// None when the dictation finished before any partial came back
+    pub first_partial_ms: Option<i64>,
+    pub final_latency_ms: i64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct DictationMetricsSummary {
+    pub model: Option<String>,
+    pub transport: String,
+    pub dictations: i64,
+    pub avg_first_partial_ms: Option<f64>,
+    pub avg_final_latency_ms: f64,
+    pub max_final_latency_ms: i64,
+}
+
+/// Word/character counts over user prompts and assistant replies (tool and system messages excluded)
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct SessionTextStats {
+    pub session_id: String,
+    pub user_messages: i64,
+    pub assistant_messages: i64,
+    pub words: i64,
+    pub characters: i64,
+    /// Rounded up, at `READING_WORDS_PER_MINUTE`
+    pub reading_minutes: i64,
+}
+
+pub const READING_WORDS_PER_MINUTE: i64 = 200;
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct ToolInvocation {
+    pub name: String,
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub zai_reader_api_url: Option<String>,
+    pub tool_use_id: Option<String>,
+    pub created_at: i64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct ToolUsageCount {
+    pub name: String,
+    pub count: i64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct ToolUsageReport {
+    pub session_id: String,
+    pub counts: Vec<ToolUsageCount>,
+    pub timeline: Vec<ToolInvocation>,
+}
+
+/// Values the UI understands for `sessions.status`
+pub const SESSION_STATUSES: &[&str] = &["idle", "running", "completed", "error"];
+
+/// Rough chars-per-token ratio for estimates when no tokenizer is available
+pub const ESTIMATED_CHARS_PER_TOKEN: i64 = 4;
+const PRUNE_SNIPPET_CHARS: usize = 120;
+/// Newest messages `trim_history_to_budget` never leaves out
+pub const AUTO_PRUNE_KEEP_LAST: usize = 10;
+pub const MAX_CONTEXT_TOKENS_SETTING: &str = "max_context_tokens";
+/// Crash-recovery drafts older than this are dropped at startup
+pub const STREAM_DRAFT_MAX_AGE_MS: i64 = 7 * 24 * 60 * 60 * 1000;
+/// Base64 strings at least this long count as attachments in token estimates
+const ATTACHMENT_MIN_CHARS: usize = 1024;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct BlobExternalization {
+    pub messages_updated: usize,
+    pub blobs: usize,
+    pub bytes_saved: i64,
+}
+
+/// A message that a prune with the given limits would delete
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct PruneCandidate {
+    pub id: String,
+    pub message_type: String,
+    pub snippet: String,
+    pub estimated_tokens: i64,
+    pub created_at: i64,
+}
+
+// ============ LLM Providers ============
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LLMProvider {
+    pub id: String,
+    pub name: String,
+    #[serde(rename = "type")]
+    pub provider_type: String,
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub enable_todos: Option<bool>,
+    pub base_url: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub locale: Option<String>,
-    // Tool group toggles
+    pub api_key: Option<String>,
+    #[serde(default = "default_true")]
+    pub enabled: bool,
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub enable_git_tools: Option<bool>,
+    pub config: Option<serde_json::Value>,
+    #[serde(default = "default_timestamp")]
+    pub created_at: i64,
+    #[serde(default = "default_timestamp")]
+    pub updated_at: i64,
+}
+
+/// `LLMProvider.config` key naming the OS keychain entry that holds the provider's API key
+pub const PROVIDER_KEY_REF_FIELD: &str = "keyRef";
+
+impl LLMProvider {
+    /// Keychain entry to read the API key from instead of `api_key`
+    pub fn key_ref(&self) -> Option<&str> {
+        self.config.as_ref()?
+            .get(PROVIDER_KEY_REF_FIELD)?
+            .as_str()
+            .map(str::trim)
+            .filter(|r| !r.is_empty())
+    }
+}
+
+fn default_timestamp() -> i64 {
+    chrono::Utc::now().timestamp_millis()
+}
+
+fn default_true() -> bool { true }
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LLMModel {
+    pub id: String,
+    pub provider_id: String,
+    pub name: String,
+    #[serde(default = "default_true")]
+    pub enabled: bool,
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub enable_browser_tools: Option<bool>,
+    pub config: Option<serde_json::Value>,
+}
+
+/// Typed view over the metadata kept in `LLMModel.config`.
+/// Unknown keys are preserved in `extra` so round-tripping never drops user data.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ModelConfig {
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub enable_duck_duck_go: Option<bool>,
+    pub context_length: Option<i64>,
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub enable_fetch_tools: Option<bool>,
+    pub max_output_tokens: Option<i64>,
+    /// USD per 1M prompt tokens
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub enable_image_tools: Option<bool>,
-    // Complex nested settings - keep as raw JSON to preserve shape
+    pub input_price_per_million: Option<f64>,
+    /// USD per 1M completion tokens
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub llm_providers: Option<JsonValue>,
-    // Voice settings
+    pub output_price_per_million: Option<f64>,
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub voice_settings: Option<VoiceSettings>,
-    // Add other settings as needed
+    pub metadata_fetched_at: Option<i64>,
+    #[serde(flatten)]
+    pub extra: serde_json::Map<String, JsonValue>,
+}
+
+impl ModelConfig {
+    /// Each typed field is read on its own; one with the wrong JSON type stays in `extra` as-is
+    /// instead of discarding the whole config.
+    pub fn from_config(config: Option<&JsonValue>) -> Self {
+        fn take<T: serde::de::DeserializeOwned>(extra: &mut serde_json::Map<String, JsonValue>, key: &str) -> Option<T> {
+            let parsed = serde_json::from_value(extra.get(key)?.clone()).ok()?;
+            extra.remove(key);
+            Some(parsed)
+        }
+
+        let Some(JsonValue::Object(map)) = config else { return Self::default() };
+        let mut extra = map.clone();
+        Self {
+            context_length: take(&mut extra, "contextLength"),
+            max_output_tokens: take(&mut extra, "maxOutputTokens"),
+            input_price_per_million: take(&mut extra, "inputPricePerMillion"),
+            output_price_per_million: take(&mut extra, "outputPricePerMillion"),
+            metadata_fetched_at: take(&mut extra, "metadataFetchedAt"),
+            extra,
+        }
+    }
+
+    /// Typed fields win over a leftover mistyped value of the same key in `extra`
+    pub fn to_value(&self) -> JsonValue {
+        let mut typed = self.clone();
+        let extra = std::mem::take(&mut typed.extra);
+        let mut value = serde_json::to_value(&typed).unwrap_or(JsonValue::Null);
+        if let Some(map) = value.as_object_mut() {
+            for (key, field) in extra {
+                map.entry(key).or_insert(field);
+            }
+        }
+        value
+    }
+
+    /// USD for the given token counts, only when both prices are known
+    pub fn estimate_cost(&self, input_tokens: i64, output_tokens: i64) -> Option<f64> {
+        match (self.input_price_per_million, self.output_price_per_million) {
+            (Some(input_price), Some(output_price)) => Some(
+                (input_tokens as f64 * input_price + output_tokens as f64 * output_price) / 1_000_000.0
+            ),
+            _ => None,
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
-pub struct VoiceSettings {
-    pub base_url: String,
+pub struct LLMProviderSettings {
+    pub providers: Vec<LLMProvider>,
+    pub models: Vec<LLMModel>,
+}
+
+// ============ Diagnostics ============
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DbStats {
+    pub row_counts: std::collections::BTreeMap<String, i64>,
+    /// page_count * page_size
+    pub size_bytes: i64,
+    /// Space a VACUUM would reclaim
+    pub free_bytes: i64,
+    pub wal_size_bytes: i64,
+    pub list_sessions_ms: f64,
+    pub session_count: usize,
+}
+
+pub const BUSY_TIMEOUT_SETTING: &str = "db_busy_timeout_ms";
+pub const DEFAULT_BUSY_TIMEOUT_MS: u64 = 5000;
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WalCheckpoint {
+    /// A reader or writer kept the checkpoint from completing
+    pub busy: bool,
+    pub wal_bytes_before: i64,
+    pub wal_bytes_after: i64,
+    pub reclaimed_bytes: i64,
+}
+
+// ============ Settings ============
+
+pub const SETTINGS_SNAPSHOT_LIMIT: i64 = 20;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SettingsSnapshotInfo {
+    pub id: i64,
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub api_key: Option<String>,
-    pub model: String,
+    pub label: Option<String>,
+    pub created_at: i64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SettingsChange {
+    /// Dotted path, e.g. `providers.openrouter.baseUrl`
+    pub path: String,
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub language: Option<String>,
+    pub before: Option<JsonValue>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub after: Option<JsonValue>,
 }
 
-// ============ Database methods for Providers ============
+/// Whether the last path segment names a secret: its final word (camelCase, snake_case or
+/// kebab-case) is key, token, secret or password. `apiKey` and `accessToken` are secrets,
+/// `maxTokens` and `keyRef` are not.
+fn is_secret_field(path: &str) -> bool {
+    let field = path.rsplit('.').next().unwrap_or(path);
+    let last_word_start = field
+        .char_indices()
+        .filter(|(_, c)| c.is_uppercase() || matches!(c, '_' | '-'))
+        .map(|(i, c)| if c.is_uppercase() { i } else { i + 1 })
+        .next_back()
+        .unwrap_or(0);
+    let last_word = field[last_word_start..].to_lowercase();
+    matches!(last_word.as_str(), "key" | "apikey" | "token" | "secret" | "password")
+}
 
-impl Database {
-    // --- Settings ---
-    
-    pub fn get_setting(&self, key: &str) -> SqliteResult<Option<String>> {
-        let conn = self.conn.lock().unwrap();
-        let mut stmt = conn.prepare("SELECT value FROM settings WHERE key = ?1")?;
-        let mut rows = stmt.query([key])?;
-        
-        if let Some(row) = rows.next()? {
-            Ok(Some(row.get(0)?))
-        } else {
-            Ok(None)
+/// Replace secret-looking strings with a short digest, so stored snapshots never hold a
+/// secret while a diff still sees that it changed
+fn fingerprint_secrets(value: &mut JsonValue) {
+    use sha2::{Digest, Sha256};
+    match value {
+        JsonValue::Object(map) => {
+            for (key, value) in map.iter_mut() {
+                match value {
+                    JsonValue::String(secret) if is_secret_field(key) => {
+                        let mut hasher = Sha256::new();
+                        hasher.update(secret.as_bytes());
+                        let digest = hasher.finalize();
+                        *secret = format!("[REDACTED:{}]", digest.iter().take(4).map(|b| format!("{:02x}", b)).collect::<String>());
+                    }
+                    other => fingerprint_secrets(other),
+                }
+            }
+        }
+        JsonValue::Array(items) => items.iter_mut().for_each(fingerprint_secrets),
+        _ => {}
+    }
+}
+
+fn flatten_json(prefix: &str, value: &JsonValue, out: &mut std::collections::BTreeMap<String, JsonValue>) {
+    match value {
+        JsonValue::Object(map) if !map.is_empty() => {
+            for (k, v) in map {
+                let path = if prefix.is_empty() { k.clone() } else { format!("{}.{}", prefix, k) };
+                flatten_json(&path, v, out);
+            }
+        }
+        JsonValue::Null => {}
+        other => {
+            out.insert(prefix.to_string(), other.clone());
+        }
+    }
+}
+
+/// Field-level differences between two snapshots; secret-looking fields only show that they changed
+pub fn diff_settings_snapshots(before: &JsonValue, after: &JsonValue) -> Vec<SettingsChange> {
+    let mut a = std::collections::BTreeMap::new();
+    let mut b = std::collections::BTreeMap::new();
+    flatten_json("", before, &mut a);
+    flatten_json("", after, &mut b);
+
+    let paths: std::collections::BTreeSet<&String> = a.keys().chain(b.keys()).collect();
+    paths
+        .into_iter()
+        .filter(|path| a.get(*path) != b.get(*path))
+        .map(|path| {
+            let redact = |v: Option<&JsonValue>| {
+                v.map(|v| if is_secret_field(path) { JsonValue::String("[REDACTED]".to_string()) } else { v.clone() })
+            };
+            SettingsChange { path: path.clone(), before: redact(a.get(path)), after: redact(b.get(path)) }
+        })
+        .collect()
+}
+
+const UI_PREFS_KEY: &str = "ui_prefs";
+pub const UI_THEMES: &[&str] = &["system", "light", "dark"];
+pub const UI_SIDEBAR_WIDTH_RANGE: (u32, u32) = (180, 600);
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct UiPrefs {
+    pub theme: String,
+    pub sidebar_width: u32,
+    pub default_view: String,
+}
+
+impl Default for UiPrefs {
+    fn default() -> Self {
+        Self {
+            theme: "system".to_string(),
+            sidebar_width: 280,
+            default_view: "chat".to_string(),
+        }
+    }
+}
+
+impl UiPrefs {
+    pub fn validate(&self) -> Result<(), String> {
+        if !UI_THEMES.contains(&self.theme.as_str()) {
+            return Err(format!("Unknown theme '{}', expected one of {:?}", self.theme, UI_THEMES));
+        }
+        let (min, max) = UI_SIDEBAR_WIDTH_RANGE;
+        if self.sidebar_width < min || self.sidebar_width > max {
+            return Err(format!("Sidebar width {} outside {}..={}", self.sidebar_width, min, max));
+        }
+        if self.default_view.trim().is_empty() {
+            return Err("Default view must not be empty".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// Why `set_ui_prefs` refused to save
+#[derive(Debug)]
+pub enum UiPrefsError {
+    /// The prefs failed `UiPrefs::validate`
+    Invalid(String),
+    Encode(serde_json::Error),
+    Db(rusqlite::Error),
+}
+
+impl std::fmt::Display for UiPrefsError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            UiPrefsError::Invalid(reason) => write!(f, "Invalid UI preferences: {}", reason),
+            UiPrefsError::Encode(e) => write!(f, "Failed to encode UI preferences: {}", e),
+            UiPrefsError::Db(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl From<rusqlite::Error> for UiPrefsError {
+    fn from(e: rusqlite::Error) -> Self {
+        UiPrefsError::Db(e)
+    }
+}
+
+pub const PROFILE_VERSION: u32 = 1;
+
+/// Portable settings file written by `profile.export`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SettingsProfile {
+    pub version: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub api_settings: Option<ApiSettings>,
+    #[serde(default)]
+    pub providers: Vec<LLMProvider>,
+    #[serde(default)]
+    pub models: Vec<LLMModel>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ProfileImportMode {
+    /// Upsert the profile's providers/models and overlay its settings, keeping everything else
+    Merge,
+    /// The profile becomes the full set of settings, providers and models
+    Replace,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ProfileImport {
+    pub mode: ProfileImportMode,
+    /// Snapshot taken right before applying, for rolling back by hand
+    pub snapshot_id: i64,
+    pub changes: Vec<SettingsChange>,
+}
+
+fn strip_secret_strings(value: &mut JsonValue) {
+    match value {
+        JsonValue::Object(map) => {
+            map.retain(|k, v| !(v.is_string() && is_secret_field(k)));
+            map.values_mut().for_each(strip_secret_strings);
+        }
+        JsonValue::Array(items) => items.iter_mut().for_each(strip_secret_strings),
+        _ => {}
+    }
+}
+
+impl SettingsProfile {
+    /// Drop API keys and other secret-looking string fields
+    pub fn strip_keys(&mut self) {
+        for provider in &mut self.providers {
+            provider.api_key = None;
+            if let Some(config) = provider.config.as_mut() {
+                strip_secret_strings(config);
+            }
+        }
+        for model in &mut self.models {
+            if let Some(config) = model.config.as_mut() {
+                strip_secret_strings(config);
+            }
+        }
+        if let Some(api) = self.api_settings.as_mut() {
+            api.api_key = None;
+            api.tavily_api_key = None;
+            api.zai_api_key = None;
+            if let Some(voice) = api.voice_settings.as_mut() {
+                voice.api_key = None;
+            }
+            if let Some(llm_providers) = api.llm_providers.as_mut() {
+                strip_secret_strings(llm_providers);
+            }
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if self.version == 0 || self.version > PROFILE_VERSION {
+            return Err(format!("Unsupported profile version {} (expected 1..={})", self.version, PROFILE_VERSION));
+        }
+        let mut provider_ids = std::collections::HashSet::new();
+        for provider in &self.providers {
+            if provider.id.trim().is_empty() {
+                return Err("Provider with empty id".to_string());
+            }
+            if !provider_ids.insert(provider.id.as_str()) {
+                return Err(format!("Duplicate provider '{}'", provider.id));
+            }
+        }
+        let mut model_ids = std::collections::HashSet::new();
+        for model in &self.models {
+            if model.id.trim().is_empty() {
+                return Err("Model with empty id".to_string());
+            }
+            if !model_ids.insert(model.id.as_str()) {
+                return Err(format!("Duplicate model '{}'", model.id));
+            }
+        }
+        if let Some(temperature) = self.api_settings.as_ref().and_then(|a| a.temperature) {
+            if !temperature.is_finite() {
+                return Err("Temperature must be a finite number".to_string());
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Merge overlays the imported fields on the current settings; replace takes the imported
+/// settings but keeps current secrets the profile doesn't carry
+fn merge_api_settings(
+    current: Option<&ApiSettings>,
+    imported: Option<&ApiSettings>,
+    mode: ProfileImportMode,
+) -> serde_json::Result<Option<ApiSettings>> {
+    let Some(imported) = imported else { return Ok(current.cloned()) };
+    let Some(current) = current else { return Ok(Some(imported.clone())) };
+
+    let current = serde_json::to_value(current)?.as_object().cloned().unwrap_or_default();
+    let imported = serde_json::to_value(imported)?.as_object().cloned().unwrap_or_default();
+    let mut merged = match mode {
+        ProfileImportMode::Merge => current.clone(),
+        ProfileImportMode::Replace => serde_json::Map::new(),
+    };
+    let imported_key = imported.contains_key("apiKey");
+    for (key, value) in imported {
+        merged.insert(key, value);
+    }
+    let mut merged = JsonValue::Object(merged);
+    let base_url_changed = merged.get("baseUrl") != current.get("baseUrl");
+    fill_missing_secrets(&mut merged, &JsonValue::Object(current));
+    // The main key belongs to the main base URL; don't carry it over to a different one
+    if base_url_changed && !imported_key {
+        if let Some(merged) = merged.as_object_mut() {
+            merged.remove("apiKey");
+        }
+    }
+    serde_json::from_value(merged).map(Some)
+}
+
+/// Copy secret-looking strings from `source` wherever `target` lacks them, at any depth
+fn fill_missing_secrets(target: &mut JsonValue, source: &JsonValue) {
+    let (JsonValue::Object(target), JsonValue::Object(source)) = (target, source) else { return };
+    for (key, value) in source {
+        match target.get_mut(key) {
+            Some(existing) => fill_missing_secrets(existing, value),
+            None if value.is_string() && is_secret_field(key) => {
+                target.insert(key.clone(), value.clone());
+            }
+            None => {}
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct ApiSettings {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub base_url: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub api_key: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub permission_mode: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub web_search_provider: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tavily_api_key: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub enable_tavily_search: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub zai_api_key: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub zai_api_url: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub enable_memory: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub enable_zai_reader: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub zai_reader_api_url: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub enable_todos: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub locale: Option<String>,
+    // Tool group toggles
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub enable_git_tools: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub enable_browser_tools: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub enable_duck_duck_go: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub enable_fetch_tools: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub enable_image_tools: Option<bool>,
+    // Complex nested settings - keep as raw JSON to preserve shape
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub llm_providers: Option<JsonValue>,
+    // Voice settings
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub voice_settings: Option<VoiceSettings>,
+    // Outbound HTTP (applied to every reqwest client)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub http_proxy: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub http_headers: Option<std::collections::BTreeMap<String, String>>,
+    // Add other settings as needed
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct VoiceSettings {
+    pub base_url: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub api_key: Option<String>,
+    pub model: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub language: Option<String>,
+    /// Server accepts a websocket at the transcription endpoint (audio in, partial text out).
+    /// Unset: detected by probing the endpoint, with multipart uploads as the fallback.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub streaming: Option<bool>,
+    /// Minimum gap between partial transcription requests (multipart path); default 1500ms
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub voice_partial_interval_ms: Option<u64>,
+    /// "json" (default) or "verbose_json" for segment/word timestamps on final transcripts
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub response_format: Option<String>,
+}
+
+// ============ Database methods for Providers ============
+
+fn wal_file_size(conn: &Connection) -> SqliteResult<i64> {
+    // Empty for in-memory databases
+    let db_file: String = conn.query_row("PRAGMA database_list", [], |row| row.get(2))?;
+    if db_file.is_empty() {
+        return Ok(0);
+    }
+    Ok(std::fs::metadata(format!("{}-wal", db_file)).map(|m| m.len() as i64).unwrap_or(0))
+}
+
+fn write_setting(conn: &Connection, key: &str, value: &str) -> SqliteResult<()> {
+    let now = chrono::Utc::now().timestamp_millis();
+    conn.execute(
+        "INSERT OR REPLACE INTO settings (key, value, updated_at) VALUES (?1, ?2, ?3)",
+        params![key, value, now],
+    )?;
+    Ok(())
+}
+
+fn write_provider(conn: &Connection, provider: &LLMProvider) -> SqliteResult<()> {
+    let now = chrono::Utc::now().timestamp_millis();
+    let config_json = provider.config.as_ref().map(|c| serde_json::to_string(c).unwrap_or_default());
+    // A keychain-backed provider never has its secret written here
+    let api_key = if provider.key_ref().is_some() { None } else { provider.api_key.as_deref() };
+
+    conn.execute(
+        r#"INSERT OR REPLACE INTO providers (id, name, type, base_url, api_key, enabled, config, created_at, updated_at)
+           VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, COALESCE((SELECT created_at FROM providers WHERE id = ?1), ?8), ?9)"#,
+        params![
+            &provider.id,
+            &provider.name,
+            &provider.provider_type,
+            &provider.base_url,
+            api_key,
+            if provider.enabled { 1 } else { 0 },
+            &config_json,
+            now,
+            now
+        ],
+    )?;
+    Ok(())
+}
+
+fn write_model(conn: &Connection, model: &LLMModel) -> SqliteResult<()> {
+    let config_json = model.config.as_ref().map(|c| serde_json::to_string(c).unwrap_or_default());
+    conn.execute(
+        "INSERT OR REPLACE INTO models (id, provider_id, name, enabled, config) VALUES (?1, ?2, ?3, ?4, ?5)",
+        params![
+            &model.id,
+            &model.provider_id,
+            &model.name,
+            if model.enabled { 1 } else { 0 },
+            &config_json
+        ],
+    )?;
+    Ok(())
+}
+
+/// Make the providers/models tables match `settings` exactly
+fn write_llm_provider_settings(conn: &Connection, settings: &LLMProviderSettings) -> SqliteResult<()> {
+    // Get IDs of providers to keep
+    let provider_ids: Vec<&str> = settings.providers.iter().map(|p| p.id.as_str()).collect();
+    
+    // Delete providers not in the new list
+    if !provider_ids.is_empty() {
+        let placeholders: Vec<String> = (1..=provider_ids.len()).map(|i| format!("?{}", i)).collect();
+        let sql = format!("DELETE FROM providers WHERE id NOT IN ({})", placeholders.join(", "));
+        let params: Vec<&dyn rusqlite::ToSql> = provider_ids.iter().map(|id| id as &dyn rusqlite::ToSql).collect();
+        conn.execute(&sql, params.as_slice())?;
+    } else {
+        // No providers - delete all
+        conn.execute("DELETE FROM providers", [])?;
+    }
+    
+    // Get IDs of models to keep
+    let model_ids: Vec<&str> = settings.models.iter().map(|m| m.id.as_str()).collect();
+    
+    // Delete models not in the new list
+    if !model_ids.is_empty() {
+        let placeholders: Vec<String> = (1..=model_ids.len()).map(|i| format!("?{}", i)).collect();
+        let sql = format!("DELETE FROM models WHERE id NOT IN ({})", placeholders.join(", "));
+        let params: Vec<&dyn rusqlite::ToSql> = model_ids.iter().map(|id| id as &dyn rusqlite::ToSql).collect();
+        conn.execute(&sql, params.as_slice())?;
+    } else {
+        // No models - delete all
+        conn.execute("DELETE FROM models", [])?;
+    }
+    
+    for provider in &settings.providers {
+        write_provider(conn, provider)?;
+    }
+    for model in &settings.models {
+        write_model(conn, model)?;
+    }
+    Ok(())
+}
+
+impl Database {
+    // --- Diagnostics ---
+
+    /// Table sizes, on-disk size and a timed `list_sessions` run, for diagnosing slow DBs
+    pub fn stats(&self) -> SqliteResult<DbStats> {
+        let started = std::time::Instant::now();
+        let session_count = self.list_sessions()?.len();
+        let list_sessions_ms = started.elapsed().as_secs_f64() * 1000.0;
+
+        let conn = self.conn()?;
+        let table_names: Vec<String> = conn
+            .prepare("SELECT name FROM sqlite_master WHERE type = 'table' AND name NOT LIKE 'sqlite_%' ORDER BY name")?
+            .query_map([], |row| row.get(0))?
+            .collect::<SqliteResult<_>>()?;
+        let mut row_counts = std::collections::BTreeMap::new();
+        for name in table_names {
+            let count: i64 = conn.query_row(&format!("SELECT COUNT(*) FROM \"{}\"", name.replace('"', "\"\"")), [], |row| row.get(0))?;
+            row_counts.insert(name, count);
+        }
+
+        let page_count: i64 = conn.query_row("PRAGMA page_count", [], |row| row.get(0))?;
+        let page_size: i64 = conn.query_row("PRAGMA page_size", [], |row| row.get(0))?;
+        let freelist_count: i64 = conn.query_row("PRAGMA freelist_count", [], |row| row.get(0))?;
+        let wal_size_bytes = wal_file_size(&conn)?;
+
+        Ok(DbStats {
+            row_counts,
+            size_bytes: page_count * page_size,
+            free_bytes: freelist_count * page_size,
+            wal_size_bytes,
+            list_sessions_ms,
+            session_count,
+        })
+    }
+
+    /// Folds the WAL back into the main file and truncates it; much cheaper than a VACUUM
+    pub fn checkpoint_wal(&self) -> SqliteResult<WalCheckpoint> {
+        let conn = self.conn()?;
+        let wal_bytes_before = wal_file_size(&conn)?;
+        let busy: i64 = conn.query_row("PRAGMA wal_checkpoint(TRUNCATE)", [], |row| row.get(0))?;
+        let wal_bytes_after = wal_file_size(&conn)?;
+
+        Ok(WalCheckpoint {
+            busy: busy != 0,
+            wal_bytes_before,
+            wal_bytes_after,
+            reclaimed_bytes: (wal_bytes_before - wal_bytes_after).max(0),
+        })
+    }
+
+    pub fn get_busy_timeout_ms(&self) -> SqliteResult<u64> {
+        Ok(self
+            .get_setting(BUSY_TIMEOUT_SETTING)?
+            .and_then(|v| v.parse::<u64>().ok())
+            .unwrap_or(DEFAULT_BUSY_TIMEOUT_MS))
+    }
+
+    /// Applies from the next connection checkout and is remembered for the next start.
+    /// 0 disables waiting (contended writes fail immediately).
+    pub fn set_busy_timeout_ms(&self, ms: u64) -> SqliteResult<()> {
+        self.busy_timeout_ms.store(ms, Ordering::Relaxed);
+        self.set_setting(BUSY_TIMEOUT_SETTING, &ms.to_string())
+    }
+
+    // --- Settings ---
+    
+    pub fn get_setting(&self, key: &str) -> SqliteResult<Option<String>> {
+        let conn = self.conn()?;
+        let mut stmt = conn.prepare("SELECT value FROM settings WHERE key = ?1")?;
+        let mut rows = stmt.query([key])?;
+        
+        if let Some(row) = rows.next()? {
+            Ok(Some(row.get(0)?))
+        } else {
+            Ok(None)
+        }
+    }
+
+    pub fn set_setting(&self, key: &str, value: &str) -> SqliteResult<()> {
+        let conn = self.conn()?;
+        write_setting(&conn, key, value)
+    }
+
+    pub fn get_spending_cap(&self) -> SqliteResult<Option<f64>> {
+        Ok(self.get_setting(SPENDING_CAP_SETTING)?.and_then(|v| v.parse::<f64>().ok()))
+    }
+
+    /// `None` removes the cap
+    pub fn set_spending_cap(&self, cap_usd: Option<f64>) -> SqliteResult<()> {
+        match cap_usd {
+            Some(cap) if !cap.is_finite() || cap < 0.0 => Err(rusqlite::Error::ToSqlConversionFailure(
+                format!("Spending cap must be a non-negative number, got {}", cap).into(),
+            )),
+            Some(cap) => self.set_setting(SPENDING_CAP_SETTING, &cap.to_string()),
+            None => {
+                let conn = self.conn()?;
+                conn.execute("DELETE FROM settings WHERE key = ?1", [SPENDING_CAP_SETTING])?;
+                Ok(())
+            }
+        }
+    }
+
+    /// Estimated spend from the usage ledger, each row priced when it was recorded. Deleting a
+    /// session or moving it to another model doesn't lower it. Rows from before pricing was
+    /// recorded are priced at their model's current rates; models without pricing count as free.
+    pub fn get_total_estimated_cost(&self) -> SqliteResult<f64> {
+        let conn = self.conn()?;
+        let recorded: f64 = conn.query_row(
+            "SELECT COALESCE(SUM(cost_usd), 0) FROM usage WHERE cost_usd IS NOT NULL",
+            [],
+            |row| row.get(0),
+        )?;
+        let mut stmt = conn.prepare(
+            r#"SELECT SUM(u.input_tokens), SUM(u.output_tokens), mo.config
+               FROM usage u
+               JOIN models mo ON mo.id = u.model
+               WHERE u.cost_usd IS NULL
+               GROUP BY u.model"#
+        )?;
+        let legacy = stmt.query_map([], |row| {
+            let input_tokens: i64 = row.get::<_, Option<i64>>(0)?.unwrap_or(0);
+            let output_tokens: i64 = row.get::<_, Option<i64>>(1)?.unwrap_or(0);
+            let config: Option<JsonValue> = row.get::<_, Option<String>>(2)?
+                .and_then(|s| serde_json::from_str(&s).ok());
+            Ok(ModelConfig::from_config(config.as_ref()).estimate_cost(input_tokens, output_tokens).unwrap_or(0.0))
+        })?;
+        Ok(recorded + legacy.sum::<SqliteResult<f64>>()?)
+    }
+
+    /// Token usage recorded since `since` (ms), per model, with the cost estimated from each model's pricing
+    pub fn usage_summary(&self, since: i64) -> SqliteResult<Vec<ModelUsage>> {
+        let conn = self.conn()?;
+        let mut stmt = conn.prepare(
+            r#"SELECT u.model, SUM(u.input_tokens), SUM(u.output_tokens), COUNT(*), mo.config
+               FROM usage u
+               LEFT JOIN models mo ON mo.id = u.model
+               WHERE u.ts >= ?1
+               GROUP BY u.model
+               ORDER BY SUM(u.input_tokens) + SUM(u.output_tokens) DESC"#
+        )?;
+        let rows = stmt.query_map([since], |row| {
+            let input_tokens: i64 = row.get(1)?;
+            let output_tokens: i64 = row.get(2)?;
+            let config: Option<JsonValue> = row.get::<_, Option<String>>(4)?
+                .and_then(|s| serde_json::from_str(&s).ok());
+            Ok(ModelUsage {
+                model: row.get(0)?,
+                input_tokens,
+                output_tokens,
+                updates: row.get(3)?,
+                estimated_cost: ModelConfig::from_config(config.as_ref()).estimate_cost(input_tokens, output_tokens),
+            })
+        })?;
+        rows.collect()
+    }
+
+    /// Cap vs. accumulated spend, plus whether `session_id` was allowed to go over it
+    pub fn get_budget_status(&self, session_id: Option<&str>) -> SqliteResult<BudgetStatus> {
+        let cap_usd = self.get_spending_cap()?;
+        let spent_usd = self.get_total_estimated_cost()?;
+        let overridden = match session_id {
+            Some(id) => self.get_session_meta(id, BUDGET_OVERRIDE_META_KEY)?.as_deref() == Some("true"),
+            None => false,
+        };
+        Ok(BudgetStatus {
+            cap_usd,
+            spent_usd,
+            exceeded: cap_usd.is_some_and(|cap| spent_usd > cap),
+            overridden,
+        })
+    }
+
+    /// Write one CSV row per session created in `[from, to]` (ms, both optional).
+    /// Rows are written as they are read, so large histories never sit in memory. Returns the row count.
+    pub fn export_usage_csv<W: std::io::Write>(&self, from: Option<i64>, to: Option<i64>, out: &mut W) -> SqliteResult<usize> {
+        let io_err = |e: std::io::Error| rusqlite::Error::ToSqlConversionFailure(Box::new(e));
+        let conn = self.conn()?;
+        let mut stmt = conn.prepare(
+            r#"SELECT strftime('%Y-%m-%d', s.created_at / 1000, 'unixepoch', 'localtime'),
+                      s.id, s.title, s.model,
+                      COALESCE(s.input_tokens, 0), COALESCE(s.output_tokens, 0),
+                      (SELECT COUNT(*) FROM messages m WHERE m.session_id = s.id),
+                      mo.config
+               FROM sessions s
+               LEFT JOIN models mo ON mo.id = s.model
+               WHERE s.created_at >= ?1 AND s.created_at <= ?2
+               ORDER BY s.created_at ASC"#
+        )?;
+        let mut rows = stmt.query(params![from.unwrap_or(0), to.unwrap_or(i64::MAX)])?;
+
+        writeln!(out, "date,session_id,title,model,messages,input_tokens,output_tokens,estimated_cost_usd").map_err(io_err)?;
+        let mut count = 0;
+        while let Some(row) = rows.next()? {
+            let input_tokens: i64 = row.get(4)?;
+            let output_tokens: i64 = row.get(5)?;
+            let config: Option<JsonValue> = row.get::<_, Option<String>>(7)?
+                .and_then(|s| serde_json::from_str(&s).ok());
+            let cost = ModelConfig::from_config(config.as_ref())
+                .estimate_cost(input_tokens, output_tokens)
+                .map(|c| format!("{:.6}", c))
+                .unwrap_or_default();
+            writeln!(
+                out,
+                "{},{},{},{},{},{},{},{}",
+                row.get::<_, String>(0)?,
+                csv_field(&row.get::<_, String>(1)?),
+                csv_field(&row.get::<_, String>(2)?),
+                csv_field(&row.get::<_, Option<String>>(3)?.unwrap_or_default()),
+                row.get::<_, i64>(6)?,
+                input_tokens,
+                output_tokens,
+                cost
+            ).map_err(io_err)?;
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    pub fn get_api_settings(&self) -> SqliteResult<Option<ApiSettings>> {
+        match self.get_setting("api_settings")? {
+            Some(json) => {
+                let settings: ApiSettings = serde_json::from_str(&json)
+                    .map_err(|e| rusqlite::Error::ToSqlConversionFailure(Box::new(e)))?;
+                Ok(Some(settings))
+            }
+            None => Ok(None),
+        }
+    }
+
+    pub fn save_api_settings(&self, settings: &ApiSettings) -> SqliteResult<()> {
+        let json = serde_json::to_string(settings)
+            .map_err(|e| rusqlite::Error::ToSqlConversionFailure(Box::new(e)))?;
+        self.set_setting("api_settings", &json)
+    }
+
+    // --- Settings snapshots ---
+
+    /// ApiSettings, providers and models keyed by id, in the shape stored in snapshots
+    /// (secrets replaced by `fingerprint_secrets`)
+    fn settings_state(&self) -> SqliteResult<JsonValue> {
+        let providers: serde_json::Map<String, JsonValue> = self.list_providers()?
+            .into_iter()
+            .map(|p| {
+                let mut value = serde_json::to_value(&p).unwrap_or(JsonValue::Null);
+                if let Some(obj) = value.as_object_mut() {
+                    // Timestamps change on every save and would drown out real differences
+                    obj.remove("createdAt");
+                    obj.remove("updatedAt");
+                }
+                (p.id, value)
+            })
+            .collect();
+        let models: serde_json::Map<String, JsonValue> = self.list_models()?
+            .into_iter()
+            .map(|m| (m.id.clone(), serde_json::to_value(&m).unwrap_or(JsonValue::Null)))
+            .collect();
+        let mut state = serde_json::json!({
+            "apiSettings": self.get_api_settings()?,
+            "providers": providers,
+            "models": models,
+        });
+        fingerprint_secrets(&mut state);
+        Ok(state)
+    }
+
+    /// Capture ApiSettings, providers and models; only the newest `SETTINGS_SNAPSHOT_LIMIT` are kept
+    pub fn create_settings_snapshot(&self, label: Option<&str>) -> SqliteResult<SettingsSnapshotInfo> {
+        let data = self.settings_state()?;
+
+        let conn = self.conn()?;
+        let now = chrono::Utc::now().timestamp_millis();
+        conn.execute(
+            "INSERT INTO settings_snapshots (label, data, created_at) VALUES (?1, ?2, ?3)",
+            params![label, data.to_string(), now],
+        )?;
+        let id = conn.last_insert_rowid();
+        conn.execute(
+            "DELETE FROM settings_snapshots WHERE id NOT IN (SELECT id FROM settings_snapshots ORDER BY id DESC LIMIT ?1)",
+            [SETTINGS_SNAPSHOT_LIMIT],
+        )?;
+
+        Ok(SettingsSnapshotInfo { id, label: label.map(String::from), created_at: now })
+    }
+
+    pub fn list_settings_snapshots(&self) -> SqliteResult<Vec<SettingsSnapshotInfo>> {
+        let conn = self.conn()?;
+        let mut stmt = conn.prepare("SELECT id, label, created_at FROM settings_snapshots ORDER BY id DESC")?;
+        let rows = stmt.query_map([], |row| {
+            Ok(SettingsSnapshotInfo { id: row.get(0)?, label: row.get(1)?, created_at: row.get(2)? })
+        })?;
+        rows.collect()
+    }
+
+    pub fn get_settings_snapshot(&self, id: i64) -> SqliteResult<Option<JsonValue>> {
+        let conn = self.conn()?;
+        let mut stmt = conn.prepare("SELECT data FROM settings_snapshots WHERE id = ?1")?;
+        let mut rows = stmt.query([id])?;
+        match rows.next()? {
+            Some(row) => Ok(serde_json::from_str(&row.get::<_, String>(0)?).ok()),
+            None => Ok(None),
+        }
+    }
+
+    /// UI preferences stored as a single JSON blob; missing or unreadable prefs yield defaults
+    pub fn get_ui_prefs(&self) -> SqliteResult<UiPrefs> {
+        Ok(self.get_setting(UI_PREFS_KEY)?
+            .and_then(|json| serde_json::from_str(&json).ok())
+            .unwrap_or_default())
+    }
+
+    pub fn set_ui_prefs(&self, prefs: &UiPrefs) -> Result<(), UiPrefsError> {
+        prefs.validate().map_err(UiPrefsError::Invalid)?;
+        let json = serde_json::to_string(prefs).map_err(UiPrefsError::Encode)?;
+        Ok(self.set_setting(UI_PREFS_KEY, &json)?)
+    }
+
+    // --- Scheduler Default Model ---
+    
+    pub fn get_scheduler_default_model(&self) -> SqliteResult<Option<String>> {
+        self.get_setting("scheduler_default_model")
+    }
+    
+    /// Stores the model as its `models.id` (see `resolved_model`) and returns what was stored
+    pub fn set_scheduler_default_model(&self, model_id: &str) -> SqliteResult<String> {
+        let resolved = self.resolved_model(Some(model_id))?.unwrap_or_else(|| model_id.to_string());
+        self.set_setting("scheduler_default_model", &resolved)?;
+        Ok(resolved)
+    }
+
+    /// Remember the focused session for the next launch. Returns false for unknown ids.
+    pub fn set_last_active_session(&self, id: &str) -> SqliteResult<bool> {
+        if self.get_session(id)?.is_none() {
+            return Ok(false);
+        }
+        self.set_setting(LAST_ACTIVE_SESSION_SETTING, id)?;
+        Ok(true)
+    }
+
+    /// The remembered session, if it still exists. A stale id (session deleted since) is cleared.
+    pub fn get_last_active_session(&self) -> SqliteResult<Option<String>> {
+        let Some(id) = self.get_setting(LAST_ACTIVE_SESSION_SETTING)? else { return Ok(None) };
+        if self.get_session(&id)?.is_some() {
+            return Ok(Some(id));
+        }
+        let conn = self.conn()?;
+        conn.execute("DELETE FROM settings WHERE key = ?1", [LAST_ACTIVE_SESSION_SETTING])?;
+        Ok(None)
+    }
+
+    pub fn get_resume_unfinished_on_launch(&self) -> SqliteResult<bool> {
+        Ok(self.get_setting(RESUME_UNFINISHED_SETTING)?.as_deref() == Some("true"))
+    }
+
+    pub fn set_resume_unfinished_on_launch(&self, enabled: bool) -> SqliteResult<()> {
+        self.set_setting(RESUME_UNFINISHED_SETTING, if enabled { "true" } else { "false" })
+    }
+
+    /// Most recently updated non-archived session left mid-turn: a leftover stream draft,
+    /// or a last message that is an incomplete assistant turn (see `is_incomplete_turn`)
+    pub fn find_unfinished_session(&self) -> SqliteResult<Option<String>> {
+        let conn = self.conn()?;
+        let mut stmt = conn.prepare(
+            "SELECT s.id,
+                    EXISTS(SELECT 1 FROM stream_drafts d WHERE d.session_id = s.id),
+                    (SELECT m.data FROM messages m WHERE m.session_id = s.id
+                     ORDER BY m.created_at DESC, m.rowid DESC LIMIT 1)
+               FROM sessions s
+              WHERE s.archived = 0
+              ORDER BY s.updated_at DESC"
+        )?;
+        let mut rows = stmt.query([])?;
+        while let Some(row) = rows.next()? {
+            let has_draft: bool = row.get(1)?;
+            let last: Option<String> = row.get(2)?;
+            let incomplete = has_draft
+                || last
+                    .and_then(|data| serde_json::from_str::<JsonValue>(&data).ok())
+                    .is_some_and(|msg| is_incomplete_turn(&msg));
+            if incomplete {
+                return Ok(Some(row.get(0)?));
+            }
+        }
+        Ok(None)
+    }
+
+    // --- Providers ---
+
+    pub fn list_providers(&self) -> SqliteResult<Vec<LLMProvider>> {
+        let conn = self.conn()?;
+        let mut stmt = conn.prepare(
+            "SELECT id, name, type, base_url, api_key, enabled, config, created_at, updated_at FROM providers ORDER BY name"
+        )?;
+
+        let rows = stmt.query_map([], |row| {
+            let config_str: Option<String> = row.get(6)?;
+            let config = config_str.and_then(|s| serde_json::from_str(&s).ok());
+            
+            Ok(LLMProvider {
+                id: row.get(0)?,
+                name: row.get(1)?,
+                provider_type: row.get(2)?,
+                base_url: row.get(3)?,
+                api_key: row.get(4)?,
+                enabled: row.get::<_, i32>(5)? != 0,
+                config,
+                created_at: row.get(7)?,
+                updated_at: row.get(8)?,
+            })
+        })?;
+
+        rows.collect()
+    }
+
+    pub fn save_provider(&self, provider: &LLMProvider) -> SqliteResult<()> {
+        let conn = self.conn()?;
+        write_provider(&conn, provider)
+    }
+
+    pub fn delete_provider(&self, id: &str) -> SqliteResult<bool> {
+        let conn = self.conn()?;
+        // Delete associated models first
+        conn.execute("DELETE FROM models WHERE provider_id = ?1", [id])?;
+        let changed = conn.execute("DELETE FROM providers WHERE id = ?1", [id])?;
+        Ok(changed > 0)
+    }
+
+    // --- Models ---
+
+    pub fn list_models(&self) -> SqliteResult<Vec<LLMModel>> {
+        let conn = self.conn()?;
+        let mut stmt = conn.prepare(
+            "SELECT id, provider_id, name, enabled, config FROM models ORDER BY name"
+        )?;
+
+        let rows = stmt.query_map([], |row| {
+            let config_str: Option<String> = row.get(4)?;
+            let config = config_str.and_then(|s| serde_json::from_str(&s).ok());
+            
+            Ok(LLMModel {
+                id: row.get(0)?,
+                provider_id: row.get(1)?,
+                name: row.get(2)?,
+                enabled: row.get::<_, i32>(3)? != 0,
+                config,
+            })
+        })?;
+
+        rows.collect()
+    }
+
+    pub fn get_model(&self, id: &str) -> SqliteResult<Option<LLMModel>> {
+        let conn = self.conn()?;
+        let mut stmt = conn.prepare(
+            "SELECT id, provider_id, name, enabled, config FROM models WHERE id = ?1"
+        )?;
+
+        let mut rows = stmt.query_map([id], |row| {
+            let config_str: Option<String> = row.get(4)?;
+            let config = config_str.and_then(|s| serde_json::from_str(&s).ok());
+
+            Ok(LLMModel {
+                id: row.get(0)?,
+                provider_id: row.get(1)?,
+                name: row.get(2)?,
+                enabled: row.get::<_, i32>(3)? != 0,
+                config,
+            })
+        })?;
+
+        rows.next().transpose()
+    }
+
+    pub fn list_models_by_provider(&self, provider_id: &str) -> SqliteResult<Vec<LLMModel>> {
+        let conn = self.conn()?;
+        let mut stmt = conn.prepare(
+            "SELECT id, provider_id, name, enabled, config FROM models WHERE provider_id = ?1 ORDER BY name"
+        )?;
+
+        let rows = stmt.query_map([provider_id], |row| {
+            let config_str: Option<String> = row.get(4)?;
+            let config = config_str.and_then(|s| serde_json::from_str(&s).ok());
+            
+            Ok(LLMModel {
+                id: row.get(0)?,
+                provider_id: row.get(1)?,
+                name: row.get(2)?,
+                enabled: row.get::<_, i32>(3)? != 0,
+                config,
+            })
+        })?;
+
+        rows.collect()
+    }
+
+    pub fn save_model(&self, model: &LLMModel) -> SqliteResult<()> {
+        let conn = self.conn()?;
+        write_model(&conn, model)
+    }
+
+    pub fn save_models_bulk(&self, models: &[LLMModel]) -> SqliteResult<()> {
+        let conn = self.conn()?;
+        
+        for model in models {
+            write_model(&conn, model)?;
+        }
+        Ok(())
+    }
+
+    /// Models whose `provider_id` no longer matches a provider (left behind by partial imports/migrations)
+    pub fn list_dangling_models(&self) -> SqliteResult<Vec<LLMModel>> {
+        let conn = self.conn()?;
+        let mut stmt = conn.prepare(
+            "SELECT id, provider_id, name, enabled, config FROM models WHERE provider_id NOT IN (SELECT id FROM providers) ORDER BY name"
+        )?;
+
+        let rows = stmt.query_map([], |row| {
+            let config_str: Option<String> = row.get(4)?;
+            let config = config_str.and_then(|s| serde_json::from_str(&s).ok());
+
+            Ok(LLMModel {
+                id: row.get(0)?,
+                provider_id: row.get(1)?,
+                name: row.get(2)?,
+                enabled: row.get::<_, i32>(3)? != 0,
+                config,
+            })
+        })?;
+
+        rows.collect()
+    }
+
+    /// Move dangling models to `reassign_to` (which must exist), or delete them when `None`.
+    /// Reassigned ids are rewritten to `<reassign_to>::<modelName>` (like the settings UI does) so
+    /// requests route to the new provider, and sessions follow; a model the target already has
+    /// replaces its dangling copy. Returns how many models were repaired.
+    pub fn repair_dangling_models(&self, reassign_to: Option<&str>) -> SqliteResult<usize> {
+        let mut conn = self.conn()?;
+        let Some(provider_id) = reassign_to else {
+            return conn.execute("DELETE FROM models WHERE provider_id NOT IN (SELECT id FROM providers)", []);
+        };
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        let exists: bool = tx.query_row("SELECT EXISTS(SELECT 1 FROM providers WHERE id = ?1)", [provider_id], |row| row.get(0))?;
+        if !exists {
+            return Err(rusqlite::Error::ToSqlConversionFailure(
+                format!("Provider '{}' not found", provider_id).into(),
+            ));
+        }
+        let dangling: Vec<String> = {
+            let mut stmt = tx.prepare("SELECT id FROM models WHERE provider_id NOT IN (SELECT id FROM providers)")?;
+            let rows = stmt.query_map([], |row| row.get(0))?;
+            rows.collect::<SqliteResult<_>>()?
+        };
+        for old_id in &dangling {
+            let model_name = old_id.split_once("::").map(|(_, name)| name).unwrap_or(old_id);
+            let new_id = format!("{}::{}", provider_id, model_name);
+            let taken: bool = tx.query_row("SELECT EXISTS(SELECT 1 FROM models WHERE id = ?1)", [&new_id], |row| row.get(0))?;
+            if taken {
+                tx.execute("DELETE FROM models WHERE id = ?1", [old_id])?;
+            } else {
+                tx.execute("UPDATE models SET id = ?1, provider_id = ?2 WHERE id = ?3", params![&new_id, provider_id, old_id])?;
+            }
+            tx.execute("UPDATE sessions SET model = ?1 WHERE model = ?2", params![&new_id, old_id])?;
+        }
+        tx.commit()?;
+        Ok(dangling.len())
+    }
+
+    /// Reconcile a provider's models with the ids it currently offers, in one transaction.
+    /// New ids are added disabled; missing ones are removed; survivors keep name, `enabled` and config.
+    pub fn sync_provider_models(&self, provider_id: &str, remote_ids: &[String]) -> SqliteResult<ModelSyncReport> {
+        let mut conn = self.conn()?;
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        let existing: Vec<String> = {
+            let mut stmt = tx.prepare("SELECT id FROM models WHERE provider_id = ?1")?;
+            let rows = stmt.query_map([provider_id], |row| row.get(0))?;
+            rows.collect::<SqliteResult<_>>()?
+        };
+
+        let offered: std::collections::HashSet<&str> = remote_ids.iter().map(String::as_str).collect();
+        let mut report = ModelSyncReport::default();
+        let mut present = std::collections::HashSet::new();
+        for id in &existing {
+            // Stored ids are usually "<providerId>::<remoteId>"
+            let remote_id = id.split_once("::").map(|(_, rest)| rest).unwrap_or(id);
+            if offered.contains(remote_id) {
+                present.insert(remote_id.to_string());
+                report.kept += 1;
+            } else {
+                tx.execute("DELETE FROM models WHERE id = ?1", [id])?;
+                report.removed.push(id.clone());
+            }
+        }
+        for remote_id in remote_ids {
+            if remote_id.is_empty() || !present.insert(remote_id.clone()) {
+                continue;
+            }
+            let id = format!("{}::{}", provider_id, remote_id);
+            let inserted = tx.execute(
+                "INSERT OR IGNORE INTO models (id, provider_id, name, enabled, config) VALUES (?1, ?2, ?3, 0, NULL)",
+                params![&id, provider_id, remote_id],
+            )?;
+            // An existing row with this id (e.g. under another provider) was left alone
+            if inserted == 1 {
+                report.added.push(id);
+            }
+        }
+
+        tx.commit()?;
+        Ok(report)
+    }
+
+    pub fn delete_models_by_provider(&self, provider_id: &str) -> SqliteResult<()> {
+        let conn = self.conn()?;
+        conn.execute("DELETE FROM models WHERE provider_id = ?1", [provider_id])?;
+        Ok(())
+    }
+
+    // --- Combined providers + models ---
+
+    pub fn get_llm_provider_settings(&self) -> SqliteResult<LLMProviderSettings> {
+        Ok(LLMProviderSettings {
+            providers: self.list_providers()?,
+            models: self.list_models()?,
+        })
+    }
+
+    pub fn save_llm_provider_settings(&self, settings: &LLMProviderSettings) -> SqliteResult<()> {
+        let mut conn = self.conn()?;
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        write_llm_provider_settings(&tx, settings)?;
+        tx.commit()
+    }
+
+    // --- Settings profiles ---
+
+    /// ApiSettings, providers and models as one portable document
+    pub fn export_profile(&self, strip_keys: bool) -> SqliteResult<SettingsProfile> {
+        let mut profile = SettingsProfile {
+            version: PROFILE_VERSION,
+            api_settings: self.get_api_settings()?,
+            providers: self.list_providers()?,
+            models: self.list_models()?,
+        };
+        if strip_keys {
+            profile.strip_keys();
+        }
+        Ok(profile)
+    }
+
+    /// Apply a profile in one transaction, after snapshotting the current settings.
+    /// Secrets missing from the profile (exported with keys stripped) keep their current values,
+    /// except that an API key is only kept while its base URL stays the same.
+    pub fn import_profile(&self, profile: &SettingsProfile, mode: ProfileImportMode) -> SqliteResult<ProfileImport> {
+        profile.validate()
+            .map_err(|e| rusqlite::Error::ToSqlConversionFailure(e.into()))?;
+
+        let before = self.settings_state()?;
+        let current_api = self.get_api_settings()?;
+        let current = self.get_llm_provider_settings()?;
+
+        let api_settings = merge_api_settings(current_api.as_ref(), profile.api_settings.as_ref(), mode)
+            .map_err(|e| rusqlite::Error::ToSqlConversionFailure(Box::new(e)))?;
+
+        let mut providers: Vec<LLMProvider> = match mode {
+            ProfileImportMode::Merge => current.providers.iter()
+                .filter(|p| !profile.providers.iter().any(|i| i.id == p.id))
+                .cloned()
+                .collect(),
+            ProfileImportMode::Replace => Vec::new(),
+        };
+        for imported in &profile.providers {
+            let mut provider = imported.clone();
+            if provider.api_key.is_none() {
+                // Never send the current key to an endpoint the profile points elsewhere
+                provider.api_key = current.providers.iter()
+                    .find(|p| p.id == provider.id && p.base_url == provider.base_url)
+                    .and_then(|p| p.api_key.clone());
+            }
+            providers.push(provider);
+        }
+        let mut models: Vec<LLMModel> = match mode {
+            ProfileImportMode::Merge => current.models.iter()
+                .filter(|m| !profile.models.iter().any(|i| i.id == m.id))
+                .cloned()
+                .collect(),
+            ProfileImportMode::Replace => Vec::new(),
+        };
+        models.extend(profile.models.iter().cloned());
+
+        if let Some(model) = models.iter().find(|m| !providers.iter().any(|p| p.id == m.provider_id)) {
+            return Err(rusqlite::Error::ToSqlConversionFailure(
+                format!("Model '{}' references unknown provider '{}'", model.id, model.provider_id).into(),
+            ));
+        }
+
+        let snapshot = self.create_settings_snapshot(Some("Before profile import"))?;
+
+        {
+            let mut conn = self.conn()?;
+            let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+            if let Some(api_settings) = &api_settings {
+                let json = serde_json::to_string(api_settings)
+                    .map_err(|e| rusqlite::Error::ToSqlConversionFailure(Box::new(e)))?;
+                write_setting(&tx, "api_settings", &json)?;
+            }
+            write_llm_provider_settings(&tx, &LLMProviderSettings { providers, models })?;
+            tx.commit()?;
+        }
+
+        let after = self.settings_state()?;
+        Ok(ProfileImport {
+            mode,
+            snapshot_id: snapshot.id,
+            changes: diff_settings_snapshots(&before, &after),
+        })
+    }
+}
+
+// ============ Scheduled Tasks ============
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct TaskRun {
+    pub id: String,
+    pub task_id: String,
+    pub session_id: String,
+    pub status: String,
+    pub started_at: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub finished_at: Option<i64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ScheduledTask {
+    pub id: String,
+    pub title: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub prompt: Option<String>,
+    pub schedule: String,
+    pub next_run: i64,
+    pub is_recurring: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub notify_before: Option<i64>,
+    pub enabled: bool,
+    /// What to do when several runs were missed; one of `CATCH_UP_POLICIES`
+    pub catch_up_policy: String,
+    pub created_at: i64,
+    pub updated_at: i64,
+}
+
+/// "coalesce" fires a missed recurring task once; "each" replays each missed slot, one per
+/// scheduler check, up to `scheduler::CATCH_UP_MAX_RUNS`
+pub const CATCH_UP_POLICIES: &[&str] = &["coalesce", "each"];
+pub const DEFAULT_CATCH_UP_POLICY: &str = "coalesce";
+
+fn validate_catch_up_policy(policy: &str) -> SqliteResult<()> {
+    if CATCH_UP_POLICIES.contains(&policy) {
+        return Ok(());
+    }
+    Err(rusqlite::Error::ToSqlConversionFailure(
+        format!("Unknown catch-up policy '{}'; expected one of {}", policy, CATCH_UP_POLICIES.join(", ")).into(),
+    ))
+}
+
+const SCHEDULED_TASK_COLUMNS: &str =
+    "id, title, prompt, schedule, next_run, is_recurring, notify_before, enabled, created_at, updated_at, catch_up_policy";
+
+fn scheduled_task_from_row(row: &rusqlite::Row) -> SqliteResult<ScheduledTask> {
+    Ok(ScheduledTask {
+        id: row.get(0)?,
+        title: row.get(1)?,
+        prompt: row.get(2)?,
+        schedule: row.get(3)?,
+        next_run: row.get(4)?,
+        is_recurring: row.get::<_, i32>(5)? != 0,
+        notify_before: row.get(6)?,
+        enabled: row.get::<_, i32>(7)? != 0,
+        created_at: row.get(8)?,
+        updated_at: row.get(9)?,
+        catch_up_policy: row.get(10)?,
+    })
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CreateScheduledTaskParams {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    pub title: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub prompt: Option<String>,
+    pub schedule: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub notify_before: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub catch_up_policy: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct UpdateScheduledTaskParams {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub prompt: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub schedule: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next_run: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub is_recurring: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub notify_before: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub enabled: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub catch_up_policy: Option<String>,
+}
+
+impl Database {
+    // --- Scheduled Tasks ---
+
+    pub fn create_scheduled_task(&self, params: &CreateScheduledTaskParams, next_run: i64, is_recurring: bool) -> SqliteResult<ScheduledTask> {
+        let catch_up_policy = params.catch_up_policy.clone().unwrap_or_else(|| DEFAULT_CATCH_UP_POLICY.to_string());
+        validate_catch_up_policy(&catch_up_policy)?;
+        let conn = self.conn()?;
+        let id = params.id.clone().unwrap_or_else(|| uuid::Uuid::new_v4().to_string());
+        let now = chrono::Utc::now().timestamp_millis();
+
+        conn.execute(
+            r#"INSERT INTO scheduled_tasks 
+               (id, title, prompt, schedule, next_run, is_recurring, notify_before, enabled, created_at, updated_at, catch_up_policy)
+               VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, 1, ?8, ?9, ?10)"#,
+            params![
+                &id,
+                &params.title,
+                &params.prompt,
+                &params.schedule,
+                next_run,
+                if is_recurring { 1 } else { 0 },
+                &params.notify_before,
+                now,
+                now,
+                &catch_up_policy
+            ],
+        )?;
+
+        Ok(ScheduledTask {
+            id,
+            title: params.title.clone(),
+            prompt: params.prompt.clone(),
+            schedule: params.schedule.clone(),
+            next_run,
+            is_recurring,
+            notify_before: params.notify_before,
+            enabled: true,
+            catch_up_policy,
+            created_at: now,
+            updated_at: now,
+        })
+    }
+
+    pub fn get_scheduled_task(&self, id: &str) -> SqliteResult<Option<ScheduledTask>> {
+        let conn = self.conn()?;
+        let mut stmt = conn.prepare(
+            &format!("SELECT {} FROM scheduled_tasks WHERE id = ?1", SCHEDULED_TASK_COLUMNS)
+        )?;
+
+        let mut rows = stmt.query_map([id], scheduled_task_from_row)?;
+
+        match rows.next() {
+            Some(result) => Ok(Some(result?)),
+            None => Ok(None),
+        }
+    }
+
+    pub fn list_scheduled_tasks(&self, include_disabled: bool) -> SqliteResult<Vec<ScheduledTask>> {
+        let conn = self.conn()?;
+        let filter = if include_disabled { "" } else { "WHERE enabled = 1 " };
+        let query = format!("SELECT {} FROM scheduled_tasks {}ORDER BY next_run ASC", SCHEDULED_TASK_COLUMNS, filter);
+        
+        let mut stmt = conn.prepare(&query)?;
+        let rows = stmt.query_map([], scheduled_task_from_row)?;
+
+        rows.collect()
+    }
+
+    pub fn get_tasks_due_now(&self, now: i64) -> SqliteResult<Vec<ScheduledTask>> {
+        let conn = self.conn()?;
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {} FROM scheduled_tasks WHERE enabled = 1 AND next_run <= ?1 ORDER BY next_run ASC",
+            SCHEDULED_TASK_COLUMNS
+        ))?;
+
+        let rows = stmt.query_map([now], scheduled_task_from_row)?;
+
+        rows.collect()
+    }
+
+    pub fn update_scheduled_task(&self, id: &str, params: &UpdateScheduledTaskParams) -> SqliteResult<bool> {
+        if let Some(ref policy) = params.catch_up_policy {
+            validate_catch_up_policy(policy)?;
+        }
+        let conn = self.conn()?;
+        let now = chrono::Utc::now().timestamp_millis();
+
+        let mut updates = vec!["updated_at = ?1".to_string()];
+        let mut values: Vec<Box<dyn rusqlite::ToSql>> = vec![Box::new(now)];
+        let mut idx = 2;
+
+        if let Some(ref title) = params.title {
+            updates.push(format!("title = ?{}", idx));
+            values.push(Box::new(title.clone()));
+            idx += 1;
+        }
+        if let Some(ref prompt) = params.prompt {
+            updates.push(format!("prompt = ?{}", idx));
+            values.push(Box::new(prompt.clone()));
+            idx += 1;
+        }
+        if let Some(ref schedule) = params.schedule {
+            updates.push(format!("schedule = ?{}", idx));
+            values.push(Box::new(schedule.clone()));
+            idx += 1;
+        }
+        if let Some(next_run) = params.next_run {
+            updates.push(format!("next_run = ?{}", idx));
+            values.push(Box::new(next_run));
+            idx += 1;
+        }
+        if let Some(is_recurring) = params.is_recurring {
+            updates.push(format!("is_recurring = ?{}", idx));
+            values.push(Box::new(if is_recurring { 1i32 } else { 0i32 }));
+            idx += 1;
+        }
+        if let Some(notify_before) = params.notify_before {
+            updates.push(format!("notify_before = ?{}", idx));
+            values.push(Box::new(notify_before));
+            idx += 1;
+        }
+        if let Some(enabled) = params.enabled {
+            updates.push(format!("enabled = ?{}", idx));
+            values.push(Box::new(if enabled { 1i32 } else { 0i32 }));
+            idx += 1;
+        }
+        if let Some(ref catch_up_policy) = params.catch_up_policy {
+            updates.push(format!("catch_up_policy = ?{}", idx));
+            values.push(Box::new(catch_up_policy.clone()));
+            idx += 1;
+        }
+
+        let sql = format!(
+            "UPDATE scheduled_tasks SET {} WHERE id = ?{}",
+            updates.join(", "),
+            idx
+        );
+        values.push(Box::new(id.to_string()));
+
+        let params_refs: Vec<&dyn rusqlite::ToSql> = values.iter().map(|v| v.as_ref()).collect();
+        let changed = conn.execute(&sql, params_refs.as_slice())?;
+        Ok(changed > 0)
+    }
+
+    pub fn delete_scheduled_task(&self, id: &str) -> SqliteResult<bool> {
+        let conn = self.conn()?;
+        let changed = conn.execute("DELETE FROM scheduled_tasks WHERE id = ?1", [id])?;
+        Ok(changed > 0)
+    }
+
+    // --- Task runs ---
+
+    pub fn start_task_run(&self, task_id: &str, session_id: &str) -> SqliteResult<TaskRun> {
+        let conn = self.conn()?;
+        let run = TaskRun {
+            id: uuid::Uuid::new_v4().to_string(),
+            task_id: task_id.to_string(),
+            session_id: session_id.to_string(),
+            status: "running".to_string(),
+            started_at: chrono::Utc::now().timestamp_millis(),
+            finished_at: None,
+        };
+        conn.execute(
+            "INSERT INTO task_runs (id, task_id, session_id, status, started_at) VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![&run.id, &run.task_id, &run.session_id, &run.status, run.started_at],
+        )?;
+        Ok(run)
+    }
+
+    /// Close the session's running task runs with `status`. Returns how many were updated.
+    pub fn finish_task_runs(&self, session_id: &str, status: &str) -> SqliteResult<usize> {
+        let conn = self.conn()?;
+        let now = chrono::Utc::now().timestamp_millis();
+        conn.execute(
+            "UPDATE task_runs SET status = ?1, finished_at = ?2 WHERE session_id = ?3 AND status = 'running'",
+            params![status, now, session_id],
+        )
+    }
+
+    /// Most recent still-running run of a task
+    pub fn get_active_task_run(&self, task_id: &str) -> SqliteResult<Option<TaskRun>> {
+        let conn = self.conn()?;
+        let mut stmt = conn.prepare(
+            r#"SELECT id, task_id, session_id, status, started_at, finished_at FROM task_runs
+               WHERE task_id = ?1 AND status = 'running'
+               ORDER BY started_at DESC LIMIT 1"#
+        )?;
+        let mut rows = stmt.query_map([task_id], |row| {
+            Ok(TaskRun {
+                id: row.get(0)?,
+                task_id: row.get(1)?,
+                session_id: row.get(2)?,
+                status: row.get(3)?,
+                started_at: row.get(4)?,
+                finished_at: row.get(5)?,
+            })
+        })?;
+        rows.next().transpose()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    #[test]
+    fn api_settings_locale_roundtrip() {
+        let db = Database::new(Path::new(":memory:")).unwrap();
+        let settings = ApiSettings {
+            base_url: Some("https://api.example.com".to_string()),
+            api_key: Some("sk-test".to_string()),
+            permission_mode: None,
+            web_search_provider: None,
+            tavily_api_key: None,
+            enable_memory: None,
+            enable_todos: None,
+            locale: Some("ru".to_string()),
+            ..Default::default()
+        };
+
+        db.save_api_settings(&settings).unwrap();
+
+        let loaded = db.get_api_settings().unwrap();
+        assert!(loaded.is_some());
+        let loaded = loaded.unwrap();
+        assert_eq!(loaded.locale, Some("ru".to_string()));
+    }
+
+    #[test]
+    fn api_settings_without_locale() {
+        let db = Database::new(Path::new(":memory:")).unwrap();
+        let settings = ApiSettings {
+            base_url: None,
+            api_key: None,
+            permission_mode: None,
+            web_search_provider: None,
+            tavily_api_key: None,
+            enable_memory: None,
+            enable_todos: None,
+            locale: None,
+            ..Default::default()
+        };
+
+        db.save_api_settings(&settings).unwrap();
+
+        let loaded = db.get_api_settings().unwrap();
+        assert!(loaded.is_some());
+        assert_eq!(loaded.unwrap().locale, None);
+    }
+
+    fn create_test_session(db: &Database, model: Option<&str>, temperature: Option<f64>) -> Session {
+        db.create_session(&CreateSessionParams {
+            id: None,
+            cwd: None,
+            allowed_tools: None,
+            prompt: None,
+            title: "Test".to_string(),
+            model: model.map(String::from),
+            thread_id: None,
+            temperature,
+            ..Default::default()
+        }).unwrap()
+    }
+
+    #[test]
+    fn session_bundle_roundtrips_under_a_new_id() {
+        let db = Database::new(Path::new(":memory:")).unwrap();
+        let session = create_test_session(&db, None, Some(0.5));
+        db.record_message(&session.id, &serde_json::json!({ "type": "user_prompt", "prompt": "hi", "uuid": "m1" })).unwrap();
+        db.record_message(&session.id, &serde_json::json!({ "type": "text", "text": "hello", "uuid": "m2" })).unwrap();
+        db.save_todos(&session.id, &[TodoItem {
+            id: "t1".to_string(),
+            content: "ship it".to_string(),
+            status: "pending".to_string(),
+            created_at: None,
+            updated_at: None,
+        }]).unwrap();
+        db.set_session_meta(&session.id, "ticket", "ABC-1").unwrap();
+
+        let blobs_dir = std::env::temp_dir().join(format!("export_blobs_test_{}", uuid::Uuid::new_v4()));
+        let bundle = db.export_session(&session.id, &blobs_dir).unwrap();
+        assert_eq!(bundle["version"], SESSION_BUNDLE_VERSION);
+
+        // Importing twice must not collide on session or message ids
+        let first = db.import_session(&bundle).unwrap();
+        let second = db.import_session(&bundle).unwrap();
+        assert_ne!(first, session.id);
+        assert_ne!(first, second);
+
+        let history = db.get_session_history(&first).unwrap().unwrap();
+        assert_eq!(history.session.title, session.title);
+        assert_eq!(history.session.temperature, Some(0.5));
+        assert_eq!(history.messages, db.get_session_messages(&session.id).unwrap());
+        assert_eq!(history.todos.len(), 1);
+        assert_eq!(history.metadata.get("ticket").map(String::as_str), Some("ABC-1"));
+
+        let mut future = bundle.clone();
+        future["version"] = serde_json::json!(SESSION_BUNDLE_VERSION + 1);
+        let err = db.import_session(&future).unwrap_err().to_string();
+        assert!(err.contains("Unsupported session bundle version"), "{}", err);
+        assert!(db.import_session(&serde_json::json!({ "messages": [] })).is_err());
+        assert!(matches!(db.export_session("missing", &blobs_dir), Err(rusqlite::Error::QueryReturnedNoRows)));
+
+        // Externalized blobs go into the bundle inline
+        let image = format!("data:image/png;base64,{}", "A".repeat(crate::blobs::BLOB_MIN_BYTES));
+        db.record_message(&session.id, &serde_json::json!({ "type": "user_prompt", "prompt": "img", "images": [image] })).unwrap();
+        db.externalize_blobs(&session.id, &blobs_dir).unwrap();
+        let bundle = db.export_session(&session.id, &blobs_dir).unwrap();
+        let exported = bundle["messages"].as_array().unwrap().iter()
+            .find(|m| m["data"]["prompt"] == "img")
+            .unwrap();
+        assert_eq!(exported["data"]["images"][0], image);
+
+        // A corrupt row fails the export rather than turning into null
+        db.conn().unwrap()
+            .execute("UPDATE messages SET data = '{' WHERE session_id = ?1 AND data LIKE '%hello%'", [&session.id])
+            .unwrap();
+        let err = db.export_session(&session.id, &blobs_dir).unwrap_err().to_string();
+        assert!(err.contains("not valid JSON"), "{}", err);
+        std::fs::remove_dir_all(&blobs_dir).unwrap();
+    }
+
+    #[test]
+    fn sessions_for_cwd_match_exact_or_subfolders() {
+        let db = Database::new(Path::new(":memory:")).unwrap();
+        let with_cwd = |cwd: &str| db.create_session(&CreateSessionParams {
+            title: cwd.to_string(),
+            cwd: Some(cwd.to_string()),
+            ..Default::default()
+        }).unwrap().id;
+        let root = with_cwd("/work/app/");
+        let nested = with_cwd("/work/app/src");
+        let windows = with_cwd("\\work\\app\\docs");
+        with_cwd("/work/application");
+        with_cwd("/other");
+
+        let ids = |sessions: Vec<Session>| {
+            let mut ids: Vec<String> = sessions.into_iter().map(|s| s.id).collect();
+            ids.sort();
+            ids
+        };
+        assert_eq!(ids(db.list_sessions_for_cwd("/work/app", false).unwrap()), vec![root.clone()]);
+        let mut expected = vec![root, nested, windows];
+        expected.sort();
+        assert_eq!(ids(db.list_sessions_for_cwd("/work/app/", true).unwrap()), expected);
+        assert!(db.list_sessions_for_cwd("  ", true).unwrap().is_empty());
+        assert_eq!(db.list_sessions_for_cwd("/", true).unwrap().len(), 5);
+
+        if cfg!(any(windows, target_os = "macos")) {
+            assert_eq!(db.list_sessions_for_cwd("/WORK/App", true).unwrap().len(), 3);
+        } else {
+            assert!(db.list_sessions_for_cwd("/WORK/App", true).unwrap().is_empty());
+        }
+    }
+
+    #[test]
+    fn archived_sessions_are_hidden_but_kept() {
+        let db = Database::new(Path::new(":memory:")).unwrap();
+        let archived = db.create_session(&CreateSessionParams {
+            title: "done".to_string(),
+            cwd: Some("/old".to_string()),
+            ..Default::default()
+        }).unwrap();
+        let active = create_test_session(&db, None, None);
+        db.update_session(&archived.id, &UpdateSessionParams { status: Some("running".to_string()), ..Default::default() }).unwrap();
+
+        assert!(db.set_archived(&archived.id, true).unwrap());
+        assert!(!db.set_archived("missing", true).unwrap());
+
+        let visible: Vec<String> = db.list_sessions().unwrap().into_iter().map(|s| s.id).collect();
+        assert_eq!(visible, vec![active.id.clone()]);
+        assert_eq!(db.list_sessions_filtered(true).unwrap().len(), 2);
+        assert!(db.get_session(&archived.id).unwrap().unwrap().is_archived);
+        assert!(db.list_recent_cwds(10).unwrap().is_empty());
+        assert_eq!(db.list_sessions_for_cwd("/old", false).unwrap().len(), 1);
+
+        assert_eq!(db.reset_running_sessions().unwrap(), 1);
+        assert_eq!(db.get_session(&archived.id).unwrap().unwrap().status, "idle");
+        assert!(db.evict_oldest_sessions(0, None).unwrap().iter().all(|id| id != &archived.id));
+
+        db.set_archived(&archived.id, false).unwrap();
+        assert!(!db.get_session(&archived.id).unwrap().unwrap().is_archived);
+    }
+
+    #[test]
+    fn dedupe_session_titles_suffixes_later_duplicates() {
+        let db = Database::new(Path::new(":memory:")).unwrap();
+        let create = |title: &str| {
+            let session = db.create_session(&CreateSessionParams {
+                title: title.to_string(),
+                ..Default::default()
+            }).unwrap();
+            std::thread::sleep(std::time::Duration::from_millis(2));
+            session.id
+        };
+        let first = create("New Chat");
+        let second = create("new chat ");
+        let existing_suffix = create("New Chat (2)");
+        let third = create("New Chat");
+        let unique = create("Release notes");
+        let title = |id: &str| db.get_session(id).unwrap().unwrap().title;
+
+        assert_eq!(db.dedupe_session_titles().unwrap(), 2);
+        assert_eq!(title(&first), "New Chat");
+        assert_eq!(title(&second), "new chat (3)");
+        assert_eq!(title(&existing_suffix), "New Chat (2)");
+        assert_eq!(title(&third), "New Chat (4)");
+        assert_eq!(title(&unique), "Release notes");
+        assert_eq!(db.dedupe_session_titles().unwrap(), 0);
+    }
+
+    #[test]
+    fn find_duplicate_sessions_groups_normalized_prompts() {
+        let db = Database::new(Path::new(":memory:")).unwrap();
+        let create = |prompt: Option<&str>| {
+            let session = db.create_session(&CreateSessionParams {
+                prompt: prompt.map(String::from),
+                title: "Test".to_string(),
+                ..Default::default()
+            }).unwrap();
+            std::thread::sleep(std::time::Duration::from_millis(2));
+            session.id
+        };
+        let a = create(Some("Fix the  build"));
+        let _unique = create(Some("write docs"));
+        let b = create(Some("fix the build\n"));
+        let _empty = create(None);
+        let _blank = create(Some("  "));
+
+        let clusters = db.find_duplicate_sessions().unwrap();
+        assert_eq!(clusters.len(), 1);
+        assert_eq!(clusters[0].prompt, "fix the build");
+        let ids: Vec<&str> = clusters[0].sessions.iter().map(|m| m.id.as_str()).collect();
+        assert_eq!(ids, vec![b.as_str(), a.as_str()]);
+    }
+
+    #[test]
+    fn create_session_clamps_temperature_to_default_range() {
+        let db = Database::new(Path::new(":memory:")).unwrap();
+
+        assert_eq!(create_test_session(&db, None, Some(5.0)).temperature, Some(2.0));
+        assert_eq!(create_test_session(&db, None, Some(-1.0)).temperature, Some(0.0));
+        assert_eq!(create_test_session(&db, None, Some(0.0)).temperature, Some(0.0));
+        assert_eq!(create_test_session(&db, None, Some(2.0)).temperature, Some(2.0));
+        assert_eq!(create_test_session(&db, None, Some(f64::NAN)).temperature, None);
+
+        let stored = create_test_session(&db, None, Some(3.5));
+        let loaded = db.get_session(&stored.id).unwrap().unwrap();
+        assert_eq!(loaded.temperature, Some(2.0));
+    }
+
+    #[test]
+    fn set_temperature_bulk_updates_selected_or_all_sessions() {
+        let db = Database::new(Path::new(":memory:")).unwrap();
+        db.save_provider(&LLMProvider {
+            id: "p".to_string(),
+            name: "Provider".to_string(),
+            provider_type: "openai".to_string(),
+            base_url: None,
+            api_key: None,
+            enabled: true,
+            config: None,
+            created_at: 0,
+            updated_at: 0,
+        }).unwrap();
+        db.save_model(&LLMModel {
+            id: "p::cool".to_string(),
+            provider_id: "p".to_string(),
+            name: "cool".to_string(),
+            enabled: true,
+            config: Some(serde_json::json!({ "maxTemperature": 1.0 })),
+        }).unwrap();
+        let a = create_test_session(&db, None, Some(0.2));
+        let b = create_test_session(&db, None, Some(0.2));
+        let capped = create_test_session(&db, Some("p::cool"), Some(0.2));
+        let temperature = |id: &str| db.get_session(id).unwrap().unwrap().temperature;
+
+        let only_a = vec![a.id.clone(), "missing".to_string()];
+        assert_eq!(db.set_temperature_bulk(Some(&only_a), 0.9).unwrap(), 1);
+        assert_eq!(temperature(&a.id), Some(0.9));
+        assert_eq!(temperature(&b.id), Some(0.2));
+
+        assert_eq!(db.set_temperature_bulk(None, 1.5).unwrap(), 3);
+        assert_eq!(temperature(&b.id), Some(1.5));
+        assert_eq!(temperature(&capped.id), Some(1.0));
+
+        assert!(db.set_temperature_bulk(None, 2.5).is_err());
+        assert!(db.set_temperature_bulk(None, f64::NAN).is_err());
+        assert_eq!(temperature(&a.id), Some(1.5));
+    }
+
+    #[test]
+    fn max_output_tokens_validated_against_model_limit() {
+        let db = Database::new(Path::new(":memory:")).unwrap();
+        db.save_provider(&LLMProvider {
+            id: "p".to_string(),
+            name: "Provider".to_string(),
+            provider_type: "openai".to_string(),
+            base_url: None,
+            api_key: None,
+            enabled: true,
+            config: None,
+            created_at: 0,
+            updated_at: 0,
+        }).unwrap();
+        db.save_model(&LLMModel {
+            id: "p::small".to_string(),
+            provider_id: "p".to_string(),
+            name: "small".to_string(),
+            enabled: true,
+            config: Some(serde_json::json!({ "maxOutputTokens": 4096 })),
+        }).unwrap();
+
+        let session = db.create_session(&CreateSessionParams {
+            title: "Capped".to_string(),
+            model: Some("p::small".to_string()),
+            max_output_tokens: Some(1024),
+            ..Default::default()
+        }).unwrap();
+        assert_eq!(db.get_session(&session.id).unwrap().unwrap().max_output_tokens, Some(1024));
+
+        let update = |max: Option<i64>| db.update_session(&session.id, &UpdateSessionParams {
+            max_output_tokens: Some(max),
+            ..Default::default()
+        });
+        assert!(update(Some(0)).is_err());
+        assert!(update(Some(8192)).unwrap_err().to_string().contains("limit of 4096"));
+        assert!(update(Some(4096)).unwrap());
+        assert_eq!(db.get_session(&session.id).unwrap().unwrap().max_output_tokens, Some(4096));
+
+        // Leaving the field out keeps the cap; null clears it
+        let absent: UpdateSessionParams = serde_json::from_value(serde_json::json!({ "title": "Renamed" })).unwrap();
+        assert!(db.update_session(&session.id, &absent).unwrap());
+        assert_eq!(db.get_session(&session.id).unwrap().unwrap().max_output_tokens, Some(4096));
+        let cleared: UpdateSessionParams = serde_json::from_value(serde_json::json!({ "maxOutputTokens": null })).unwrap();
+        assert_eq!(cleared.max_output_tokens, Some(None));
+        assert!(db.update_session(&session.id, &db.sanitize_update_params(&session.id, &cleared)).unwrap());
+        assert_eq!(db.get_session(&session.id).unwrap().unwrap().max_output_tokens, None);
+    }
+
+    #[test]
+    fn sanitized_sync_params_drop_only_the_invalid_fields() {
+        let db = Database::new(Path::new(":memory:")).unwrap();
+        db.save_provider(&LLMProvider {
+            id: "p".to_string(),
+            name: "Provider".to_string(),
+            provider_type: "openai".to_string(),
+            base_url: None,
+            api_key: None,
+            enabled: true,
+            config: None,
+            created_at: 0,
+            updated_at: 0,
+        }).unwrap();
+        db.save_model(&LLMModel {
+            id: "p::small".to_string(),
+            provider_id: "p".to_string(),
+            name: "small".to_string(),
+            enabled: true,
+            config: Some(serde_json::json!({ "maxOutputTokens": 4096 })),
+        }).unwrap();
+
+        let params = CreateSessionParams {
+            id: Some("synced".to_string()),
+            title: "From sidecar".to_string(),
+            cwd: Some("/work".to_string()),
+            model: Some("p::gone".to_string()),
+            max_output_tokens: Some(-1),
+            permission_mode: Some("yolo".to_string()),
+            ..Default::default()
+        };
+        assert!(db.create_session(&params).is_err());
+        let session = db.create_session(&db.sanitize_create_params(&params)).unwrap();
+        assert_eq!((session.id.as_str(), session.cwd.as_deref()), ("synced", Some("/work")));
+        assert_eq!((session.model, session.max_output_tokens, session.permission_mode), (None, None, None));
+
+        let update = UpdateSessionParams {
+            title: Some("Renamed".to_string()),
+            model: Some("small".to_string()),
+            max_output_tokens: Some(Some(8192)),
+            permission_mode: Some("ask".to_string()),
+            ..Default::default()
+        };
+        assert!(db.update_session("synced", &update).is_err());
+        assert!(db.update_session("synced", &db.sanitize_update_params("synced", &update)).unwrap());
+        let stored = db.get_session("synced").unwrap().unwrap();
+        assert_eq!(stored.title, "Renamed");
+        assert_eq!(stored.model.as_deref(), Some("p::small"));
+        assert_eq!(stored.max_output_tokens, None);
+        assert_eq!(stored.permission_mode.as_deref(), Some("ask"));
+    }
+
+    #[test]
+    fn permission_mode_validated_and_persisted() {
+        let db = Database::new(Path::new(":memory:")).unwrap();
+        let session = db.create_session(&CreateSessionParams {
+            title: "Careful".to_string(),
+            permission_mode: Some("ask".to_string()),
+            ..Default::default()
+        }).unwrap();
+        assert_eq!(db.get_session(&session.id).unwrap().unwrap().permission_mode.as_deref(), Some("ask"));
+
+        let update = |mode: &str| db.update_session(&session.id, &UpdateSessionParams {
+            permission_mode: Some(mode.to_string()),
+            ..Default::default()
+        });
+        assert!(update("yolo").is_err());
+        assert!(update("default").unwrap());
+        assert_eq!(db.get_session(&session.id).unwrap().unwrap().permission_mode.as_deref(), Some("default"));
+        assert!(db.create_session(&CreateSessionParams {
+            title: "Bad".to_string(),
+            permission_mode: Some("auto".to_string()),
+            ..Default::default()
+        }).is_err());
+    }
+
+    #[test]
+    fn update_session_clamps_temperature_to_model_range() {
+        let db = Database::new(Path::new(":memory:")).unwrap();
+        db.save_provider(&LLMProvider {
+            id: "p".to_string(),
+            name: "Provider".to_string(),
+            provider_type: "openai".to_string(),
+            base_url: None,
+            api_key: None,
+            enabled: true,
+            config: None,
+            created_at: 0,
+            updated_at: 0,
+        }).unwrap();
+        db.save_model(&LLMModel {
+            id: "p::strict".to_string(),
+            provider_id: "p".to_string(),
+            name: "strict".to_string(),
+            enabled: true,
+            config: Some(serde_json::json!({ "maxTemperature": 1.0 })),
+        }).unwrap();
+        let session = create_test_session(&db, Some("p::strict"), Some(0.5));
+
+        db.update_session(&session.id, &UpdateSessionParams {
+            temperature: Some(1.5),
+            ..Default::default()
+        }).unwrap();
+        assert_eq!(db.get_session(&session.id).unwrap().unwrap().temperature, Some(1.0));
+
+        db.update_session(&session.id, &UpdateSessionParams {
+            temperature: Some(1.0),
+            ..Default::default()
+        }).unwrap();
+        assert_eq!(db.get_session(&session.id).unwrap().unwrap().temperature, Some(1.0));
+
+        db.update_session(&session.id, &UpdateSessionParams {
+            temperature: Some(-0.1),
+            ..Default::default()
+        }).unwrap();
+        assert_eq!(db.get_session(&session.id).unwrap().unwrap().temperature, Some(0.0));
+
+        let check = db.check_temperature(1.5, Some("p::strict")).unwrap();
+        assert_eq!((check.value, check.clamped, check.range), (Some(1.0), true, (0.0, 1.0)));
+        assert!(!db.check_temperature(1.0, Some("p::strict")).unwrap().clamped);
+        assert_eq!(db.check_temperature(f64::NAN, None).unwrap().value, None);
+    }
+
+    #[test]
+    fn reassign_model_moves_only_matching_sessions() {
+        let db = Database::new(Path::new(":memory:")).unwrap();
+        let old_a = create_test_session(&db, Some("old::m"), None);
+        let old_b = create_test_session(&db, Some("old::m"), None);
+        let other = create_test_session(&db, Some("other::m"), None);
+
+        let mut moved = db.reassign_model("old::m", "new::m").unwrap();
+        moved.sort();
+        let mut expected = vec![old_a.id.clone(), old_b.id.clone()];
+        expected.sort();
+        assert_eq!(moved, expected);
+        for id in [&old_a.id, &old_b.id] {
+            assert_eq!(db.get_session(id).unwrap().unwrap().model.as_deref(), Some("new::m"));
+        }
+        assert_eq!(db.get_session(&other.id).unwrap().unwrap().model.as_deref(), Some("other::m"));
+        assert!(db.reassign_model("old::m", "new::m").unwrap().is_empty());
+    }
+
+    #[test]
+    fn session_summary_aggregates_messages_tokens_and_cost() {
+        let db = Database::new(Path::new(":memory:")).unwrap();
+        db.save_provider(&LLMProvider {
+            id: "p".to_string(),
+            name: "Provider".to_string(),
+            provider_type: "openrouter".to_string(),
+            base_url: None,
+            api_key: None,
+            enabled: true,
+            config: None,
+            created_at: 0,
+            updated_at: 0,
+        }).unwrap();
+        db.save_model(&LLMModel {
+            id: "p::priced".to_string(),
+            provider_id: "p".to_string(),
+            name: "priced".to_string(),
+            enabled: true,
+            config: Some(serde_json::json!({ "inputPricePerMillion": 2.0, "outputPricePerMillion": 10.0 })),
+        }).unwrap();
+        let session = create_test_session(&db, Some("p::priced"), None);
+        db.record_message(&session.id, &serde_json::json!({ "type": "user_prompt", "prompt": "hi" })).unwrap();
+        db.record_message(&session.id, &serde_json::json!({ "type": "assistant", "text": "hello" })).unwrap();
+        db.update_tokens(&session.id, 500_000, 100_000).unwrap();
+        db.save_file_changes(&session.id, &[FileChange {
+            path: "a.txt".to_string(),
+            additions: 1,
+            deletions: 0,
+            status: None,
+        }]).unwrap();
+
+        let summary = db.get_session_summary(&session.id).unwrap().unwrap();
+        assert_eq!(summary.message_count, 2);
+        assert_eq!((summary.input_tokens, summary.output_tokens), (500_000, 100_000));
+        assert_eq!(summary.estimated_cost, Some(2.0));
+        assert_eq!(summary.file_change_count, 1);
+        assert!(summary.first_activity_at <= summary.last_activity_at);
+
+        let unpriced = create_test_session(&db, None, None);
+        let summary = db.get_session_summary(&unpriced.id).unwrap().unwrap();
+        assert_eq!((summary.message_count, summary.file_change_count), (0, 0));
+        assert_eq!(summary.estimated_cost, None);
+        assert!(db.get_session_summary("missing").unwrap().is_none());
+    }
+
+    #[test]
+    fn budget_status_sums_priced_sessions_and_honors_override() {
+        let db = Database::new(Path::new(":memory:")).unwrap();
+        db.save_provider(&LLMProvider {
+            id: "p".to_string(),
+            name: "Provider".to_string(),
+            provider_type: "openrouter".to_string(),
+            base_url: None,
+            api_key: None,
+            enabled: true,
+            config: None,
+            created_at: 0,
+            updated_at: 0,
+        }).unwrap();
+        db.save_model(&LLMModel {
+            id: "p::priced".to_string(),
+            provider_id: "p".to_string(),
+            name: "priced".to_string(),
+            enabled: true,
+            config: Some(serde_json::json!({ "inputPricePerMillion": 2.0, "outputPricePerMillion": 10.0 })),
+        }).unwrap();
+        for _ in 0..2 {
+            let session = create_test_session(&db, Some("p::priced"), None);
+            db.update_tokens(&session.id, 500_000, 100_000).unwrap();
         }
+        let unpriced = create_test_session(&db, None, None);
+        db.update_tokens(&unpriced.id, 1_000_000, 1_000_000).unwrap();
+
+        let status = db.get_budget_status(None).unwrap();
+        assert_eq!((status.cap_usd, status.spent_usd, status.exceeded), (None, 4.0, false));
+
+        db.set_spending_cap(Some(3.5)).unwrap();
+        assert!(db.get_budget_status(None).unwrap().blocks());
+        db.set_session_meta(&unpriced.id, BUDGET_OVERRIDE_META_KEY, "true").unwrap();
+        let status = db.get_budget_status(Some(&unpriced.id)).unwrap();
+        assert!(status.exceeded && status.overridden && !status.blocks());
+
+        // Past spend survives deleting history, moving sessions to a free model and repricing
+        let first = db.list_sessions().unwrap().into_iter().find(|s| s.model.as_deref() == Some("p::priced")).unwrap();
+        db.delete_session(&first.id).unwrap();
+        db.reassign_model("p::priced", "p::free").unwrap();
+        db.save_model(&LLMModel {
+            id: "p::priced".to_string(),
+            provider_id: "p".to_string(),
+            name: "priced".to_string(),
+            enabled: true,
+            config: Some(serde_json::json!({ "inputPricePerMillion": 0.0, "outputPricePerMillion": 0.0 })),
+        }).unwrap();
+        assert_eq!(db.get_budget_status(None).unwrap().spent_usd, 4.0);
+
+        assert!(db.set_spending_cap(Some(-1.0)).is_err());
+        db.set_spending_cap(None).unwrap();
+        assert_eq!(db.get_spending_cap().unwrap(), None);
     }
 
-    pub fn set_setting(&self, key: &str, value: &str) -> SqliteResult<()> {
-        let conn = self.conn.lock().unwrap();
-        let now = chrono::Utc::now().timestamp_millis();
-        conn.execute(
-            "INSERT OR REPLACE INTO settings (key, value, updated_at) VALUES (?1, ?2, ?3)",
-            params![key, value, now],
-        )?;
-        Ok(())
+    #[test]
+    fn model_config_keeps_other_fields_when_one_is_mistyped() {
+        let raw = serde_json::json!({
+            "contextLength": "128k",
+            "maxOutputTokens": 4096,
+            "inputPricePerMillion": 1.5,
+            "maxTemperature": 1.0
+        });
+        let mut config = ModelConfig::from_config(Some(&raw));
+        assert_eq!(config.context_length, None);
+        assert_eq!(config.max_output_tokens, Some(4096));
+        assert_eq!(config.input_price_per_million, Some(1.5));
+        assert_eq!(config.extra.get("maxTemperature"), Some(&serde_json::json!(1.0)));
+
+        // Saving it back loses nothing; a typed value replaces the mistyped one
+        assert_eq!(config.to_value(), raw);
+        config.context_length = Some(128_000);
+        assert_eq!(config.to_value()["contextLength"], 128_000);
     }
 
-    pub fn get_api_settings(&self) -> SqliteResult<Option<ApiSettings>> {
-        match self.get_setting("api_settings")? {
-            Some(json) => {
-                let settings: ApiSettings = serde_json::from_str(&json)
-                    .map_err(|e| rusqlite::Error::ToSqlConversionFailure(Box::new(e)))?;
-                Ok(Some(settings))
-            }
-            None => Ok(None),
+    #[test]
+    fn usage_summary_aggregates_per_model_with_cost() {
+        let db = Database::new(Path::new(":memory:")).unwrap();
+        db.save_provider(&LLMProvider {
+            id: "p".to_string(),
+            name: "Provider".to_string(),
+            provider_type: "openrouter".to_string(),
+            base_url: None,
+            api_key: None,
+            enabled: true,
+            config: None,
+            created_at: 0,
+            updated_at: 0,
+        }).unwrap();
+        for (id, input_price, output_price) in [("p::big", 3.0, 15.0), ("p::small", 0.5, 1.5)] {
+            db.save_model(&LLMModel {
+                id: id.to_string(),
+                provider_id: "p".to_string(),
+                name: id.to_string(),
+                enabled: true,
+                config: Some(serde_json::json!({ "inputPricePerMillion": input_price, "outputPricePerMillion": output_price })),
+            }).unwrap();
         }
-    }
+        let start = chrono::Utc::now().timestamp_millis();
 
-    pub fn save_api_settings(&self, settings: &ApiSettings) -> SqliteResult<()> {
-        let json = serde_json::to_string(settings)
-            .map_err(|e| rusqlite::Error::ToSqlConversionFailure(Box::new(e)))?;
-        self.set_setting("api_settings", &json)
+        // Increments via update_tokens
+        let big = create_test_session(&db, Some("p::big"), None);
+        db.update_tokens(&big.id, 200_000, 10_000).unwrap();
+        db.update_tokens(&big.id, 100_000, 20_000).unwrap();
+        // Running totals via session.sync; only the increase is recorded
+        let small = create_test_session(&db, Some("p::small"), None);
+        for (input, output) in [(400_000, 100_000), (1_000_000, 300_000), (1_000_000, 300_000)] {
+            db.update_session(&small.id, &UpdateSessionParams {
+                input_tokens: Some(input),
+                output_tokens: Some(output),
+                ..Default::default()
+            }).unwrap();
+        }
+        // Kept for spend tracking after the session is gone
+        db.delete_session(&big.id).unwrap();
+
+        let summary = db.usage_summary(start).unwrap();
+        assert_eq!(summary.len(), 2);
+        let small_usage = &summary[0];
+        assert_eq!(small_usage.model.as_deref(), Some("p::small"));
+        assert_eq!((small_usage.input_tokens, small_usage.output_tokens, small_usage.updates), (1_000_000, 300_000, 2));
+        assert!((small_usage.estimated_cost.unwrap() - 0.95).abs() < 1e-9);
+        let big_usage = &summary[1];
+        assert_eq!(big_usage.model.as_deref(), Some("p::big"));
+        assert_eq!((big_usage.input_tokens, big_usage.output_tokens, big_usage.updates), (300_000, 30_000, 2));
+        assert!((big_usage.estimated_cost.unwrap() - 1.35).abs() < 1e-9);
+
+        assert!(db.usage_summary(i64::MAX).unwrap().is_empty());
     }
 
-    // --- Scheduler Default Model ---
-    
-    pub fn get_scheduler_default_model(&self) -> SqliteResult<Option<String>> {
-        self.get_setting("scheduler_default_model")
+    #[test]
+    fn usage_csv_escapes_fields_and_filters_range() {
+        let db = Database::new(Path::new(":memory:")).unwrap();
+        let session = create_test_session(&db, Some("p::m"), None);
+        db.update_session(&session.id, &UpdateSessionParams { title: Some("Fix \"login\", again".to_string()), ..Default::default() }).unwrap();
+        db.update_tokens(&session.id, 120, 30).unwrap();
+
+        let mut out = Vec::new();
+        assert_eq!(db.export_usage_csv(None, None, &mut out).unwrap(), 1);
+        let csv = String::from_utf8(out).unwrap();
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines[0], "date,session_id,title,model,messages,input_tokens,output_tokens,estimated_cost_usd");
+        assert!(lines[1].ends_with(&format!(",{},\"Fix \"\"login\"\", again\",p::m,0,120,30,", session.id)));
+
+        let mut out = Vec::new();
+        assert_eq!(db.export_usage_csv(Some(i64::MAX - 1), None, &mut out).unwrap(), 0);
+
+        assert_eq!(csv_field("=HYPERLINK(\"http://x\")"), "\"'=HYPERLINK(\"\"http://x\"\")\"");
+        assert_eq!(csv_field("+1"), "'+1");
+        assert_eq!(csv_field("-2"), "'-2");
+        assert_eq!(csv_field("@SUM(A1)"), "'@SUM(A1)");
+        assert_eq!(csv_field("plain = text"), "plain = text");
     }
-    
-    pub fn set_scheduler_default_model(&self, model_id: &str) -> SqliteResult<()> {
-        self.set_setting("scheduler_default_model", model_id)
+
+    #[test]
+    fn semantic_search_ranks_by_cosine_similarity() {
+        let db = Database::new(Path::new(":memory:")).unwrap();
+        let session = create_test_session(&db, None, None);
+        for (id, text) in [("m-cats", "cats purr"), ("m-dogs", "dogs bark"), ("m-tax", "tax forms")] {
+            db.record_message(&session.id, &serde_json::json!({ "type": "user_prompt", "uuid": id, "prompt": text })).unwrap();
+        }
+        db.record_message(&session.id, &serde_json::json!({ "type": "tool_use", "uuid": "m-tool" })).unwrap();
+
+        let pending = db.messages_missing_embeddings("emb", 10).unwrap();
+        assert_eq!(pending.iter().map(|(id, _, _)| id.as_str()).collect::<Vec<_>>(), vec!["m-cats", "m-dogs", "m-tax"]);
+        db.save_message_embedding("m-cats", &session.id, "emb", &[1.0, 0.0, 0.0]).unwrap();
+        db.save_message_embedding("m-dogs", &session.id, "emb", &[0.8, 0.6, 0.0]).unwrap();
+        db.save_message_embedding("m-tax", &session.id, "emb", &[0.0, 0.0, 1.0]).unwrap();
+        assert!(db.messages_missing_embeddings("emb", 10).unwrap().is_empty());
+        assert_eq!(db.messages_missing_embeddings("other-model", 10).unwrap().len(), 3);
+
+        let hits = db.semantic_search("emb", &[1.0, 0.1, 0.0], 2).unwrap();
+        assert_eq!(hits.iter().map(|h| h.message_id.as_str()).collect::<Vec<_>>(), vec!["m-cats", "m-dogs"]);
+        assert!(hits[0].score > 0.99 && hits[1].score < hits[0].score);
+        assert_eq!(hits[0].snippet, "cats purr");
+
+        assert_eq!(cosine_similarity(&[1.0, 0.0], &[0.0, 1.0]), Some(0.0));
+        assert_eq!(cosine_similarity(&[1.0], &[1.0, 0.0]), None);
+        assert_eq!(cosine_similarity(&[0.0, 0.0], &[1.0, 0.0]), None);
+
+        db.delete_session(&session.id).unwrap();
+        assert!(db.semantic_search("emb", &[1.0, 0.0, 0.0], 5).unwrap().is_empty());
     }
 
-    // --- Providers ---
+    #[test]
+    fn search_messages_uses_fts_and_like_fallback() {
+        let db = Database::new(Path::new(":memory:")).unwrap();
+        let session = create_test_session(&db, None, None);
+        db.record_message(&session.id, &serde_json::json!({ "type": "user_prompt", "prompt": "How do I rotate the Postgres credentials?", "uuid": "q" })).unwrap();
+        db.record_message(&session.id, &serde_json::json!({ "type": "text", "text": "Use the vault CLI to rotate them.", "uuid": "a" })).unwrap();
+        db.record_message(&session.id, &serde_json::json!({ "type": "tool_use", "name": "rotate", "input": {}, "uuid": "t" })).unwrap();
 
-    pub fn list_providers(&self) -> SqliteResult<Vec<LLMProvider>> {
-        let conn = self.conn.lock().unwrap();
-        let mut stmt = conn.prepare(
-            "SELECT id, name, type, base_url, api_key, enabled, config, created_at, updated_at FROM providers ORDER BY name"
-        )?;
+        let hits = db.search_messages("rotate", 10).unwrap();
+        let mut ids: Vec<&str> = hits.iter().map(|h| h.message_id.as_str()).collect();
+        ids.sort();
+        assert_eq!(ids, vec!["a", "q"]);
+        assert!(hits.iter().all(|h| h.snippet.contains("<mark>") && h.session_title == "Test"));
+        assert_eq!(db.search_messages("postgres credentials", 10).unwrap().len(), 1);
+        assert!(db.search_messages("\"unbalanced", 10).is_ok());
 
-        let rows = stmt.query_map([], |row| {
-            let config_str: Option<String> = row.get(6)?;
-            let config = config_str.and_then(|s| serde_json::from_str(&s).ok());
-            
-            Ok(LLMProvider {
-                id: row.get(0)?,
-                name: row.get(1)?,
-                provider_type: row.get(2)?,
-                base_url: row.get(3)?,
-                api_key: row.get(4)?,
-                enabled: row.get::<_, i32>(5)? != 0,
-                config,
-                created_at: row.get(7)?,
-                updated_at: row.get(8)?,
-            })
-        })?;
+        // Message text is escaped; only the highlight is markup
+        db.record_message(&session.id, &serde_json::json!({ "type": "text", "text": "<img src=x onerror=alert(1)> payload", "uuid": "x" })).unwrap();
+        let hits = db.search_messages("payload", 10).unwrap();
+        assert_eq!(hits[0].snippet, "&lt;img src=x onerror=alert(1)&gt; <mark>payload</mark>");
 
-        rows.collect()
+        // Index follows deletes
+        db.truncate_history_after(&session.id, 0).unwrap();
+        assert_eq!(db.search_messages("vault", 10).unwrap().len(), 0);
+
+        // Without the FTS table the LIKE scan answers the same question
+        db.conn().unwrap().execute_batch(
+            "DROP TRIGGER messages_fts_insert; DROP TRIGGER messages_fts_delete; DROP TRIGGER messages_fts_update; DROP TABLE messages_fts;"
+        ).unwrap();
+        let hits = db.search_messages("POSTGRES", 10).unwrap();
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].snippet, "How do I rotate the <mark>Postgres</mark> credentials?");
+        assert_eq!(highlight_snippet("a <b> & c", "&"), "a &lt;b&gt; <mark>&amp;</mark> c");
     }
 
-    pub fn save_provider(&self, provider: &LLMProvider) -> SqliteResult<()> {
-        let conn = self.conn.lock().unwrap();
-        let now = chrono::Utc::now().timestamp_millis();
-        let config_json = provider.config.as_ref().map(|c| serde_json::to_string(c).unwrap_or_default());
+    #[test]
+    fn list_tool_invocations_counts_and_orders_calls() {
+        let db = Database::new(Path::new(":memory:")).unwrap();
+        let session = create_test_session(&db, None, None);
+        for (i, name) in ["read_file", "run_command", "read_file"].iter().enumerate() {
+            db.record_message(&session.id, &serde_json::json!({ "type": "tool_use", "id": format!("call_{}", i), "name": name, "input": {}, "uuid": format!("tool_call_{}", i) })).unwrap();
+            std::thread::sleep(std::time::Duration::from_millis(2));
+        }
+        db.record_message(&session.id, &serde_json::json!({ "type": "text", "text": "done" })).unwrap();
 
-        conn.execute(
-            r#"INSERT OR REPLACE INTO providers (id, name, type, base_url, api_key, enabled, config, created_at, updated_at)
-               VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, COALESCE((SELECT created_at FROM providers WHERE id = ?1), ?8), ?9)"#,
-            params![
-                &provider.id,
-                &provider.name,
-                &provider.provider_type,
-                &provider.base_url,
-                &provider.api_key,
-                if provider.enabled { 1 } else { 0 },
-                &config_json,
-                now,
-                now
-            ],
-        )?;
-        Ok(())
+        let report = db.list_tool_invocations(&session.id).unwrap().unwrap();
+        let counts: Vec<(&str, i64)> = report.counts.iter().map(|c| (c.name.as_str(), c.count)).collect();
+        assert_eq!(counts, vec![("read_file", 2), ("run_command", 1)]);
+        let timeline: Vec<&str> = report.timeline.iter().map(|t| t.name.as_str()).collect();
+        assert_eq!(timeline, vec!["read_file", "run_command", "read_file"]);
+        assert_eq!(report.timeline[1].tool_use_id.as_deref(), Some("call_1"));
+        assert!(db.list_tool_invocations("missing").unwrap().is_none());
     }
 
-    pub fn delete_provider(&self, id: &str) -> SqliteResult<bool> {
-        let conn = self.conn.lock().unwrap();
-        // Delete associated models first
-        conn.execute("DELETE FROM models WHERE provider_id = ?1", [id])?;
-        let changed = conn.execute("DELETE FROM providers WHERE id = ?1", [id])?;
-        Ok(changed > 0)
+    #[test]
+    fn session_text_stats_counts_prompts_and_replies_only() {
+        let db = Database::new(Path::new(":memory:")).unwrap();
+        let session = create_test_session(&db, None, None);
+        db.record_message(&session.id, &serde_json::json!({ "type": "user_prompt", "prompt": "Write a haiku" })).unwrap();
+        db.record_message(&session.id, &serde_json::json!({ "type": "user", "message": { "content": [{ "type": "tool_result", "content": "ignored words here" }] } })).unwrap();
+        db.record_message(&session.id, &serde_json::json!({ "type": "system", "text": "ignored" })).unwrap();
+        db.record_message(&session.id, &serde_json::json!({ "type": "text", "text": "Тихий пруд, лягушка" })).unwrap();
+
+        let stats = db.get_session_text_stats(&session.id).unwrap().unwrap();
+        assert_eq!((stats.user_messages, stats.assistant_messages), (1, 1));
+        assert_eq!(stats.words, 6);
+        assert_eq!(stats.characters, 13 + 19);
+        assert_eq!(stats.reading_minutes, 1);
+        assert!(db.get_session_text_stats("missing").unwrap().is_none());
     }
 
-    // --- Models ---
+    #[test]
+    fn preview_prune_respects_keep_last_and_budget_without_deleting() {
+        let db = Database::new(Path::new(":memory:")).unwrap();
+        let session = create_test_session(&db, None, None);
+        for i in 0..5 {
+            db.record_message(&session.id, &serde_json::json!({ "type": "user_prompt", "prompt": format!("message {}", i), "uuid": format!("m{}", i) })).unwrap();
+            std::thread::sleep(std::time::Duration::from_millis(2));
+        }
 
-    pub fn list_models(&self) -> SqliteResult<Vec<LLMModel>> {
-        let conn = self.conn.lock().unwrap();
-        let mut stmt = conn.prepare(
-            "SELECT id, provider_id, name, enabled, config FROM models ORDER BY name"
-        )?;
+        let ids = |candidates: Vec<PruneCandidate>| candidates.into_iter().map(|c| c.id).collect::<Vec<_>>();
+        assert_eq!(ids(db.preview_prune(&session.id, 2, None).unwrap()), vec!["m0", "m1", "m2"]);
 
-        let rows = stmt.query_map([], |row| {
-            let config_str: Option<String> = row.get(4)?;
-            let config = config_str.and_then(|s| serde_json::from_str(&s).ok());
-            
-            Ok(LLMModel {
-                id: row.get(0)?,
-                provider_id: row.get(1)?,
-                name: row.get(2)?,
-                enabled: row.get::<_, i32>(3)? != 0,
-                config,
-            })
-        })?;
+        let per_message = db.preview_prune(&session.id, 0, None).unwrap()[0].estimated_tokens;
+        assert_eq!(ids(db.preview_prune(&session.id, 1, Some(per_message * 3)).unwrap()), vec!["m0", "m1"]);
+        // keep_last_n wins over a budget that is too small
+        assert_eq!(ids(db.preview_prune(&session.id, 2, Some(0)).unwrap()), vec!["m0", "m1", "m2"]);
 
-        rows.collect()
+        let first = &db.preview_prune(&session.id, 4, None).unwrap()[0];
+        assert_eq!((first.message_type.as_str(), first.snippet.as_str()), ("user_prompt", "message 0"));
+        assert_eq!(db.get_session_messages(&session.id).unwrap().len(), 5);
     }
 
-    pub fn list_models_by_provider(&self, provider_id: &str) -> SqliteResult<Vec<LLMModel>> {
-        let conn = self.conn.lock().unwrap();
-        let mut stmt = conn.prepare(
-            "SELECT id, provider_id, name, enabled, config FROM models WHERE provider_id = ?1 ORDER BY name"
-        )?;
+    #[test]
+    fn trim_history_keeps_first_and_recent_without_touching_rows() {
+        let db = Database::new(Path::new(":memory:")).unwrap();
+        let session = create_test_session(&db, None, None);
+        let count = AUTO_PRUNE_KEEP_LAST + 5;
+        for i in 0..count {
+            db.record_message(&session.id, &serde_json::json!({ "type": "user_prompt", "prompt": format!("message {:02}", i), "uuid": format!("m{:02}", i) })).unwrap();
+            std::thread::sleep(std::time::Duration::from_millis(2));
+        }
+        let messages = db.get_session_messages(&session.id).unwrap();
+        let per_message = estimate_message_tokens(&messages[0]);
 
-        let rows = stmt.query_map([provider_id], |row| {
-            let config_str: Option<String> = row.get(4)?;
-            let config = config_str.and_then(|s| serde_json::from_str(&s).ok());
-            
-            Ok(LLMModel {
-                id: row.get(0)?,
-                provider_id: row.get(1)?,
-                name: row.get(2)?,
-                enabled: row.get::<_, i32>(3)? != 0,
-                config,
-            })
-        })?;
+        // Room for everything: nothing goes
+        let mut all = messages.clone();
+        assert_eq!(trim_history_to_budget(&mut all, per_message * count as i64), 0);
+        assert_eq!(all.len(), count);
+        // Room for first + recent + 2 more
+        let mut trimmed = messages.clone();
+        assert_eq!(trim_history_to_budget(&mut trimmed, per_message * (AUTO_PRUNE_KEEP_LAST as i64 + 3)), 2);
+        assert_eq!(trimmed[0]["uuid"], "m00");
+        assert_eq!(trimmed[1]["uuid"], "m03");
+        // A budget too small for anything still keeps first + recent
+        let mut minimal = messages;
+        assert_eq!(trim_history_to_budget(&mut minimal, 0), 4);
+        assert_eq!(minimal.len(), AUTO_PRUNE_KEEP_LAST + 1);
+        assert_eq!(db.get_session_messages(&session.id).unwrap().len(), count);
 
-        rows.collect()
+        // Off unless configured
+        assert_eq!(db.get_max_context_tokens().unwrap(), None);
+        db.set_setting(MAX_CONTEXT_TOKENS_SETTING, "5000").unwrap();
+        assert_eq!(db.get_max_context_tokens().unwrap(), Some(5000));
     }
 
-    pub fn save_model(&self, model: &LLMModel) -> SqliteResult<()> {
-        let conn = self.conn.lock().unwrap();
-        let config_json = model.config.as_ref().map(|c| serde_json::to_string(c).unwrap_or_default());
+    #[test]
+    fn message_token_estimate_skips_attachments() {
+        let text = serde_json::json!({ "type": "user_prompt", "prompt": "abcdefgh" });
+        let image = format!("data:image/png;base64,{}", "A".repeat(4 * ATTACHMENT_MIN_CHARS));
+        let with_attachments = serde_json::json!({
+            "type": "user_prompt",
+            "prompt": "abcdefgh",
+            "images": [image, format!("{}abc.b64", crate::blobs::BLOB_REF_PREFIX)]
+        });
+        assert_eq!(estimate_message_tokens(&text), estimate_message_tokens(&with_attachments));
+        // Only string contents count, not keys or JSON punctuation
+        assert_eq!(estimate_message_tokens(&serde_json::json!({ "prompt": "abcdefgh" })), 2);
+    }
 
-        conn.execute(
-            "INSERT OR REPLACE INTO models (id, provider_id, name, enabled, config) VALUES (?1, ?2, ?3, ?4, ?5)",
-            params![
-                &model.id,
-                &model.provider_id,
-                &model.name,
-                if model.enabled { 1 } else { 0 },
-                &config_json
-            ],
-        )?;
-        Ok(())
+    #[test]
+    fn reset_session_status_validates_target() {
+        let db = Database::new(Path::new(":memory:")).unwrap();
+        let session = create_test_session(&db, None, None);
+        db.update_session(&session.id, &UpdateSessionParams { status: Some("running".to_string()), ..Default::default() }).unwrap();
+
+        assert!(db.reset_session_status(&session.id, "idle").unwrap());
+        assert_eq!(db.get_session(&session.id).unwrap().unwrap().status, "idle");
+        assert!(db.reset_session_status(&session.id, "stuck").unwrap_err().to_string().contains("Unknown session status"));
+        assert!(!db.reset_session_status("missing", "idle").unwrap());
     }
 
-    pub fn save_models_bulk(&self, models: &[LLMModel]) -> SqliteResult<()> {
-        let conn = self.conn.lock().unwrap();
-        
-        for model in models {
-            let config_json = model.config.as_ref().map(|c| serde_json::to_string(c).unwrap_or_default());
-            conn.execute(
-                "INSERT OR REPLACE INTO models (id, provider_id, name, enabled, config) VALUES (?1, ?2, ?3, ?4, ?5)",
-                params![
-                    &model.id,
-                    &model.provider_id,
-                    &model.name,
-                    if model.enabled { 1 } else { 0 },
-                    &config_json
-                ],
-            )?;
+    #[test]
+    fn evict_oldest_sessions_skips_pinned_locked_and_running() {
+        let db = Database::new(Path::new(":memory:")).unwrap();
+        let ids: Vec<String> = (0..5).map(|_| create_test_session(&db, None, None).id).collect();
+        for (i, id) in ids.iter().enumerate() {
+            db.conn().unwrap().execute("UPDATE sessions SET updated_at = ?1 WHERE id = ?2", params![i as i64, id]).unwrap();
         }
-        Ok(())
+        db.set_pinned(&ids[0], true).unwrap();
+        db.set_locked(&ids[1], true).unwrap();
+        db.conn().unwrap().execute("UPDATE sessions SET status = 'running' WHERE id = ?1", [&ids[2]]).unwrap();
+        db.record_message(&ids[3], &serde_json::json!({ "type": "user_prompt", "prompt": "hi" })).unwrap();
+
+        assert_eq!(db.get_max_sessions().unwrap(), None);
+        db.set_max_sessions(Some(3)).unwrap();
+        assert_eq!(db.get_max_sessions().unwrap(), Some(3));
+
+        let evicted = db.evict_oldest_sessions(3, Some(&ids[4])).unwrap();
+        assert_eq!(evicted, vec![ids[3].clone()]);
+        // Archived, not deleted: the history survives
+        assert!(db.get_session(&ids[3]).unwrap().unwrap().is_archived);
+        assert_eq!(db.get_session_messages(&ids[3]).unwrap().len(), 1);
+        assert_eq!(db.list_sessions().unwrap().len(), 4);
+        assert!(db.evict_oldest_sessions(10, None).unwrap().is_empty());
     }
 
-    pub fn delete_models_by_provider(&self, provider_id: &str) -> SqliteResult<()> {
-        let conn = self.conn.lock().unwrap();
-        conn.execute("DELETE FROM models WHERE provider_id = ?1", [provider_id])?;
-        Ok(())
+    #[test]
+    fn evict_oldest_sessions_ignores_archived_sessions() {
+        let db = Database::new(Path::new(":memory:")).unwrap();
+        let ids: Vec<String> = (0..4).map(|_| create_test_session(&db, None, None).id).collect();
+        for (i, id) in ids.iter().enumerate() {
+            db.conn().unwrap().execute("UPDATE sessions SET updated_at = ?1 WHERE id = ?2", params![i as i64, id]).unwrap();
+        }
+        db.set_archived(&ids[0], true).unwrap();
+        db.set_archived(&ids[1], true).unwrap();
+
+        // Two live sessions under a cap of two: archiving must not push them out
+        assert!(db.evict_oldest_sessions(2, None).unwrap().is_empty());
+        assert!(db.get_session(&ids[2]).unwrap().is_some());
+
+        let evicted = db.evict_oldest_sessions(1, None).unwrap();
+        assert_eq!(evicted, vec![ids[2].clone()]);
+        assert!(db.get_session(&ids[0]).unwrap().unwrap().is_archived);
     }
 
-    // --- Combined providers + models ---
+    #[test]
+    fn last_active_session_is_cleared_when_stale() {
+        let db = Database::new(Path::new(":memory:")).unwrap();
+        let session = create_test_session(&db, None, None);
 
-    pub fn get_llm_provider_settings(&self) -> SqliteResult<LLMProviderSettings> {
-        Ok(LLMProviderSettings {
-            providers: self.list_providers()?,
-            models: self.list_models()?,
-        })
+        assert!(!db.set_last_active_session("missing").unwrap());
+        assert_eq!(db.get_last_active_session().unwrap(), None);
+        assert!(db.set_last_active_session(&session.id).unwrap());
+        assert_eq!(db.get_last_active_session().unwrap(), Some(session.id.clone()));
+
+        db.delete_session(&session.id).unwrap();
+        assert_eq!(db.get_last_active_session().unwrap(), None);
+        assert_eq!(db.get_setting(LAST_ACTIVE_SESSION_SETTING).unwrap(), None);
     }
 
-    pub fn save_llm_provider_settings(&self, settings: &LLMProviderSettings) -> SqliteResult<()> {
-        let conn = self.conn.lock().unwrap();
-        
-        // Get IDs of providers to keep
-        let provider_ids: Vec<&str> = settings.providers.iter().map(|p| p.id.as_str()).collect();
-        
-        // Delete providers not in the new list
-        if !provider_ids.is_empty() {
-            let placeholders: Vec<String> = (1..=provider_ids.len()).map(|i| format!("?{}", i)).collect();
-            let sql = format!("DELETE FROM providers WHERE id NOT IN ({})", placeholders.join(", "));
-            let params: Vec<&dyn rusqlite::ToSql> = provider_ids.iter().map(|id| id as &dyn rusqlite::ToSql).collect();
-            conn.execute(&sql, params.as_slice())?;
-        } else {
-            // No providers - delete all
-            conn.execute("DELETE FROM providers", [])?;
-        }
-        
-        // Get IDs of models to keep
-        let model_ids: Vec<&str> = settings.models.iter().map(|m| m.id.as_str()).collect();
-        
-        // Delete models not in the new list
-        if !model_ids.is_empty() {
-            let placeholders: Vec<String> = (1..=model_ids.len()).map(|i| format!("?{}", i)).collect();
-            let sql = format!("DELETE FROM models WHERE id NOT IN ({})", placeholders.join(", "));
-            let params: Vec<&dyn rusqlite::ToSql> = model_ids.iter().map(|id| id as &dyn rusqlite::ToSql).collect();
-            conn.execute(&sql, params.as_slice())?;
-        } else {
-            // No models - delete all
-            conn.execute("DELETE FROM models", [])?;
-        }
-        
-        drop(conn); // Release lock before calling other methods
-        
-        // Save providers
-        for provider in &settings.providers {
-            self.save_provider(provider)?;
+    #[test]
+    fn unfinished_session_is_detected_from_last_message() {
+        let db = Database::new(Path::new(":memory:")).unwrap();
+        assert!(!db.get_resume_unfinished_on_launch().unwrap());
+        db.set_resume_unfinished_on_launch(true).unwrap();
+        assert!(db.get_resume_unfinished_on_launch().unwrap());
+
+        let done = create_test_session(&db, None, None);
+        let pending_tool = create_test_session(&db, None, None);
+        let unanswered = create_test_session(&db, None, None);
+        for (i, id) in [&done.id, &pending_tool.id, &unanswered.id].into_iter().enumerate() {
+            db.conn().unwrap().execute("UPDATE sessions SET updated_at = ?1 WHERE id = ?2", params![i as i64, id]).unwrap();
         }
-        
-        // Save models
-        self.save_models_bulk(&settings.models)?;
-        
-        Ok(())
+        db.record_message(&done.id, &serde_json::json!({"type": "user_prompt", "prompt": "hi"})).unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(2));
+        db.record_message(&done.id, &serde_json::json!({"type": "result", "subtype": "success"})).unwrap();
+        db.record_message(&pending_tool.id, &serde_json::json!({
+            "type": "assistant",
+            "message": {"content": [{"type": "tool_use", "id": "t1", "name": "read_file"}]}
+        })).unwrap();
+        assert_eq!(db.find_unfinished_session().unwrap(), Some(pending_tool.id.clone()));
+
+        // Newest wins; an unanswered prompt counts, archived sessions don't
+        db.record_message(&unanswered.id, &serde_json::json!({"type": "user_prompt", "prompt": "go"})).unwrap();
+        assert_eq!(db.find_unfinished_session().unwrap(), Some(unanswered.id.clone()));
+        db.set_archived(&unanswered.id, true).unwrap();
+        assert_eq!(db.find_unfinished_session().unwrap(), Some(pending_tool.id.clone()));
+
+        db.delete_session(&pending_tool.id).unwrap();
+        assert_eq!(db.find_unfinished_session().unwrap(), None);
+        db.save_stream_draft(&done.id, "partial answer").unwrap();
+        assert_eq!(db.find_unfinished_session().unwrap(), Some(done.id.clone()));
     }
-}
 
-// ============ Scheduled Tasks ============
+    #[test]
+    fn raw_message_lookup_by_uuid_or_index() {
+        let db = Database::new(Path::new(":memory:")).unwrap();
+        let session = create_test_session(&db, None, None);
+        let other = create_test_session(&db, None, None);
+        let first = serde_json::json!({"type": "user_prompt", "prompt": "hi", "uuid": "m1"});
+        let second = serde_json::json!({"type": "text", "text": "hello", "uuid": "m2", "unexpected": {"nested": true}});
+        db.record_message(&session.id, &first).unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(2));
+        db.record_message(&session.id, &second).unwrap();
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-#[serde(rename_all = "camelCase")]
-pub struct ScheduledTask {
-    pub id: String,
-    pub title: String,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub prompt: Option<String>,
-    pub schedule: String,
-    pub next_run: i64,
-    pub is_recurring: bool,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub notify_before: Option<i64>,
-    pub enabled: bool,
-    pub created_at: i64,
-    pub updated_at: i64,
-}
+        let raw = db.get_message_raw(&session.id, &MessageRef::Uuid("m2".to_string())).unwrap().unwrap();
+        assert_eq!(raw, serde_json::to_string(&second).unwrap());
+        assert_eq!(db.get_message_raw(&session.id, &MessageRef::Index(0)).unwrap(), Some(serde_json::to_string(&first).unwrap()));
+        assert_eq!(db.get_message_raw(&session.id, &MessageRef::Index(1)).unwrap(), Some(raw));
+        assert_eq!(db.get_message_raw(&session.id, &MessageRef::Index(2)).unwrap(), None);
+        assert_eq!(db.get_message_raw(&other.id, &MessageRef::Uuid("m1".to_string())).unwrap(), None);
+    }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-#[serde(rename_all = "camelCase")]
-pub struct CreateScheduledTaskParams {
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub id: Option<String>,
-    pub title: String,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub prompt: Option<String>,
-    pub schedule: String,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub notify_before: Option<i64>,
-}
+    #[test]
+    fn session_content_hash_is_stable_until_the_session_changes() {
+        let db = Database::new(Path::new(":memory:")).unwrap();
+        let session = create_test_session(&db, None, None);
+        let other = create_test_session(&db, None, None);
 
-#[derive(Debug, Clone, Serialize, Deserialize, Default)]
-#[serde(rename_all = "camelCase")]
-pub struct UpdateScheduledTaskParams {
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub title: Option<String>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub prompt: Option<String>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub schedule: Option<String>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub next_run: Option<i64>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub is_recurring: Option<bool>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub notify_before: Option<i64>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub enabled: Option<bool>,
-}
+        let empty = db.session_content_hash(&session.id).unwrap().unwrap();
+        assert_eq!(empty.len(), 64);
+        assert!(empty.chars().all(|c| c.is_ascii_hexdigit()));
+        assert_eq!(db.session_content_hash(&session.id).unwrap().unwrap(), empty);
+        assert_ne!(db.session_content_hash(&other.id).unwrap().unwrap(), empty);
+        assert_eq!(db.session_content_hash("missing").unwrap(), None);
+
+        db.record_message(&session.id, &serde_json::json!({ "type": "user_prompt", "uuid": "m1", "prompt": "hi" })).unwrap();
+        let with_message = db.session_content_hash(&session.id).unwrap().unwrap();
+        assert_ne!(with_message, empty);
+        assert_eq!(db.session_content_hash(&session.id).unwrap().unwrap(), with_message);
+
+        db.update_session(&session.id, &UpdateSessionParams { title: Some("Renamed".to_string()), ..Default::default() }).unwrap();
+        assert_ne!(db.session_content_hash(&session.id).unwrap().unwrap(), with_message);
+    }
 
-impl Database {
-    // --- Scheduled Tasks ---
+    #[test]
+    fn session_metadata_roundtrip_and_history() {
+        let db = Database::new(Path::new(":memory:")).unwrap();
+        let session = create_test_session(&db, None, None);
 
-    pub fn create_scheduled_task(&self, params: &CreateScheduledTaskParams, next_run: i64, is_recurring: bool) -> SqliteResult<ScheduledTask> {
-        let conn = self.conn.lock().unwrap();
-        let id = params.id.clone().unwrap_or_else(|| uuid::Uuid::new_v4().to_string());
-        let now = chrono::Utc::now().timestamp_millis();
+        assert!(db.set_session_meta(&session.id, " ticket ", "JIRA-42").unwrap());
+        assert!(db.set_session_meta(&session.id, "category", "bug").unwrap());
+        assert!(db.set_session_meta(&session.id, "category", "feature").unwrap());
+        assert!(!db.set_session_meta("missing", "ticket", "x").unwrap());
+        assert!(db.set_session_meta(&session.id, " ", "x").is_err());
 
-        conn.execute(
-            r#"INSERT INTO scheduled_tasks 
-               (id, title, prompt, schedule, next_run, is_recurring, notify_before, enabled, created_at, updated_at)
-               VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, 1, ?8, ?9)"#,
-            params![
-                &id,
-                &params.title,
-                &params.prompt,
-                &params.schedule,
-                next_run,
-                if is_recurring { 1 } else { 0 },
-                &params.notify_before,
-                now,
-                now
-            ],
-        )?;
+        assert_eq!(db.get_session_meta(&session.id, "ticket").unwrap().as_deref(), Some("JIRA-42"));
+        let history = db.get_session_history(&session.id).unwrap().unwrap();
+        assert_eq!(history.metadata.get("category").map(String::as_str), Some("feature"));
+        assert_eq!(history.metadata.len(), 2);
 
-        Ok(ScheduledTask {
-            id,
-            title: params.title.clone(),
-            prompt: params.prompt.clone(),
-            schedule: params.schedule.clone(),
-            next_run,
-            is_recurring,
-            notify_before: params.notify_before,
-            enabled: true,
-            created_at: now,
-            updated_at: now,
-        })
+        assert!(db.delete_session_meta(&session.id, "ticket").unwrap());
+        assert_eq!(db.get_session_meta(&session.id, "ticket").unwrap(), None);
+        db.delete_session(&session.id).unwrap();
+        assert!(db.list_session_meta(&session.id).unwrap().is_empty());
     }
 
-    pub fn get_scheduled_task(&self, id: &str) -> SqliteResult<Option<ScheduledTask>> {
-        let conn = self.conn.lock().unwrap();
-        let mut stmt = conn.prepare(
-            r#"SELECT id, title, prompt, schedule, next_run, is_recurring, notify_before, enabled, created_at, updated_at
-               FROM scheduled_tasks WHERE id = ?1"#
-        )?;
+    #[test]
+    fn dictation_metrics_are_averaged_per_model_and_transport() {
+        let db = Database::new(Path::new(":memory:")).unwrap();
+        let sample = |model: &str, transport: &str, first: Option<i64>, total: i64| DictationMetrics {
+            session_id: "s1".to_string(),
+            model: Some(model.to_string()),
+            transport: transport.to_string(),
+            first_partial_ms: first,
+            final_latency_ms: total,
+        };
+        db.add_dictation_metrics(&sample("whisper", "multipart", Some(1_000), 3_000)).unwrap();
+        db.add_dictation_metrics(&sample("whisper", "multipart", None, 2_000)).unwrap();
+        db.add_dictation_metrics(&sample("whisper", "websocket", Some(200), 900)).unwrap();
 
-        let mut rows = stmt.query_map([id], |row| {
-            Ok(ScheduledTask {
-                id: row.get(0)?,
-                title: row.get(1)?,
-                prompt: row.get(2)?,
-                schedule: row.get(3)?,
-                next_run: row.get(4)?,
-                is_recurring: row.get::<_, i32>(5)? != 0,
-                notify_before: row.get(6)?,
-                enabled: row.get::<_, i32>(7)? != 0,
-                created_at: row.get(8)?,
-                updated_at: row.get(9)?,
-            })
-        })?;
+        let summary = db.dictation_metrics_summary(0).unwrap();
+        assert_eq!(summary.len(), 2);
+        assert_eq!(summary[0], DictationMetricsSummary {
+            model: Some("whisper".to_string()),
+            transport: "multipart".to_string(),
+            dictations: 2,
+            avg_first_partial_ms: Some(1_000.0),
+            avg_final_latency_ms: 2_500.0,
+            max_final_latency_ms: 3_000,
+        });
+        assert_eq!(summary[1].avg_first_partial_ms, Some(200.0));
+        assert!(db.dictation_metrics_summary(i64::MAX).unwrap().is_empty());
+    }
 
-        match rows.next() {
-            Some(result) => Ok(Some(result?)),
-            None => Ok(None),
-        }
+    #[test]
+    fn voice_usage_accumulates() {
+        let db = Database::new(Path::new(":memory:")).unwrap();
+        assert_eq!(db.get_voice_usage("s1").unwrap().requests, 0);
+
+        db.add_voice_usage("s1", 1.5, 2).unwrap();
+        db.add_voice_usage("s1", 3.0, 1).unwrap();
+        let usage = db.get_voice_usage("s1").unwrap();
+        assert_eq!(usage.requests, 3);
+        assert!((usage.audio_seconds - 4.5).abs() < 1e-9);
     }
 
-    pub fn list_scheduled_tasks(&self, include_disabled: bool) -> SqliteResult<Vec<ScheduledTask>> {
-        let conn = self.conn.lock().unwrap();
-        let query = if include_disabled {
-            "SELECT id, title, prompt, schedule, next_run, is_recurring, notify_before, enabled, created_at, updated_at FROM scheduled_tasks ORDER BY next_run ASC"
-        } else {
-            "SELECT id, title, prompt, schedule, next_run, is_recurring, notify_before, enabled, created_at, updated_at FROM scheduled_tasks WHERE enabled = 1 ORDER BY next_run ASC"
+    #[test]
+    fn dangling_models_are_listed_and_repaired() {
+        let db = Database::new(Path::new(":memory:")).unwrap();
+        let provider = |id: &str| LLMProvider {
+            id: id.to_string(),
+            name: id.to_string(),
+            provider_type: "openai".to_string(),
+            base_url: None,
+            api_key: None,
+            enabled: true,
+            config: None,
+            created_at: 0,
+            updated_at: 0,
         };
-        
-        let mut stmt = conn.prepare(query)?;
-        let rows = stmt.query_map([], |row| {
-            Ok(ScheduledTask {
-                id: row.get(0)?,
-                title: row.get(1)?,
-                prompt: row.get(2)?,
-                schedule: row.get(3)?,
-                next_run: row.get(4)?,
-                is_recurring: row.get::<_, i32>(5)? != 0,
-                notify_before: row.get(6)?,
-                enabled: row.get::<_, i32>(7)? != 0,
-                created_at: row.get(8)?,
-                updated_at: row.get(9)?,
-            })
-        })?;
+        let model = |id: &str, provider_id: &str| LLMModel {
+            id: id.to_string(),
+            provider_id: provider_id.to_string(),
+            name: id.to_string(),
+            enabled: true,
+            config: None,
+        };
+        db.save_provider(&provider("live")).unwrap();
+        db.save_model(&model("ok", "live")).unwrap();
+        // Older DBs were written without foreign key enforcement
+        db.conn().unwrap().execute_batch("PRAGMA foreign_keys = OFF").unwrap();
+        db.save_model(&model("gone::orphan-a", "gone")).unwrap();
+        db.save_model(&model("orphan-b", "gone")).unwrap();
+        db.save_model(&model("gone::ok", "gone")).unwrap();
+        db.save_model(&model("live::ok", "live")).unwrap();
+        let session = create_test_session(&db, None, None);
+        db.conn().unwrap().execute("UPDATE sessions SET model = 'gone::orphan-a' WHERE id = ?1", [&session.id]).unwrap();
 
-        rows.collect()
+        let dangling: Vec<String> = db.list_dangling_models().unwrap().into_iter().map(|m| m.id).collect();
+        assert_eq!(dangling, vec!["gone::ok", "gone::orphan-a", "orphan-b"]);
+
+        assert!(db.repair_dangling_models(Some("missing")).is_err());
+        assert_eq!(db.repair_dangling_models(Some("live")).unwrap(), 3);
+        let mut ids: Vec<String> = db.list_models_by_provider("live").unwrap().into_iter().map(|m| m.id).collect();
+        ids.sort();
+        assert_eq!(ids, vec!["live::ok", "live::orphan-a", "live::orphan-b", "ok"]);
+        assert_eq!(db.get_session(&session.id).unwrap().unwrap().model.as_deref(), Some("live::orphan-a"));
+
+        db.save_model(&model("orphan-c", "gone")).unwrap();
+        assert_eq!(db.repair_dangling_models(None).unwrap(), 1);
+        assert!(db.list_dangling_models().unwrap().is_empty());
     }
 
-    pub fn get_tasks_due_now(&self, now: i64) -> SqliteResult<Vec<ScheduledTask>> {
-        let conn = self.conn.lock().unwrap();
-        let mut stmt = conn.prepare(
-            r#"SELECT id, title, prompt, schedule, next_run, is_recurring, notify_before, enabled, created_at, updated_at
-               FROM scheduled_tasks
-               WHERE enabled = 1 AND next_run <= ?1
-               ORDER BY next_run ASC"#
-        )?;
+    #[test]
+    fn keychain_backed_provider_never_stores_its_key() {
+        let db = Database::new(Path::new(":memory:")).unwrap();
+        let provider = |id: &str, config: Option<JsonValue>| LLMProvider {
+            id: id.to_string(),
+            name: id.to_string(),
+            provider_type: "openai".to_string(),
+            base_url: None,
+            api_key: Some("sk-secret".to_string()),
+            enabled: true,
+            config,
+            created_at: 0,
+            updated_at: 0,
+        };
+        db.save_provider(&provider("plain", None)).unwrap();
+        db.save_provider(&provider("keychain", Some(serde_json::json!({ "keyRef": " provider:keychain " })))).unwrap();
+        db.save_provider(&provider("blank_ref", Some(serde_json::json!({ "keyRef": "" })))).unwrap();
 
-        let rows = stmt.query_map([now], |row| {
-            Ok(ScheduledTask {
-                id: row.get(0)?,
-                title: row.get(1)?,
-                prompt: row.get(2)?,
-                schedule: row.get(3)?,
-                next_run: row.get(4)?,
-                is_recurring: row.get::<_, i32>(5)? != 0,
-                notify_before: row.get(6)?,
-                enabled: row.get::<_, i32>(7)? != 0,
-                created_at: row.get(8)?,
-                updated_at: row.get(9)?,
-            })
-        })?;
+        let providers = db.list_providers().unwrap();
+        let find = |id: &str| providers.iter().find(|p| p.id == id).unwrap();
+        assert_eq!(find("plain").api_key.as_deref(), Some("sk-secret"));
+        assert_eq!(find("plain").key_ref(), None);
+        assert_eq!(find("keychain").api_key, None);
+        assert_eq!(find("keychain").key_ref(), Some("provider:keychain"));
+        assert_eq!(find("blank_ref").api_key.as_deref(), Some("sk-secret"));
+    }
 
-        rows.collect()
+    #[test]
+    fn sync_provider_models_preserves_survivors() {
+        let db = Database::new(Path::new(":memory:")).unwrap();
+        db.save_provider(&LLMProvider {
+            id: "p".to_string(),
+            name: "Provider".to_string(),
+            provider_type: "openai".to_string(),
+            base_url: None,
+            api_key: None,
+            enabled: true,
+            config: None,
+            created_at: 0,
+            updated_at: 0,
+        }).unwrap();
+        let model = |remote: &str, enabled: bool| LLMModel {
+            id: format!("p::{}", remote),
+            provider_id: "p".to_string(),
+            name: format!("My {}", remote),
+            enabled,
+            config: Some(serde_json::json!({ "contextLength": 8192 })),
+        };
+        db.save_model(&model("keep", false)).unwrap();
+        db.save_model(&model("old", true)).unwrap();
+
+        let remote = vec!["keep".to_string(), "new".to_string(), "new".to_string()];
+        let report = db.sync_provider_models("p", &remote).unwrap();
+        assert_eq!(report, ModelSyncReport { added: vec!["p::new".to_string()], removed: vec!["p::old".to_string()], kept: 1 });
+
+        let models = db.list_models_by_provider("p").unwrap();
+        assert_eq!(models.len(), 2);
+        let kept = models.iter().find(|m| m.id == "p::keep").unwrap();
+        assert_eq!((kept.name.as_str(), kept.enabled), ("My keep", false));
+        assert_eq!(kept.config, Some(serde_json::json!({ "contextLength": 8192 })));
+        assert!(!models.iter().find(|m| m.id == "p::new").unwrap().enabled);
+
+        // An id already taken by another provider's row is not reported as added
+        db.save_provider(&LLMProvider { id: "q".to_string(), name: "Other".to_string(), ..db.list_providers().unwrap()[0].clone() }).unwrap();
+        db.save_model(&LLMModel { provider_id: "q".to_string(), ..model("clash", false) }).unwrap();
+        let remote = vec!["keep".to_string(), "new".to_string(), "clash".to_string()];
+        let report = db.sync_provider_models("p", &remote).unwrap();
+        assert!(report.added.is_empty());
+        assert_eq!(report.kept, 2);
     }
 
-    pub fn update_scheduled_task(&self, id: &str, params: &UpdateScheduledTaskParams) -> SqliteResult<bool> {
-        let conn = self.conn.lock().unwrap();
-        let now = chrono::Utc::now().timestamp_millis();
+    #[test]
+    fn task_runs_track_active_execution() {
+        let db = Database::new(Path::new(":memory:")).unwrap();
+        assert!(db.get_active_task_run("t1").unwrap().is_none());
 
-        let mut updates = vec!["updated_at = ?1".to_string()];
-        let mut values: Vec<Box<dyn rusqlite::ToSql>> = vec![Box::new(now)];
-        let mut idx = 2;
+        let run = db.start_task_run("t1", "s1").unwrap();
+        assert_eq!(db.get_active_task_run("t1").unwrap(), Some(run));
 
-        if let Some(ref title) = params.title {
-            updates.push(format!("title = ?{}", idx));
-            values.push(Box::new(title.clone()));
-            idx += 1;
-        }
-        if let Some(ref prompt) = params.prompt {
-            updates.push(format!("prompt = ?{}", idx));
-            values.push(Box::new(prompt.clone()));
-            idx += 1;
-        }
-        if let Some(ref schedule) = params.schedule {
-            updates.push(format!("schedule = ?{}", idx));
-            values.push(Box::new(schedule.clone()));
-            idx += 1;
-        }
-        if let Some(next_run) = params.next_run {
-            updates.push(format!("next_run = ?{}", idx));
-            values.push(Box::new(next_run));
-            idx += 1;
+        assert_eq!(db.finish_task_runs("s1", "cancelled").unwrap(), 1);
+        assert!(db.get_active_task_run("t1").unwrap().is_none());
+        // Already finished runs keep their first terminal status
+        assert_eq!(db.finish_task_runs("s1", "completed").unwrap(), 0);
+    }
+
+    #[test]
+    fn externalize_blobs_shrinks_messages_and_rehydrates() {
+        let db = Database::new(Path::new(":memory:")).unwrap();
+        let session = create_test_session(&db, None, None);
+        let image = format!("data:image/png;base64,{}", "A".repeat(crate::blobs::BLOB_MIN_BYTES));
+        let message = serde_json::json!({ "type": "user_prompt", "prompt": "see image", "images": [image] });
+        db.record_message(&session.id, &message).unwrap();
+        db.record_message(&session.id, &serde_json::json!({ "type": "text", "text": "small" })).unwrap();
+
+        let dir = std::env::temp_dir().join(format!("externalize_blobs_test_{}", uuid::Uuid::new_v4()));
+        let result = db.externalize_blobs(&session.id, &dir).unwrap();
+        assert_eq!((result.messages_updated, result.blobs), (1, 1));
+        assert!(result.bytes_saved > crate::blobs::BLOB_MIN_BYTES as i64 - 100);
+
+        let mut stored = db.get_session_messages(&session.id).unwrap()
+            .into_iter()
+            .find(|m| m["type"] == "user_prompt")
+            .unwrap();
+        assert!(stored["images"][0].as_str().unwrap().starts_with(crate::blobs::BLOB_REF_PREFIX));
+        assert_eq!(crate::blobs::rehydrate_value(&mut stored, &dir), 1);
+        assert_eq!(stored, message);
+
+        assert_eq!(db.externalize_blobs(&session.id, &dir).unwrap().blobs, 0);
+        assert_eq!(db.referenced_blobs().unwrap().len(), 1);
+
+        db.set_locked(&session.id, true).unwrap();
+        assert!(db.externalize_blobs(&session.id, &dir).is_err());
+        db.set_locked(&session.id, false).unwrap();
+
+        db.delete_session(&session.id).unwrap();
+        assert!(db.referenced_blobs().unwrap().is_empty());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn concurrent_writers_wait_for_the_lock() {
+        let dir = std::env::temp_dir().join(format!("busy_timeout_test_{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("test.db");
+        let first = Database::new(&path).unwrap();
+        assert_eq!(first.get_busy_timeout_ms().unwrap(), DEFAULT_BUSY_TIMEOUT_MS);
+        let session = create_test_session(&first, None, None);
+        drop(first);
+
+        // Two connections to the same file, each holding write transactions open for a moment
+        let writers: Vec<_> = (0..2).map(|w| {
+            let path = path.clone();
+            let session_id = session.id.clone();
+            std::thread::spawn(move || {
+                let db = Database::new(&path).unwrap();
+                for i in 0..20 {
+                    let mut conn = db.conn().unwrap();
+                    let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+                    tx.execute(
+                        "INSERT INTO messages (id, session_id, data, created_at) VALUES (?1, ?2, '{}', 0)",
+                        params![format!("w{}_{}", w, i), session_id],
+                    )?;
+                    std::thread::sleep(std::time::Duration::from_millis(2));
+                    tx.commit()?;
+                }
+                Ok::<_, rusqlite::Error>(())
+            })
+        }).collect();
+        for writer in writers {
+            writer.join().unwrap().unwrap();
         }
-        if let Some(is_recurring) = params.is_recurring {
-            updates.push(format!("is_recurring = ?{}", idx));
-            values.push(Box::new(if is_recurring { 1i32 } else { 0i32 }));
-            idx += 1;
+
+        let db = Database::new(&path).unwrap();
+        assert_eq!(db.get_session_messages(&session.id).unwrap().len(), 40);
+        db.set_busy_timeout_ms(250).unwrap();
+        assert_eq!(db.get_busy_timeout_ms().unwrap(), 250);
+        drop(db);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn pooled_readers_and_writers_do_not_hit_locked_errors() {
+        let dir = std::env::temp_dir().join(format!("pool_stress_test_{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let db = std::sync::Arc::new(Database::new(&dir.join("test.db")).unwrap());
+        let session = create_test_session(&db, None, None);
+        for w in [1, 5] {
+            db.save_provider(&LLMProvider {
+                id: format!("p{}", w),
+                name: "Provider".to_string(),
+                provider_type: "openai".to_string(),
+                base_url: None,
+                api_key: None,
+                enabled: true,
+                config: None,
+                created_at: 0,
+                updated_at: 0,
+            }).unwrap();
         }
-        if let Some(notify_before) = params.notify_before {
-            updates.push(format!("notify_before = ?{}", idx));
-            values.push(Box::new(notify_before));
-            idx += 1;
+
+        let workers: Vec<_> = (0..8).map(|w| {
+            let db = db.clone();
+            let session_id = session.id.clone();
+            std::thread::spawn(move || {
+                for i in 0..25 {
+                    if w % 2 == 0 {
+                        db.record_message(&session_id, &serde_json::json!({
+                            "type": "user_prompt",
+                            "prompt": format!("writer {} message {}", w, i)
+                        }))?;
+                    } else if w % 4 == 1 {
+                        // Read-then-write transactions must wait for the lock, not fail the upgrade
+                        let remote = if i % 2 == 0 { ["a", "b"] } else { ["b", "c"] };
+                        db.sync_provider_models(&format!("p{}", w), &remote.map(String::from))?;
+                        db.set_temperature_bulk(Some(std::slice::from_ref(&session_id)), 0.5)?;
+                    } else {
+                        db.get_session_messages(&session_id)?;
+                        db.search_messages("writer", 20)?;
+                        db.list_sessions()?;
+                    }
+                }
+                Ok::<_, rusqlite::Error>(())
+            })
+        }).collect();
+        for worker in workers {
+            worker.join().unwrap().unwrap();
         }
-        if let Some(enabled) = params.enabled {
-            updates.push(format!("enabled = ?{}", idx));
-            values.push(Box::new(if enabled { 1i32 } else { 0i32 }));
-            idx += 1;
+
+        assert_eq!(db.get_session_messages(&session.id).unwrap().len(), 100);
+        drop(db);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn checkpoint_truncates_wal() {
+        let dir = std::env::temp_dir().join(format!("checkpoint_wal_test_{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let db = Database::new(&dir.join("test.db")).unwrap();
+        let session = create_test_session(&db, None, None);
+        for i in 0..50 {
+            db.record_message(&session.id, &serde_json::json!({ "type": "text", "text": format!("chunk {}", i) })).unwrap();
         }
 
-        let sql = format!(
-            "UPDATE scheduled_tasks SET {} WHERE id = ?{}",
-            updates.join(", "),
-            idx
-        );
-        values.push(Box::new(id.to_string()));
+        let result = db.checkpoint_wal().unwrap();
+        assert!(!result.busy);
+        assert!(result.wal_bytes_before > 0);
+        assert_eq!(result.wal_bytes_after, 0);
+        assert_eq!(result.reclaimed_bytes, result.wal_bytes_before);
+        assert_eq!(db.get_session_messages(&session.id).unwrap().len(), 50);
 
-        let params_refs: Vec<&dyn rusqlite::ToSql> = values.iter().map(|v| v.as_ref()).collect();
-        let changed = conn.execute(&sql, params_refs.as_slice())?;
-        Ok(changed > 0)
+        drop(db);
+        std::fs::remove_dir_all(&dir).unwrap();
     }
 
-    pub fn delete_scheduled_task(&self, id: &str) -> SqliteResult<bool> {
-        let conn = self.conn.lock().unwrap();
-        let changed = conn.execute("DELETE FROM scheduled_tasks WHERE id = ?1", [id])?;
-        Ok(changed > 0)
+    #[test]
+    fn locked_session_refuses_mutations_but_stays_readable() {
+        let db = Database::new(Path::new(":memory:")).unwrap();
+        let session = create_test_session(&db, None, None);
+        db.record_message(&session.id, &serde_json::json!({ "type": "user_prompt", "prompt": "keep me" })).unwrap();
+
+        assert!(db.set_locked(&session.id, true).unwrap());
+        assert!(db.get_session(&session.id).unwrap().unwrap().is_locked);
+        let err = db.record_message(&session.id, &serde_json::json!({ "type": "text", "text": "x" })).unwrap_err();
+        assert!(err.to_string().contains("is locked"));
+        assert!(db.truncate_history_after(&session.id, 0).is_err());
+        assert!(db.delete_session(&session.id).is_err());
+        assert_eq!(db.get_session_history(&session.id).unwrap().unwrap().messages.len(), 1);
+
+        db.set_locked(&session.id, false).unwrap();
+        assert!(db.delete_session(&session.id).unwrap());
+        assert!(!db.set_locked("missing", true).unwrap());
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use std::path::Path;
+    #[test]
+    fn stream_draft_surfaces_as_last_partial_message() {
+        let db = Database::new(Path::new(":memory:")).unwrap();
+        let session = create_test_session(&db, None, None);
+        db.record_message(&session.id, &serde_json::json!({ "type": "user_prompt", "prompt": "hi" })).unwrap();
+        db.save_stream_draft(&session.id, "Hel").unwrap();
+        db.save_stream_draft(&session.id, "Hello wor").unwrap();
+
+        let history = db.get_session_history(&session.id).unwrap().unwrap();
+        assert_eq!(history.messages.len(), 2);
+        let last = history.messages.last().unwrap();
+        assert_eq!(last["text"], "Hello wor");
+        assert_eq!(last["partial"], true);
+
+        db.clear_stream_draft(&session.id).unwrap();
+        let history = db.get_session_history(&session.id).unwrap().unwrap();
+        assert_eq!(history.messages.len(), 1);
+
+        db.save_stream_draft(&session.id, "stale").unwrap();
+        let now = chrono::Utc::now().timestamp_millis();
+        assert_eq!(db.expire_stream_drafts(now - STREAM_DRAFT_MAX_AGE_MS).unwrap(), 0);
+        assert_eq!(db.expire_stream_drafts(now + 1).unwrap(), 1);
+        assert_eq!(db.get_stream_draft(&session.id).unwrap(), None);
+    }
 
     #[test]
-    fn api_settings_locale_roundtrip() {
+    fn sessions_grouped_by_cwd_with_unfiled_bucket() {
         let db = Database::new(Path::new(":memory:")).unwrap();
-        let settings = ApiSettings {
-            base_url: Some("https://api.example.com".to_string()),
-            api_key: Some("sk-test".to_string()),
-            permission_mode: None,
-            web_search_provider: None,
-            tavily_api_key: None,
-            enable_memory: None,
-            enable_todos: None,
-            locale: Some("ru".to_string()),
+        for (id, cwd, updated_at) in [
+            ("a1", Some("/proj/a"), 10),
+            ("b1", Some("/proj/b"), 30),
+            ("a2", Some("/proj/a"), 40),
+            ("n1", None, 50),
+            ("n2", Some("  "), 20),
+        ] {
+            db.create_session(&CreateSessionParams {
+                id: Some(id.to_string()),
+                cwd: cwd.map(String::from),
+                title: id.to_string(),
+                ..Default::default()
+            }).unwrap();
+            db.conn().unwrap()
+                .execute("UPDATE sessions SET updated_at = ?1 WHERE id = ?2", params![updated_at, id])
+                .unwrap();
+        }
+
+        let groups = db.list_sessions_grouped_by_cwd().unwrap();
+        let layout: Vec<(&str, Vec<&str>)> = groups.iter()
+            .map(|g| (g.cwd.as_str(), g.sessions.iter().map(|s| s.id.as_str()).collect()))
+            .collect();
+        assert_eq!(layout, vec![
+            ("/proj/a", vec!["a2", "a1"]),
+            ("/proj/b", vec!["b1"]),
+            (UNFILED_PROJECT, vec!["n1", "n2"]),
+        ]);
+    }
+
+    #[test]
+    fn ui_prefs_roundtrip_and_validation() {
+        let db = Database::new(Path::new(":memory:")).unwrap();
+        assert_eq!(db.get_ui_prefs().unwrap(), UiPrefs::default());
+
+        let prefs = UiPrefs {
+            theme: "dark".to_string(),
+            sidebar_width: 320,
+            default_view: "tasks".to_string(),
         };
+        db.set_ui_prefs(&prefs).unwrap();
+        assert_eq!(db.get_ui_prefs().unwrap(), prefs);
 
-        db.save_api_settings(&settings).unwrap();
+        assert!(matches!(db.set_ui_prefs(&UiPrefs { theme: "neon".to_string(), ..prefs.clone() }), Err(UiPrefsError::Invalid(_))));
+        assert!(matches!(db.set_ui_prefs(&UiPrefs { sidebar_width: 10, ..prefs.clone() }), Err(UiPrefsError::Invalid(_))));
+        assert_eq!(db.get_ui_prefs().unwrap(), prefs);
 
-        let loaded = db.get_api_settings().unwrap();
-        assert!(loaded.is_some());
-        let loaded = loaded.unwrap();
-        assert_eq!(loaded.locale, Some("ru".to_string()));
+        // Partial blobs fill in defaults for missing fields
+        db.set_setting("ui_prefs", r#"{"theme":"light"}"#).unwrap();
+        assert_eq!(db.get_ui_prefs().unwrap().sidebar_width, UiPrefs::default().sidebar_width);
     }
 
     #[test]
-    fn api_settings_without_locale() {
+    fn stats_reports_row_counts_and_size() {
         let db = Database::new(Path::new(":memory:")).unwrap();
-        let settings = ApiSettings {
+        let session = create_test_session(&db, None, None);
+        db.record_message(&session.id, &serde_json::json!({ "type": "user_prompt", "prompt": "hi" })).unwrap();
+
+        let stats = db.stats().unwrap();
+        assert_eq!(stats.row_counts.get("sessions"), Some(&1));
+        assert_eq!(stats.row_counts.get("messages"), Some(&1));
+        assert_eq!(stats.session_count, 1);
+        assert!(stats.size_bytes > 0);
+        assert_eq!(stats.wal_size_bytes, 0);
+    }
+
+    #[test]
+    fn session_model_is_resolved_to_known_id() {
+        let db = Database::new(Path::new(":memory:")).unwrap();
+        db.save_provider(&LLMProvider {
+            id: "p".to_string(),
+            name: "Provider".to_string(),
+            provider_type: "openai".to_string(),
             base_url: None,
             api_key: None,
-            permission_mode: None,
-            web_search_provider: None,
-            tavily_api_key: None,
-            enable_memory: None,
-            enable_todos: None,
-            locale: None,
+            enabled: true,
+            config: None,
+            created_at: 0,
+            updated_at: 0,
+        }).unwrap();
+        db.save_model(&LLMModel {
+            id: "p::gpt-4o".to_string(),
+            provider_id: "p".to_string(),
+            name: "GPT-4o".to_string(),
+            enabled: true,
+            config: None,
+        }).unwrap();
+
+        assert_eq!(create_test_session(&db, Some("p::gpt-4o"), None).model.as_deref(), Some("p::gpt-4o"));
+        let corrected = create_test_session(&db, Some("GPT-4o"), None);
+        assert_eq!(db.get_session(&corrected.id).unwrap().unwrap().model.as_deref(), Some("p::gpt-4o"));
+
+        assert!(db.create_session(&CreateSessionParams {
+            title: "bad".to_string(),
+            model: Some("gpt-5-preview".to_string()),
+            ..Default::default()
+        }).is_err());
+        assert!(db.update_session(&corrected.id, &UpdateSessionParams {
+            model: Some("nope".to_string()),
+            ..Default::default()
+        }).is_err());
+        db.update_session(&corrected.id, &UpdateSessionParams {
+            model: Some("p::GPT-4o".to_string()),
+            ..Default::default()
+        }).unwrap();
+        assert_eq!(db.get_session(&corrected.id).unwrap().unwrap().model.as_deref(), Some("p::gpt-4o"));
+
+        // Scheduler default model goes through the same resolution
+        assert_eq!(db.set_scheduler_default_model("GPT-4o").unwrap(), "p::gpt-4o");
+        assert_eq!(db.set_scheduler_default_model("p::GPT-4o").unwrap(), "p::gpt-4o");
+        assert!(db.set_scheduler_default_model("gpt-5-preview").is_err());
+        assert_eq!(db.get_scheduler_default_model().unwrap().as_deref(), Some("p::gpt-4o"));
+    }
+
+    #[test]
+    fn settings_snapshots_diff_redacts_secrets_and_are_bounded() {
+        let db = Database::new(Path::new(":memory:")).unwrap();
+        let provider = LLMProvider {
+            id: "p".to_string(),
+            name: "Provider".to_string(),
+            provider_type: "openai".to_string(),
+            base_url: Some("http://a".to_string()),
+            api_key: Some("sk-old".to_string()),
+            enabled: true,
+            config: None,
+            created_at: 0,
+            updated_at: 0,
         };
+        db.save_provider(&provider).unwrap();
+        let first = db.create_settings_snapshot(Some("before")).unwrap();
 
-        db.save_api_settings(&settings).unwrap();
+        db.save_provider(&LLMProvider {
+            base_url: Some("http://b".to_string()),
+            api_key: Some("sk-new".to_string()),
+            ..provider
+        }).unwrap();
+        db.save_api_settings(&ApiSettings { model: Some("m".to_string()), ..Default::default() }).unwrap();
+        let second = db.create_settings_snapshot(None).unwrap();
 
-        let loaded = db.get_api_settings().unwrap();
-        assert!(loaded.is_some());
-        assert_eq!(loaded.unwrap().locale, None);
+        let a = db.get_settings_snapshot(first.id).unwrap().unwrap();
+        let b = db.get_settings_snapshot(second.id).unwrap().unwrap();
+        let changes = diff_settings_snapshots(&a, &b);
+        let paths: Vec<&str> = changes.iter().map(|c| c.path.as_str()).collect();
+        assert_eq!(paths, vec!["apiSettings.model", "providers.p.apiKey", "providers.p.baseUrl"]);
+        let key_change = &changes[1];
+        assert_eq!(key_change.before, Some(serde_json::json!("[REDACTED]")));
+        assert_eq!(changes[2].after, Some(serde_json::json!("http://b")));
+        assert!(!a.to_string().contains("sk-old") && !b.to_string().contains("sk-new"));
+
+        for field in ["apiKey", "tavilyApiKey", "access_token", "clientSecret", "password", "providers.p.apiKey"] {
+            assert!(is_secret_field(field), "{}", field);
+        }
+        for field in ["maxTokens", "max_tokens", "keyRef", "tokenizer", "monkey"] {
+            assert!(!is_secret_field(field), "{}", field);
+        }
+
+        for _ in 0..SETTINGS_SNAPSHOT_LIMIT {
+            db.create_settings_snapshot(None).unwrap();
+        }
+        assert_eq!(db.list_settings_snapshots().unwrap().len() as i64, SETTINGS_SNAPSHOT_LIMIT);
+        assert!(db.get_settings_snapshot(first.id).unwrap().is_none());
+    }
+
+    #[test]
+    fn profile_roundtrip_keeps_stripped_keys_and_merge_vs_replace() {
+        let db = Database::new(Path::new(":memory:")).unwrap();
+        let provider = |id: &str, key: Option<&str>| LLMProvider {
+            id: id.to_string(),
+            name: id.to_string(),
+            provider_type: "openai".to_string(),
+            base_url: Some(format!("http://{}", id)),
+            api_key: key.map(String::from),
+            enabled: true,
+            config: None,
+            created_at: 0,
+            updated_at: 0,
+        };
+        let model = |id: &str, provider_id: &str| LLMModel {
+            id: id.to_string(),
+            provider_id: provider_id.to_string(),
+            name: id.to_string(),
+            enabled: true,
+            config: None,
+        };
+        db.save_llm_provider_settings(&LLMProviderSettings {
+            providers: vec![provider("a", Some("sk-a")), provider("b", Some("sk-b"))],
+            models: vec![model("a::m", "a"), model("b::m", "b")],
+        }).unwrap();
+        db.save_api_settings(&ApiSettings {
+            model: Some("a::m".to_string()),
+            api_key: Some("sk-main".to_string()),
+            ..Default::default()
+        }).unwrap();
+
+        let mut profile = db.export_profile(true).unwrap();
+        assert!(profile.providers.iter().all(|p| p.api_key.is_none()));
+        assert!(profile.api_settings.as_ref().unwrap().api_key.is_none());
+
+        // Only provider "a" in the profile, with a changed URL: its key must not follow
+        profile.providers.retain(|p| p.id == "a");
+        profile.providers[0].base_url = Some("http://a2".to_string());
+        profile.models.retain(|m| m.provider_id == "a");
+
+        let merged = db.import_profile(&profile, ProfileImportMode::Merge).unwrap();
+        let paths: Vec<&str> = merged.changes.iter().map(|c| c.path.as_str()).collect();
+        assert!(paths.contains(&"providers.a.baseUrl"), "{:?}", paths);
+        assert_eq!(db.list_providers().unwrap().len(), 2);
+        let a = db.list_providers().unwrap().into_iter().find(|p| p.id == "a").unwrap();
+        assert_eq!(a.api_key, None);
+
+        // Same URL again: the key stored for it is kept
+        db.save_provider(&LLMProvider { api_key: Some("sk-a2".to_string()), ..a }).unwrap();
+        db.import_profile(&profile, ProfileImportMode::Merge).unwrap();
+        let a = db.list_providers().unwrap().into_iter().find(|p| p.id == "a").unwrap();
+        assert_eq!(a.api_key.as_deref(), Some("sk-a2"));
+
+        let replaced = db.import_profile(&profile, ProfileImportMode::Replace).unwrap();
+        assert!(replaced.changes.iter().any(|c| c.path == "providers.b.name" && c.after.is_none()));
+        assert_eq!(db.list_providers().unwrap().len(), 1);
+        assert_eq!(db.list_models().unwrap().len(), 1);
+        assert_eq!(db.get_api_settings().unwrap().unwrap().api_key.as_deref(), Some("sk-main"));
+
+        let mut moved = profile.clone();
+        moved.api_settings.as_mut().unwrap().base_url = Some("http://elsewhere".to_string());
+        db.import_profile(&moved, ProfileImportMode::Merge).unwrap();
+        assert_eq!(db.get_api_settings().unwrap().unwrap().api_key, None);
+
+        // Invalid profiles leave the DB untouched
+        let mut dangling = profile.clone();
+        dangling.models.push(model("x::m", "x"));
+        assert!(db.import_profile(&dangling, ProfileImportMode::Replace).is_err());
+        let mut future = profile.clone();
+        future.version = PROFILE_VERSION + 1;
+        assert!(db.import_profile(&future, ProfileImportMode::Merge).is_err());
+        assert_eq!(db.list_models().unwrap().len(), 1);
     }
 }
@@ -1,11 +1,15 @@
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 #![allow(dead_code)] // TODO: remove after migration complete
 
+mod blobs;
+mod chatgpt_import;
 mod db;
+mod redaction;
 mod sandbox;
 mod scheduler;
+mod transcript;
 
-use db::{Database, CreateSessionParams, UpdateSessionParams, Session, SessionHistory, TodoItem, FileChange, LLMProvider, LLMModel, LLMProviderSettings, ApiSettings, ScheduledTask, CreateScheduledTaskParams, UpdateScheduledTaskParams, VoiceSettings};
+use db::{Database, DictationMetrics, MessageRef, CreateSessionParams, UpdateSessionParams, Session, SessionHistory, TodoItem, FileChange, LLMProvider, LLMModel, LLMProviderSettings, ApiSettings, ScheduledTask, CreateScheduledTaskParams, UpdateScheduledTaskParams, VoiceSettings, UiPrefs};
 use scheduler::SchedulerService;
 use base64::Engine;
 use serde::Serialize;
@@ -30,6 +34,110 @@ fn thumb_cache() -> &'static Mutex<ThumbCache> {
   THUMB_CACHE.get_or_init(|| Mutex::new(ThumbCache { map: HashMap::new() }))
 }
 
+// --- Outbound HTTP (proxy + default headers applied to every reqwest client) ---
+#[derive(Debug, Clone, Default, PartialEq)]
+struct NetworkSettings {
+  proxy: Option<String>,
+  headers: Vec<(String, String)>,
+}
+
+impl NetworkSettings {
+  fn from_api(settings: Option<&ApiSettings>) -> Self {
+    let Some(settings) = settings else { return Self::default() };
+    Self {
+      proxy: settings.http_proxy.as_deref().map(str::trim).filter(|p| !p.is_empty()).map(String::from),
+      headers: settings.http_headers.iter().flatten().map(|(k, v)| (k.trim().to_string(), v.clone())).collect(),
+    }
+  }
+
+  /// Explicit proxy (if any) and the default header map. Without an explicit proxy reqwest
+  /// falls back to the system proxy env (`HTTPS_PROXY`, `HTTP_PROXY`, `NO_PROXY`).
+  fn build_parts(&self) -> Result<(Option<reqwest::Proxy>, reqwest::header::HeaderMap), String> {
+    let proxy = match &self.proxy {
+      Some(url) => Some(reqwest::Proxy::all(url).map_err(|e| format!("invalid proxy {url:?}: {e}"))?),
+      None => None,
+    };
+    let mut headers = reqwest::header::HeaderMap::new();
+    for (name, value) in &self.headers {
+      let name = reqwest::header::HeaderName::from_bytes(name.as_bytes())
+        .map_err(|e| format!("invalid header name {name:?}: {e}"))?;
+      let value = reqwest::header::HeaderValue::from_str(value)
+        .map_err(|e| format!("invalid value for header {name}: {e}"))?;
+      headers.insert(name, value);
+    }
+    Ok((proxy, headers))
+  }
+}
+
+static NETWORK_SETTINGS: OnceLock<Mutex<NetworkSettings>> = OnceLock::new();
+
+fn network_settings() -> &'static Mutex<NetworkSettings> {
+  NETWORK_SETTINGS.get_or_init(|| Mutex::new(NetworkSettings::default()))
+}
+
+fn apply_network_settings(settings: Option<&ApiSettings>) {
+  *network_settings().lock().unwrap() = NetworkSettings::from_api(settings);
+}
+
+/// Fallback when a request doesn't set its own timeout
+const HTTP_DEFAULT_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(60);
+const HTTP_CONNECT_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(10);
+
+/// Pooled reqwest clients shared by all outbound HTTP, so connections and TLS sessions are reused.
+/// Built on first use and rebuilt only when the proxy/header settings change; callers set
+/// per-request timeouts. Clones are cheap handles to the same pool.
+#[derive(Default)]
+struct HttpClients {
+  async_client: Mutex<Option<(NetworkSettings, reqwest::Client)>>,
+  blocking_client: Mutex<Option<(NetworkSettings, reqwest::blocking::Client)>>,
+}
+
+impl HttpClients {
+  fn client(&self) -> Result<reqwest::Client, String> {
+    let network = network_settings().lock().unwrap().clone();
+    let mut cached = self.async_client.lock().unwrap();
+    if let Some((built_for, client)) = cached.as_ref() {
+      if *built_for == network {
+        return Ok(client.clone());
+      }
+    }
+    let (proxy, headers) = network.build_parts()?;
+    let mut builder = reqwest::Client::builder()
+      .timeout(HTTP_DEFAULT_TIMEOUT)
+      .connect_timeout(HTTP_CONNECT_TIMEOUT)
+      .default_headers(headers);
+    if let Some(proxy) = proxy {
+      builder = builder.proxy(proxy);
+    }
+    let client = builder.build().map_err(|e| e.to_string())?;
+    *cached = Some((network, client.clone()));
+    Ok(client)
+  }
+
+  /// Blocking counterpart of [`HttpClients::client`]. Only call from plain threads:
+  /// the blocking client owns a runtime that must not be dropped inside an async context.
+  fn blocking_client(&self) -> Result<reqwest::blocking::Client, String> {
+    let network = network_settings().lock().unwrap().clone();
+    let mut cached = self.blocking_client.lock().unwrap();
+    if let Some((built_for, client)) = cached.as_ref() {
+      if *built_for == network {
+        return Ok(client.clone());
+      }
+    }
+    let (proxy, headers) = network.build_parts()?;
+    let mut builder = reqwest::blocking::Client::builder()
+      .timeout(HTTP_DEFAULT_TIMEOUT)
+      .connect_timeout(HTTP_CONNECT_TIMEOUT)
+      .default_headers(headers);
+    if let Some(proxy) = proxy {
+      builder = builder.proxy(proxy);
+    }
+    let client = builder.build().map_err(|e| e.to_string())?;
+    *cached = Some((network, client.clone()));
+    Ok(client)
+  }
+}
+
 #[derive(Serialize)]
 #[serde(rename_all = "camelCase")]
 struct FileItem {
@@ -48,6 +156,37 @@ struct BuildInfo {
   build_time: String,
 }
 
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct FileInspection {
+  size_bytes: u64,
+  is_probably_binary: bool,
+  /// "utf-8", "utf-8-bom", "utf-16le", "utf-16be", "unknown" (not UTF-8) or "binary"
+  encoding: String,
+  too_large: bool,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct CwdWritable {
+  writable: bool,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  reason: Option<String>,
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+struct ProjectType {
+  /// In marker-table order, e.g. ["node", "rust"]
+  ecosystems: Vec<String>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  primary: Option<String>,
+  is_git_repo: bool,
+  /// Sandbox language id (see `sandbox.languages`) that fits the primary ecosystem
+  #[serde(skip_serializing_if = "Option::is_none")]
+  sandbox_language: Option<String>,
+}
+
 #[derive(Serialize)]
 #[serde(rename_all = "camelCase")]
 struct OpResult {
@@ -198,7 +337,110 @@ fn emit_server_event_app(app: &tauri::AppHandle, event: &Value) -> Result<(), St
     let msg = format!("[ipc] Failed to emit server-event: {error}");
     eprintln!("{msg}");
     msg
-  })
+  })?;
+
+  if let Some(state) = app.try_state::<AppState>() {
+    if let Ok(mut log) = state.recent_events.lock() {
+      log.record(event, chrono::Utc::now().timestamp_millis());
+    }
+  }
+  Ok(())
+}
+
+/// How many recent server events are kept for `events.replay`
+const EVENT_REPLAY_CAPACITY: usize = 200;
+/// Events larger than this (serialized bytes) are not kept for replay
+const EVENT_REPLAY_MAX_BYTES: usize = 64 * 1024;
+
+/// Ring buffer of recent non-streaming server events so a reloaded UI can catch up
+#[derive(Default)]
+struct EventLog {
+  events: std::collections::VecDeque<(i64, Value)>,
+}
+
+impl EventLog {
+  fn record(&mut self, event: &Value, at: i64) {
+    let event_type = event.get("type").and_then(|t| t.as_str()).unwrap_or("");
+    // High-volume or replay bookkeeping events would evict everything useful; bulk
+    // exports and dumps are answers to one request, not state a reloaded UI needs
+    if matches!(
+      event_type,
+      "stream.message"
+        | "events.replayed"
+        | "fs.tree.chunk"
+        | "sandbox.output"
+        | "voice.transcription.partial"
+        | "session.history"
+        | "session.exported"
+        | "stats.exported"
+        | "session.debug_payload"
+        | "message.raw"
+    ) {
+      return;
+    }
+    if serde_json::to_string(event).map_or(true, |raw| raw.len() > EVENT_REPLAY_MAX_BYTES) {
+      return;
+    }
+    if self.events.len() == EVENT_REPLAY_CAPACITY {
+      self.events.pop_front();
+    }
+    self.events.push_back((at, event.clone()));
+  }
+
+  /// Events recorded strictly after `since` (ms), oldest first
+  fn since(&self, since: i64) -> Vec<(i64, Value)> {
+    self.events.iter().filter(|(at, _)| *at > since).cloned().collect()
+  }
+}
+
+/// How often running sessions are checked against their idle timeout
+const IDLE_CHECK_INTERVAL_SECS: u64 = 5;
+
+/// Last streaming activity (ms) per running session, fed from sidecar events
+#[derive(Default)]
+struct StreamActivity {
+  last_seen: HashMap<String, i64>,
+  /// Sessions waiting on a `permission.request`; silence there is the user's, not the model's
+  awaiting_permission: std::collections::HashSet<String>,
+}
+
+impl StreamActivity {
+  fn touch(&mut self, session_id: &str, at: i64) {
+    self.last_seen.insert(session_id.to_string(), at);
+  }
+
+  fn forget(&mut self, session_id: &str) {
+    self.last_seen.remove(session_id);
+    self.awaiting_permission.remove(session_id);
+  }
+
+  fn set_awaiting_permission(&mut self, session_id: &str, awaiting: bool) {
+    if awaiting {
+      self.awaiting_permission.insert(session_id.to_string());
+    } else {
+      self.awaiting_permission.remove(session_id);
+    }
+  }
+
+  fn tracked_ids(&self) -> Vec<String> {
+    self.last_seen.keys().cloned().collect()
+  }
+
+  /// Removes and returns sessions silent for longer than their timeout, with the idle time in ms.
+  /// Sessions without a timeout (`timeout_ms` → None) or awaiting a permission answer are never reported.
+  fn take_idle(&mut self, now: i64, timeout_ms: impl Fn(&str) -> Option<i64>) -> Vec<(String, i64)> {
+    let idle: Vec<(String, i64)> = self.last_seen.iter()
+      .filter(|(id, _)| !self.awaiting_permission.contains(*id))
+      .filter_map(|(id, at)| {
+        let timeout = timeout_ms(id)?;
+        (now - at > timeout).then(|| (id.clone(), now - at))
+      })
+      .collect();
+    for (id, _) in &idle {
+      self.last_seen.remove(id);
+    }
+    idle
+  }
 }
 
 #[derive(Default)]
@@ -220,6 +462,172 @@ struct VoiceState {
   // - skip if the last SUCCESSFUL warmup was for the same (base_url, model)
   warmup_last_success_key: Mutex<Option<(String, String)>>,
   warmup_in_flight: Mutex<bool>,
+  // Open websocket transcription streams, keyed by dictation session
+  streams: Mutex<HashMap<String, VoiceStream>>,
+  // Source of `VoiceStream::id`
+  next_stream_id: std::sync::atomic::AtomicU64,
+  // Whether a websocket transcription URL accepted the upgrade probe (see `voice_server_supports_realtime`)
+  realtime_support: Mutex<HashMap<String, bool>>,
+  // Bumped when voice settings change; results started under an older generation are dropped
+  generation: std::sync::atomic::AtomicU64,
+  // Transcription usage not yet written to the voice_usage table, keyed by dictation session
+  usage: Mutex<HashMap<String, VoiceUsageTally>>,
+  // Latency clocks of dictations in progress, keyed by dictation session
+  timings: Mutex<HashMap<String, DictationTiming>>,
+}
+
+/// An open websocket dictation; the id tells it apart from a later stream of the same session
+struct VoiceStream {
+  id: u64,
+  tx: std::sync::mpsc::Sender<VoiceStreamMsg>,
+}
+
+/// When a dictation's first chunk arrived and its first partial went out (ms)
+struct DictationTiming {
+  started_ms: u64,
+  first_partial_ms: Option<u64>,
+  model: String,
+  transport: &'static str,
+}
+
+#[derive(Default, Clone, Copy)]
+struct VoiceUsageTally {
+  audio_seconds: f64,
+  requests: i64,
+}
+
+/// Uncompressed 16 kHz mono 16-bit PCM
+const VOICE_PCM_BYTES_PER_SEC: f64 = 32_000.0;
+/// MediaRecorder's default 128 kbps for compressed formats (webm/ogg/mp4)
+const VOICE_COMPRESSED_BYTES_PER_SEC: f64 = 16_000.0;
+
+/// Rough audio duration from byte count; servers don't report it back
+fn estimate_audio_seconds(bytes: usize, mime: &str) -> f64 {
+  let mime = mime.to_ascii_lowercase();
+  let bytes_per_sec = if mime.contains("wav") || mime.contains("pcm") {
+    VOICE_PCM_BYTES_PER_SEC
+  } else {
+    VOICE_COMPRESSED_BYTES_PER_SEC
+  };
+  bytes as f64 / bytes_per_sec
+}
+
+impl VoiceState {
+  fn current_generation(&self) -> u64 {
+    self.generation.load(std::sync::atomic::Ordering::SeqCst)
+  }
+
+  /// Drop the session's stream entry only if it is still the one with `stream_id`
+  fn remove_stream(&self, session_id: &str, stream_id: u64) {
+    if let Ok(mut streams) = self.streams.lock() {
+      if streams.get(session_id).is_some_and(|s| s.id == stream_id) {
+        streams.remove(session_id);
+      }
+    }
+  }
+
+  /// Invalidate everything in flight: pending results are ignored, buffers and streams dropped
+  fn reset(&self) -> u64 {
+    let generation = self.generation.fetch_add(1, std::sync::atomic::Ordering::SeqCst) + 1;
+    if let Ok(mut buffers) = self.buffers.lock() {
+      buffers.clear();
+    }
+    if let Ok(mut streams) = self.streams.lock() {
+      streams.clear();
+    }
+    if let Ok(mut realtime) = self.realtime_support.lock() {
+      realtime.clear();
+    }
+    if let Ok(mut timings) = self.timings.lock() {
+      timings.clear();
+    }
+    if let Ok(mut last_ok) = self.warmup_last_success_key.lock() {
+      *last_ok = None;
+    }
+    generation
+  }
+
+  fn record_usage(&self, session_id: &str, audio_seconds: f64, requests: i64) {
+    if let Ok(mut usage) = self.usage.lock() {
+      let tally = usage.entry(session_id.to_string()).or_default();
+      tally.audio_seconds += audio_seconds;
+      tally.requests += requests;
+    }
+  }
+
+  fn pending_usage(&self, session_id: &str) -> VoiceUsageTally {
+    self.usage.lock().ok().and_then(|usage| usage.get(session_id).copied()).unwrap_or_default()
+  }
+
+  /// The first chunk of a dictation starts its latency clock; later chunks leave it running
+  fn start_dictation_timing(&self, session_id: &str, model: &str, transport: &'static str, now: u64) {
+    if let Ok(mut timings) = self.timings.lock() {
+      timings.entry(session_id.to_string()).or_insert_with(|| DictationTiming {
+        started_ms: now,
+        first_partial_ms: None,
+        model: model.to_string(),
+        transport,
+      });
+    }
+  }
+
+  fn mark_dictation_partial(&self, session_id: &str, now: u64) {
+    if let Some(timing) = self.timings.lock().ok().as_mut().and_then(|t| t.get_mut(session_id)) {
+      timing.first_partial_ms.get_or_insert(now);
+    }
+  }
+
+  /// Stops the clock; None when the dictation wasn't timed (or failed, see `cancel_dictation_timing`)
+  fn finish_dictation_timing(&self, session_id: &str, now: u64) -> Option<DictationMetrics> {
+    let timing = self.timings.lock().ok()?.remove(session_id)?;
+    Some(DictationMetrics {
+      session_id: session_id.to_string(),
+      model: Some(timing.model),
+      transport: timing.transport.to_string(),
+      first_partial_ms: timing.first_partial_ms.map(|t| t.saturating_sub(timing.started_ms) as i64),
+      final_latency_ms: now.saturating_sub(timing.started_ms) as i64,
+    })
+  }
+
+  fn cancel_dictation_timing(&self, session_id: &str) {
+    if let Ok(mut timings) = self.timings.lock() {
+      timings.remove(session_id);
+    }
+  }
+
+  /// Forget the session's pending usage without saving it (the session was deleted)
+  fn discard_usage(&self, session_id: &str) {
+    if let Ok(mut usage) = self.usage.lock() {
+      usage.remove(session_id);
+    }
+  }
+
+  /// Persist the session's pending usage (called when a dictation is finalized or fails)
+  fn flush_usage(&self, db: &Database, session_id: &str) {
+    let Some(tally) = self.usage.lock().ok().and_then(|mut usage| usage.remove(session_id)) else { return };
+    if let Err(e) = db.add_voice_usage(session_id, tally.audio_seconds, tally.requests) {
+      eprintln!("[voice] Failed to save usage for {}: {}", session_id, e);
+    }
+  }
+}
+
+/// Emits `voice.dictation.metrics` for a dictation that just produced its final transcript
+/// and keeps the sample for `voice.dictation.metrics.summary`
+fn report_dictation_metrics(app: &tauri::AppHandle, session_id: &str) {
+  let state = app.state::<AppState>();
+  let Some(metrics) = state.voice.finish_dictation_timing(session_id, now_ms().unwrap_or(0)) else { return };
+  if let Err(e) = state.db.add_dictation_metrics(&metrics) {
+    eprintln!("[voice] Failed to save dictation metrics for {}: {}", session_id, e);
+  }
+  let _ = emit_server_event_app(app, &json!({
+    "type": "voice.dictation.metrics",
+    "payload": metrics
+  }));
+}
+
+enum VoiceStreamMsg {
+  Audio(Vec<u8>),
+  Finish,
 }
 
 fn memory_path() -> Result<PathBuf, String> {
@@ -227,6 +635,33 @@ fn memory_path() -> Result<PathBuf, String> {
   Ok(app_data_dir()?.join("memory.md"))
 }
 
+/// Fit `payload.temperature` to the model's range before the sidecar sees it, emitting
+/// `session.temperature.warning` when the requested value had to change
+fn clamp_payload_temperature(
+  app: &tauri::AppHandle,
+  db: &Database,
+  payload: &mut serde_json::Map<String, Value>,
+  model_id: Option<&str>,
+) -> Result<(), String> {
+  let Some(requested) = payload.get("temperature").and_then(|v| v.as_f64()) else { return Ok(()) };
+  let check = db.check_temperature(requested, model_id)
+    .map_err(|e| format!("[session.temperature] {}", e))?;
+  if !check.clamped {
+    return Ok(());
+  }
+  payload.insert("temperature".to_string(), json!(check.value));
+  emit_server_event_app(app, &json!({
+    "type": "session.temperature.warning",
+    "payload": {
+      "sessionId": payload.get("sessionId"),
+      "requested": requested,
+      "temperature": check.value,
+      "min": check.range.0,
+      "max": check.range.1
+    }
+  }))
+}
+
 /// Handle scheduler.request events from sidecar - execute scheduler operations
 fn handle_scheduler_request(_app: &tauri::AppHandle, db: &Arc<Database>, sidecar_state: &SidecarState, payload: &Value) {
   let request_id = payload.get("requestId").and_then(|v| v.as_str()).unwrap_or("");
@@ -325,7 +760,10 @@ fn handle_scheduler_request(_app: &tauri::AppHandle, db: &Arc<Database>, sidecar
     }
   });
   
+  // Registered before sending so a fast ack can't race the registration
+  sidecar_state.requests.track_ack(request_id, std::time::Duration::from_millis(SIDECAR_REQUEST_TIMEOUT_MS));
   if let Err(e) = send_to_sidecar_raw(sidecar_state, &response_msg) {
+    sidecar_state.requests.forget(request_id);
     eprintln!("[scheduler] ✗ send response: {}", e);
   }
 }
@@ -344,6 +782,87 @@ fn send_to_sidecar_raw(sidecar_state: &SidecarState, msg: &Value) -> Result<(),
   Ok(())
 }
 
+/// Apply the `max_sessions` setting after a session was created: archive the oldest
+/// evictable sessions and tell the UI which ones went away.
+fn enforce_session_cap(app: &tauri::AppHandle, db: &Database, created_id: Option<&str>) {
+  let max = match db.get_max_sessions() {
+    Ok(Some(max)) => max,
+    Ok(None) => return,
+    Err(e) => {
+      eprintln!("[session.evict] Failed to read max_sessions: {}", e);
+      return;
+    }
+  };
+  let evicted = match db.evict_oldest_sessions(max, created_id) {
+    Ok(ids) if ids.is_empty() => return,
+    Ok(ids) => ids,
+    Err(e) => {
+      eprintln!("[session.evict] Failed: {}", e);
+      return;
+    }
+  };
+  eprintln!("[session.evict] Archived {} sessions over the cap of {}", evicted.len(), max);
+  let _ = emit_server_event_app(app, &json!({
+    "type": "session.evicted",
+    "payload": { "sessionIds": evicted, "maxSessions": max }
+  }));
+  if let Ok(sessions) = db.list_sessions() {
+    let _ = emit_server_event_app(app, &json!({
+      "type": "session.list",
+      "payload": { "sessions": sessions }
+    }));
+  }
+}
+
+/// Per-session idle timeout in seconds, from session metadata
+fn session_idle_timeout_secs(db: &Database, session_id: &str) -> Option<u64> {
+  db.get_session_meta(session_id, db::IDLE_TIMEOUT_META_KEY).ok().flatten()
+    .and_then(|v| v.parse::<u64>().ok())
+    .filter(|secs| *secs > 0)
+}
+
+/// Stop running sessions that have streamed nothing for longer than their idle timeout
+fn stop_idle_sessions(app: &tauri::AppHandle) {
+  let state: tauri::State<'_, AppState> = app.state();
+  let now = chrono::Utc::now().timestamp_millis();
+  // Look timeouts up without holding the lock the sidecar reader needs for every stream event
+  let tracked = state.stream_activity.lock().unwrap().tracked_ids();
+  let timeouts: HashMap<String, i64> = tracked.into_iter()
+    .filter_map(|id| {
+      let secs = session_idle_timeout_secs(&state.db, &id)?;
+      Some((id, secs as i64 * 1000))
+    })
+    .collect();
+  let idle = state.stream_activity.lock().unwrap()
+    .take_idle(now, |id| timeouts.get(id).copied());
+  for (session_id, idle_ms) in idle {
+    let running = matches!(state.db.get_session(&session_id), Ok(Some(s)) if s.status == "running");
+    if !running {
+      continue;
+    }
+    eprintln!("[session.idle_timeout] Stopping {} after {}s without activity", session_id, idle_ms / 1000);
+    if let Err(e) = send_to_sidecar(app.clone(), state.inner(), &json!({
+      "type": "session.stop",
+      "payload": { "sessionId": session_id }
+    })) {
+      eprintln!("[session.idle_timeout] Failed to stop {}: {}", session_id, e);
+    }
+    // The sidecar may be the thing that's stuck; don't wait for its status update
+    let params = UpdateSessionParams { status: Some("idle".to_string()), ..Default::default() };
+    if let Err(e) = state.db.update_session(&session_id, &params) {
+      eprintln!("[session.idle_timeout] Failed to reset status: {}", e);
+    }
+    let _ = emit_server_event_app(app, &json!({
+      "type": "session.idle_timeout",
+      "payload": { "sessionId": session_id, "idleSeconds": idle_ms / 1000 }
+    }));
+    let _ = emit_server_event_app(app, &json!({
+      "type": "session.status",
+      "payload": { "sessionId": session_id, "status": "idle" }
+    }));
+  }
+}
+
 /// Handle session.sync events from sidecar - save to DB
 fn handle_session_sync(db: &Arc<Database>, payload: &Value) {
   let sync_type = payload.get("syncType").and_then(|v| v.as_str()).unwrap_or("");
@@ -365,8 +884,10 @@ fn handle_session_sync(db: &Arc<Database>, payload: &Value) {
         model: data.get("model").and_then(|v| v.as_str()).map(String::from),
         thread_id: data.get("threadId").and_then(|v| v.as_str()).map(String::from),
         temperature: None,
+        max_output_tokens: data.get("maxOutputTokens").and_then(|v| v.as_i64()),
+        permission_mode: data.get("permissionMode").and_then(|v| v.as_str()).map(String::from),
       };
-      if let Err(e) = db.create_session(&params) {
+      if let Err(e) = db.create_session(&db.sanitize_create_params(&params)) {
         eprintln!("[session.sync:create] Failed: {}", e);
       }
     }
@@ -378,15 +899,30 @@ fn handle_session_sync(db: &Arc<Database>, payload: &Value) {
         model: data.get("model").and_then(|v| v.as_str()).map(String::from),
         input_tokens: data.get("inputTokens").and_then(|v| v.as_i64()),
         output_tokens: data.get("outputTokens").and_then(|v| v.as_i64()),
+        temperature: data.get("temperature").and_then(|v| v.as_f64()),
+        // null clears the cap
+        max_output_tokens: data.get("maxOutputTokens")
+          .and_then(|v| if v.is_null() { Some(None) } else { v.as_i64().map(Some) }),
+        permission_mode: data.get("permissionMode").and_then(|v| v.as_str()).map(String::from),
         ..Default::default()
       };
-      if let Err(e) = db.update_session(session_id, &params) {
+      if let Err(e) = db.update_session(session_id, &db.sanitize_update_params(session_id, &params)) {
         eprintln!("[session.sync:update] Failed: {}", e);
       }
+      if let Some(status) = params.status.as_deref().filter(|s| *s != "running") {
+        if let Err(e) = db.finish_task_runs(session_id, status) {
+          eprintln!("[session.sync:update] Failed to close task runs: {}", e);
+        }
+      }
     }
     "message" => {
       if let Err(e) = db.record_message(session_id, &data) {
         eprintln!("[session.sync:message] Failed: {}", e);
+      } else if data.get("type").and_then(|v| v.as_str()) == Some("text") {
+        // Final assistant text is persisted, the streaming checkpoint is obsolete
+        if let Err(e) = db.clear_stream_draft(session_id) {
+          eprintln!("[session.sync:message] Failed to clear draft: {}", e);
+        }
       }
     }
     "todos" => {
@@ -437,6 +973,196 @@ fn apply_llm_models_fetched(db: &db::Database, payload: &Value) -> Result<(), St
     .map_err(|e| format!("save_llm_provider_settings: {}", e))
 }
 
+/// How long fetched model metadata (pricing, context length) stays fresh before refetching.
+const MODEL_METADATA_TTL_MS: i64 = 24 * 60 * 60 * 1000;
+
+fn model_metadata_cache_key(provider_id: &str) -> String {
+  format!("model_metadata_fetched_at:{provider_id}")
+}
+
+/// Keychain service that `provider.key.set` stores provider keys under
+const KEYCHAIN_SERVICE: &str = "ValeDesk";
+
+fn keychain_entry(key_ref: &str) -> Result<keyring::Entry, String> {
+  keyring::Entry::new(KEYCHAIN_SERVICE, key_ref).map_err(|e| format!("keychain entry '{}': {}", key_ref, e))
+}
+
+/// API key for native requests, resolved from the keychain at call time when the provider has a `keyRef`
+fn provider_api_key(provider: &LLMProvider) -> Option<String> {
+  let key = match provider.key_ref() {
+    Some(key_ref) => match keychain_entry(key_ref).and_then(|entry| entry.get_password().map_err(|e| e.to_string())) {
+      Ok(key) => key,
+      Err(e) => {
+        eprintln!("[keychain] No key for provider {}: {}", provider.id, e);
+        return None;
+      }
+    },
+    None => provider.api_key.clone()?,
+  };
+  Some(key.trim().to_string()).filter(|k| !k.is_empty())
+}
+
+/// `provider.keys.sync` for the sidecar: resolved keys of keychain-backed providers, which the DB
+/// (and the sidecar's settings file) only reference by `keyRef`. The sidecar keeps them in memory.
+fn provider_keys_event(db: &Database) -> Value {
+  let keys: serde_json::Map<String, Value> = db.list_providers().unwrap_or_default()
+    .iter()
+    .filter(|p| p.key_ref().is_some())
+    .filter_map(|p| provider_api_key(p).map(|key| (p.id.clone(), json!(key))))
+    .collect();
+  json!({ "type": "provider.keys.sync", "payload": { "keys": keys } })
+}
+
+/// Push provider settings plus keychain keys to the sidecar after a key moved in or out of the keychain.
+/// The keys go as a request so a sidecar that failed to apply them is reported rather than missed.
+fn resync_sidecar_providers(app: &tauri::AppHandle, state: &AppState, settings: &LLMProviderSettings) -> Result<(), String> {
+  send_to_sidecar(app.clone(), state, &json!({
+    "type": "llm.providers.save",
+    "payload": { "settings": settings }
+  }))?;
+
+  let keys_event = provider_keys_event(&state.db);
+  let app = app.clone();
+  std::thread::spawn(move || {
+    let state = app.state::<AppState>();
+    let timeout = std::time::Duration::from_millis(SIDECAR_REQUEST_TIMEOUT_MS);
+    match state.sidecar.request(&keys_event, timeout) {
+      Ok(result) if result.get("success").and_then(|v| v.as_bool()) == Some(true) => {}
+      Ok(result) => {
+        let error = result.get("error").and_then(|v| v.as_str()).unwrap_or("unknown error");
+        let _ = emit_server_event_app(&app, &json!({
+          "type": "runner.error",
+          "payload": { "message": format!("The engine did not apply the provider keys: {}", error) }
+        }));
+      }
+      // Not running (a restart hands it the keys on start) or hung
+      Err(error) => eprintln!("{error}; provider keys not confirmed"),
+    }
+  });
+  Ok(())
+}
+
+/// Base URL to use for a provider, falling back to the well-known endpoint for hosted types.
+fn provider_base_url(provider: &LLMProvider) -> Option<String> {
+  if let Some(url) = provider.base_url.as_deref() {
+    if !url.trim().is_empty() {
+      return Some(url.trim().to_string());
+    }
+  }
+  match provider.provider_type.as_str() {
+    "openrouter" => Some("https://openrouter.ai/api/v1".to_string()),
+    "openai" => Some("https://api.openai.com/v1".to_string()),
+    _ => None,
+  }
+}
+
+/// OpenRouter reports prices as USD per token (usually as strings); convert to USD per 1M tokens.
+fn parse_price_per_million(value: Option<&Value>) -> Option<f64> {
+  let per_token = match value? {
+    Value::String(s) => s.trim().parse::<f64>().ok()?,
+    Value::Number(n) => n.as_f64()?,
+    _ => return None,
+  };
+  // Negative values mean "variable pricing" on OpenRouter
+  if per_token < 0.0 {
+    return None;
+  }
+  Some(per_token * 1_000_000.0)
+}
+
+/// Applies a provider's `/models` response onto the stored models' config (context length, pricing).
+/// Returns the number of models updated. Extracted for testability.
+fn apply_model_metadata(db: &db::Database, provider_id: &str, response: &Value, now: i64) -> Result<usize, String> {
+  let list = response
+    .get("data")
+    .and_then(|v| v.as_array())
+    .or_else(|| response.as_array())
+    .ok_or_else(|| "[provider.models.metadata] response has no model list".to_string())?;
+
+  let mut by_id: HashMap<&str, &Value> = HashMap::new();
+  for item in list {
+    if let Some(id) = item.get("id").and_then(|v| v.as_str()) {
+      by_id.insert(id, item);
+    }
+  }
+
+  let models = db
+    .list_models_by_provider(provider_id)
+    .map_err(|e| format!("[provider.models.metadata] {}", e))?;
+
+  let mut updated: Vec<LLMModel> = Vec::new();
+  for mut model in models {
+    // Stored ids are usually "<providerId>::<remoteId>"
+    let remote_id = model.id.split_once("::").map(|(_, rest)| rest).unwrap_or(&model.id);
+    let Some(meta) = by_id.get(remote_id).or_else(|| by_id.get(model.name.as_str())).copied() else {
+      continue;
+    };
+
+    let mut config = db::ModelConfig::from_config(model.config.as_ref());
+    if let Some(ctx) = meta
+      .get("context_length")
+      .or_else(|| meta.get("context_window"))
+      .and_then(|v| v.as_i64())
+    {
+      config.context_length = Some(ctx);
+    }
+    if let Some(max_out) = meta
+      .get("top_provider")
+      .and_then(|t| t.get("max_completion_tokens"))
+      .and_then(|v| v.as_i64())
+    {
+      config.max_output_tokens = Some(max_out);
+    }
+    if let Some(pricing) = meta.get("pricing") {
+      if let Some(price) = parse_price_per_million(pricing.get("prompt")) {
+        config.input_price_per_million = Some(price);
+      }
+      if let Some(price) = parse_price_per_million(pricing.get("completion")) {
+        config.output_price_per_million = Some(price);
+      }
+    }
+    config.metadata_fetched_at = Some(now);
+    model.config = Some(config.to_value());
+    updated.push(model);
+  }
+
+  db.save_models_bulk(&updated)
+    .map_err(|e| format!("[provider.models.metadata] save failed: {}", e))?;
+  Ok(updated.len())
+}
+
+fn fetch_provider_models_blocking(client: &reqwest::blocking::Client, base_url: &str, api_key: Option<&str>) -> Result<Value, String> {
+  let url = build_models_url(base_url)?;
+  let mut req = client.get(&url).timeout(std::time::Duration::from_secs(30));
+  if let Some(key) = api_key {
+    if !key.trim().is_empty() {
+      req = req.bearer_auth(key.trim());
+    }
+  }
+
+  let resp = req.send().map_err(|e| format!("[provider.models.metadata] request failed: {e}"))?;
+  let status = resp.status();
+  let body = resp.text().map_err(|e| format!("[provider.models.metadata] failed to read response: {e}"))?;
+  if !status.is_success() {
+    return Err(format!("[provider.models.metadata] http {status}: {body}"));
+  }
+  serde_json::from_str(&body).map_err(|e| format!("[provider.models.metadata] invalid json: {e}"))
+}
+
+/// Model ids from an OpenAI-style `/models` response (`{"data": [...]}` or a bare array)
+fn remote_model_ids(response: &Value) -> Result<Vec<String>, String> {
+  let list = response
+    .get("data")
+    .and_then(|v| v.as_array())
+    .or_else(|| response.as_array())
+    .ok_or_else(|| "[provider.models.sync] response has no model list".to_string())?;
+  Ok(list
+    .iter()
+    .filter_map(|item| item.get("id").and_then(|v| v.as_str()))
+    .map(String::from)
+    .collect())
+}
+
 fn normalize_llm_provider_settings(value: Option<Value>) -> Value {
   let mut obj = match value {
     Some(Value::Object(o)) => o,
@@ -506,37 +1232,198 @@ struct AppState {
   sidecar: SidecarState,
   scheduler: SchedulerService,
   voice: VoiceState,
+  recent_events: Mutex<EventLog>,
+  http: HttpClients,
+  /// Running fs.tree walks by walk id → cancel flag
+  tree_walks: Mutex<HashMap<String, Arc<std::sync::atomic::AtomicBool>>>,
+  stream_activity: Mutex<StreamActivity>,
 }
 
 #[derive(Default)]
 struct SidecarState {
   child: Mutex<Option<SidecarChild>>,
+  restarts: Mutex<SidecarRestartPolicy>,
+  requests: Arc<SidecarRequests>,
+  /// Some while a background restart waits out its backoff; messages sent meanwhile queue here
+  restarting: Mutex<Option<Vec<Value>>>,
+  /// After a crash loop, no respawns before this time (ms)
+  fatal_until: Mutex<Option<u64>>,
+}
+
+impl SidecarState {
+  /// Count a crash against the restart policy. Returns the backoff before respawning, or None
+  /// when the budget is spent, in which case respawns are refused until the window passes.
+  fn record_crash(&self, now: u64) -> Result<Option<u64>, String> {
+    let delay = self.restarts.lock()
+      .map_err(|_| "[sidecar] restart lock poisoned".to_string())?
+      .next_delay(now);
+    if delay.is_none() {
+      *self.fatal_until.lock().map_err(|_| "[sidecar] restart lock poisoned".to_string())? = Some(now + SIDECAR_RESTART_WINDOW_MS);
+    }
+    Ok(delay)
+  }
+
+  /// Err while a crash loop keeps the sidecar down
+  fn check_crash_loop(&self, now: u64) -> Result<(), String> {
+    let mut fatal_until = self.fatal_until.lock().map_err(|_| "[sidecar] restart lock poisoned".to_string())?;
+    match *fatal_until {
+      Some(until) if now < until => Err(format!(
+        "[sidecar] engine crashed {SIDECAR_MAX_RESTARTS} times within {SIDECAR_RESTART_WINDOW_MS}ms, not restarting for another {}ms",
+        until - now
+      )),
+      _ => {
+        *fatal_until = None;
+        Ok(())
+      }
+    }
+  }
+
+  /// Send `event` to the sidecar as a `sidecar-request` and wait for its `sidecar-response`
+  fn request(&self, event: &Value, timeout: std::time::Duration) -> Result<Value, String> {
+    self.requests.request(|msg| send_to_sidecar_raw(self, msg), event, timeout)
+  }
 }
 
 struct SidecarChild {
   stdin: std::process::ChildStdin,
-  #[allow(dead_code)]
   child: Child,
 }
 
-fn resolve_sidecar_entry() -> Result<PathBuf, String> {
-  if let Ok(p) = std::env::var("VALERA_SIDECAR_ENTRY") {
-    if !p.trim().is_empty() {
-      return Ok(PathBuf::from(p));
-    }
-  }
+const SIDECAR_RESTART_BACKOFF_BASE_MS: u64 = 500;
+const SIDECAR_RESTART_BACKOFF_MAX_MS: u64 = 8_000;
+/// More crashes than this within the window and the sidecar is left down (`sidecar.fatal`)
+const SIDECAR_MAX_RESTARTS: usize = 5;
+const SIDECAR_RESTART_WINDOW_MS: u64 = 60_000;
 
-  #[cfg(debug_assertions)]
-  {
-    // Dev default: run from workspace root (ValeDesk/)
-    let candidate = PathBuf::from("dist-sidecar/sidecar/main.js");
-    if candidate.exists() {
-      return Ok(candidate);
+/// Exponential backoff for respawning a crashed sidecar, with a crash-loop guard
+#[derive(Default)]
+struct SidecarRestartPolicy {
+  /// When recent restarts happened (ms), oldest first
+  recent: std::collections::VecDeque<u64>,
+}
+
+impl SidecarRestartPolicy {
+  /// Delay before the next restart, or None when the window's restart budget is spent
+  fn next_delay(&mut self, now: u64) -> Option<u64> {
+    while self.recent.front().is_some_and(|t| now.saturating_sub(*t) > SIDECAR_RESTART_WINDOW_MS) {
+      self.recent.pop_front();
     }
-    return Err("dist-sidecar/sidecar/main.js not found. Run npm run transpile:sidecar".to_string());
+    if self.recent.len() >= SIDECAR_MAX_RESTARTS {
+      return None;
+    }
+    let delay = SIDECAR_RESTART_BACKOFF_BASE_MS
+      .saturating_mul(1 << self.recent.len())
+      .min(SIDECAR_RESTART_BACKOFF_MAX_MS);
+    self.recent.push_back(now);
+    Some(delay)
   }
+}
 
-  #[cfg(not(debug_assertions))]
+/// How long a message sent to the sidecar waits for its ack/response
+const SIDECAR_REQUEST_TIMEOUT_MS: u64 = 10_000;
+
+/// Messages sent to the sidecar that still wait for a reply, keyed by requestId
+#[derive(Default)]
+struct SidecarRequests {
+  pending: Mutex<HashMap<String, std::sync::mpsc::SyncSender<Value>>>,
+  next_id: std::sync::atomic::AtomicU64,
+}
+
+impl SidecarRequests {
+  fn register(&self, request_id: &str) -> std::sync::mpsc::Receiver<Value> {
+    let (tx, rx) = std::sync::mpsc::sync_channel(1);
+    if let Ok(mut pending) = self.pending.lock() {
+      pending.insert(request_id.to_string(), tx);
+    }
+    rx
+  }
+
+  /// Hand a reply to whoever waits on `request_id`; false when nobody does (late or unknown)
+  fn resolve(&self, request_id: &str, value: Value) -> bool {
+    let sender = self.pending.lock().ok().and_then(|mut pending| pending.remove(request_id));
+    match sender {
+      Some(tx) => tx.try_send(value).is_ok(),
+      None => false,
+    }
+  }
+
+  fn forget(&self, request_id: &str) {
+    if let Ok(mut pending) = self.pending.lock() {
+      pending.remove(request_id);
+    }
+  }
+
+  fn len(&self) -> usize {
+    self.pending.lock().map(|pending| pending.len()).unwrap_or(0)
+  }
+
+  /// Route a `sidecar-ack` / `sidecar-response` line from stdout; false for other message types
+  fn route(&self, msg: &Value) -> bool {
+    let msg_type = msg.get("type").and_then(|v| v.as_str()).unwrap_or("");
+    if msg_type != "sidecar-ack" && msg_type != "sidecar-response" {
+      return false;
+    }
+    let request_id = msg.get("requestId").and_then(|v| v.as_str()).unwrap_or("");
+    let value = msg.get("result").cloned().unwrap_or(Value::Null);
+    if !self.resolve(request_id, value) {
+      eprintln!("[sidecar] {msg_type} for unknown or expired request {request_id:?}");
+    }
+    true
+  }
+
+  /// Expect a `sidecar-ack` for `request_id`; log and drop the entry if none arrives in time
+  fn track_ack(self: &Arc<Self>, request_id: &str, timeout: std::time::Duration) {
+    let rx = self.register(request_id);
+    let requests = Arc::clone(self);
+    let request_id = request_id.to_string();
+    std::thread::spawn(move || {
+      if rx.recv_timeout(timeout).is_err() {
+        requests.forget(&request_id);
+        eprintln!("[sidecar] ✗ no ack for {request_id} after {}ms", timeout.as_millis());
+      }
+    });
+  }
+
+  /// Send `event` through `send` and block until the matching response or `timeout`
+  fn request(
+    &self,
+    send: impl FnOnce(&Value) -> Result<(), String>,
+    event: &Value,
+    timeout: std::time::Duration,
+  ) -> Result<Value, String> {
+    let id = self.next_id.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+    let request_id = format!("rust-{id}");
+    let rx = self.register(&request_id);
+    let msg = json!({ "type": "sidecar-request", "requestId": request_id, "event": event });
+    if let Err(error) = send(&msg) {
+      self.forget(&request_id);
+      return Err(error);
+    }
+    rx.recv_timeout(timeout).map_err(|_| {
+      self.forget(&request_id);
+      format!("[sidecar] request {request_id} timed out after {}ms", timeout.as_millis())
+    })
+  }
+}
+
+fn resolve_sidecar_entry() -> Result<PathBuf, String> {
+  if let Ok(p) = std::env::var("VALERA_SIDECAR_ENTRY") {
+    if !p.trim().is_empty() {
+      return Ok(PathBuf::from(p));
+    }
+  }
+
+  #[cfg(debug_assertions)]
+  {
+    // Dev default: run from workspace root (ValeDesk/)
+    let candidate = PathBuf::from("dist-sidecar/sidecar/main.js");
+    if candidate.exists() {
+      return Ok(candidate);
+    }
+    return Err("dist-sidecar/sidecar/main.js not found. Run npm run transpile:sidecar".to_string());
+  }
+
+  #[cfg(not(debug_assertions))]
   {
       // Prod: Look for sidecar binary in the executables directory
       // Name formatting: valera-sidecar-<target-triple>
@@ -557,6 +1444,69 @@ fn resolve_sidecar_entry() -> Result<PathBuf, String> {
   }
 }
 
+/// Default interval between checkpoints of streaming assistant text.
+/// Overridable via the `stream_checkpoint_interval_ms` setting (0 disables).
+const STREAM_CHECKPOINT_INTERVAL_MS: i64 = 3000;
+
+fn stream_checkpoint_interval_ms(db: &Database) -> i64 {
+  db.get_setting("stream_checkpoint_interval_ms")
+    .ok()
+    .flatten()
+    .and_then(|v| v.trim().parse::<i64>().ok())
+    .filter(|v| *v >= 0)
+    .unwrap_or(STREAM_CHECKPOINT_INTERVAL_MS)
+}
+
+#[derive(Default)]
+struct StreamDraft {
+  content: String,
+  saved_at: i64,
+}
+
+/// Accumulates text deltas from `stream.message` events per session and persists them
+/// to `stream_drafts` at most every `interval_ms`, so a crash loses only the tail.
+fn checkpoint_stream_event(
+  db: &Database,
+  drafts: &mut HashMap<String, StreamDraft>,
+  payload: &Value,
+  interval_ms: i64,
+  now: i64,
+) {
+  if interval_ms <= 0 {
+    return;
+  }
+  let Some(session_id) = payload.get("sessionId").and_then(|v| v.as_str()) else { return };
+  let Some(message) = payload.get("message") else { return };
+  if message.get("type").and_then(|v| v.as_str()) != Some("stream_event") {
+    return;
+  }
+  let Some(stream_event) = message.get("event") else { return };
+
+  match stream_event.get("type").and_then(|v| v.as_str()) {
+    Some("content_block_start") => {
+      drafts.insert(session_id.to_string(), StreamDraft { content: String::new(), saved_at: now });
+    }
+    Some("content_block_delta") => {
+      let Some(text) = stream_event.pointer("/delta/text").and_then(|v| v.as_str()) else { return };
+      let draft = drafts.entry(session_id.to_string()).or_insert_with(|| StreamDraft { content: String::new(), saved_at: now });
+      draft.content.push_str(text);
+      if now - draft.saved_at >= interval_ms {
+        if let Err(e) = db.save_stream_draft(session_id, &draft.content) {
+          eprintln!("[stream.checkpoint] Failed to save draft: {}", e);
+        }
+        draft.saved_at = now;
+      }
+    }
+    Some("content_block_stop") => {
+      drafts.remove(session_id);
+      if let Err(e) = db.clear_stream_draft(session_id) {
+        eprintln!("[stream.checkpoint] Failed to clear draft: {}", e);
+      }
+    }
+    _ => {}
+  }
+}
+
 fn resolve_node_bin() -> Result<String, String> {
   if let Ok(v) = std::env::var("VALERA_NODE_BIN") {
     if !v.trim().is_empty() {
@@ -566,11 +1516,146 @@ fn resolve_node_bin() -> Result<String, String> {
   Ok("node".to_string())
 }
 
+/// What `start_sidecar` would launch, for diagnosing a sidecar that won't start
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct SidecarConfig {
+  entry: Option<String>,
+  entry_error: Option<String>,
+  entry_exists: bool,
+  entry_executable: bool,
+  /// Dev builds run the entry with node; release builds execute the bundled binary directly
+  uses_node: bool,
+  node_bin: String,
+  node_version: Option<String>,
+  node_error: Option<String>,
+  env_sidecar_entry: Option<String>,
+  env_node_bin: Option<String>,
+}
+
+fn is_executable(path: &Path) -> bool {
+  #[cfg(unix)]
+  {
+    use std::os::unix::fs::PermissionsExt;
+    fs::metadata(path).map(|m| m.is_file() && m.permissions().mode() & 0o111 != 0).unwrap_or(false)
+  }
+  #[cfg(not(unix))]
+  {
+    path.is_file()
+  }
+}
+
+fn sidecar_config() -> SidecarConfig {
+  let (entry, entry_error) = match resolve_sidecar_entry() {
+    Ok(path) => (Some(path), None),
+    Err(e) => (None, Some(e)),
+  };
+  let node_bin = resolve_node_bin().unwrap_or_else(|_| "node".to_string());
+  let (node_version, node_error) = match Command::new(&node_bin).arg("--version").output() {
+    Ok(output) if output.status.success() => (Some(String::from_utf8_lossy(&output.stdout).trim().to_string()), None),
+    Ok(output) => (None, Some(format!("{} --version exited with {}: {}", node_bin, output.status, String::from_utf8_lossy(&output.stderr).trim()))),
+    Err(e) => (None, Some(format!("Failed to run {}: {}", node_bin, e))),
+  };
+
+  SidecarConfig {
+    entry_exists: entry.as_ref().is_some_and(|p| p.exists()),
+    entry_executable: entry.as_deref().is_some_and(is_executable),
+    entry: entry.map(|p| p.to_string_lossy().to_string()),
+    entry_error,
+    uses_node: cfg!(debug_assertions),
+    node_bin,
+    node_version,
+    node_error,
+    env_sidecar_entry: std::env::var("VALERA_SIDECAR_ENTRY").ok(),
+    env_node_bin: std::env::var("VALERA_NODE_BIN").ok(),
+  }
+}
+
+/// Upper bound for a single JSON message being reassembled from several stdout lines
+const SIDECAR_MAX_MESSAGE_BYTES: usize = 32 * 1024 * 1024;
+
+/// Reassembles sidecar stdout into JSON messages. A message normally fits on one line, but if
+/// one arrives split across lines the pieces are accumulated until they parse.
+#[derive(Default)]
+struct SidecarLineBuffer {
+  pending: String,
+}
+
+impl SidecarLineBuffer {
+  /// Feed one stdout line. Returns None while there is nothing to deliver (blank/log lines or an
+  /// incomplete message), otherwise the parsed message or why it was dropped.
+  fn push(&mut self, line: &str) -> Option<Result<Value, String>> {
+    let trimmed = line.trim();
+    if self.pending.is_empty() {
+      // Skip debug log lines (not JSON) - they start with [ or other non-JSON chars
+      if !trimmed.starts_with('{') {
+        return None;
+      }
+    } else {
+      self.pending.push('\n');
+    }
+    self.pending.push_str(line);
+
+    match serde_json::from_str::<Value>(&self.pending) {
+      Ok(value) => {
+        self.pending.clear();
+        Some(Ok(value))
+      }
+      Err(error) if error.is_eof() => {
+        if self.pending.len() > SIDECAR_MAX_MESSAGE_BYTES {
+          let size = self.pending.len();
+          self.pending.clear();
+          return Some(Err(format!("incomplete message exceeded {SIDECAR_MAX_MESSAGE_BYTES} bytes ({size} buffered), dropped")));
+        }
+        None
+      }
+      Err(error) => {
+        let dropped = std::mem::take(&mut self.pending);
+        let preview: String = dropped.chars().take(200).collect();
+        // A fresh object after a garbled fragment: drop the fragment, keep the new message
+        if trimmed.starts_with('{') && dropped.len() > line.len() {
+          eprintln!("[sidecar] Dropping garbled partial message: {preview}");
+          return self.push(line);
+        }
+        Some(Err(format!("{error}; line={preview}")))
+      }
+    }
+  }
+}
+
+/// Consecutive unparseable sidecar messages before the UI is told the engine is misbehaving
+const SIDECAR_PROTOCOL_ERROR_THRESHOLD: usize = 3;
+
+/// Tracks runs of sidecar messages that failed to parse, so a broken protocol is surfaced
+/// instead of only logged
+#[derive(Default)]
+struct SidecarProtocolMonitor {
+  consecutive: usize,
+  samples: Vec<String>,
+}
+
+impl SidecarProtocolMonitor {
+  fn record_ok(&mut self) {
+    self.consecutive = 0;
+    self.samples.clear();
+  }
+
+  /// Returns the samples collected so far exactly once per run, when it reaches the threshold
+  fn record_failure(&mut self, error: &str) -> Option<Vec<String>> {
+    self.consecutive += 1;
+    if self.samples.len() < SIDECAR_PROTOCOL_ERROR_THRESHOLD {
+      self.samples.push(error.to_string());
+    }
+    (self.consecutive == SIDECAR_PROTOCOL_ERROR_THRESHOLD).then(|| self.samples.clone())
+  }
+}
+
 fn start_sidecar(app: tauri::AppHandle, sidecar_state: &SidecarState) -> Result<(), String> {
   let mut guard = sidecar_state.child.lock().map_err(|_| "[sidecar] state lock poisoned".to_string())?;
   if guard.is_some() {
     return Ok(());
   }
+  sidecar_state.check_crash_loop(now_ms().unwrap_or(0))?;
 
   let entry = resolve_sidecar_entry()?;
   if !entry.exists() {
@@ -610,7 +1695,7 @@ fn start_sidecar(app: tauri::AppHandle, sidecar_state: &SidecarState) -> Result<
     .spawn()
     .map_err(|error| format!("[sidecar] Failed to spawn sidecar: {error}"))?;
 
-  let stdin = child.stdin.take().ok_or_else(|| "[sidecar] Failed to capture stdin".to_string())?;
+  let mut stdin = child.stdin.take().ok_or_else(|| "[sidecar] Failed to capture stdin".to_string())?;
   let stdout = child.stdout.take().ok_or_else(|| "[sidecar] Failed to capture stdout".to_string())?;
   let stderr = child.stderr.take().ok_or_else(|| "[sidecar] Failed to capture stderr".to_string())?;
 
@@ -619,22 +1704,34 @@ fn start_sidecar(app: tauri::AppHandle, sidecar_state: &SidecarState) -> Result<
     let app_handle = app.clone();
     std::thread::spawn(move || {
       let reader = BufReader::new(stdout);
+      let mut stream_drafts: HashMap<String, StreamDraft> = HashMap::new();
+      let checkpoint_interval_ms = {
+        let state: tauri::State<'_, AppState> = app_handle.state();
+        stream_checkpoint_interval_ms(&state.db)
+      };
+      let mut line_buffer = SidecarLineBuffer::default();
+      let mut protocol_monitor = SidecarProtocolMonitor::default();
       for line in reader.lines() {
         match line {
           Ok(raw) => {
-            let raw: String = raw;
-            let trimmed = raw.trim();
-            if trimmed.is_empty() {
-              continue;
-            }
-            // Skip debug log lines (not JSON) - they start with [ or other non-JSON chars
-            if !trimmed.starts_with('{') {
-              continue;
-            }
-            let parsed: serde_json::Value = match serde_json::from_str(&raw) {
-              Ok(v) => v,
-              Err(error) => {
-                eprintln!("[sidecar] Invalid JSON from stdout: {error}; line={raw}");
+            let parsed: serde_json::Value = match line_buffer.push(&raw) {
+              None => continue,
+              Some(Ok(v)) => {
+                protocol_monitor.record_ok();
+                v
+              }
+              Some(Err(error)) => {
+                eprintln!("[sidecar] Invalid JSON from stdout: {error}");
+                if let Some(samples) = protocol_monitor.record_failure(&error) {
+                  let _ = emit_server_event_app(&app_handle, &json!({
+                    "type": "sidecar.protocol_error",
+                    "payload": {
+                      "message": "The engine is sending unexpected data",
+                      "consecutiveFailures": SIDECAR_PROTOCOL_ERROR_THRESHOLD,
+                      "samples": samples
+                    }
+                  }));
+                }
                 continue;
               }
             };
@@ -649,6 +1746,9 @@ fn start_sidecar(app: tauri::AppHandle, sidecar_state: &SidecarState) -> Result<
                   if let Some(payload) = event.get("payload") {
                     let state: tauri::State<'_, AppState> = app_handle.state();
                     handle_session_sync(&state.db, payload);
+                    if payload.get("syncType").and_then(|v| v.as_str()) == Some("create") {
+                      enforce_session_cap(&app_handle, &state.db, payload.get("sessionId").and_then(|v| v.as_str()));
+                    }
                   }
                   continue; // Don't emit to frontend
                 }
@@ -746,6 +1846,55 @@ fn start_sidecar(app: tauri::AppHandle, sidecar_state: &SidecarState) -> Result<
                   // Continue to emit to frontend
                 }
 
+                // Track streaming activity for the idle timeout watchdog
+                if event_type == "stream.message" || event_type == "session.status" {
+                  if let Some(payload) = event.get("payload") {
+                    if let Some(session_id) = payload.get("sessionId").and_then(|v| v.as_str()) {
+                      let state: tauri::State<'_, AppState> = app_handle.state();
+                      let mut activity = state.stream_activity.lock().unwrap();
+                      let status = payload.get("status").and_then(|v| v.as_str());
+                      if event_type == "stream.message" || status == Some("running") {
+                        activity.touch(session_id, chrono::Utc::now().timestamp_millis());
+                      } else {
+                        activity.forget(session_id);
+                      }
+                    }
+                  }
+                  // Continue to emit to frontend
+                }
+
+                if event_type == "permission.request" {
+                  if let Some(session_id) = event.get("payload").and_then(|p| p.get("sessionId")).and_then(|v| v.as_str()) {
+                    let state: tauri::State<'_, AppState> = app_handle.state();
+                    state.stream_activity.lock().unwrap().set_awaiting_permission(session_id, true);
+                  }
+                }
+
+                // Checkpoint streaming assistant text so a crash doesn't lose the whole turn
+                if event_type == "stream.message" {
+                  if let Some(payload) = event.get("payload") {
+                    let state: tauri::State<'_, AppState> = app_handle.state();
+                    checkpoint_stream_event(&state.db, &mut stream_drafts, payload, checkpoint_interval_ms, chrono::Utc::now().timestamp_millis());
+                  }
+                  // Continue to emit to frontend
+                }
+
+                // A turn that ended (finished, stopped or failed) leaves no stream to recover
+                if event_type == "session.status" {
+                  let payload = event.get("payload");
+                  let status = payload.and_then(|p| p.get("status")).and_then(|v| v.as_str());
+                  if let Some(session_id) = payload.and_then(|p| p.get("sessionId")).and_then(|v| v.as_str()) {
+                    if status.is_some_and(|s| s != "running") {
+                      stream_drafts.remove(session_id);
+                      let state: tauri::State<'_, AppState> = app_handle.state();
+                      if let Err(e) = state.db.clear_stream_draft(session_id) {
+                        eprintln!("[stream.checkpoint] Failed to clear draft: {}", e);
+                      }
+                    }
+                  }
+                  // Continue to emit to frontend
+                }
+
                 // Only log non-streaming events to reduce noise
                 if event_type != "stream.message" {
                   eprintln!("[sidecar] → {}", event_type);
@@ -757,6 +1906,13 @@ fn start_sidecar(app: tauri::AppHandle, sidecar_state: &SidecarState) -> Result<
               continue;
             }
 
+            // Replies to requests/responses we sent over stdin
+            if msg_type == "sidecar-ack" || msg_type == "sidecar-response" {
+              let state: tauri::State<'_, AppState> = app_handle.state();
+              state.sidecar.requests.route(&parsed);
+              continue;
+            }
+
             // Log messages from sidecar
             if msg_type == "log" {
               eprintln!("[sidecar] {raw}");
@@ -796,25 +1952,133 @@ fn start_sidecar(app: tauri::AppHandle, sidecar_state: &SidecarState) -> Result<
     });
   }
 
+  // Keychain-backed keys first, so nothing the sidecar runs sees a provider without its key
+  {
+    let state: tauri::State<'_, AppState> = app.state();
+    let msg = json!({ "type": "client-event", "event": provider_keys_event(&state.db) });
+    if let Err(error) = writeln!(stdin, "{msg}").and_then(|()| stdin.flush()) {
+      eprintln!("[sidecar] Failed to send provider keys: {error}");
+    }
+  }
+
   *guard = Some(SidecarChild { stdin, child });
   Ok(())
 }
 
-fn send_to_sidecar(app: tauri::AppHandle, state: &AppState, event: &Value) -> Result<(), String> {
-  start_sidecar(app, &state.sidecar)?;
+/// Reap the sidecar if its process has exited (or, with `force`, when its stdin broke) and
+/// respawn it on a background thread after the restart policy's backoff. Returns whether a
+/// restart was scheduled. Crash loops emit `sidecar.fatal` and leave it down until the window passes.
+fn restart_dead_sidecar(app: &tauri::AppHandle, sidecar_state: &SidecarState, force: bool) -> Result<bool, String> {
+  let exit = {
+    let mut guard = sidecar_state.child.lock().map_err(|_| "[sidecar] state lock poisoned".to_string())?;
+    let Some(running) = guard.as_mut() else { return Ok(false) };
+    let exit = match running.child.try_wait() {
+      Ok(Some(status)) => status.to_string(),
+      Ok(None) if !force => return Ok(false),
+      Ok(None) => {
+        let _ = running.child.kill();
+        let _ = running.child.wait();
+        "stdin closed".to_string()
+      }
+      Err(error) => format!("unknown ({error})"),
+    };
+    *guard = None;
+    exit
+  };
+  eprintln!("[sidecar] Process exited: {exit}");
+
+  let Some(delay_ms) = sidecar_state.record_crash(now_ms().unwrap_or(0))? else {
+    let _ = emit_server_event_app(app, &json!({
+      "type": "sidecar.fatal",
+      "payload": {
+        "message": "The engine keeps crashing and was not restarted",
+        "exit": exit,
+        "maxRestarts": SIDECAR_MAX_RESTARTS,
+        "windowMs": SIDECAR_RESTART_WINDOW_MS
+      }
+    }));
+    return Err(format!("[sidecar] crashed {SIDECAR_MAX_RESTARTS} times within {SIDECAR_RESTART_WINDOW_MS}ms, not restarting"));
+  };
+  {
+    let mut restarting = sidecar_state.restarting.lock().map_err(|_| "[sidecar] restart lock poisoned".to_string())?;
+    if restarting.is_some() {
+      return Ok(true);
+    }
+    *restarting = Some(Vec::new());
+  }
 
-  let mut guard = state.sidecar.child.lock().map_err(|_| "[sidecar] state lock poisoned".to_string())?;
-  let child = guard.as_mut().ok_or_else(|| "[sidecar] sidecar is not running".to_string())?;
+  let app = app.clone();
+  std::thread::spawn(move || {
+    std::thread::sleep(std::time::Duration::from_millis(delay_ms));
+    let state = app.state::<AppState>();
+    let started = start_sidecar(app.clone(), &state.sidecar);
+    // Flush under the lock so nothing queued meanwhile is left behind or reordered
+    let Ok(mut restarting) = state.sidecar.restarting.lock() else { return };
+    let queued = restarting.take().unwrap_or_default();
+    match started {
+      Ok(()) => {
+        eprintln!("[sidecar] Restarted after {delay_ms}ms");
+        for msg in &queued {
+          if let Err(error) = send_to_sidecar_raw(&state.sidecar, msg) {
+            eprintln!("{error}; dropping queued message");
+          }
+        }
+        let _ = emit_server_event_app(&app, &json!({
+          "type": "sidecar.restarted",
+          "payload": { "exit": exit, "delayMs": delay_ms }
+        }));
+      }
+      Err(error) => {
+        eprintln!("{error}; dropped {} queued message(s)", queued.len());
+        let _ = emit_server_event_app(&app, &json!({
+          "type": "runner.error",
+          "payload": { "message": format!("Failed to restart the engine: {error}") }
+        }));
+      }
+    }
+  });
+  Ok(true)
+}
+
+/// Queue `msg` for the sidecar if a restart is pending; returns whether it was queued
+fn queue_during_sidecar_restart(sidecar_state: &SidecarState, msg: &Value) -> Result<bool, String> {
+  let mut restarting = sidecar_state.restarting.lock().map_err(|_| "[sidecar] restart lock poisoned".to_string())?;
+  match restarting.as_mut() {
+    Some(queue) => {
+      queue.push(msg.clone());
+      Ok(true)
+    }
+    None => Ok(false),
+  }
+}
 
+fn send_to_sidecar(app: tauri::AppHandle, state: &AppState, event: &Value) -> Result<(), String> {
   let msg = json!({ "type": "client-event", "event": event });
-  let raw = serde_json::to_string(&msg).map_err(|error| format!("[sidecar] Failed to serialize message: {error}"))?;
+  if let Err(error) = state.sidecar.check_crash_loop(now_ms().unwrap_or(0)) {
+    let _ = emit_server_event_app(&app, &json!({
+      "type": "runner.error",
+      "payload": { "message": "The engine keeps crashing; try again in a minute" }
+    }));
+    return Err(error);
+  }
+  restart_dead_sidecar(&app, &state.sidecar, false)?;
+  if queue_during_sidecar_restart(&state.sidecar, &msg)? {
+    return Ok(());
+  }
+  start_sidecar(app.clone(), &state.sidecar)?;
 
-  child
-    .stdin
-    .write_all(format!("{raw}\n").as_bytes())
-    .map_err(|error| format!("[sidecar] Failed to write to stdin: {error}"))?;
-  child.stdin.flush().map_err(|error| format!("[sidecar] Failed to flush stdin: {error}"))?;
-  Ok(())
+  match send_to_sidecar_raw(&state.sidecar, &msg) {
+    Ok(()) => Ok(()),
+    // Died between the liveness check and the write: hand it to the restart
+    Err(error) => {
+      eprintln!("{error}; restarting sidecar");
+      restart_dead_sidecar(&app, &state.sidecar, true)?;
+      if queue_during_sidecar_restart(&state.sidecar, &msg)? {
+        return Ok(());
+      }
+      Err(error)
+    }
+  }
 }
 
 #[tauri::command]
@@ -853,6 +2117,189 @@ fn list_directory(path: String) -> Result<Vec<FileItem>, String> {
   Ok(out)
 }
 
+/// Recursive search under `root` honoring .gitignore. A `glob` without `/` matches file names at
+/// any depth (`*.rs`); otherwise it matches the `/`-separated path relative to `root` (`src/**/*.ts`).
+#[tauri::command]
+fn search_files(root: String, glob: String, max_results: u32) -> Result<Vec<FileItem>, String> {
+  if root.trim().is_empty() {
+    return Err("[search_files] root is empty".to_string());
+  }
+
+  let root_dir = PathBuf::from(&root);
+  if !root_dir.exists() {
+    return Err(format!("[search_files] root does not exist: {}", root_dir.display()));
+  }
+  if !root_dir.is_dir() {
+    return Err(format!("[search_files] root is not a directory: {}", root_dir.display()));
+  }
+  let root_dir = root_dir.canonicalize().map_err(|error| format!("[search_files] canonicalize failed: {error}"))?;
+
+  let glob = glob.trim();
+  let matcher = globset::GlobBuilder::new(glob)
+    .literal_separator(true)
+    .build()
+    .map_err(|error| format!("[search_files] invalid glob {glob:?}: {error}"))?
+    .compile_matcher();
+  let match_name = !glob.contains('/');
+
+  let mut out: Vec<FileItem> = Vec::new();
+  if max_results == 0 {
+    return Ok(out);
+  }
+
+  // Symlinks are listed but not followed, so link cycles can't trap the walk
+  let walker = ignore::WalkBuilder::new(&root_dir)
+    .follow_links(false)
+    .require_git(false)
+    .sort_by_file_name(|a, b| a.cmp(b))
+    .build();
+
+  for entry in walker {
+    let Ok(entry) = entry else { continue };
+    if entry.depth() == 0 {
+      continue;
+    }
+    let path = entry.path();
+    let Ok(relative) = path.strip_prefix(&root_dir) else { continue };
+    let matched = if match_name { matcher.is_match(entry.file_name()) } else { matcher.is_match(relative) };
+    if !matched {
+      continue;
+    }
+    // A symlink can still point outside root; only report ones that resolve inside it
+    if entry.path_is_symlink() && !path.canonicalize().is_ok_and(|target| target.starts_with(&root_dir)) {
+      continue;
+    }
+    let Ok(meta) = entry.metadata() else { continue };
+
+    out.push(FileItem {
+      name: entry.file_name().to_string_lossy().to_string(),
+      path: path.to_string_lossy().to_string(),
+      is_directory: meta.is_dir(),
+      size: if meta.is_file() { Some(meta.len()) } else { None },
+    });
+    // Dropping the walker stops it: no need to finish the tree once the cap is hit
+    if out.len() >= max_results as usize {
+      break;
+    }
+  }
+
+  Ok(out)
+}
+
+// --- Streaming directory walk (fs.tree.*) ---
+
+/// Skipped unless the caller passes its own ignore list
+const DEFAULT_TREE_IGNORES: &[&str] = &[".git", "node_modules", "target", "dist", "build", ".venv", "__pycache__", ".DS_Store"];
+const TREE_WALK_BATCH_SIZE: usize = 500;
+const TREE_WALK_DEFAULT_MAX_ENTRIES: usize = 50_000;
+
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+struct TreeNode {
+  path: String,
+  /// Relative to the walk root, always `/`-separated
+  relative_path: String,
+  name: String,
+  is_directory: bool,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  size: Option<u64>,
+  depth: usize,
+}
+
+#[derive(Serialize, Debug, Default, PartialEq)]
+#[serde(rename_all = "camelCase")]
+struct TreeWalkSummary {
+  total: usize,
+  truncated: bool,
+  aborted: bool,
+}
+
+/// `*` matches any run of characters; everything else is literal. Matched against entry names.
+fn name_matches_pattern(name: &str, pattern: &str) -> bool {
+  let parts: Vec<&str> = pattern.split('*').collect();
+  if parts.len() == 1 {
+    return name == pattern;
+  }
+  let (first, last) = (parts[0], parts[parts.len() - 1]);
+  if !name.starts_with(first) || name.len() < first.len() + last.len() || !name.ends_with(last) {
+    return false;
+  }
+  let mut rest = &name[first.len()..name.len() - last.len()];
+  for part in &parts[1..parts.len() - 1] {
+    match rest.find(part) {
+      Some(idx) => rest = &rest[idx + part.len()..],
+      None => return false,
+    }
+  }
+  true
+}
+
+/// Depth-first walk that hands nodes to `on_batch` in groups of `batch_size`.
+/// Symlinks are listed but never followed; unreadable directories are skipped.
+fn walk_tree(
+  root: &Path,
+  ignore: &[String],
+  max_entries: usize,
+  batch_size: usize,
+  cancel: &std::sync::atomic::AtomicBool,
+  mut on_batch: impl FnMut(Vec<TreeNode>),
+) -> TreeWalkSummary {
+  let mut summary = TreeWalkSummary::default();
+  let mut batch: Vec<TreeNode> = Vec::new();
+  let mut stack: Vec<(PathBuf, usize)> = vec![(root.to_path_buf(), 0)];
+
+  'walk: while let Some((dir, depth)) = stack.pop() {
+    let Ok(entries) = fs::read_dir(&dir) else { continue };
+    let mut entries: Vec<fs::DirEntry> = entries.filter_map(|e| e.ok()).collect();
+    entries.sort_by_key(|e| e.file_name());
+    let mut subdirs = Vec::new();
+
+    for entry in entries {
+      if cancel.load(std::sync::atomic::Ordering::Relaxed) {
+        summary.aborted = true;
+        break 'walk;
+      }
+      if summary.total >= max_entries {
+        summary.truncated = true;
+        break 'walk;
+      }
+      let name = entry.file_name().to_string_lossy().to_string();
+      if ignore.iter().any(|pattern| name_matches_pattern(&name, pattern)) {
+        continue;
+      }
+      let Ok(meta) = fs::symlink_metadata(entry.path()) else { continue };
+      let path = entry.path();
+      let relative_path = path
+        .strip_prefix(root)
+        .map(|p| p.components().map(|c| c.as_os_str().to_string_lossy().to_string()).collect::<Vec<_>>().join("/"))
+        .unwrap_or_else(|_| name.clone());
+
+      if meta.is_dir() {
+        subdirs.push((path.clone(), depth + 1));
+      }
+      batch.push(TreeNode {
+        path: path.to_string_lossy().to_string(),
+        relative_path,
+        name,
+        is_directory: meta.is_dir(),
+        size: if meta.is_file() { Some(meta.len()) } else { None },
+        depth,
+      });
+      summary.total += 1;
+      if batch.len() >= batch_size {
+        on_batch(std::mem::take(&mut batch));
+      }
+    }
+    // Reverse so the stack pops subdirectories in name order
+    stack.extend(subdirs.into_iter().rev());
+  }
+
+  if !batch.is_empty() {
+    on_batch(batch);
+  }
+  summary
+}
+
 #[tauri::command]
 fn get_thumbnail(path: String, size: Option<u32>) -> Result<Option<String>, String> {
   let thumb_size = size.unwrap_or(128);
@@ -919,7 +2366,124 @@ fn get_file_text_preview(path: String, max_bytes: Option<usize>) -> Result<Optio
   Ok(Some(String::from_utf8_lossy(&buf).into_owned()))
 }
 
-#[tauri::command]
+/// Probe a directory by creating and removing a temp file in it
+fn cwd_writable(path: &Path) -> CwdWritable {
+  let fail = |reason: String| CwdWritable { writable: false, reason: Some(reason) };
+  if !path.is_dir() {
+    return fail(format!("Not a directory: {}", path.display()));
+  }
+  let probe = path.join(format!(".valedesk_write_test_{}", uuid::Uuid::new_v4()));
+  match fs::File::create(&probe) {
+    Ok(_) => {
+      if let Err(e) = fs::remove_file(&probe) {
+        eprintln!("[check_cwd_writable] Failed to remove probe {}: {e}", probe.display());
+      }
+      CwdWritable { writable: true, reason: None }
+    }
+    Err(e) => fail(format!("Cannot write to {}: {e}", path.display())),
+  }
+}
+
+/// Top-level marker file → ecosystem. The first match decides the primary ecosystem.
+const PROJECT_MARKERS: &[(&str, &str)] = &[
+  ("package.json", "node"),
+  ("Cargo.toml", "rust"),
+  ("pyproject.toml", "python"),
+  ("requirements.txt", "python"),
+  ("setup.py", "python"),
+  ("go.mod", "go"),
+];
+
+/// Only looks at the directory itself (no recursion), so it stays fast on big trees
+fn project_type_at(path: &Path) -> ProjectType {
+  let mut ecosystems: Vec<String> = Vec::new();
+  for (marker, ecosystem) in PROJECT_MARKERS {
+    if path.join(marker).is_file() && !ecosystems.iter().any(|e| e == ecosystem) {
+      ecosystems.push(ecosystem.to_string());
+    }
+  }
+  let primary = ecosystems.first().cloned();
+  let sandbox_language = match primary.as_deref() {
+    Some("node") => Some("javascript".to_string()),
+    Some("python") => Some("python".to_string()),
+    _ => None,
+  };
+  ProjectType {
+    ecosystems,
+    primary,
+    is_git_repo: path.join(".git").exists(),
+    sandbox_language,
+  }
+}
+
+#[tauri::command]
+fn detect_project_type(cwd: String) -> Result<ProjectType, String> {
+  let path = Path::new(cwd.trim());
+  if !path.is_dir() {
+    return Err(format!("[detect_project_type] not a directory: {}", path.display()));
+  }
+  Ok(project_type_at(path))
+}
+
+#[tauri::command]
+fn check_cwd_writable(path: String) -> CwdWritable {
+  if path.trim().is_empty() {
+    return CwdWritable { writable: false, reason: Some("Path is empty".to_string()) };
+  }
+  cwd_writable(Path::new(path.trim()))
+}
+
+/// How much of a file `inspect_file` samples to guess binary/encoding.
+const FILE_INSPECT_SAMPLE_BYTES: usize = 8192;
+/// Files above this size are not read into memory as text.
+const MAX_TEXT_FILE_BYTES: u64 = 10 * 1024 * 1024;
+
+fn inspect_file_at(path: &Path) -> Result<FileInspection, String> {
+  let meta = fs::metadata(path).map_err(|e| format!("Cannot stat {}: {e}", path.display()))?;
+  if !meta.is_file() {
+    return Err(format!("Not a file: {}", path.display()));
+  }
+
+  let mut file = fs::File::open(path).map_err(|e| format!("Cannot open {}: {e}", path.display()))?;
+  let mut sample = Vec::with_capacity(FILE_INSPECT_SAMPLE_BYTES);
+  Read::take(&mut file, FILE_INSPECT_SAMPLE_BYTES as u64)
+    .read_to_end(&mut sample)
+    .map_err(|e| format!("Read failed for {}: {e}", path.display()))?;
+
+  let encoding = if sample.starts_with(&[0xEF, 0xBB, 0xBF]) {
+    "utf-8-bom"
+  } else if sample.starts_with(&[0xFF, 0xFE]) {
+    "utf-16le"
+  } else if sample.starts_with(&[0xFE, 0xFF]) {
+    "utf-16be"
+  } else if sample.contains(&0) {
+    "binary"
+  } else {
+    match std::str::from_utf8(&sample) {
+      Ok(_) => "utf-8",
+      // Sample may end mid-character; only a hard error means it's not UTF-8
+      Err(e) if e.error_len().is_none() => "utf-8",
+      Err(_) => "unknown",
+    }
+  };
+
+  Ok(FileInspection {
+    size_bytes: meta.len(),
+    is_probably_binary: encoding == "binary",
+    encoding: encoding.to_string(),
+    too_large: meta.len() > MAX_TEXT_FILE_BYTES,
+  })
+}
+
+#[tauri::command]
+fn inspect_file(path: String) -> Result<FileInspection, String> {
+  if path.trim().is_empty() {
+    return Err("[inspect_file] path is empty".to_string());
+  }
+  inspect_file_at(Path::new(&path)).map_err(|e| format!("[inspect_file] {e}"))
+}
+
+#[tauri::command]
 fn read_memory() -> Result<String, String> {
   let path = memory_path()?;
   match fs::read_to_string(&path) {
@@ -1069,6 +2633,16 @@ fn get_file_new_content(params: GetFileContentParams) -> Result<String, String>
   eprintln!("[get_file_new_content] Reading file: cwd={}, file_path={}, full_path={}", 
     params.cwd, params.file_path, full_path.display());
   
+  // Refuse to pull huge or binary files into the diff viewer
+  if let Ok(inspection) = inspect_file_at(&full_path) {
+    if inspection.too_large || inspection.is_probably_binary {
+      return Err(format!(
+        "[get_file_new_content] Not a readable text file: {} ({} bytes, encoding {})",
+        full_path.display(), inspection.size_bytes, inspection.encoding
+      ));
+    }
+  }
+
   // Always read from working directory (disk) - this contains the current/new content
   // If file doesn't exist, return empty string (new file)
   match fs::read_to_string(&full_path) {
@@ -1197,9 +2771,11 @@ fn db_session_list(state: tauri::State<'_, AppState>) -> Result<Vec<Session>, St
 }
 
 #[tauri::command]
-fn db_session_create(state: tauri::State<'_, AppState>, params: CreateSessionParams) -> Result<Session, String> {
-  state.db.create_session(&params)
-    .map_err(|e| format!("[db_session_create] {}", e))
+fn db_session_create(app: tauri::AppHandle, state: tauri::State<'_, AppState>, params: CreateSessionParams) -> Result<Session, String> {
+  let session = state.db.create_session(&params)
+    .map_err(|e| format!("[db_session_create] {}", e))?;
+  enforce_session_cap(&app, &state.db, Some(&session.id));
+  Ok(session)
 }
 
 #[tauri::command]
@@ -1216,13 +2792,18 @@ fn db_session_update(state: tauri::State<'_, AppState>, id: String, params: Upda
 
 #[tauri::command]
 fn db_session_delete(state: tauri::State<'_, AppState>, id: String) -> Result<bool, String> {
-  state.db.delete_session(&id)
-    .map_err(|e| format!("[db_session_delete] {}", e))
+  let deleted = state.db.delete_session(&id)
+    .map_err(|e| format!("[db_session_delete] {}", e))?;
+  state.voice.discard_usage(&id);
+  if deleted {
+    sweep_orphan_blobs(&state.db);
+  }
+  Ok(deleted)
 }
 
 #[tauri::command]
 fn db_session_history(state: tauri::State<'_, AppState>, id: String) -> Result<Option<SessionHistory>, String> {
-  state.db.get_session_history(&id)
+  load_session_history(&state.db, &id)
     .map_err(|e| format!("[db_session_history] {}", e))
 }
 
@@ -1270,6 +2851,18 @@ fn db_save_api_settings(state: tauri::State<'_, AppState>, settings: ApiSettings
     .map_err(|e| format!("[db_save_api_settings] {}", e))
 }
 
+#[tauri::command]
+fn db_get_ui_prefs(state: tauri::State<'_, AppState>) -> Result<UiPrefs, String> {
+  state.db.get_ui_prefs()
+    .map_err(|e| format!("[db_get_ui_prefs] {}", e))
+}
+
+#[tauri::command]
+fn db_save_ui_prefs(state: tauri::State<'_, AppState>, prefs: UiPrefs) -> Result<(), String> {
+  state.db.set_ui_prefs(&prefs)
+    .map_err(|e| format!("[db_save_ui_prefs] {}", e))
+}
+
 // ============ LLM Providers commands ============
 
 #[tauri::command]
@@ -1375,27 +2968,29 @@ fn build_healthcheck_urls(base_url: &str) -> Vec<String> {
   urls
 }
 
-fn check_voice_server_status_blocking(base_url: &str, api_key: Option<&str>) -> Result<(bool, Option<String>), String> {
+/// Result of probing a server's health/models endpoints
+enum HealthProbe {
+  Healthy,
+  Unauthorized,
+  Unreachable,
+}
+
+fn probe_server_health_blocking(client: &reqwest::blocking::Client, base_url: &str, api_key: Option<&str>) -> HealthProbe {
   let urls = build_healthcheck_urls(base_url);
   if urls.is_empty() {
-    return Ok((false, None));
+    return HealthProbe::Unreachable;
   }
 
-  let client = reqwest::blocking::Client::builder()
-    .timeout(std::time::Duration::from_secs(5))
-    .build()
-    .map_err(|e| format!("[voice] failed to build http client: {e}"))?;
-
   let mut unauthorized = false;
   for url in urls {
-    let mut req = client.get(&url);
+    let mut req = client.get(&url).timeout(std::time::Duration::from_secs(5));
     if let Some(key) = api_key {
       if !key.trim().is_empty() {
         req = req.bearer_auth(key.trim());
       }
     }
     match req.send() {
-      Ok(resp) if resp.status().is_success() => return Ok((true, None)),
+      Ok(resp) if resp.status().is_success() => return HealthProbe::Healthy,
       Ok(resp) if resp.status().as_u16() == 401 || resp.status().as_u16() == 403 => {
         unauthorized = true;
         continue;
@@ -1406,23 +3001,391 @@ fn check_voice_server_status_blocking(base_url: &str, api_key: Option<&str>) ->
   }
 
   if unauthorized {
-    return Ok((false, Some("Unauthorized (проверь API key)".to_string())));
+    return HealthProbe::Unauthorized;
   }
-  Ok((false, None))
+  HealthProbe::Unreachable
 }
 
-fn build_transcription_url(base_url: &str) -> Result<String, String> {
+fn check_voice_server_status_blocking(client: &reqwest::blocking::Client, base_url: &str, api_key: Option<&str>) -> Result<(bool, Option<String>), String> {
+  Ok(match probe_server_health_blocking(client, base_url, api_key) {
+    HealthProbe::Healthy => (true, None),
+    HealthProbe::Unauthorized => (false, Some("Unauthorized (проверь API key)".to_string())),
+    HealthProbe::Unreachable => (false, None),
+  })
+}
+
+/// Max providers probed at once by `providers.test_all`
+const PROVIDER_HEALTHCHECK_CONCURRENCY: usize = 4;
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ProviderHealth {
+  ok: bool,
+  latency_ms: u64,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  error: Option<String>,
+}
+
+fn check_provider_health_blocking(client: &reqwest::blocking::Client, provider: &LLMProvider) -> ProviderHealth {
+  let Some(base_url) = provider_base_url(provider) else {
+    return ProviderHealth { ok: false, latency_ms: 0, error: Some("No baseUrl configured".to_string()) };
+  };
+
+  let started = std::time::Instant::now();
+  let probe = probe_server_health_blocking(client, &base_url, provider_api_key(provider).as_deref());
+  let latency_ms = started.elapsed().as_millis() as u64;
+
+  let error = match probe {
+    HealthProbe::Healthy => None,
+    HealthProbe::Unauthorized => Some(format!("[providers.test_all] {} rejected the API key", provider.id)),
+    HealthProbe::Unreachable => Some(format!("[providers.test_all] {} is unreachable", provider.id)),
+  };
+  ProviderHealth { ok: error.is_none(), latency_ms, error }
+}
+
+/// Run `f` over `items` on at most `concurrency` threads. Results come back in completion order.
+fn run_bounded<T, R, F>(items: Vec<T>, concurrency: usize, f: F) -> Vec<R>
+where
+  T: Send + 'static,
+  R: Send + 'static,
+  F: Fn(T) -> R + Send + Sync + 'static,
+{
+  let queue = Arc::new(Mutex::new(items));
+  let f = Arc::new(f);
+  let (tx, rx) = std::sync::mpsc::channel();
+
+  let workers: Vec<_> = (0..concurrency.max(1))
+    .map(|_| {
+      let queue = queue.clone();
+      let tx = tx.clone();
+      let f = f.clone();
+      std::thread::spawn(move || loop {
+        let next = queue.lock().ok().and_then(|mut q| q.pop());
+        let Some(item) = next else { break };
+        let _ = tx.send(f(item));
+      })
+    })
+    .collect();
+  drop(tx);
+
+  let results = rx.into_iter().collect();
+  for worker in workers {
+    let _ = worker.join();
+  }
+  results
+}
+
+/// Healthcheck every provider with at most `concurrency` requests in flight.
+/// Each probe is bounded by the healthcheck request timeout.
+fn check_providers_health_blocking(client: &reqwest::blocking::Client, providers: Vec<LLMProvider>, concurrency: usize) -> HashMap<String, ProviderHealth> {
+  let client = client.clone();
+  run_bounded(providers, concurrency, move |provider: LLMProvider| {
+    let health = check_provider_health_blocking(&client, &provider);
+    (provider.id, health)
+  })
+  .into_iter()
+  .collect()
+}
+
+/// Model names from a pasted list (newline- or comma-separated), trimmed and deduped in order
+fn parse_model_list(text: &str) -> Vec<String> {
+  let mut seen = HashSet::new();
+  text
+    .split(['\n', ','])
+    .map(str::trim)
+    .filter(|name| !name.is_empty())
+    .filter(|name| seen.insert(name.to_string()))
+    .map(str::to_string)
+    .collect()
+}
+
+/// Max models pinged at once by `models.prune_unreachable`
+const MODEL_PING_CONCURRENCY: usize = 4;
+const MODEL_PING_TIMEOUT_SECS: u64 = 20;
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct ModelPing {
+  model_id: String,
+  ok: bool,
+  latency_ms: u64,
+  // The provider answered that the model doesn't exist; only these get disabled by a prune
+  model_missing: bool,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  error: Option<String>,
+}
+
+fn build_chat_completions_url(base_url: &str) -> Result<String, String> {
+  let base = normalize_base_url(base_url);
+  if base.is_empty() {
+    return Err("baseUrl is empty".to_string());
+  }
+  if base.ends_with("/v1") || base.contains("/v1/") {
+    return Ok(format!("{base}/chat/completions"));
+  }
+  Ok(format!("{base}/v1/chat/completions"))
+}
+
+/// Name of the output cap field: OpenAI's own API and Azure OpenAI reject `max_tokens` for
+/// reasoning models and want `max_completion_tokens`; compatible servers only know `max_tokens`
+fn max_tokens_param(url: &str) -> &'static str {
+  let host = reqwest::Url::parse(url).ok().and_then(|u| u.host_str().map(str::to_string)).unwrap_or_default();
+  if host == "api.openai.com" || host.ends_with(".openai.azure.com") {
+    "max_completion_tokens"
+  } else {
+    "max_tokens"
+  }
+}
+
+fn build_embeddings_url(base_url: &str) -> Result<String, String> {
+  let base = normalize_base_url(base_url);
+  if base.is_empty() {
+    return Err("baseUrl is empty".to_string());
+  }
+  if base.ends_with("/v1") || base.contains("/v1/") {
+    return Ok(format!("{base}/embeddings"));
+  }
+  Ok(format!("{base}/v1/embeddings"))
+}
+
+/// Texts embedded per request when filling in message vectors
+const EMBEDDING_BATCH_SIZE: usize = 32;
+/// Messages embedded before each `search.semantic`, so new history is searchable without a backfill
+const SEMANTIC_SEARCH_LAZY_EMBED_LIMIT: usize = 256;
+
+/// Vectors from an OpenAI-style `/embeddings` response, in input order
+fn parse_embeddings_response(body: &Value, expected: usize) -> Result<Vec<Vec<f32>>, String> {
+  let data = body.get("data").and_then(|v| v.as_array())
+    .ok_or_else(|| "embeddings response has no data array".to_string())?;
+  let mut indexed: Vec<(u64, Vec<f32>)> = data.iter().enumerate()
+    .map(|(position, item)| {
+      let index = item.get("index").and_then(|v| v.as_u64()).unwrap_or(position as u64);
+      let vector = item.get("embedding").and_then(|v| v.as_array())
+        .ok_or_else(|| "embeddings response item has no embedding".to_string())?
+        .iter()
+        .map(|x| x.as_f64().map(|x| x as f32).ok_or_else(|| "embedding contains a non-number".to_string()))
+        .collect::<Result<Vec<f32>, String>>()?;
+      Ok((index, vector))
+    })
+    .collect::<Result<_, String>>()?;
+  if indexed.len() != expected {
+    return Err(format!("expected {} embeddings, got {}", expected, indexed.len()));
+  }
+  indexed.sort_by_key(|(index, _)| *index);
+  Ok(indexed.into_iter().map(|(_, vector)| vector).collect())
+}
+
+fn embed_texts_blocking(client: &reqwest::blocking::Client, provider: &LLMProvider, model: &str, texts: &[String]) -> Result<Vec<Vec<f32>>, String> {
+  let base_url = provider_base_url(provider).ok_or_else(|| "No baseUrl configured".to_string())?;
+  let mut req = client
+    .post(build_embeddings_url(&base_url)?)
+    .timeout(std::time::Duration::from_secs(60))
+    .json(&json!({ "model": model, "input": texts }));
+  if let Some(key) = provider_api_key(provider) {
+    req = req.bearer_auth(key);
+  }
+  let resp = req.send().map_err(|e| format!("embeddings request failed: {e}"))?;
+  let status = resp.status();
+  let body = resp.text().unwrap_or_default();
+  if !status.is_success() {
+    return Err(format!("embeddings http {status}: {}", body.chars().take(300).collect::<String>()));
+  }
+  let parsed: Value = serde_json::from_str(&body).map_err(|e| format!("invalid embeddings response: {e}"))?;
+  parse_embeddings_response(&parsed, texts.len())
+}
+
+/// The configured embeddings provider, or a user-facing reason semantic search can't run
+fn semantic_search_setup(db: &Database) -> Result<(db::EmbeddingSettings, LLMProvider), String> {
+  let settings = db.get_embedding_settings().map_err(|e| e.to_string())?
+    .ok_or_else(|| "Semantic search is off; configure an embeddings provider first".to_string())?;
+  let provider = db.list_providers().map_err(|e| e.to_string())?
+    .into_iter()
+    .find(|p| p.id == settings.provider_id)
+    .ok_or_else(|| format!("Embeddings provider '{}' not found", settings.provider_id))?;
+  Ok((settings, provider))
+}
+
+/// Embed up to `limit` messages that have no vector for the configured model yet. Returns how many were stored.
+fn embed_pending_messages(db: &Database, client: &reqwest::blocking::Client, provider: &LLMProvider, model: &str, limit: usize) -> Result<usize, String> {
+  let mut embedded = 0;
+  while embedded < limit {
+    let batch = db.messages_missing_embeddings(model, EMBEDDING_BATCH_SIZE.min(limit - embedded) as u32)
+      .map_err(|e| e.to_string())?;
+    if batch.is_empty() {
+      break;
+    }
+    let texts: Vec<String> = batch.iter().map(|(_, _, text)| text.clone()).collect();
+    let vectors = embed_texts_blocking(client, provider, model, &texts)?;
+    for ((message_id, session_id, _), vector) in batch.iter().zip(vectors) {
+      db.save_message_embedding(message_id, session_id, model, &vector).map_err(|e| e.to_string())?;
+    }
+    embedded += batch.len();
+  }
+  Ok(embedded)
+}
+
+/// Smallest possible chat completion (1 output token) to prove the model is actually served
+/// Chat completions endpoint for a provider's type, matching how the agent runner resolves it.
+/// OAuth-backed types (claude-code, codex) have no OpenAI-style endpoint to probe.
+fn model_ping_url(provider: &LLMProvider) -> Result<String, String> {
+  match provider.provider_type.as_str() {
+    "claude-code" | "codex" => Err(format!("model.ping is not supported for {} providers", provider.provider_type)),
+    "zai" => {
+      let coding = provider.config.as_ref()
+        .and_then(|c| c.get("zaiApiPrefix"))
+        .and_then(|v| v.as_str()) == Some("coding");
+      let prefix = if coding { "api/coding/paas" } else { "api/paas" };
+      Ok(format!("https://api.z.ai/{prefix}/v4/chat/completions"))
+    }
+    "ollama" => build_chat_completions_url(&provider_base_url(provider).unwrap_or_else(|| "http://localhost:11434/v1".to_string())),
+    _ => build_chat_completions_url(&provider_base_url(provider).ok_or_else(|| "No baseUrl configured".to_string())?),
+  }
+}
+
+/// Whether a failed ping says the model itself is gone, as opposed to an outage, rate limit or auth problem
+fn ping_failure_is_missing_model(status: reqwest::StatusCode, body: &str) -> bool {
+  if status == reqwest::StatusCode::NOT_FOUND {
+    return true;
+  }
+  if !status.is_client_error() || status == reqwest::StatusCode::TOO_MANY_REQUESTS {
+    return false;
+  }
+  let body = body.to_ascii_lowercase();
+  body.contains("model_not_found")
+    || (body.contains("model") && (body.contains("not found") || body.contains("does not exist")))
+}
+
+fn ping_model_blocking(client: &reqwest::blocking::Client, provider: &LLMProvider, model: &LLMModel) -> ModelPing {
+  let started = std::time::Instant::now();
+  let mut model_missing = false;
+  let result = (|| {
+    let url = model_ping_url(provider)?;
+    let mut body = json!({
+      "model": model.name,
+      "messages": [{ "role": "user", "content": "ping" }]
+    });
+    body[max_tokens_param(&url)] = json!(1);
+    let mut req = client
+      .post(&url)
+      .timeout(std::time::Duration::from_secs(MODEL_PING_TIMEOUT_SECS))
+      .json(&body);
+    if let Some(key) = provider_api_key(provider) {
+      req = req.bearer_auth(key);
+    }
+    let resp = req.send().map_err(|e| if e.is_timeout() { "Timed out".to_string() } else { e.to_string() })?;
+    if !resp.status().is_success() {
+      let status = resp.status();
+      let body: String = resp.text().unwrap_or_default().chars().take(300).collect();
+      model_missing = ping_failure_is_missing_model(status, &body);
+      return Err(format!("http {status}: {body}"));
+    }
+    Ok(())
+  })();
+
+  ModelPing {
+    model_id: model.id.clone(),
+    ok: result.is_ok(),
+    latency_ms: started.elapsed().as_millis() as u64,
+    model_missing,
+    error: result.err(),
+  }
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ProviderAuth {
+  /// None when the answer says nothing about the key (server error, rate limit, no connection)
+  authorized: Option<bool>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  status: Option<u16>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  error: Option<String>,
+}
+
+/// Only 401/403 mean the key was rejected. Other 4xx answers (even a 400/404 about the model)
+/// came from past the gateway's auth layer. A 429 or 5xx may come from in front of it, so
+/// those leave the question open.
+fn provider_auth_from_status(status: reqwest::StatusCode, body: &str) -> ProviderAuth {
+  let rejected = status == reqwest::StatusCode::UNAUTHORIZED || status == reqwest::StatusCode::FORBIDDEN;
+  let inconclusive = status == reqwest::StatusCode::TOO_MANY_REQUESTS || status.is_server_error();
+  ProviderAuth {
+    authorized: if inconclusive { None } else { Some(!rejected) },
+    status: Some(status.as_u16()),
+    error: (rejected || inconclusive).then(|| format!("http {status}: {}", body.chars().take(300).collect::<String>())),
+  }
+}
+
+/// Authenticated 1-token chat completion, for gateways that don't expose `/models`
+fn check_provider_auth_blocking(client: &reqwest::blocking::Client, provider: &LLMProvider, model_name: &str) -> ProviderAuth {
+  let unknown = |error: String| ProviderAuth { authorized: None, status: None, error: Some(error) };
+  let Some(base_url) = provider_base_url(provider) else {
+    return unknown("No baseUrl configured".to_string());
+  };
+  let url = match build_chat_completions_url(&base_url) {
+    Ok(url) => url,
+    Err(e) => return unknown(e),
+  };
+  let Some(key) = provider_api_key(provider) else {
+    return ProviderAuth { authorized: Some(false), status: None, error: Some("No API key configured".to_string()) };
+  };
+
+  let mut body = json!({
+    "model": model_name,
+    "messages": [{ "role": "user", "content": "ping" }]
+  });
+  body[max_tokens_param(&url)] = json!(1);
+  let resp = client
+    .post(&url)
+    .timeout(std::time::Duration::from_secs(MODEL_PING_TIMEOUT_SECS))
+    .bearer_auth(key)
+    .json(&body)
+    .send();
+  match resp {
+    Ok(resp) => {
+      let status = resp.status();
+      provider_auth_from_status(status, &resp.text().unwrap_or_default())
+    }
+    Err(e) => unknown(if e.is_timeout() { "Timed out".to_string() } else { e.to_string() }),
+  }
+}
+
+fn build_audio_url(base_url: &str, endpoint: &str) -> Result<String, String> {
   let base = normalize_base_url(base_url);
   if base.is_empty() {
     return Err("[voice] baseUrl is empty".to_string());
   }
   if base.ends_with("/v1") {
-    return Ok(format!("{base}/audio/transcriptions"));
+    return Ok(format!("{base}/audio/{endpoint}"));
   }
   if base.contains("/v1/") {
-    return Ok(format!("{base}/audio/transcriptions"));
+    return Ok(format!("{base}/audio/{endpoint}"));
+  }
+  Ok(format!("{base}/v1/audio/{endpoint}"))
+}
+
+fn build_transcription_url(base_url: &str) -> Result<String, String> {
+  build_audio_url(base_url, "transcriptions")
+}
+
+/// `/v1/audio/translations`: speech in any language, English text out
+fn build_translation_url(base_url: &str) -> Result<String, String> {
+  build_audio_url(base_url, "translations")
+}
+
+/// Websocket variant of the transcription endpoint, with model/language as query params
+fn build_transcription_ws_url(base_url: &str, model: &str, language: Option<&str>) -> Result<String, String> {
+  let http_url = build_transcription_url(base_url)?;
+  let mut url = reqwest::Url::parse(&http_url).map_err(|e| format!("[voice.stream] invalid url '{http_url}': {e}"))?;
+  let scheme = if url.scheme() == "https" { "wss" } else { "ws" };
+  url.set_scheme(scheme).map_err(|_| format!("[voice.stream] cannot use websocket scheme for '{http_url}'"))?;
+  {
+    let mut query = url.query_pairs_mut();
+    query.append_pair("model", model);
+    if let Some(lang) = language.map(str::trim).filter(|l| !l.is_empty()) {
+      query.append_pair("language", lang);
+    }
   }
-  Ok(format!("{base}/v1/audio/transcriptions"))
+  Ok(url.to_string())
 }
 
 fn build_models_url(base_url: &str) -> Result<String, String> {
@@ -1483,25 +3446,113 @@ fn guess_extension_from_mime(mime: &str) -> &'static str {
   "bin"
 }
 
-async fn transcribe_audio(
-  base_url: &str,
-  api_key: Option<&str>,
-  model: &str,
-  language: Option<&str>,
-  audio_mime: &str,
-  bytes: Vec<u8>
-) -> Result<String, String> {
-  if bytes.is_empty() {
-    return Err("[voice] audio buffer is empty".to_string());
+/// Incremental parser for streamed transcription bodies: SSE (`data: {...}` lines, OpenAI's
+/// `transcript.text.delta`/`transcript.text.done`) or newline-delimited JSON with `text` segments
+#[derive(Default)]
+struct TranscriptStream {
+  pending: String,
+  joined: String,
+  done_text: Option<String>,
+}
+
+impl TranscriptStream {
+  /// Feed a body chunk; returns the segments completed by it
+  fn push(&mut self, chunk: &str) -> Vec<String> {
+    self.pending.push_str(chunk);
+    let mut segments = Vec::new();
+    while let Some(end) = self.pending.find('\n') {
+      let line: String = self.pending.drain(..=end).collect();
+      if let Some(segment) = self.parse_line(line.trim()) {
+        segments.push(segment);
+      }
+    }
+    segments
   }
 
-  let url = build_transcription_url(base_url)?;
-  let client = reqwest::Client::builder()
-    // First request can block on model download/load (often 1-2+ minutes).
-    .timeout(std::time::Duration::from_secs(240))
-    .build()
-    .map_err(|e| format!("[voice] failed to build http client: {e}"))?;
+  fn parse_line(&mut self, line: &str) -> Option<String> {
+    let data = line.strip_prefix("data:").map(str::trim).unwrap_or(line);
+    if data.is_empty() || data == "[DONE]" || !data.starts_with('{') {
+      // Blank separators, `event:`/`id:` fields and `:` comments
+      return None;
+    }
+    let event: Value = serde_json::from_str(data).ok()?;
+    let segment = match event.get("type").and_then(|v| v.as_str()) {
+      Some("transcript.text.done") => {
+        self.done_text = event.get("text").and_then(|v| v.as_str()).map(String::from);
+        return None;
+      }
+      Some("transcript.text.delta") => event.get("delta").and_then(|v| v.as_str())?,
+      _ => event.get("text").and_then(|v| v.as_str())?,
+    };
+    if segment.is_empty() {
+      return None;
+    }
+    // Deltas carry their own spacing; whole segments from whisper servers don't always
+    if !self.joined.is_empty() && event.get("delta").is_none() && !segment.starts_with(char::is_whitespace) {
+      self.joined.push(' ');
+    }
+    self.joined.push_str(segment);
+    Some(segment.to_string())
+  }
+
+  /// Full transcript: the server's own final text when it sent one
+  fn finish(mut self) -> String {
+    let rest = std::mem::take(&mut self.pending);
+    self.parse_line(rest.trim());
+    self.done_text.unwrap_or_else(|| self.joined.trim().to_string())
+  }
+}
+
+fn is_streamed_transcription(content_type: &str) -> bool {
+  let content_type = content_type.to_ascii_lowercase();
+  content_type.starts_with("text/event-stream") || content_type.starts_with("application/x-ndjson")
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+struct TranscriptSegment {
+  text: String,
+  start: f64,
+  end: f64,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+struct Transcript {
+  text: String,
+  /// Only for `verbose_json` responses; None when the server ignored the format
+  segments: Option<Vec<TranscriptSegment>>,
+}
+
+/// Timestamps from a `verbose_json` body: `segments`, or `words` when only those came back
+fn parse_transcript_segments(parsed: &Value) -> Option<Vec<TranscriptSegment>> {
+  let (items, text_key) = match parsed.get("segments").and_then(|v| v.as_array()) {
+    Some(segments) if !segments.is_empty() => (segments, "text"),
+    _ => (parsed.get("words").and_then(|v| v.as_array())?, "word"),
+  };
+  let segments = items
+    .iter()
+    .filter_map(|item| Some(TranscriptSegment {
+      text: item.get(text_key).and_then(|v| v.as_str())?.trim().to_string(),
+      start: item.get("start").and_then(|v| v.as_f64())?,
+      end: item.get("end").and_then(|v| v.as_f64())?,
+    }))
+    .collect();
+  Some(segments)
+}
+
+/// `voiceSettings.responseFormat` as sent to the server; unknown values fall back to the default
+fn voice_response_format(settings: Option<&VoiceSettings>) -> Option<&'static str> {
+  match settings.and_then(|v| v.response_format.as_deref()).map(str::trim) {
+    Some("verbose_json") => Some("verbose_json"),
+    Some("json") => Some("json"),
+    _ => None,
+  }
+}
+
+/// Used when the voice settings leave the model blank
+const DEFAULT_VOICE_MODEL: &str = "deepdml/faster-whisper-large-v3-turbo-ct2";
 
+/// Multipart body shared by the transcription and translation endpoints
+fn audio_multipart_form(audio_mime: &str, bytes: Vec<u8>, model: &str) -> Result<reqwest::multipart::Form, String> {
   let filename = format!("audio.{}", guess_extension_from_mime(audio_mime));
   let mut part = reqwest::multipart::Part::bytes(bytes).file_name(filename);
   let mime_raw = audio_mime.trim();
@@ -1512,30 +3563,124 @@ async fn transcribe_audio(
     }
   }
 
-  let mut form = reqwest::multipart::Form::new()
+  Ok(reqwest::multipart::Form::new()
     .part("file", part)
-    .text("model", model.to_string());
-  if let Some(lang) = language {
-    if !lang.trim().is_empty() {
-      form = form.text("language", lang.trim().to_string());
-    }
-  }
+    .text("model", model.to_string()))
+}
 
-  let mut req = client.post(url).multipart(form);
+async fn post_audio_form(
+  client: &reqwest::Client,
+  url: &str,
+  form: reqwest::multipart::Form,
+  api_key: Option<&str>,
+) -> Result<reqwest::Response, String> {
+  // First request can block on model download/load (often 1-2+ minutes).
+  let mut req = client.post(url).multipart(form).timeout(std::time::Duration::from_secs(240));
   if let Some(key) = api_key {
     if !key.trim().is_empty() {
       req = req.bearer_auth(key.trim());
     }
   }
 
-  let resp = req.send().await.map_err(|e| {
+  req.send().await.map_err(|e| {
     if e.is_timeout() {
       "[voice] request timed out (model may still be loading; try again in ~1-2 minutes)".to_string()
     } else {
       format!("[voice] request failed: {e}")
     }
-  })?;
+  })
+}
+
+/// Voice server, key and model of one transcription or translation request, plus the spoken
+/// language where the endpoint takes one
+#[derive(Debug, Clone)]
+struct TranscriptionTarget {
+  base_url: String,
+  api_key: Option<String>,
+  model: String,
+  language: Option<String>,
+}
+
+/// Like `transcribe_audio`, against the translations endpoint (no language or streaming)
+async fn translate_audio(
+  client: &reqwest::Client,
+  target: &TranscriptionTarget,
+  audio_mime: &str,
+  bytes: Vec<u8>,
+) -> Result<String, String> {
+  if bytes.is_empty() {
+    return Err("[voice] audio buffer is empty".to_string());
+  }
+
+  let url = build_translation_url(&target.base_url)?;
+  let form = audio_multipart_form(audio_mime, bytes, &target.model)?;
+  let resp = post_audio_form(client, &url, form, target.api_key.as_deref()).await?;
+  let status = resp.status();
+  let body = resp.text().await.map_err(|e| format!("[voice] failed to read response: {e}"))?;
+  if !status.is_success() {
+    return Err(format!("[voice] http {status}: {body}"));
+  }
+
+  let parsed: Value = serde_json::from_str(&body).map_err(|e| format!("[voice] invalid json: {e}; body={body}"))?;
+  Ok(parsed.get("text").and_then(|v| v.as_str()).unwrap_or("").to_string())
+}
+
+/// With `on_segment`, asks the server to stream (`stream=true`); servers that answer with
+/// SSE/NDJSON get each segment forwarded as it arrives, others are read as a single JSON body.
+/// `verbose_json` needs the whole body for timestamps, so it turns streaming off.
+async fn transcribe_audio(
+  client: &reqwest::Client,
+  target: &TranscriptionTarget,
+  audio_mime: &str,
+  bytes: Vec<u8>,
+  response_format: Option<&str>,
+  on_segment: Option<&mut (dyn FnMut(&str) + Send)>,
+) -> Result<Transcript, String> {
+  if bytes.is_empty() {
+    return Err("[voice] audio buffer is empty".to_string());
+  }
+
+  let url = build_transcription_url(&target.base_url)?;
+  let mut form = audio_multipart_form(audio_mime, bytes, &target.model)?;
+  if let Some(lang) = target.language.as_deref() {
+    if !lang.trim().is_empty() {
+      form = form.text("language", lang.trim().to_string());
+    }
+  }
+  let verbose = response_format == Some("verbose_json");
+  let on_segment = on_segment.filter(|_| !verbose);
+  if let Some(format) = response_format {
+    form = form.text("response_format", format.to_string());
+  }
+  if on_segment.is_some() {
+    form = form.text("stream", "true");
+  }
+
+  let mut resp = post_audio_form(client, &url, form, target.api_key.as_deref()).await?;
   let status = resp.status();
+  let content_type = resp.headers()
+    .get(reqwest::header::CONTENT_TYPE)
+    .and_then(|v| v.to_str().ok())
+    .unwrap_or("")
+    .to_string();
+  if let (Some(on_segment), true) = (on_segment, status.is_success() && is_streamed_transcription(&content_type)) {
+    let mut stream = TranscriptStream::default();
+    // Chunks can split a UTF-8 sequence; only decode up to the last complete character
+    let mut undecoded: Vec<u8> = Vec::new();
+    while let Some(chunk) = resp.chunk().await.map_err(|e| format!("[voice] failed to read stream: {e}"))? {
+      undecoded.extend_from_slice(&chunk);
+      let valid = match std::str::from_utf8(&undecoded) {
+        Ok(text) => text.len(),
+        Err(e) => e.valid_up_to(),
+      };
+      let text = String::from_utf8_lossy(&undecoded[..valid]).into_owned();
+      undecoded.drain(..valid);
+      for segment in stream.push(&text) {
+        on_segment(&segment);
+      }
+    }
+    return Ok(Transcript { text: stream.finish(), segments: None });
+  }
   let body = resp.text().await.map_err(|e| format!("[voice] failed to read response: {e}"))?;
   if !status.is_success() {
     return Err(format!("[voice] http {status}: {body}"));
@@ -1543,18 +3688,17 @@ async fn transcribe_audio(
 
   let parsed: Value = serde_json::from_str(&body).map_err(|e| format!("[voice] invalid json: {e}; body={body}"))?;
   let text = parsed.get("text").and_then(|v| v.as_str()).unwrap_or("").to_string();
-  Ok(text)
+  let segments = if verbose { parse_transcript_segments(&parsed) } else { None };
+  Ok(Transcript { text, segments })
 }
 
 #[tauri::command]
-async fn list_voice_models(base_url: String, api_key: Option<String>) -> Result<Vec<String>, String> {
+async fn list_voice_models(state: tauri::State<'_, AppState>, base_url: String, api_key: Option<String>) -> Result<Vec<String>, String> {
   let url = build_models_url(&base_url)?;
-  let client = reqwest::Client::builder()
-    .timeout(std::time::Duration::from_secs(30))
-    .build()
+  let client = state.http.client()
     .map_err(|e| format!("[voice.models] failed to build http client: {e}"))?;
 
-  let mut req = client.get(url);
+  let mut req = client.get(url).timeout(std::time::Duration::from_secs(30));
   if let Some(key) = api_key.as_deref() {
     if !key.trim().is_empty() {
       req = req.bearer_auth(key.trim());
@@ -1572,8 +3716,294 @@ async fn list_voice_models(base_url: String, api_key: Option<String>) -> Result<
   Ok(extract_models(&parsed))
 }
 
-#[tauri::command]
-async fn transcribe_voice_stream(
+const VOICE_PARTIAL_INTERVAL_DEFAULT_MS: u64 = 1500;
+const VOICE_PARTIAL_INTERVAL_MIN_MS: u64 = 250;
+const VOICE_PARTIAL_INTERVAL_MAX_MS: u64 = 5000;
+
+/// `voiceSettings.voicePartialIntervalMs`, clamped to a range that neither floods nor stalls the server
+fn voice_partial_interval_ms(settings: Option<&VoiceSettings>) -> u64 {
+  settings
+    .and_then(|v| v.voice_partial_interval_ms)
+    .unwrap_or(VOICE_PARTIAL_INTERVAL_DEFAULT_MS)
+    .clamp(VOICE_PARTIAL_INTERVAL_MIN_MS, VOICE_PARTIAL_INTERVAL_MAX_MS)
+}
+
+/// Whether enough time has passed since the last partial request to send another
+fn voice_partial_due(last_sent_ms: u64, now: u64, interval_ms: u64) -> bool {
+  now.saturating_sub(last_sent_ms) >= interval_ms
+}
+
+/// `voiceSettings.streaming`: Some to force the websocket or multipart transport, None to detect
+fn voice_streaming_setting(db: &Database) -> Option<bool> {
+  db.get_api_settings()
+    .ok()
+    .flatten()
+    .and_then(|s| s.voice_settings)
+    .and_then(|v| v.streaming)
+}
+
+/// HTTP(S) URL used to send the websocket upgrade probe for `ws_url`
+fn realtime_probe_url(ws_url: &str) -> Option<reqwest::Url> {
+  let mut url = reqwest::Url::parse(ws_url).ok()?;
+  let scheme = match url.scheme() {
+    "wss" => "https",
+    "ws" => "http",
+    _ => return None,
+  };
+  url.set_scheme(scheme).ok()?;
+  Some(url)
+}
+
+/// Whether the websocket transcription endpoint at `ws_url` accepts an upgrade. Probed with a
+/// plain HTTP upgrade request (servers without realtime support answer 404/405/400) and cached
+/// per URL until voice settings change.
+async fn voice_server_supports_realtime(voice: &VoiceState, client: &reqwest::Client, ws_url: &str, api_key: Option<&str>) -> bool {
+  if let Some(known) = voice.realtime_support.lock().ok().and_then(|m| m.get(ws_url).copied()) {
+    return known;
+  }
+  let Some(probe_url) = realtime_probe_url(ws_url) else { return false };
+  let key = base64::engine::general_purpose::STANDARD.encode(uuid::Uuid::new_v4().as_bytes());
+  let mut req = client.get(probe_url)
+    .header(reqwest::header::CONNECTION, "Upgrade")
+    .header(reqwest::header::UPGRADE, "websocket")
+    .header("Sec-WebSocket-Version", "13")
+    .header("Sec-WebSocket-Key", key)
+    .timeout(std::time::Duration::from_secs(5));
+  if let Some(key) = api_key.map(str::trim).filter(|k| !k.is_empty()) {
+    req = req.bearer_auth(key);
+  }
+  let supported = match req.send().await {
+    Ok(resp) => resp.status() == reqwest::StatusCode::SWITCHING_PROTOCOLS,
+    Err(e) => {
+      eprintln!("[voice.stream] realtime probe failed for {ws_url}: {e}");
+      false
+    }
+  };
+  eprintln!("[voice.stream] {ws_url} realtime transcription: {}", if supported { "yes" } else { "no, using multipart" });
+  if let Ok(mut known) = voice.realtime_support.lock() {
+    known.insert(ws_url.to_string(), supported);
+  }
+  supported
+}
+
+fn extract_transcription_text(raw: &str) -> String {
+  match serde_json::from_str::<Value>(raw) {
+    Ok(parsed) => parsed.get("text").and_then(|v| v.as_str()).unwrap_or("").to_string(),
+    Err(_) => raw.to_string(),
+  }
+}
+
+/// Connect a transcription websocket with the configured default headers. An explicit `http://`
+/// proxy is tunnelled through with CONNECT; without one the connection goes direct.
+fn connect_voice_websocket(
+  mut request: tungstenite::handshake::client::Request,
+) -> Result<tungstenite::WebSocket<tungstenite::stream::MaybeTlsStream<std::net::TcpStream>>, String> {
+  let network = network_settings().lock().unwrap().clone();
+  for (name, value) in &network.headers {
+    let name = tungstenite::http::HeaderName::from_bytes(name.as_bytes())
+      .map_err(|e| format!("[voice.stream] invalid header name {name:?}: {e}"))?;
+    let value = tungstenite::http::HeaderValue::from_str(value)
+      .map_err(|e| format!("[voice.stream] invalid value for header {name}: {e}"))?;
+    request.headers_mut().entry(name).or_insert(value);
+  }
+
+  let Some(proxy) = network.proxy else {
+    let (socket, _) = tungstenite::connect(request).map_err(|e| format!("[voice.stream] connect failed: {e}"))?;
+    return Ok(socket);
+  };
+  let proxy_url = reqwest::Url::parse(&proxy).map_err(|e| format!("[voice.stream] invalid proxy {proxy:?}: {e}"))?;
+  if proxy_url.scheme() != "http" {
+    return Err(format!("[voice.stream] unsupported proxy scheme '{}' for websocket transcription", proxy_url.scheme()));
+  }
+  let proxy_host = proxy_url.host_str().ok_or_else(|| format!("[voice.stream] proxy {proxy:?} has no host"))?;
+  let proxy_port = proxy_url.port_or_known_default().unwrap_or(80);
+
+  let uri = request.uri();
+  let host = uri.host().ok_or_else(|| "[voice.stream] websocket url has no host".to_string())?;
+  let port = uri.port_u16().unwrap_or(if uri.scheme_str() == Some("wss") { 443 } else { 80 });
+  let target = format!("{host}:{port}");
+
+  let mut stream = std::net::TcpStream::connect((proxy_host, proxy_port))
+    .map_err(|e| format!("[voice.stream] proxy connect failed: {e}"))?;
+  let mut connect = format!("CONNECT {target} HTTP/1.1\r\nHost: {target}\r\n");
+  if !proxy_url.username().is_empty() {
+    let credentials = format!("{}:{}", proxy_url.username(), proxy_url.password().unwrap_or(""));
+    let encoded = base64::engine::general_purpose::STANDARD.encode(credentials);
+    connect.push_str(&format!("Proxy-Authorization: Basic {encoded}\r\n"));
+  }
+  connect.push_str("\r\n");
+  stream.write_all(connect.as_bytes()).map_err(|e| format!("[voice.stream] proxy CONNECT failed: {e}"))?;
+
+  // Read the proxy's reply head byte by byte so no tunnelled data is consumed
+  let mut head = Vec::new();
+  let mut byte = [0u8; 1];
+  while !head.ends_with(b"\r\n\r\n") {
+    if head.len() >= 8 * 1024 {
+      return Err("[voice.stream] proxy reply too large".to_string());
+    }
+    let n = stream.read(&mut byte).map_err(|e| format!("[voice.stream] proxy CONNECT failed: {e}"))?;
+    if n == 0 {
+      return Err("[voice.stream] proxy closed the connection".to_string());
+    }
+    head.push(byte[0]);
+  }
+  let head = String::from_utf8_lossy(&head);
+  let status_line = head.lines().next().unwrap_or("");
+  let status_ok = status_line.split_whitespace().nth(1).is_some_and(|code| code.starts_with('2'));
+  if !status_ok {
+    return Err(format!("[voice.stream] proxy refused CONNECT: {status_line}"));
+  }
+
+  let (socket, _) = tungstenite::client_tls(request, stream)
+    .map_err(|e| format!("[voice.stream] connect failed: {e}"))?;
+  Ok(socket)
+}
+
+/// Open one websocket per dictation session on a background thread. Audio chunks are sent as
+/// binary frames; every text frame from the server is a (cumulative) partial transcript, and the
+/// last one received after we close our side becomes the final.
+fn open_voice_stream(
+  app: tauri::AppHandle,
+  session_id: String,
+  stream_id: u64,
+  url: String,
+  api_key: Option<String>,
+) -> std::sync::mpsc::Sender<VoiceStreamMsg> {
+  use tungstenite::client::IntoClientRequest;
+  use tungstenite::stream::MaybeTlsStream;
+  use tungstenite::Message;
+
+  let (tx, rx) = std::sync::mpsc::channel::<VoiceStreamMsg>();
+  let generation = app.state::<AppState>().voice.current_generation();
+
+  std::thread::spawn(move || {
+    let is_stale = || app.state::<AppState>().voice.current_generation() != generation;
+    let run = || -> Result<String, String> {
+      let mut request = url.as_str().into_client_request()
+        .map_err(|e| format!("[voice.stream] invalid request: {e}"))?;
+      if let Some(key) = api_key.as_deref().map(str::trim).filter(|k| !k.is_empty()) {
+        let header = format!("Bearer {key}").parse()
+          .map_err(|e| format!("[voice.stream] invalid api key header: {e}"))?;
+        request.headers_mut().insert("Authorization", header);
+      }
+      let mut socket = connect_voice_websocket(request)?;
+
+      // Short read timeout lets one thread interleave sending audio and reading partials
+      let poll = Some(std::time::Duration::from_millis(50));
+      match socket.get_mut() {
+        MaybeTlsStream::Plain(stream) => { let _ = stream.set_read_timeout(poll); }
+        MaybeTlsStream::Rustls(stream) => { let _ = stream.sock.set_read_timeout(poll); }
+        _ => {}
+      }
+
+      let mut last_text = String::new();
+      let mut finish_deadline: Option<std::time::Instant> = None;
+      loop {
+        if finish_deadline.is_none() {
+          loop {
+            match rx.try_recv() {
+              Ok(VoiceStreamMsg::Audio(bytes)) => {
+                socket.send(Message::Binary(bytes))
+                  .map_err(|e| format!("[voice.stream] send failed: {e}"))?;
+              }
+              Ok(VoiceStreamMsg::Finish) | Err(std::sync::mpsc::TryRecvError::Disconnected) => {
+                let _ = socket.close(None);
+                finish_deadline = Some(std::time::Instant::now() + std::time::Duration::from_secs(30));
+                break;
+              }
+              Err(std::sync::mpsc::TryRecvError::Empty) => break,
+            }
+          }
+        } else if finish_deadline.is_some_and(|d| std::time::Instant::now() > d) {
+          return Ok(last_text);
+        }
+
+        match socket.read() {
+          Ok(Message::Text(raw)) => {
+            if is_stale() {
+              return Ok(String::new());
+            }
+            last_text = extract_transcription_text(&raw);
+            let _ = emit_server_event_app(&app, &json!({
+              "type": "voice.transcription.partial",
+              "payload": { "sessionId": session_id, "text": last_text }
+            }));
+            app.state::<AppState>().voice.mark_dictation_partial(&session_id, now_ms().unwrap_or(0));
+          }
+          Ok(Message::Close(_)) => return Ok(last_text),
+          Ok(_) => {}
+          Err(tungstenite::Error::Io(e))
+            if matches!(e.kind(), std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut) => {}
+          Err(tungstenite::Error::ConnectionClosed) | Err(tungstenite::Error::AlreadyClosed) => return Ok(last_text),
+          Err(e) => return Err(format!("[voice.stream] read failed: {e}")),
+        }
+      }
+    };
+
+    let result = run();
+    if is_stale() {
+      // Settings changed mid-dictation; the stream map was already cleared by the reset
+      return;
+    }
+    // A newer dictation may already own the session's entry; leave it alone
+    app.state::<AppState>().voice.remove_stream(&session_id, stream_id);
+    let succeeded = result.is_ok();
+    let event = match result {
+      Ok(text) => json!({ "type": "voice.transcription.final", "payload": { "sessionId": session_id, "text": text } }),
+      Err(message) => json!({ "type": "voice.transcription.error", "payload": { "sessionId": session_id, "message": message } }),
+    };
+    let _ = emit_server_event_app(&app, &event);
+    if succeeded {
+      report_dictation_metrics(&app, &session_id);
+    } else {
+      let state = app.state::<AppState>();
+      state.voice.cancel_dictation_timing(&session_id);
+      // Audio sent before the failure was still billed
+      state.voice.flush_usage(&state.db, &session_id);
+    }
+  });
+
+  tx
+}
+
+/// Streaming transport for `transcribe_voice_stream`: forwards chunks to the session's websocket
+fn push_voice_stream_chunk(
+  app: &tauri::AppHandle,
+  state: &AppState,
+  session_id: &str,
+  chunk: Vec<u8>,
+  is_final: bool,
+  target: &TranscriptionTarget,
+) -> Result<(), String> {
+  let mut streams = state.voice.streams.lock().map_err(|_| "[voice] streams lock poisoned".to_string())?;
+  if !streams.contains_key(session_id) {
+    if chunk.is_empty() && is_final {
+      return Ok(());
+    }
+    let url = build_transcription_ws_url(&target.base_url, &target.model, target.language.as_deref())?;
+    let id = state.voice.next_stream_id.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+    let tx = open_voice_stream(app.clone(), session_id.to_string(), id, url, target.api_key.clone());
+    streams.insert(session_id.to_string(), VoiceStream { id, tx });
+    state.voice.record_usage(session_id, 0.0, 1);
+  }
+
+  let tx = streams.get(session_id).map(|s| s.tx.clone()).ok_or_else(|| "[voice] stream missing".to_string())?;
+  if is_final {
+    streams.remove(session_id);
+  }
+  drop(streams);
+
+  if !chunk.is_empty() {
+    let _ = tx.send(VoiceStreamMsg::Audio(chunk));
+  }
+  if is_final {
+    let _ = tx.send(VoiceStreamMsg::Finish);
+  }
+  Ok(())
+}
+
+#[tauri::command]
+async fn transcribe_voice_stream(
   app: tauri::AppHandle,
   state: tauri::State<'_, AppState>,
   audio_chunk_b64: String,
@@ -1594,6 +4024,7 @@ async fn transcribe_voice_stream(
       if let Ok(mut guard) = state.voice.buffers.lock() {
         guard.remove(&session_id);
       }
+      state.voice.flush_usage(&state.db, &session_id);
       emit_server_event_app(&app, &json!({
         "type": "voice.transcription.error",
         "payload": {
@@ -1605,6 +4036,52 @@ async fn transcribe_voice_stream(
     }
   }
 
+  let model_name = if model.trim().is_empty() {
+    DEFAULT_VOICE_MODEL.to_string()
+  } else {
+    model.trim().to_string()
+  };
+  let target = TranscriptionTarget { base_url, api_key, model: model_name.clone(), language };
+
+  // Servers that support it get one websocket per dictation instead of re-posting the buffer
+  let use_websocket = match voice_streaming_setting(&state.db) {
+    Some(enabled) => enabled,
+    // A dictation already streaming stays on its websocket
+    None if state.voice.streams.lock().is_ok_and(|s| s.contains_key(&session_id)) => true,
+    // Mid-dictation on the multipart path: don't switch transports halfway
+    None if state.voice.buffers.lock().is_ok_and(|b| b.contains_key(&session_id)) => false,
+    None => {
+      let ws_url = build_transcription_ws_url(&target.base_url, &target.model, target.language.as_deref())?;
+      let client = state.http.client()
+        .map_err(|e| format!("[voice] failed to build http client: {e}"))?;
+      voice_server_supports_realtime(&state.voice, &client, &ws_url, target.api_key.as_deref()).await
+    }
+  };
+  let transport = if use_websocket { "websocket" } else { "multipart" };
+  state.voice.start_dictation_timing(&session_id, &model_name, transport, now_ms().unwrap_or(0));
+  if use_websocket {
+    let chunk = if audio_chunk_b64.trim().is_empty() {
+      Vec::new()
+    } else {
+      base64::engine::general_purpose::STANDARD
+        .decode(audio_chunk_b64.trim())
+        .map_err(|e| format!("[transcribe_voice_stream] invalid base64: {e}"))?
+    };
+    state.voice.record_usage(&session_id, estimate_audio_seconds(chunk.len(), &audio_mime), 0);
+    push_voice_stream_chunk(
+      &app,
+      state.inner(),
+      &session_id,
+      chunk,
+      is_final,
+      &target,
+    )?;
+    if is_final {
+      state.voice.flush_usage(&state.db, &session_id);
+    }
+    return Ok(());
+  }
+
   // Append chunk (if provided)
   if !audio_chunk_b64.trim().is_empty() {
     let decoded = base64::engine::general_purpose::STANDARD
@@ -1626,10 +4103,12 @@ async fn transcribe_voice_stream(
   // Decide whether to send a partial update
   if !is_final {
     let now = now_ms().unwrap_or(0);
+    let voice_settings = state.db.get_api_settings().ok().flatten().and_then(|s| s.voice_settings);
+    let interval_ms = voice_partial_interval_ms(voice_settings.as_ref());
     {
       let mut guard = state.voice.buffers.lock().map_err(|_| "[voice] buffers lock poisoned".to_string())?;
       let entry = guard.entry(session_id.clone()).or_default();
-      if now.saturating_sub(entry.last_sent_ms) < 1500 {
+      if !voice_partial_due(entry.last_sent_ms, now, interval_ms) {
         return Ok(());
       }
       entry.last_sent_ms = now;
@@ -1651,15 +4130,26 @@ async fn transcribe_voice_stream(
     (bytes, mime, last_partial_text, last_partial_ms, last_partial_bytes_len)
   };
 
-  if is_final {
+  // Partials stay plain text; only the final transcript asks for timestamps
+  let response_format = if is_final {
+    let voice_settings = state.db.get_api_settings().ok().flatten().and_then(|s| s.voice_settings);
+    voice_response_format(voice_settings.as_ref())
+  } else {
+    None
+  };
+
+  // A cached partial has no timestamps, so verbose finals always re-upload
+  if is_final && response_format != Some("verbose_json") {
     let now = now_ms().unwrap_or(0);
     if let Some(text) = last_partial_text {
       if last_partial_bytes_len == bytes.len() && now.saturating_sub(last_partial_ms) <= 2000 {
+        state.voice.flush_usage(&state.db, &session_id);
         let event_type = "voice.transcription.final";
         emit_server_event_app(&app, &json!({
           "type": event_type,
           "payload": { "sessionId": session_id, "text": text }
         }))?;
+        report_dictation_metrics(&app, &session_id);
         return Ok(());
       }
     }
@@ -1667,30 +4157,51 @@ async fn transcribe_voice_stream(
 
   let app_handle = app.clone();
   let session_id_clone = session_id.clone();
-  let model_name = if model.trim().is_empty() {
-    "deepdml/faster-whisper-large-v3-turbo-ct2".to_string()
-  } else {
-    model.trim().to_string()
-  };
-  let base_url_clone = base_url.clone();
   let audio_mime_clone = mime.clone();
-  let api_key_clone = api_key.clone();
-  let language_clone = language.clone();
   let bytes_len = bytes.len();
   let is_final_call = is_final;
+  let generation = state.voice.current_generation();
+
+  let client = state.http.client()
+    .map_err(|e| format!("[voice] failed to build http client: {e}"))?;
+
+  // Each partial re-uploads the whole buffer, and that's what the server bills
+  state.voice.record_usage(&session_id, estimate_audio_seconds(bytes_len, &mime), 1);
+  if is_final {
+    state.voice.flush_usage(&state.db, &session_id);
+  }
 
   tauri::async_runtime::spawn(async move {
+    // Long final uploads stream segments as the server produces them, when it can
+    let segment_app = app_handle.clone();
+    let segment_session = session_id_clone.clone();
+    let mut segment_index = 0usize;
+    let mut emit_segment = move |text: &str| {
+      if segment_app.state::<AppState>().voice.current_generation() != generation {
+        return;
+      }
+      let _ = emit_server_event_app(&segment_app, &json!({
+        "type": "voice.transcription.segment",
+        "payload": { "sessionId": segment_session, "index": segment_index, "text": text }
+      }));
+      segment_index += 1;
+    };
     let result = transcribe_audio(
-      &base_url_clone,
-      api_key_clone.as_deref(),
-      &model_name,
-      language_clone.as_deref(),
+      &client,
+      &target,
       &audio_mime_clone,
-      bytes
+      bytes,
+      response_format,
+      if is_final_call { Some(&mut emit_segment) } else { None },
     ).await;
 
+    if app_handle.state::<AppState>().voice.current_generation() != generation {
+      // Started against a previous voice config; voice.reset already told the UI
+      return;
+    }
+
     match result {
-      Ok(text) => {
+      Ok(Transcript { text, segments }) => {
         let event_type = if is_final_call { "voice.transcription.final" } else { "voice.transcription.partial" };
         if !is_final_call {
           if let Ok(mut guard) = app_handle.state::<AppState>().voice.buffers.lock() {
@@ -1701,15 +4212,24 @@ async fn transcribe_voice_stream(
             }
           }
         }
-        let _ = emit_server_event_app(&app_handle, &json!({
-          "type": event_type,
-          "payload": { "sessionId": session_id_clone, "text": text }
-        }));
+        let mut payload = json!({ "sessionId": session_id_clone, "text": text });
+        if let Some(segments) = segments {
+          payload["segments"] = json!(segments);
+        }
+        let _ = emit_server_event_app(&app_handle, &json!({ "type": event_type, "payload": payload }));
+        if is_final_call {
+          report_dictation_metrics(&app_handle, &session_id_clone);
+        } else {
+          app_handle.state::<AppState>().voice.mark_dictation_partial(&session_id_clone, now_ms().unwrap_or(0));
+        }
       }
       Err(message) => {
         if let Ok(mut guard) = app_handle.state::<AppState>().voice.buffers.lock() {
           guard.remove(&session_id_clone);
         }
+        let state = app_handle.state::<AppState>();
+        state.voice.cancel_dictation_timing(&session_id_clone);
+        state.voice.flush_usage(&state.db, &session_id_clone);
         let _ = emit_server_event_app(&app_handle, &json!({
           "type": "voice.transcription.error",
           "payload": { "sessionId": session_id_clone, "message": message }
@@ -1721,6 +4241,57 @@ async fn transcribe_voice_stream(
   Ok(())
 }
 
+/// Whole-clip speech-to-English for a session: emits `voice.translation.final` (or `.error`)
+fn start_voice_translation(
+  app: &tauri::AppHandle,
+  state: &AppState,
+  session_id: String,
+  audio_b64: &str,
+  audio_mime: String,
+  target: TranscriptionTarget,
+) -> Result<(), String> {
+  if session_id.trim().is_empty() {
+    return Err("[translate_voice] sessionId is empty".to_string());
+  }
+  let bytes = base64::engine::general_purpose::STANDARD
+    .decode(audio_b64.trim())
+    .map_err(|e| format!("[translate_voice] invalid base64: {e}"))?;
+  let model = if target.model.trim().is_empty() { DEFAULT_VOICE_MODEL.to_string() } else { target.model.trim().to_string() };
+  // The translations endpoint always produces English; it takes no language
+  let target = TranscriptionTarget { model, language: None, ..target };
+  let client = state.http.client()
+    .map_err(|e| format!("[voice] failed to build http client: {e}"))?;
+
+  state.voice.record_usage(&session_id, estimate_audio_seconds(bytes.len(), &audio_mime), 1);
+  state.voice.flush_usage(&state.db, &session_id);
+
+  let app_handle = app.clone();
+  tauri::async_runtime::spawn(async move {
+    let result = translate_audio(&client, &target, &audio_mime, bytes).await;
+    let event = match result {
+      Ok(text) => json!({ "type": "voice.translation.final", "payload": { "sessionId": session_id, "text": text } }),
+      Err(message) => json!({ "type": "voice.translation.error", "payload": { "sessionId": session_id, "message": message } }),
+    };
+    let _ = emit_server_event_app(&app_handle, &event);
+  });
+  Ok(())
+}
+
+#[tauri::command]
+async fn translate_voice(
+  app: tauri::AppHandle,
+  audio_b64: String,
+  audio_mime: String,
+  session_id: String,
+  base_url: String,
+  api_key: Option<String>,
+  model: String,
+) -> Result<(), String> {
+  let state = app.state::<AppState>();
+  let target = TranscriptionTarget { base_url, api_key, model, language: None };
+  start_voice_translation(&app, state.inner(), session_id, &audio_b64, audio_mime, target)
+}
+
 fn build_silence_wav_16k_mono(duration_ms: u32) -> Vec<u8> {
   // Minimal PCM WAV (16-bit, 16kHz, mono) filled with silence.
   let sample_rate: u32 = 16_000;
@@ -1784,6 +4355,7 @@ fn mark_warmup_success(state: &AppState, base_url: &str, model: &str) {
 }
 
 fn transcribe_audio_blocking(
+  client: &reqwest::blocking::Client,
   base_url: &str,
   api_key: Option<&str>,
   model: &str,
@@ -1794,10 +4366,6 @@ fn transcribe_audio_blocking(
     return Err("[voice.warmup] audio buffer is empty".to_string());
   }
   let url = build_transcription_url(base_url)?;
-  let client = reqwest::blocking::Client::builder()
-    .timeout(std::time::Duration::from_secs(240))
-    .build()
-    .map_err(|e| format!("[voice.warmup] failed to build http client: {e}"))?;
 
   let filename = format!("audio.{}", guess_extension_from_mime(audio_mime));
   let mut part = reqwest::blocking::multipart::Part::bytes(bytes).file_name(filename);
@@ -1812,7 +4380,7 @@ fn transcribe_audio_blocking(
     .part("file", part)
     .text("model", model.to_string());
 
-  let mut req = client.post(url).multipart(form);
+  let mut req = client.post(url).multipart(form).timeout(std::time::Duration::from_secs(240));
   if let Some(key) = api_key {
     if !key.trim().is_empty() {
       req = req.bearer_auth(key.trim());
@@ -1835,6 +4403,150 @@ fn transcribe_audio_blocking(
   Ok(())
 }
 
+fn blobs_dir() -> Result<PathBuf, String> {
+  Ok(app_data_dir()?.join("blobs"))
+}
+
+/// Blob files younger than this survive a sweep (their externalization may still be committing)
+const BLOB_SWEEP_GRACE: std::time::Duration = std::time::Duration::from_secs(60);
+
+/// After messages were deleted, remove blob files no stored message refers to anymore.
+/// Scans every message with a blob reference, so it runs off the calling thread.
+fn sweep_orphan_blobs(db: &Arc<Database>) {
+  let Ok(dir) = blobs_dir() else { return };
+  if !dir.is_dir() {
+    return;
+  }
+  let db = db.clone();
+  std::thread::spawn(move || {
+    let removed = db.referenced_blobs()
+      .map_err(|e| e.to_string())
+      .and_then(|refs| blobs::remove_unreferenced(&dir, &refs, BLOB_SWEEP_GRACE).map_err(|e| e.to_string()));
+    match removed {
+      Ok(0) => {}
+      Ok(n) => eprintln!("[blobs] Removed {} unreferenced blob files", n),
+      Err(e) => eprintln!("[blobs] Sweep failed: {}", e),
+    }
+  });
+}
+
+/// `get_session_history` with externalized blobs (see `session.blobs.externalize`) read back in
+fn load_session_history(db: &Database, id: &str) -> rusqlite::Result<Option<SessionHistory>> {
+  let mut history = db.get_session_history(id)?;
+  if let (Some(history), Ok(dir)) = (history.as_mut(), blobs_dir()) {
+    for message in history.messages.iter_mut() {
+      blobs::rehydrate_value(message, &dir);
+    }
+  }
+  Ok(history)
+}
+
+/// Apply the opt-in `max_context_tokens` budget to a history about to be sent on continue.
+/// Returns how many messages were left out and the budget, when any were.
+fn trim_history_for_context(db: &Database, history: &mut SessionHistory) -> Option<(usize, i64)> {
+  let max_tokens = match db.get_max_context_tokens() {
+    Ok(max_tokens) => max_tokens?,
+    Err(e) => {
+      eprintln!("[session.continue] Failed to read context budget: {}", e);
+      return None;
+    }
+  };
+  let omitted = db::trim_history_to_budget(&mut history.messages, max_tokens);
+  (omitted > 0).then_some((omitted, max_tokens))
+}
+
+/// Bumped when the `session.snapshot` layout changes incompatibly
+const SESSION_SNAPSHOT_VERSION: u64 = 1;
+
+/// Everything needed to look into a reported issue, in one redacted JSON object: the
+/// `session.export` bundle plus build info, model/provider names and token totals.
+/// Returns the snapshot and the number of redactions, or None when the session doesn't exist.
+fn build_session_snapshot(db: &Database, session_id: &str) -> Result<Option<(Value, usize)>, String> {
+  let bundle = match db.export_session(session_id, &blobs_dir()?) {
+    Ok(bundle) => bundle,
+    Err(rusqlite::Error::QueryReturnedNoRows) => return Ok(None),
+    Err(e) => return Err(e.to_string()),
+  };
+  let summary = db.get_session_summary(session_id).map_err(|e| e.to_string())?;
+  let model = match summary.as_ref().and_then(|s| s.model.as_deref()) {
+    Some(id) => db.get_model(id).map_err(|e| e.to_string())?,
+    None => None,
+  };
+  let provider = match &model {
+    Some(model) => db.list_providers().map_err(|e| e.to_string())?
+      .into_iter()
+      .find(|p| p.id == model.provider_id),
+    None => None,
+  };
+
+  let mut snapshot = json!({
+    "version": SESSION_SNAPSHOT_VERSION,
+    "createdAt": chrono::Utc::now().timestamp_millis(),
+    "app": get_build_info()?,
+    "model": {
+      "id": summary.as_ref().and_then(|s| s.model.clone()),
+      "name": model.as_ref().map(|m| m.name.clone()),
+      "provider": provider.as_ref().map(|p| p.name.clone()),
+      "providerType": provider.as_ref().map(|p| p.provider_type.clone())
+    },
+    "tokens": {
+      "input": summary.as_ref().map_or(0, |s| s.input_tokens),
+      "output": summary.as_ref().map_or(0, |s| s.output_tokens),
+      "estimatedCost": summary.as_ref().and_then(|s| s.estimated_cost)
+    },
+    "session": bundle
+  });
+  let patterns = db.get_setting(redaction::REDACTION_PATTERNS_SETTING).map_err(|e| e.to_string())?;
+  let redactions = redaction::Redactor::from_setting(patterns.as_deref()).redact_value(&mut snapshot);
+  Ok(Some((snapshot, redactions)))
+}
+
+/// When the spending cap is exceeded (and the session has no override), emit `budget.exceeded`
+/// and return true so the caller doesn't forward the request to the sidecar.
+fn budget_exceeded(app: &tauri::AppHandle, db: &Database, session_id: Option<&str>) -> Result<bool, String> {
+  let status = match db.get_budget_status(session_id) {
+    Ok(status) => status,
+    Err(e) => {
+      // Never lock users out because the estimate couldn't be computed
+      eprintln!("[budget] Status check failed: {}", e);
+      return Ok(false);
+    }
+  };
+  if !status.blocks() {
+    return Ok(false);
+  }
+  eprintln!("[budget] Spend ${:.2} exceeds cap ${:.2}, refusing", status.spent_usd, status.cap_usd.unwrap_or(0.0));
+  emit_server_event_app(app, &json!({
+    "type": "budget.exceeded",
+    "payload": { "sessionId": session_id, "capUsd": status.cap_usd, "spentUsd": status.spent_usd }
+  }))?;
+  Ok(true)
+}
+
+/// `session.continue` enriched with session data and message history, as forwarded to the sidecar
+fn build_continue_event(session_id: &str, prompt: &str, cwd: &str, history: &SessionHistory) -> Value {
+  json!({
+    "type": "session.continue",
+    "payload": {
+      "sessionId": session_id,
+      "prompt": prompt,
+      // Session data for restoration in sidecar
+      "sessionData": {
+        "title": history.session.title,
+        "cwd": cwd,
+        "model": history.session.model,
+        "allowedTools": history.session.allowed_tools,
+        "temperature": history.session.temperature,
+        "maxOutputTokens": history.session.max_output_tokens,
+        "permissionMode": history.session.permission_mode
+      },
+      // Message history for LLM context
+      "messages": history.messages,
+      "todos": history.todos
+    }
+  })
+}
+
 #[tauri::command]
 fn client_event(app: tauri::AppHandle, state: tauri::State<'_, AppState>, event: Value) -> Result<(), String> {
   let event_type = event
@@ -1843,7 +4555,7 @@ fn client_event(app: tauri::AppHandle, state: tauri::State<'_, AppState>, event:
     .ok_or_else(|| "[client_event] Missing event.type".to_string())?;
 
   // Log user actions (skip noisy events)
-  let noisy = ["session.list", "session.history", "settings.get", "models.get", "llm.providers.get", "skills.get"];
+  let noisy = ["session.list", "session.list.by_project", "session.history", "settings.get", "models.get", "llm.providers.get", "skills.get"];
   if !noisy.contains(&event_type) {
     eprintln!("[event] {}", event_type);
   }
@@ -1859,7 +4571,8 @@ fn client_event(app: tauri::AppHandle, state: tauri::State<'_, AppState>, event:
       let api_key = payload.get("apiKey").and_then(|v| v.as_str()).map(|s| s.to_string());
       let app_handle = app.clone();
       std::thread::spawn(move || {
-        let (available, error) = check_voice_server_status_blocking(&base_url, api_key.as_deref())
+        let (available, error) = app_handle.state::<AppState>().http.blocking_client()
+          .and_then(|client| check_voice_server_status_blocking(&client, &base_url, api_key.as_deref()))
           .unwrap_or((false, Some("Healthcheck failed".to_string())));
 
         if let Ok(mut last_guard) = app_handle.state::<AppState>().voice.last_status.lock() {
@@ -1874,6 +4587,56 @@ fn client_event(app: tauri::AppHandle, state: tauri::State<'_, AppState>, event:
       Ok(())
     }
 
+    "voice.usage" => {
+      let payload = event.get("payload")
+        .ok_or_else(|| "[voice.usage] missing payload".to_string())?;
+      let session_id = payload.get("sessionId").and_then(|v| v.as_str())
+        .ok_or_else(|| "[voice.usage] missing sessionId".to_string())?;
+      let saved = state.db.get_voice_usage(session_id)
+        .map_err(|e| format!("[voice.usage] {}", e))?;
+      // Include the dictation in progress, which is only persisted on finalization
+      let pending = state.voice.pending_usage(session_id);
+      emit_server_event_app(&app, &json!({
+        "type": "voice.usage",
+        "payload": {
+          "sessionId": session_id,
+          "audioSeconds": saved.audio_seconds + pending.audio_seconds,
+          "requests": saved.requests + pending.requests
+        }
+      }))?;
+      Ok(())
+    }
+
+    // Same as the translate_voice command; settings fields fall back to the saved voice settings
+    "voice.translate" => {
+      let payload = event.get("payload")
+        .ok_or_else(|| "[voice.translate] missing payload".to_string())?;
+      let session_id = payload.get("sessionId").and_then(|v| v.as_str())
+        .ok_or_else(|| "[voice.translate] missing sessionId".to_string())?;
+      let audio_b64 = payload.get("audioB64").and_then(|v| v.as_str())
+        .ok_or_else(|| "[voice.translate] missing audioB64".to_string())?;
+      let audio_mime = payload.get("audioMime").and_then(|v| v.as_str()).unwrap_or("").to_string();
+      let saved = state.db.get_api_settings().ok().flatten().and_then(|s| s.voice_settings);
+      let field = |key: &str| payload.get(key).and_then(|v| v.as_str()).map(String::from);
+      let base_url = field("baseUrl").or_else(|| saved.as_ref().map(|v| v.base_url.clone())).unwrap_or_default();
+      let api_key = field("apiKey").or_else(|| saved.as_ref().and_then(|v| v.api_key.clone()));
+      let model = field("model").or_else(|| saved.as_ref().map(|v| v.model.clone())).unwrap_or_default();
+      let target = TranscriptionTarget { base_url, api_key, model, language: None };
+      start_voice_translation(&app, state.inner(), session_id.to_string(), audio_b64, audio_mime, target)
+    }
+
+    // Dictation latency per model/transport; since = ms timestamp (default: everything)
+    "voice.dictation.metrics.summary" => {
+      let since = event.get("payload").and_then(|p| p.get("since")).and_then(|v| v.as_i64()).unwrap_or(0);
+      let summary = state.db.dictation_metrics_summary(since)
+        .map_err(|e| format!("[voice.dictation.metrics.summary] {}", e))?;
+      emit_server_event_app(&app, &json!({
+        "type": "voice.dictation.metrics.summary",
+        "payload": { "since": since, "summary": summary }
+      }))?;
+      Ok(())
+    }
+
     "voice.preload" => {
       let payload = event.get("payload")
         .ok_or_else(|| "[voice.preload] missing payload".to_string())?;
@@ -1889,13 +4652,16 @@ fn client_event(app: tauri::AppHandle, state: tauri::State<'_, AppState>, event:
       std::thread::spawn(move || {
         // Run a tiny transcription to force model load on server.
         let wav = build_silence_wav_16k_mono(800);
-        let res = transcribe_audio_blocking(
-          &base_url,
-          api_key.as_deref(),
-          model.trim(),
-          "audio/wav",
-          wav
-        );
+        let res = app_handle.state::<AppState>().http.blocking_client()
+          .map_err(|e| format!("[voice.warmup] failed to build http client: {e}"))
+          .and_then(|client| transcribe_audio_blocking(
+            &client,
+            &base_url,
+            api_key.as_deref(),
+            model.trim(),
+            "audio/wav",
+            wav
+          ));
         match res {
           Ok(()) => {
             // Record successful warmup so we can skip duplicate warmups later.
@@ -1972,7 +4738,11 @@ fn client_event(app: tauri::AppHandle, state: tauri::State<'_, AppState>, event:
 
     // Session list - handled directly from Rust DB
     "session.list" => {
-      let sessions = state.db.list_sessions()
+      let include_archived = event.get("payload")
+        .and_then(|p| p.get("includeArchived"))
+        .and_then(|v| v.as_bool())
+        .unwrap_or(false);
+      let sessions = state.db.list_sessions_filtered(include_archived)
         .map_err(|e| format!("[session.list] {}", e))?;
       emit_server_event_app(&app, &json!({
         "type": "session.list",
@@ -1981,67 +4751,1006 @@ fn client_event(app: tauri::AppHandle, state: tauri::State<'_, AppState>, event:
       Ok(())
     }
 
-    // Session history - handled directly from Rust DB
-    "session.history" => {
+    // Session export with secrets scrubbed, for sharing
+    "session.export.redacted" => {
       let payload = event.get("payload")
-        .ok_or_else(|| "[session.history] missing payload".to_string())?;
-      let session_id = payload.get("sessionId")
-        .and_then(|v| v.as_str())
-        .ok_or_else(|| "[session.history] missing sessionId".to_string())?;
-      
-      match state.db.get_session_history(session_id) {
-        Ok(Some(history)) => {
-          emit_server_event_app(&app, &json!({
-            "type": "session.history",
-            "payload": {
-              "sessionId": history.session.id,
-              "status": history.session.status,
-              "messages": history.messages,
-              "inputTokens": history.session.input_tokens,
-              "outputTokens": history.session.output_tokens,
-              "todos": history.todos,
-              "model": history.session.model,
-              "fileChanges": history.file_changes,
-              "hasMore": false,
-              "page": "initial"
-            }
-          }))?;
-        }
+        .ok_or_else(|| "[session.export.redacted] missing payload".to_string())?;
+      let session_id = payload.get("sessionId").and_then(|v| v.as_str())
+        .ok_or_else(|| "[session.export.redacted] missing sessionId".to_string())?;
+
+      let history = match load_session_history(&state.db, session_id) {
+        Ok(Some(history)) => history,
         Ok(None) => {
           emit_server_event_app(&app, &json!({
             "type": "runner.error",
             "payload": { "message": "Session not found" }
           }))?;
+          return Ok(());
         }
-        Err(e) => {
-          emit_server_event_app(&app, &json!({
-            "type": "runner.error",
-            "payload": { "message": format!("Failed to get session history: {}", e) }
-          }))?;
-        }
+        Err(e) => return Err(format!("[session.export.redacted] {}", e)),
+      };
+
+      let patterns = state.db.get_setting(redaction::REDACTION_PATTERNS_SETTING)
+        .map_err(|e| format!("[session.export.redacted] {}", e))?;
+      let redactor = redaction::Redactor::from_setting(patterns.as_deref());
+      let mut export = serde_json::to_value(&history)
+        .map_err(|e| format!("[session.export.redacted] serialize failed: {}", e))?;
+      let redactions = redactor.redact_value(&mut export);
+
+      let file_name = format!("valedesk-{}-redacted.json", session_id.chars().take(8).collect::<String>());
+      let Some(path) = rfd::FileDialog::new()
+        .add_filter("JSON", &["json"])
+        .set_file_name(&file_name)
+        .save_file() else {
+        emit_server_event_app(&app, &json!({
+          "type": "session.exported",
+          "payload": { "sessionId": session_id, "redacted": true, "cancelled": true }
+        }))?;
+        return Ok(());
+      };
+      let raw = serde_json::to_string_pretty(&export)
+        .map_err(|e| format!("[session.export.redacted] serialize failed: {}", e))?;
+      if let Err(e) = fs::write(&path, raw) {
+        emit_server_event_app(&app, &json!({
+          "type": "runner.error",
+          "payload": { "message": format!("Failed to write export to {}: {}", path.display(), e) }
+        }))?;
+        return Ok(());
       }
+
+      emit_server_event_app(&app, &json!({
+        "type": "session.exported",
+        "payload": {
+          "sessionId": session_id,
+          "redacted": true,
+          "cancelled": false,
+          "redactions": redactions,
+          "path": path.to_string_lossy(),
+          "history": export
+        }
+      }))?;
       Ok(())
     }
 
-    // Session delete - handled in Rust
-    "session.delete" => {
+    // Redacted single-file snapshot for bug reports; saved where the user picks
+    "session.snapshot" => {
       let payload = event.get("payload")
-        .ok_or_else(|| "[session.delete] missing payload".to_string())?;
-      let session_id = payload.get("sessionId")
-        .and_then(|v| v.as_str())
-        .ok_or_else(|| "[session.delete] missing sessionId".to_string())?;
-      
-      state.db.delete_session(session_id)
-        .map_err(|e| format!("[session.delete] {}", e))?;
-      
-      emit_server_event_app(&app, &json!({
+        .ok_or_else(|| "[session.snapshot] missing payload".to_string())?;
+      let session_id = payload.get("sessionId").and_then(|v| v.as_str())
+        .ok_or_else(|| "[session.snapshot] missing sessionId".to_string())?;
+
+      let Some((snapshot, redactions)) = build_session_snapshot(&state.db, session_id)
+        .map_err(|e| format!("[session.snapshot] {}", e))? else {
+        emit_server_event_app(&app, &json!({
+          "type": "runner.error",
+          "payload": { "message": "Session not found" }
+        }))?;
+        return Ok(());
+      };
+
+      // Always the user's pick: the renderer never chooses where files get written
+      let file_name = format!("valedesk-snapshot-{}.json", session_id.chars().take(8).collect::<String>());
+      let Some(path) = rfd::FileDialog::new()
+        .add_filter("JSON", &["json"])
+        .set_file_name(&file_name)
+        .save_file() else {
+        emit_server_event_app(&app, &json!({
+          "type": "session.snapshot.saved",
+          "payload": { "sessionId": session_id, "cancelled": true }
+        }))?;
+        return Ok(());
+      };
+      let body = serde_json::to_string_pretty(&snapshot)
+        .map_err(|e| format!("[session.snapshot] serialize failed: {}", e))?;
+      if let Err(e) = fs::write(&path, body) {
+        emit_server_event_app(&app, &json!({
+          "type": "runner.error",
+          "payload": { "message": format!("Failed to write snapshot to {}: {}", path.display(), e) }
+        }))?;
+        return Ok(());
+      }
+
+      emit_server_event_app(&app, &json!({
+        "type": "session.snapshot.saved",
+        "payload": {
+          "sessionId": session_id,
+          "cancelled": false,
+          "path": path.to_string_lossy(),
+          "redactions": redactions
+        }
+      }))?;
+      Ok(())
+    }
+
+    // Self-contained HTML transcript for sharing outside the app
+    "session.export.html" => {
+      let payload = event.get("payload")
+        .ok_or_else(|| "[session.export.html] missing payload".to_string())?;
+      let session_id = payload.get("sessionId").and_then(|v| v.as_str())
+        .ok_or_else(|| "[session.export.html] missing sessionId".to_string())?;
+
+      let history = match load_session_history(&state.db, session_id) {
+        Ok(Some(history)) => history,
+        Ok(None) => {
+          emit_server_event_app(&app, &json!({
+            "type": "runner.error",
+            "payload": { "message": "Session not found" }
+          }))?;
+          return Ok(());
+        }
+        Err(e) => return Err(format!("[session.export.html] {}", e)),
+      };
+
+      let entries = transcript::collect_entries(&history.messages);
+      let meta = format!("Exported {} · {} messages", chrono::Local::now().format("%Y-%m-%d %H:%M"), entries.len());
+      let html = transcript::render_html(&history.session.title, &meta, &entries);
+
+      let file_name = format!("valedesk-{}.html", session_id.chars().take(8).collect::<String>());
+      let Some(path) = rfd::FileDialog::new()
+        .add_filter("HTML", &["html"])
+        .set_file_name(&file_name)
+        .save_file() else {
+        emit_server_event_app(&app, &json!({
+          "type": "session.exported",
+          "payload": { "sessionId": session_id, "format": "html", "cancelled": true }
+        }))?;
+        return Ok(());
+      };
+      if let Err(e) = fs::write(&path, html) {
+        emit_server_event_app(&app, &json!({
+          "type": "runner.error",
+          "payload": { "message": format!("Failed to write export to {}: {}", path.display(), e) }
+        }))?;
+        return Ok(());
+      }
+
+      emit_server_event_app(&app, &json!({
+        "type": "session.exported",
+        "payload": { "sessionId": session_id, "format": "html", "cancelled": false, "path": path.to_string_lossy() }
+      }))?;
+      Ok(())
+    }
+
+    // Versioned JSON bundle for moving a session between machines, saved where the user picks
+    "session.export" => {
+      let payload = event.get("payload")
+        .ok_or_else(|| "[session.export] missing payload".to_string())?;
+      let session_id = payload.get("sessionId").and_then(|v| v.as_str())
+        .ok_or_else(|| "[session.export] missing sessionId".to_string())?;
+
+      let bundle = match state.db.export_session(session_id, &blobs_dir()?) {
+        Ok(bundle) => bundle,
+        Err(rusqlite::Error::QueryReturnedNoRows) => {
+          emit_server_event_app(&app, &json!({
+            "type": "runner.error",
+            "payload": { "message": "Session not found" }
+          }))?;
+          return Ok(());
+        }
+        Err(e) => return Err(format!("[session.export] {}", e)),
+      };
+
+      let file_name = format!("valedesk-session-{}.json", session_id.chars().take(8).collect::<String>());
+      let Some(path) = rfd::FileDialog::new()
+        .add_filter("JSON", &["json"])
+        .set_file_name(&file_name)
+        .save_file() else {
+        emit_server_event_app(&app, &json!({
+          "type": "session.exported",
+          "payload": { "sessionId": session_id, "format": "bundle", "cancelled": true }
+        }))?;
+        return Ok(());
+      };
+      let raw = serde_json::to_string_pretty(&bundle)
+        .map_err(|e| format!("[session.export] serialize failed: {}", e))?;
+      if let Err(e) = fs::write(&path, raw) {
+        emit_server_event_app(&app, &json!({
+          "type": "runner.error",
+          "payload": { "message": format!("Failed to write export to {}: {}", path.display(), e) }
+        }))?;
+        return Ok(());
+      }
+
+      emit_server_event_app(&app, &json!({
+        "type": "session.exported",
+        "payload": {
+          "sessionId": session_id,
+          "format": "bundle",
+          "cancelled": false,
+          "path": path.to_string_lossy()
+        }
+      }))?;
+      Ok(())
+    }
+
+    // Import a `session.export` bundle from a file the user picks
+    "session.import" => {
+      let Some(path) = rfd::FileDialog::new()
+        .add_filter("JSON", &["json"])
+        .pick_file() else {
+        emit_server_event_app(&app, &json!({
+          "type": "session.imported",
+          "payload": { "source": "bundle", "cancelled": true }
+        }))?;
+        return Ok(());
+      };
+      let imported = fs::read_to_string(&path)
+        .map_err(|e| format!("Failed to read {}: {}", path.display(), e))
+        .and_then(|raw| serde_json::from_str::<Value>(&raw).map_err(|e| format!("Invalid JSON in {}: {}", path.display(), e)))
+        .and_then(|bundle| state.db.import_session(&bundle).map_err(|e| format!("Failed to import session: {}", e)));
+
+      match imported {
+        Ok(session_id) => {
+          emit_server_event_app(&app, &json!({
+            "type": "session.imported",
+            "payload": { "source": "bundle", "cancelled": false, "sessionId": session_id }
+          }))?;
+          let sessions = state.db.list_sessions()
+            .map_err(|e| format!("[session.import] list failed: {}", e))?;
+          emit_server_event_app(&app, &json!({
+            "type": "session.list",
+            "payload": { "sessions": sessions }
+          }))?;
+        }
+        Err(message) => {
+          emit_server_event_app(&app, &json!({
+            "type": "runner.error",
+            "payload": { "message": message }
+          }))?;
+        }
+      }
+      Ok(())
+    }
+
+    // Per-session usage for spreadsheets; from/to are optional ms timestamps on session creation
+    "stats.export_csv" => {
+      let payload = event.get("payload")
+        .ok_or_else(|| "[stats.export_csv] missing payload".to_string())?;
+      let from = payload.get("from").and_then(|v| v.as_i64());
+      let to = payload.get("to").and_then(|v| v.as_i64());
+
+      let Some(output_path) = rfd::FileDialog::new()
+        .add_filter("CSV", &["csv"])
+        .set_file_name("valedesk-usage.csv")
+        .save_file() else {
+        emit_server_event_app(&app, &json!({
+          "type": "stats.exported",
+          "payload": { "format": "csv", "cancelled": true }
+        }))?;
+        return Ok(());
+      };
+      let written = fs::File::create(&output_path)
+        .map_err(|e| e.to_string())
+        .and_then(|file| {
+          let mut writer = std::io::BufWriter::new(file);
+          let rows = state.db.export_usage_csv(from, to, &mut writer).map_err(|e| e.to_string())?;
+          writer.flush().map_err(|e| e.to_string())?;
+          Ok(rows)
+        });
+      let rows = match written {
+        Ok(rows) => rows,
+        Err(e) => {
+          emit_server_event_app(&app, &json!({
+            "type": "runner.error",
+            "payload": { "message": format!("Failed to write usage CSV to {}: {}", output_path.display(), e) }
+          }))?;
+          return Ok(());
+        }
+      };
+
+      emit_server_event_app(&app, &json!({
+        "type": "stats.exported",
+        "payload": { "format": "csv", "cancelled": false, "path": output_path.to_string_lossy(), "rows": rows }
+      }))?;
+      Ok(())
+    }
+
+    // Import conversations from a ChatGPT data export (conversations.json)
+    "session.import.chatgpt" => {
+      let payload = event.get("payload")
+        .ok_or_else(|| "[session.import.chatgpt] missing payload".to_string())?;
+      let path = payload.get("path").and_then(|v| v.as_str())
+        .ok_or_else(|| "[session.import.chatgpt] missing path".to_string())?;
+
+      let imported = fs::read_to_string(path)
+        .map_err(|e| format!("Failed to read {}: {}", path, e))
+        .and_then(|raw| serde_json::from_str::<Value>(&raw).map_err(|e| format!("Invalid JSON in {}: {}", path, e)))
+        .and_then(|export| chatgpt_import::parse_conversations(&export))
+        .and_then(|sessions| {
+          state.db.import_sessions(&sessions)
+            .map(|messages| (sessions.len(), messages))
+            .map_err(|e| format!("Failed to import conversations: {}", e))
+        });
+
+      match imported {
+        Ok((conversations, messages)) => {
+          emit_server_event_app(&app, &json!({
+            "type": "session.imported",
+            "payload": { "source": "chatgpt", "conversations": conversations, "messages": messages }
+          }))?;
+          let sessions = state.db.list_sessions()
+            .map_err(|e| format!("[session.import.chatgpt] list failed: {}", e))?;
+          emit_server_event_app(&app, &json!({
+            "type": "session.list",
+            "payload": { "sessions": sessions }
+          }))?;
+        }
+        Err(message) => {
+          emit_server_event_app(&app, &json!({
+            "type": "runner.error",
+            "payload": { "message": message }
+          }))?;
+        }
+      }
+      Ok(())
+    }
+
+    // Sessions grouped by project folder
+    "session.list.by_project" => {
+      let projects = state.db.list_sessions_grouped_by_cwd()
+        .map_err(|e| format!("[session.list.by_project] {}", e))?;
+      emit_server_event_app(&app, &json!({
+        "type": "session.list.by_project",
+        "payload": { "projects": projects }
+      }))?;
+      Ok(())
+    }
+
+    // Which sessions depend on a folder, before removing or remapping it
+    "session.list.for_cwd" => {
+      let payload = event.get("payload")
+        .ok_or_else(|| "[session.list.for_cwd] missing payload".to_string())?;
+      let cwd = payload.get("cwd").and_then(|v| v.as_str())
+        .ok_or_else(|| "[session.list.for_cwd] missing cwd".to_string())?;
+      let include_subfolders = payload.get("includeSubfolders").and_then(|v| v.as_bool()).unwrap_or(false);
+
+      let sessions = state.db.list_sessions_for_cwd(cwd, include_subfolders)
+        .map_err(|e| format!("[session.list.for_cwd] {}", e))?;
+      emit_server_event_app(&app, &json!({
+        "type": "session.list.for_cwd",
+        "payload": { "cwd": cwd, "includeSubfolders": include_subfolders, "sessions": sessions }
+      }))?;
+      Ok(())
+    }
+
+    // Session history - handled directly from Rust DB
+    "session.history" => {
+      let payload = event.get("payload")
+        .ok_or_else(|| "[session.history] missing payload".to_string())?;
+      let session_id = payload.get("sessionId")
+        .and_then(|v| v.as_str())
+        .ok_or_else(|| "[session.history] missing sessionId".to_string())?;
+      
+      match load_session_history(&state.db, session_id) {
+        Ok(Some(history)) => {
+          emit_server_event_app(&app, &json!({
+            "type": "session.history",
+            "payload": {
+              "sessionId": history.session.id,
+              "status": history.session.status,
+              "messages": history.messages,
+              "inputTokens": history.session.input_tokens,
+              "outputTokens": history.session.output_tokens,
+              "todos": history.todos,
+              "model": history.session.model,
+              "fileChanges": history.file_changes,
+              "metadata": history.metadata,
+              "hasMore": false,
+              "page": "initial"
+            }
+          }))?;
+        }
+        Ok(None) => {
+          emit_server_event_app(&app, &json!({
+            "type": "runner.error",
+            "payload": { "message": "Session not found" }
+          }))?;
+        }
+        Err(e) => {
+          emit_server_event_app(&app, &json!({
+            "type": "runner.error",
+            "payload": { "message": format!("Failed to get session history: {}", e) }
+          }))?;
+        }
+      }
+      Ok(())
+    }
+
+    // Session summary - aggregates only, no message bodies
+    "session.summary" => {
+      let payload = event.get("payload")
+        .ok_or_else(|| "[session.summary] missing payload".to_string())?;
+      let session_id = payload.get("sessionId")
+        .and_then(|v| v.as_str())
+        .ok_or_else(|| "[session.summary] missing sessionId".to_string())?;
+
+      match state.db.get_session_summary(session_id) {
+        Ok(Some(summary)) => {
+          emit_server_event_app(&app, &json!({
+            "type": "session.summary",
+            "payload": summary
+          }))?;
+        }
+        Ok(None) => {
+          emit_server_event_app(&app, &json!({
+            "type": "runner.error",
+            "payload": { "message": "Session not found" }
+          }))?;
+        }
+        Err(e) => {
+          emit_server_event_app(&app, &json!({
+            "type": "runner.error",
+            "payload": { "message": format!("Failed to get session summary: {}", e) }
+          }))?;
+        }
+      }
+      Ok(())
+    }
+
+    // payload.since: ms timestamp, defaults to all recorded usage
+    "usage.summary" => {
+      let since = event.get("payload").and_then(|p| p.get("since")).and_then(|v| v.as_i64()).unwrap_or(0);
+      let models = state.db.usage_summary(since)
+        .map_err(|e| format!("[usage.summary] {}", e))?;
+      let total_cost: f64 = models.iter().filter_map(|m| m.estimated_cost).sum();
+      emit_server_event_app(&app, &json!({
+        "type": "usage.summary",
+        "payload": { "since": since, "models": models, "totalCost": total_cost }
+      }))?;
+      Ok(())
+    }
+
+    "budget.get" => {
+      let session_id = event.get("payload").and_then(|p| p.get("sessionId")).and_then(|v| v.as_str());
+      let status = state.db.get_budget_status(session_id)
+        .map_err(|e| format!("[budget.get] {}", e))?;
+      emit_server_event_app(&app, &json!({
+        "type": "budget.status",
+        "payload": { "sessionId": session_id, "status": status }
+      }))?;
+      Ok(())
+    }
+
+    // payload.capUsd: number, or null to remove the cap
+    "budget.cap.set" => {
+      let payload = event.get("payload")
+        .ok_or_else(|| "[budget.cap.set] missing payload".to_string())?;
+      let cap_usd = match payload.get("capUsd") {
+        None | Some(Value::Null) => None,
+        Some(v) => Some(v.as_f64().ok_or_else(|| "[budget.cap.set] capUsd must be a number".to_string())?),
+      };
+      state.db.set_spending_cap(cap_usd)
+        .map_err(|e| format!("[budget.cap.set] {}", e))?;
+      let status = state.db.get_budget_status(None)
+        .map_err(|e| format!("[budget.cap.set] {}", e))?;
+      emit_server_event_app(&app, &json!({
+        "type": "budget.status",
+        "payload": { "sessionId": null, "status": status }
+      }))?;
+      Ok(())
+    }
+
+    // payload.seconds: idle timeout for a running session, or null/0 to disable
+    "session.idle_timeout.set" => {
+      let payload = event.get("payload")
+        .ok_or_else(|| "[session.idle_timeout.set] missing payload".to_string())?;
+      let session_id = payload.get("sessionId").and_then(|v| v.as_str())
+        .ok_or_else(|| "[session.idle_timeout.set] missing sessionId".to_string())?;
+      let seconds = match payload.get("seconds") {
+        None | Some(Value::Null) => None,
+        Some(v) => Some(v.as_u64().ok_or_else(|| "[session.idle_timeout.set] seconds must be a non-negative integer".to_string())?),
+      }.filter(|secs| *secs > 0);
+      match seconds {
+        Some(secs) => {
+          if !state.db.set_session_meta(session_id, db::IDLE_TIMEOUT_META_KEY, &secs.to_string())
+            .map_err(|e| format!("[session.idle_timeout.set] {}", e))? {
+            return Err(format!("[session.idle_timeout.set] session not found: {}", session_id));
+          }
+        }
+        None => {
+          state.db.delete_session_meta(session_id, db::IDLE_TIMEOUT_META_KEY)
+            .map_err(|e| format!("[session.idle_timeout.set] {}", e))?;
+        }
+      }
+      emit_server_event_app(&app, &json!({
+        "type": "session.idle_timeout.updated",
+        "payload": { "sessionId": session_id, "seconds": seconds }
+      }))?;
+      Ok(())
+    }
+
+    // Let one session keep running past the cap
+    "budget.override" => {
+      let payload = event.get("payload")
+        .ok_or_else(|| "[budget.override] missing payload".to_string())?;
+      let session_id = payload.get("sessionId").and_then(|v| v.as_str())
+        .ok_or_else(|| "[budget.override] missing sessionId".to_string())?;
+      let enabled = payload.get("enabled").and_then(|v| v.as_bool()).unwrap_or(true);
+      if enabled {
+        if !state.db.set_session_meta(session_id, db::BUDGET_OVERRIDE_META_KEY, "true")
+          .map_err(|e| format!("[budget.override] {}", e))? {
+          return Err(format!("[budget.override] session not found: {}", session_id));
+        }
+      } else {
+        state.db.delete_session_meta(session_id, db::BUDGET_OVERRIDE_META_KEY)
+          .map_err(|e| format!("[budget.override] {}", e))?;
+      }
+      let status = state.db.get_budget_status(Some(session_id))
+        .map_err(|e| format!("[budget.override] {}", e))?;
+      emit_server_event_app(&app, &json!({
+        "type": "budget.status",
+        "payload": { "sessionId": session_id, "status": status }
+      }))?;
+      Ok(())
+    }
+
+    "session.text_stats" => {
+      let payload = event.get("payload")
+        .ok_or_else(|| "[session.text_stats] missing payload".to_string())?;
+      let session_id = payload.get("sessionId")
+        .and_then(|v| v.as_str())
+        .ok_or_else(|| "[session.text_stats] missing sessionId".to_string())?;
+
+      match state.db.get_session_text_stats(session_id) {
+        Ok(Some(stats)) => {
+          emit_server_event_app(&app, &json!({
+            "type": "session.text_stats",
+            "payload": stats
+          }))?;
+        }
+        Ok(None) => {
+          emit_server_event_app(&app, &json!({
+            "type": "runner.error",
+            "payload": { "message": "Session not found" }
+          }))?;
+        }
+        Err(e) => {
+          emit_server_event_app(&app, &json!({
+            "type": "runner.error",
+            "payload": { "message": format!("Failed to get session text stats: {}", e) }
+          }))?;
+        }
+      }
+      Ok(())
+    }
+
+    "session.tools.used" => {
+      let payload = event.get("payload")
+        .ok_or_else(|| "[session.tools.used] missing payload".to_string())?;
+      let session_id = payload.get("sessionId")
+        .and_then(|v| v.as_str())
+        .ok_or_else(|| "[session.tools.used] missing sessionId".to_string())?;
+
+      match state.db.list_tool_invocations(session_id) {
+        Ok(Some(report)) => {
+          emit_server_event_app(&app, &json!({
+            "type": "session.tools.used",
+            "payload": report
+          }))?;
+        }
+        Ok(None) => {
+          emit_server_event_app(&app, &json!({
+            "type": "runner.error",
+            "payload": { "message": "Session not found" }
+          }))?;
+        }
+        Err(e) => {
+          emit_server_event_app(&app, &json!({
+            "type": "runner.error",
+            "payload": { "message": format!("Failed to list tool invocations: {}", e) }
+          }))?;
+        }
+      }
+      Ok(())
+    }
+
+    // Session delete - handled in Rust
+    "session.delete" => {
+      let payload = event.get("payload")
+        .ok_or_else(|| "[session.delete] missing payload".to_string())?;
+      let session_id = payload.get("sessionId")
+        .and_then(|v| v.as_str())
+        .ok_or_else(|| "[session.delete] missing sessionId".to_string())?;
+
+      if state.db.is_session_locked(session_id).unwrap_or(false) {
+        emit_server_event_app(&app, &json!({
+          "type": "runner.error",
+          "payload": { "sessionId": session_id, "message": "Session is locked; unlock it before deleting" }
+        }))?;
+        return Ok(());
+      }
+      
+      state.db.delete_session(session_id)
+        .map_err(|e| format!("[session.delete] {}", e))?;
+      state.voice.discard_usage(session_id);
+      sweep_orphan_blobs(&state.db);
+      
+      emit_server_event_app(&app, &json!({
         "type": "session.deleted",
         "payload": { "sessionId": session_id }
       }))?;
-      
-      // Also send updated session list
+      
+      // Also send updated session list
+      let sessions = state.db.list_sessions()
+        .map_err(|e| format!("[session.delete] list failed: {}", e))?;
+      emit_server_event_app(&app, &json!({
+        "type": "session.list",
+        "payload": { "sessions": sessions }
+      }))?;
+      Ok(())
+    }
+
+    // Session pin - handled in Rust
+    "session.pin" => {
+      let payload = event.get("payload")
+        .ok_or_else(|| "[session.pin] missing payload".to_string())?;
+      let session_id = payload.get("sessionId")
+        .and_then(|v| v.as_str())
+        .ok_or_else(|| "[session.pin] missing sessionId".to_string())?;
+      let is_pinned = payload.get("isPinned")
+        .and_then(|v| v.as_bool())
+        .unwrap_or(false);
+      
+      state.db.set_pinned(session_id, is_pinned)
+        .map_err(|e| format!("[session.pin] {}", e))?;
+      
+      // Send updated session list
+      let sessions = state.db.list_sessions()
+        .map_err(|e| format!("[session.pin] list failed: {}", e))?;
+      emit_server_event_app(&app, &json!({
+        "type": "session.list",
+        "payload": { "sessions": sessions }
+      }))?;
+      Ok(())
+    }
+
+    "session.archive" => {
+      let payload = event.get("payload")
+        .ok_or_else(|| "[session.archive] missing payload".to_string())?;
+      let session_id = payload.get("sessionId")
+        .and_then(|v| v.as_str())
+        .ok_or_else(|| "[session.archive] missing sessionId".to_string())?;
+      let archived = payload.get("archived")
+        .and_then(|v| v.as_bool())
+        .unwrap_or(true);
+
+      state.db.set_archived(session_id, archived)
+        .map_err(|e| format!("[session.archive] {}", e))?;
+
+      // Send updated session list
+      let sessions = state.db.list_sessions()
+        .map_err(|e| format!("[session.archive] list failed: {}", e))?;
+      emit_server_event_app(&app, &json!({
+        "type": "session.list",
+        "payload": { "sessions": sessions }
+      }))?;
+      Ok(())
+    }
+
+    // Full-text search over prompts and replies, grouped by session in best-hit order
+    "search.messages" => {
+      let payload = event.get("payload").ok_or_else(|| "[search.messages] missing payload".to_string())?;
+      let query = payload.get("query").and_then(|v| v.as_str())
+        .ok_or_else(|| "[search.messages] missing query".to_string())?;
+      let limit = payload.get("limit").and_then(|v| v.as_u64()).unwrap_or(50).min(500) as u32;
+
+      let hits = state.db.search_messages(query, limit)
+        .map_err(|e| format!("[search.messages] {}", e))?;
+      let mut groups: Vec<Value> = Vec::new();
+      for hit in hits {
+        match groups.iter_mut().find(|g| g["sessionId"] == hit.session_id.as_str()) {
+          Some(group) => group["hits"].as_array_mut().unwrap().push(json!(hit)),
+          None => groups.push(json!({
+            "sessionId": hit.session_id,
+            "sessionTitle": hit.session_title,
+            "hits": [hit]
+          })),
+        }
+      }
+      emit_server_event_app(&app, &json!({
+        "type": "search.messages.results",
+        "payload": { "query": query, "groups": groups }
+      }))?;
+      Ok(())
+    }
+
+    // payload: { providerId, model } to enable semantic search, or { providerId: null } to turn it off
+    "search.semantic.configure" => {
+      let payload = event.get("payload")
+        .ok_or_else(|| "[search.semantic.configure] missing payload".to_string())?;
+      let settings = match payload.get("providerId").and_then(|v| v.as_str()).filter(|id| !id.trim().is_empty()) {
+        Some(provider_id) => {
+          let model = payload.get("model").and_then(|v| v.as_str()).filter(|m| !m.trim().is_empty())
+            .ok_or_else(|| "[search.semantic.configure] missing model".to_string())?;
+          Some(db::EmbeddingSettings { provider_id: provider_id.to_string(), model: model.trim().to_string() })
+        }
+        None => None,
+      };
+      state.db.set_embedding_settings(settings.as_ref())
+        .map_err(|e| format!("[search.semantic.configure] {}", e))?;
+      emit_server_event_app(&app, &json!({
+        "type": "search.semantic.configured",
+        "payload": { "settings": settings }
+      }))?;
+      Ok(())
+    }
+
+    // Embeds the query (and any not-yet-embedded messages, up to a limit) and ranks stored vectors
+    "search.semantic" => {
+      let payload = event.get("payload").ok_or_else(|| "[search.semantic] missing payload".to_string())?;
+      let query = payload.get("query").and_then(|v| v.as_str()).filter(|q| !q.trim().is_empty())
+        .ok_or_else(|| "[search.semantic] missing query".to_string())?
+        .to_string();
+      let top_k = payload.get("topK").and_then(|v| v.as_u64()).unwrap_or(10).clamp(1, 100) as usize;
+      let client = state.http.blocking_client()?;
+
+      let app_handle = app.clone();
+      std::thread::spawn(move || {
+        let state = app_handle.state::<AppState>();
+        let result = semantic_search_setup(&state.db).and_then(|(settings, provider)| {
+          embed_pending_messages(&state.db, &client, &provider, &settings.model, SEMANTIC_SEARCH_LAZY_EMBED_LIMIT)?;
+          let vector = embed_texts_blocking(&client, &provider, &settings.model, std::slice::from_ref(&query))?
+            .pop()
+            .ok_or_else(|| "empty embeddings response".to_string())?;
+          state.db.semantic_search(&settings.model, &vector, top_k).map_err(|e| e.to_string())
+        });
+        let event = match result {
+          Ok(hits) => json!({
+            "type": "search.semantic.results",
+            "payload": { "query": query, "hits": hits }
+          }),
+          Err(message) => json!({
+            "type": "runner.error",
+            "payload": { "message": format!("Semantic search failed: {}", message) }
+          }),
+        };
+        let _ = emit_server_event_app(&app_handle, &event);
+      });
+      Ok(())
+    }
+
+    // Embed every message that has no vector for the configured model yet
+    "search.semantic.backfill" => {
+      let client = state.http.blocking_client()?;
+      let app_handle = app.clone();
+      std::thread::spawn(move || {
+        let state = app_handle.state::<AppState>();
+        let result = semantic_search_setup(&state.db).and_then(|(settings, provider)| {
+          embed_pending_messages(&state.db, &client, &provider, &settings.model, usize::MAX)
+        });
+        let event = match result {
+          Ok(embedded) => json!({
+            "type": "search.semantic.backfilled",
+            "payload": { "embedded": embedded }
+          }),
+          Err(message) => json!({
+            "type": "runner.error",
+            "payload": { "message": format!("Embedding backfill failed: {}", message) }
+          }),
+        };
+        let _ = emit_server_event_app(&app_handle, &event);
+      });
+      Ok(())
+    }
+
+    // Sessions that look like reruns of the same prompt, for the UI to offer cleanup
+    "session.find_duplicates" => {
+      let clusters = state.db.find_duplicate_sessions()
+        .map_err(|e| format!("[session.find_duplicates] {}", e))?;
+      emit_server_event_app(&app, &json!({
+        "type": "session.duplicates",
+        "payload": { "clusters": clusters }
+      }))?;
+      Ok(())
+    }
+
+    "session.dedupe_titles" => {
+      let renamed = state.db.dedupe_session_titles()
+        .map_err(|e| format!("[session.dedupe_titles] {}", e))?;
+      emit_server_event_app(&app, &json!({
+        "type": "session.titles_deduped",
+        "payload": { "renamed": renamed }
+      }))?;
+      if renamed > 0 {
+        let sessions = state.db.list_sessions()
+          .map_err(|e| format!("[session.dedupe_titles] list failed: {}", e))?;
+        emit_server_event_app(&app, &json!({
+          "type": "session.list",
+          "payload": { "sessions": sessions }
+        }))?;
+      }
+      Ok(())
+    }
+
+    // Dry run of history pruning: what would be cut, nothing is deleted
+    "session.prune.preview" => {
+      let payload = event.get("payload")
+        .ok_or_else(|| "[session.prune.preview] missing payload".to_string())?;
+      let session_id = payload.get("sessionId")
+        .and_then(|v| v.as_str())
+        .ok_or_else(|| "[session.prune.preview] missing sessionId".to_string())?;
+      let keep_last_n = payload.get("keepLastN").and_then(|v| v.as_u64()).unwrap_or(0) as usize;
+      let token_budget = payload.get("tokenBudget").and_then(|v| v.as_i64());
+
+      let candidates = state.db.preview_prune(session_id, keep_last_n, token_budget)
+        .map_err(|e| format!("[session.prune.preview] {}", e))?;
+      let estimated_tokens: i64 = candidates.iter().map(|c| c.estimated_tokens).sum();
+      emit_server_event_app(&app, &json!({
+        "type": "session.prune.preview",
+        "payload": {
+          "sessionId": session_id,
+          "keepLastN": keep_last_n,
+          "tokenBudget": token_budget,
+          "messages": candidates,
+          "estimatedTokens": estimated_tokens
+        }
+      }))?;
+      Ok(())
+    }
+
+    // Unstick a single session (e.g. left "running" after the sidecar dropped its completion)
+    "session.reset_status" => {
+      let payload = event.get("payload")
+        .ok_or_else(|| "[session.reset_status] missing payload".to_string())?;
+      let session_id = payload.get("sessionId")
+        .and_then(|v| v.as_str())
+        .ok_or_else(|| "[session.reset_status] missing sessionId".to_string())?;
+      let status = payload.get("status").and_then(|v| v.as_str()).unwrap_or("idle");
+
+      match state.db.reset_session_status(session_id, status) {
+        Ok(true) => {}
+        Ok(false) => {
+          emit_server_event_app(&app, &json!({
+            "type": "runner.error",
+            "payload": { "message": "Session not found" }
+          }))?;
+          return Ok(());
+        }
+        Err(e) => {
+          emit_server_event_app(&app, &json!({
+            "type": "runner.error",
+            "payload": { "sessionId": session_id, "message": e.to_string() }
+          }))?;
+          return Ok(());
+        }
+      }
+
+      emit_server_event_app(&app, &json!({
+        "type": "session.status",
+        "payload": { "sessionId": session_id, "status": status }
+      }))?;
+      let sessions = state.db.list_sessions()
+        .map_err(|e| format!("[session.reset_status] list failed: {}", e))?;
+      emit_server_event_app(&app, &json!({
+        "type": "session.list",
+        "payload": { "sessions": sessions }
+      }))?;
+      Ok(())
+    }
+
+    // Free-form per-session metadata (ticket ids, categories, ...)
+    "session.meta.set" | "session.meta.delete" | "session.meta.get" | "session.meta.list" => {
+      let payload = event.get("payload")
+        .ok_or_else(|| format!("[{}] missing payload", event_type))?;
+      let session_id = payload.get("sessionId")
+        .and_then(|v| v.as_str())
+        .ok_or_else(|| format!("[{}] missing sessionId", event_type))?;
+      let key = payload.get("key").and_then(|v| v.as_str());
+      let require_key = || key.ok_or_else(|| format!("[{}] missing key", event_type));
+
+      match event_type {
+        "session.meta.set" => {
+          let value = match payload.get("value") {
+            Some(Value::String(s)) => s.clone(),
+            Some(Value::Null) | None => return Err(format!("[{}] missing value", event_type)),
+            Some(other) => other.to_string(),
+          };
+          match state.db.set_session_meta(session_id, require_key()?, &value) {
+            Ok(true) => {}
+            Ok(false) => {
+              emit_server_event_app(&app, &json!({
+                "type": "runner.error",
+                "payload": { "message": "Session not found" }
+              }))?;
+              return Ok(());
+            }
+            Err(e) => return Err(format!("[{}] {}", event_type, e)),
+          }
+        }
+        "session.meta.delete" => {
+          state.db.delete_session_meta(session_id, require_key()?)
+            .map_err(|e| format!("[{}] {}", event_type, e))?;
+        }
+        "session.meta.get" => {
+          let key = require_key()?;
+          let value = state.db.get_session_meta(session_id, key)
+            .map_err(|e| format!("[{}] {}", event_type, e))?;
+          return emit_server_event_app(&app, &json!({
+            "type": "session.meta",
+            "payload": { "sessionId": session_id, "key": key, "value": value }
+          }));
+        }
+        _ => {}
+      }
+
+      let metadata = state.db.list_session_meta(session_id)
+        .map_err(|e| format!("[{}] {}", event_type, e))?;
+      emit_server_event_app(&app, &json!({
+        "type": "session.meta",
+        "payload": { "sessionId": session_id, "metadata": metadata }
+      }))
+    }
+
+    // Session lock - protects curated sessions from edits/deletes
+    "session.lock" | "session.unlock" => {
+      let payload = event.get("payload")
+        .ok_or_else(|| format!("[{}] missing payload", event_type))?;
+      let session_id = payload.get("sessionId")
+        .and_then(|v| v.as_str())
+        .ok_or_else(|| format!("[{}] missing sessionId", event_type))?;
+
+      let found = state.db.set_locked(session_id, event_type == "session.lock")
+        .map_err(|e| format!("[{}] {}", event_type, e))?;
+      if !found {
+        emit_server_event_app(&app, &json!({
+          "type": "runner.error",
+          "payload": { "message": "Session not found" }
+        }))?;
+        return Ok(());
+      }
+
+      let sessions = state.db.list_sessions()
+        .map_err(|e| format!("[{}] list failed: {}", event_type, e))?;
+      emit_server_event_app(&app, &json!({
+        "type": "session.list",
+        "payload": { "sessions": sessions }
+      }))?;
+      Ok(())
+    }
+
+    // Bulk model reassignment (e.g. when retiring a provider)
+    "sessions.reassign_model" => {
+      let payload = event.get("payload")
+        .ok_or_else(|| "[sessions.reassign_model] missing payload".to_string())?;
+      let from_model_id = payload.get("fromModelId").and_then(|v| v.as_str())
+        .ok_or_else(|| "[sessions.reassign_model] missing fromModelId".to_string())?;
+      let to_model_id = payload.get("toModelId").and_then(|v| v.as_str())
+        .ok_or_else(|| "[sessions.reassign_model] missing toModelId".to_string())?;
+
+      let target = state.db.get_model(to_model_id)
+        .map_err(|e| format!("[sessions.reassign_model] {}", e))?;
+      let error = match target {
+        None => Some(format!("Model not found: {}", to_model_id)),
+        Some(model) if !model.enabled => Some(format!("Model is disabled: {}", to_model_id)),
+        Some(_) => None,
+      };
+      if let Some(message) = error {
+        emit_server_event_app(&app, &json!({
+          "type": "runner.error",
+          "payload": { "message": message }
+        }))?;
+        return Ok(());
+      }
+
+      let reassigned = state.db.reassign_model(from_model_id, to_model_id)
+        .map_err(|e| format!("[sessions.reassign_model] {}", e))?;
+      emit_server_event_app(&app, &json!({
+        "type": "sessions.model_reassigned",
+        "payload": { "fromModelId": from_model_id, "toModelId": to_model_id, "count": reassigned.len() }
+      }))?;
+
+      // Sessions the sidecar has loaded keep their own copy of the model; it ignores ids it hasn't loaded
+      for session_id in &reassigned {
+        if let Err(e) = send_to_sidecar(app.clone(), state.inner(), &json!({
+          "type": "session.update",
+          "payload": { "sessionId": session_id, "model": to_model_id }
+        })) {
+          eprintln!("[sessions.reassign_model] Failed to update {} in the sidecar: {}", session_id, e);
+        }
+      }
+
       let sessions = state.db.list_sessions()
-        .map_err(|e| format!("[session.delete] list failed: {}", e))?;
+        .map_err(|e| format!("[sessions.reassign_model] list failed: {}", e))?;
       emit_server_event_app(&app, &json!({
         "type": "session.list",
         "payload": { "sessions": sessions }
@@ -2049,23 +5758,54 @@ fn client_event(app: tauri::AppHandle, state: tauri::State<'_, AppState>, event:
       Ok(())
     }
 
-    // Session pin - handled in Rust
-    "session.pin" => {
+    // Omitted sessionIds = every session
+    "sessions.set_temperature" => {
       let payload = event.get("payload")
-        .ok_or_else(|| "[session.pin] missing payload".to_string())?;
-      let session_id = payload.get("sessionId")
-        .and_then(|v| v.as_str())
-        .ok_or_else(|| "[session.pin] missing sessionId".to_string())?;
-      let is_pinned = payload.get("isPinned")
-        .and_then(|v| v.as_bool())
-        .unwrap_or(false);
-      
-      state.db.set_pinned(session_id, is_pinned)
-        .map_err(|e| format!("[session.pin] {}", e))?;
-      
-      // Send updated session list
+        .ok_or_else(|| "[sessions.set_temperature] missing payload".to_string())?;
+      let temperature = payload.get("temperature").and_then(|v| v.as_f64())
+        .ok_or_else(|| "[sessions.set_temperature] missing temperature".to_string())?;
+      let ids: Option<Vec<String>> = match payload.get("sessionIds") {
+        None | Some(Value::Null) => None,
+        Some(Value::Array(items)) => Some(items.iter()
+          .map(|v| v.as_str().map(String::from))
+          .collect::<Option<_>>()
+          .ok_or_else(|| "[sessions.set_temperature] sessionIds must be strings".to_string())?),
+        Some(_) => return Err("[sessions.set_temperature] sessionIds must be an array".to_string()),
+      };
+
+      let count = match state.db.set_temperature_bulk(ids.as_deref(), temperature) {
+        Ok(count) => count,
+        Err(rusqlite::Error::ToSqlConversionFailure(e)) => {
+          emit_server_event_app(&app, &json!({
+            "type": "runner.error",
+            "payload": { "message": format!("Invalid temperature: {}", e) }
+          }))?;
+          return Ok(());
+        }
+        Err(e) => return Err(format!("[sessions.set_temperature] {}", e)),
+      };
+      emit_server_event_app(&app, &json!({
+        "type": "sessions.temperature_updated",
+        "payload": { "temperature": temperature, "count": count }
+      }))?;
+
+      // Sessions the sidecar has loaded keep their own copy; send each its stored (clamped) value.
+      // The sidecar ignores ids it hasn't loaded.
+      let updated = state.db.list_sessions_filtered(true)
+        .map_err(|e| format!("[sessions.set_temperature] list failed: {}", e))?
+        .into_iter()
+        .filter(|s| ids.as_ref().is_none_or(|ids| ids.contains(&s.id)));
+      for session in updated {
+        if let Err(e) = send_to_sidecar(app.clone(), state.inner(), &json!({
+          "type": "session.update",
+          "payload": { "sessionId": session.id, "temperature": session.temperature }
+        })) {
+          eprintln!("[sessions.set_temperature] Failed to update {} in the sidecar: {}", session.id, e);
+        }
+      }
+
       let sessions = state.db.list_sessions()
-        .map_err(|e| format!("[session.pin] list failed: {}", e))?;
+        .map_err(|e| format!("[sessions.set_temperature] list failed: {}", e))?;
       emit_server_event_app(&app, &json!({
         "type": "session.list",
         "payload": { "sessions": sessions }
@@ -2073,7 +5813,140 @@ fn client_event(app: tauri::AppHandle, state: tauri::State<'_, AppState>, event:
       Ok(())
     }
 
-    // Code Sandbox - execute JS/Python in Rust
+    // Host commands from the user's allowlist (name -> absolute binary path), never a shell
+    "tools.native.allowlist.get" => {
+      let setting = state.db.get_setting(sandbox::NATIVE_TOOLS_SETTING)
+        .map_err(|e| format!("[tools.native.allowlist.get] {}", e))?;
+      emit_server_event_app(&app, &json!({
+        "type": "tools.native.allowlist.loaded",
+        "payload": { "tools": sandbox::parse_native_allowlist(setting.as_deref()) }
+      }))?;
+      Ok(())
+    }
+
+    "tools.native.allowlist.set" => {
+      let payload = event.get("payload")
+        .ok_or_else(|| "[tools.native.allowlist.set] missing payload".to_string())?;
+      let tools = payload.get("tools").filter(|v| v.is_object())
+        .ok_or_else(|| "[tools.native.allowlist.set] tools must be an object of name -> binary path".to_string())?;
+      let allowlist = sandbox::parse_native_allowlist(Some(&tools.to_string()));
+      if allowlist.len() != tools.as_object().map(|o| o.len()).unwrap_or(0) {
+        return Err("[tools.native.allowlist.set] every binary must be an absolute path".to_string());
+      }
+
+      // The renderer only proposes: new or changed binaries need the user's OK in a native dialog
+      let current = sandbox::parse_native_allowlist(
+        state.db.get_setting(sandbox::NATIVE_TOOLS_SETTING)
+          .map_err(|e| format!("[tools.native.allowlist.set] {}", e))?
+          .as_deref(),
+      );
+      let mut added: Vec<String> = allowlist.iter()
+        .filter(|(name, binary)| current.get(*name) != Some(*binary))
+        .map(|(name, binary)| format!("{} → {}", name, binary))
+        .collect();
+      added.sort();
+      if !added.is_empty() {
+        let confirmed = rfd::MessageDialog::new()
+          .set_level(rfd::MessageLevel::Warning)
+          .set_title("Allow native tools?")
+          .set_description(format!(
+            "The agent will be able to run these programs on this computer:\n\n{}",
+            added.join("\n")
+          ))
+          .set_buttons(rfd::MessageButtons::YesNo)
+          .show() == rfd::MessageDialogResult::Yes;
+        if !confirmed {
+          emit_server_event_app(&app, &json!({
+            "type": "tools.native.allowlist.loaded",
+            "payload": { "tools": current }
+          }))?;
+          emit_server_event_app(&app, &json!({
+            "type": "runner.error",
+            "payload": { "message": "Native tools allowlist change was not confirmed" }
+          }))?;
+          return Ok(());
+        }
+      }
+      let serialized = serde_json::to_string(&allowlist).map_err(|e| format!("[tools.native.allowlist.set] {}", e))?;
+      state.db.set_setting(sandbox::NATIVE_TOOLS_SETTING, &serialized)
+        .map_err(|e| format!("[tools.native.allowlist.set] {}", e))?;
+      emit_server_event_app(&app, &json!({
+        "type": "tools.native.allowlist.loaded",
+        "payload": { "tools": allowlist }
+      }))?;
+      Ok(())
+    }
+
+    "tools.run_native" => {
+      let payload = event.get("payload").ok_or_else(|| "[tools.run_native] missing payload".to_string())?;
+      let name = payload.get("name").and_then(|v| v.as_str())
+        .ok_or_else(|| "[tools.run_native] missing name".to_string())?
+        .to_string();
+      let args: Vec<String> = match payload.get("args") {
+        None | Some(Value::Null) => Vec::new(),
+        Some(Value::Array(items)) => items.iter()
+          .map(|v| v.as_str().map(String::from))
+          .collect::<Option<_>>()
+          .ok_or_else(|| "[tools.run_native] args must be strings".to_string())?,
+        Some(_) => return Err("[tools.run_native] args must be an array".to_string()),
+      };
+      let cwd = payload.get("cwd").and_then(|v| v.as_str()).unwrap_or("/tmp").to_string();
+      let timeout_ms = payload.get("timeoutMs").and_then(|v| v.as_u64())
+        .unwrap_or(sandbox::NATIVE_DEFAULT_TIMEOUT_MS)
+        .min(sandbox::NATIVE_MAX_TIMEOUT_MS);
+      let request_id = payload.get("requestId").and_then(|v| v.as_str()).map(String::from);
+      let setting = state.db.get_setting(sandbox::NATIVE_TOOLS_SETTING)
+        .map_err(|e| format!("[tools.run_native] {}", e))?;
+      let allowlist = sandbox::parse_native_allowlist(setting.as_deref());
+
+      let app_handle = app.clone();
+      std::thread::spawn(move || {
+        let result = sandbox::run_native_command(&allowlist, &name, &args, &cwd, timeout_ms);
+        let _ = emit_server_event_app(&app_handle, &json!({
+          "type": "tools.native.result",
+          "payload": { "requestId": request_id, "name": name, "result": result }
+        }));
+      });
+      Ok(())
+    }
+
+    // Re-run the execute_js/execute_python calls a session made, in the session's cwd
+    "session.replay_sandbox" => {
+      let payload = event.get("payload").ok_or_else(|| "[session.replay_sandbox] missing payload".to_string())?;
+      let session_id = payload.get("sessionId").and_then(|v| v.as_str())
+        .ok_or_else(|| "[session.replay_sandbox] missing sessionId".to_string())?
+        .to_string();
+
+      let session = match state.db.get_session(&session_id).map_err(|e| format!("[session.replay_sandbox] {}", e))? {
+        Some(session) => session,
+        None => {
+          emit_server_event_app(&app, &json!({
+            "type": "runner.error",
+            "payload": { "message": "Session not found" }
+          }))?;
+          return Ok(());
+        }
+      };
+      let messages = state.db.get_session_messages(&session_id)
+        .map_err(|e| format!("[session.replay_sandbox] {}", e))?;
+      let steps = sandbox::replay_steps(&messages);
+      let cwd = session.cwd.filter(|c| !c.trim().is_empty()).unwrap_or_else(|| "/tmp".to_string());
+
+      let app_handle = app.clone();
+      std::thread::spawn(move || {
+        let results: Vec<Value> = steps.iter().map(|step| {
+          let result = sandbox::execute_code(&step.code, step.language, &cwd, step.timeout_ms);
+          json!({ "messageIndex": step.message_index, "language": step.language, "result": result })
+        }).collect();
+        eprintln!("[session.replay_sandbox] {}: replayed {} code blocks", session_id, results.len());
+        let _ = emit_server_event_app(&app_handle, &json!({
+          "type": "session.replay_sandbox.result",
+          "payload": { "sessionId": session_id, "cwd": cwd, "results": results }
+        }));
+      });
+      Ok(())
+    }
+
     "sandbox.execute" => {
       let payload = event.get("payload").ok_or_else(|| "[sandbox.execute] missing payload".to_string())?;
       let code = payload.get("code").and_then(|v| v.as_str())
@@ -2082,22 +5955,200 @@ fn client_event(app: tauri::AppHandle, state: tauri::State<'_, AppState>, event:
       let cwd = payload.get("cwd").and_then(|v| v.as_str()).unwrap_or("/tmp");
       let timeout_ms = payload.get("timeoutMs").and_then(|v| v.as_u64()).unwrap_or(5000);
       let request_id = payload.get("requestId").and_then(|v| v.as_str()).map(String::from);
+      let sandbox_id = payload.get("sandboxId").and_then(|v| v.as_str());
+      let streaming = payload.get("streaming").and_then(|v| v.as_bool()).unwrap_or(false);
       
-      let result = sandbox::execute_code(code, language, cwd, timeout_ms);
-      
-      emit_server_event_app(&app, &json!({
-        "type": "sandbox.result",
-        "payload": {
-          "requestId": request_id,
-          "result": result
+      let code = code.to_string();
+      let language = language.to_string();
+      let cwd = cwd.to_string();
+      let sandbox_id = sandbox_id.map(String::from);
+
+      // Runs can take up to timeout_ms; don't hold up other client events meanwhile
+      let app_handle = app.clone();
+      std::thread::spawn(move || {
+        let result = match sandbox_id.as_deref() {
+          Some(id) if matches!(language.as_str(), "javascript" | "js") => sandbox::execute_in_js_session(id, &code, timeout_ms),
+          // Output lines go out as sandbox.output while the code runs, then the usual sandbox.result
+          _ if streaming => {
+            let output_app = app_handle.clone();
+            let output_request_id = request_id.clone();
+            let on_line: sandbox::OutputSink = Arc::new(move |stream, line: &str| {
+              let _ = emit_server_event_app(&output_app, &json!({
+                "type": "sandbox.output",
+                "payload": { "requestId": output_request_id, "stream": stream, "line": line }
+              }));
+            });
+            sandbox::execute_code_streaming(&code, &language, &cwd, timeout_ms, on_line)
+          }
+          _ => sandbox::execute_code(&code, &language, &cwd, timeout_ms),
+        };
+
+        let _ = emit_server_event_app(&app_handle, &json!({
+          "type": "sandbox.result",
+          "payload": {
+            "requestId": request_id,
+            "result": result
+          }
+        }));
+      });
+      Ok(())
+    }
+
+    // Incremental directory walk for large projects: fs.tree.chunk batches, then fs.tree.done
+    "fs.tree.start" => {
+      let payload = event.get("payload")
+        .ok_or_else(|| "[fs.tree.start] missing payload".to_string())?;
+      let root = payload.get("root").and_then(|v| v.as_str()).filter(|p| !p.trim().is_empty())
+        .ok_or_else(|| "[fs.tree.start] missing root".to_string())?;
+      let root = PathBuf::from(root);
+      if !root.is_dir() {
+        return Err(format!("[fs.tree.start] not a directory: {}", root.display()));
+      }
+      let ignore: Vec<String> = match payload.get("ignore").and_then(|v| v.as_array()) {
+        Some(patterns) => patterns.iter().filter_map(|p| p.as_str()).map(String::from).collect(),
+        None => DEFAULT_TREE_IGNORES.iter().map(|p| p.to_string()).collect(),
+      };
+      let max_entries = payload.get("maxEntries").and_then(|v| v.as_u64())
+        .map(|n| n as usize)
+        .unwrap_or(TREE_WALK_DEFAULT_MAX_ENTRIES);
+      let walk_id = payload.get("walkId").and_then(|v| v.as_str())
+        .map(String::from)
+        .unwrap_or_else(|| uuid::Uuid::new_v4().to_string());
+
+      let cancel = Arc::new(std::sync::atomic::AtomicBool::new(false));
+      {
+        let mut walks = state.tree_walks.lock().unwrap();
+        if walks.contains_key(&walk_id) {
+          return Err(format!("[fs.tree.start] walk {} is already running", walk_id));
         }
+        walks.insert(walk_id.clone(), cancel.clone());
+      }
+
+      let app_handle = app.clone();
+      std::thread::spawn(move || {
+        let summary = walk_tree(&root, &ignore, max_entries, TREE_WALK_BATCH_SIZE, &cancel, |nodes| {
+          let _ = emit_server_event_app(&app_handle, &json!({
+            "type": "fs.tree.chunk",
+            "payload": { "walkId": walk_id, "nodes": nodes }
+          }));
+        });
+        {
+          let state = app_handle.state::<AppState>();
+          let mut walks = state.tree_walks.lock().unwrap();
+          if walks.get(&walk_id).is_some_and(|flag| Arc::ptr_eq(flag, &cancel)) {
+            walks.remove(&walk_id);
+          }
+        }
+        let _ = emit_server_event_app(&app_handle, &json!({
+          "type": "fs.tree.done",
+          "payload": {
+            "walkId": walk_id,
+            "root": root.to_string_lossy(),
+            "total": summary.total,
+            "truncated": summary.truncated,
+            "aborted": summary.aborted
+          }
+        }));
+      });
+      Ok(())
+    }
+
+    "fs.tree.abort" => {
+      let payload = event.get("payload")
+        .ok_or_else(|| "[fs.tree.abort] missing payload".to_string())?;
+      let walk_id = payload.get("walkId").and_then(|v| v.as_str())
+        .ok_or_else(|| "[fs.tree.abort] missing walkId".to_string())?;
+      // fs.tree.done (aborted: true) follows from the walker thread
+      if let Some(cancel) = state.tree_walks.lock().unwrap().get(walk_id) {
+        cancel.store(true, std::sync::atomic::Ordering::Relaxed);
+      }
+      Ok(())
+    }
+
+    "sidecar.config" => {
+      emit_server_event_app(&app, &json!({
+        "type": "sidecar.config",
+        "payload": sidecar_config()
+      }))?;
+      Ok(())
+    }
+
+    "sandbox.languages" => {
+      emit_server_event_app(&app, &json!({
+        "type": "sandbox.languages",
+        "payload": { "languages": sandbox::supported_languages() }
+      }))?;
+      Ok(())
+    }
+
+    // Smoke test for the built-in JS engine (fixed scripts, known results)
+    "sandbox.selftest" => {
+      let report = sandbox::run_js_selftest();
+      if !report.passed {
+        eprintln!("[sandbox.selftest] failed: {:?}", report.checks.iter().filter(|c| !c.passed).collect::<Vec<_>>());
+      }
+      emit_server_event_app(&app, &json!({
+        "type": "sandbox.selftest",
+        "payload": report
+      }))?;
+      Ok(())
+    }
+
+    // Persistent JS contexts reused across sandbox.execute calls (payload.sandboxId)
+    "sandbox.session.create" => {
+      let payload = event.get("payload").ok_or_else(|| "[sandbox.session.create] missing payload".to_string())?;
+      let sandbox_id = payload.get("sandboxId").and_then(|v| v.as_str())
+        .ok_or_else(|| "[sandbox.session.create] missing sandboxId".to_string())?;
+      let cwd = payload.get("cwd").and_then(|v| v.as_str()).unwrap_or("/tmp");
+
+      let error = sandbox::create_js_session(sandbox_id, cwd).err();
+      emit_server_event_app(&app, &json!({
+        "type": "sandbox.session.created",
+        "payload": { "sandboxId": sandbox_id, "success": error.is_none(), "error": error }
+      }))?;
+      Ok(())
+    }
+
+    "sandbox.session.close" => {
+      let payload = event.get("payload").ok_or_else(|| "[sandbox.session.close] missing payload".to_string())?;
+      let sandbox_id = payload.get("sandboxId").and_then(|v| v.as_str())
+        .ok_or_else(|| "[sandbox.session.close] missing sandboxId".to_string())?;
+
+      let closed = sandbox::close_js_session(sandbox_id);
+      emit_server_event_app(&app, &json!({
+        "type": "sandbox.session.closed",
+        "payload": { "sandboxId": sandbox_id, "closed": closed }
       }))?;
       Ok(())
     }
 
     // session.start - ensure model is set (use scheduler default if missing)
     "session.start" => {
+      if budget_exceeded(&app, &state.db, None)? {
+        return Ok(());
+      }
       let payload = event.get("payload").cloned().unwrap_or(json!({}));
+      // Warn early instead of letting agent tools fail mid-session on a read-only cwd
+      if let Some(cwd) = payload.get("cwd").and_then(|v| v.as_str()).filter(|c| !c.trim().is_empty()) {
+        let check = cwd_writable(Path::new(cwd.trim()));
+        if !check.writable {
+          emit_server_event_app(&app, &json!({
+            "type": "session.cwd.warning",
+            "payload": { "cwd": cwd, "writable": false, "reason": check.reason }
+          }))?;
+        }
+      }
+      let mut payload = payload.as_object().cloned().unwrap_or_default();
+      // Scheduler-started runs get their session id up front so scheduler.execution.cancel can stop them
+      if let Some(task_id) = payload.get("scheduledTaskId").and_then(|v| v.as_str()).map(String::from) {
+        let session_id = uuid::Uuid::new_v4().to_string();
+        match state.db.start_task_run(&task_id, &session_id) {
+          Ok(_) => {
+            payload.insert("sessionId".to_string(), json!(session_id));
+          }
+          Err(e) => eprintln!("[session.start] Failed to record run for task {}: {}", task_id, e),
+        }
+      }
       let model_empty = payload
         .get("model")
         .and_then(|v| v.as_str())
@@ -2105,20 +6156,80 @@ fn client_event(app: tauri::AppHandle, state: tauri::State<'_, AppState>, event:
         .unwrap_or(true);
       if model_empty {
         if let Ok(Some(model_id)) = state.db.get_scheduler_default_model() {
-          let mut payload = payload.as_object().cloned().unwrap_or_default();
           payload.insert("model".to_string(), json!(model_id));
-          let event_with_model = json!({ "type": "session.start", "payload": payload });
-          return send_to_sidecar(app, state.inner(), &event_with_model);
         }
       }
-      send_to_sidecar(app, state.inner(), &event)
+      let model_id = payload.get("model").and_then(|v| v.as_str()).map(String::from);
+      clamp_payload_temperature(&app, &state.db, &mut payload, model_id.as_deref())?;
+      send_to_sidecar(app, state.inner(), &json!({ "type": "session.start", "payload": payload }))
     }
 
     // LLM operations - forward to sidecar
     "session.stop" | "permission.response" => {
+      if event_type == "permission.response" {
+        if let Some(session_id) = event.get("payload").and_then(|p| p.get("sessionId")).and_then(|v| v.as_str()) {
+          // The answer restarts the idle clock: the model gets a full timeout to continue
+          let mut activity = state.stream_activity.lock().unwrap();
+          activity.set_awaiting_permission(session_id, false);
+          activity.touch(session_id, chrono::Utc::now().timestamp_millis());
+        }
+      }
       send_to_sidecar(app, state.inner(), &event)
     }
 
+    // Exact stored JSON of one message, for debugging how the UI renders it
+    "message.raw" => {
+      let payload = event.get("payload").ok_or_else(|| "[message.raw] missing payload".to_string())?;
+      let session_id = payload.get("sessionId").and_then(|v| v.as_str())
+        .ok_or_else(|| "[message.raw] missing sessionId".to_string())?;
+      let message_ref = match payload.get("uuidOrIndex") {
+        Some(Value::String(uuid)) => MessageRef::Uuid(uuid.clone()),
+        Some(value) => value.as_u64().map(|i| MessageRef::Index(i as usize))
+          .ok_or_else(|| "[message.raw] uuidOrIndex must be a uuid or a non-negative index".to_string())?,
+        None => return Err("[message.raw] missing uuidOrIndex".to_string()),
+      };
+
+      match state.db.get_message_raw(session_id, &message_ref)
+        .map_err(|e| format!("[message.raw] {}", e))? {
+        Some(raw) => {
+          emit_server_event_app(&app, &json!({
+            "type": "message.raw",
+            "payload": { "sessionId": session_id, "uuidOrIndex": payload.get("uuidOrIndex"), "raw": raw }
+          }))?;
+        }
+        None => {
+          emit_server_event_app(&app, &json!({
+            "type": "runner.error",
+            "payload": { "sessionId": session_id, "message": "Message not found" }
+          }))?;
+        }
+      }
+      Ok(())
+    }
+
+    "session.hash" => {
+      let payload = event.get("payload").ok_or_else(|| "[session.hash] missing payload".to_string())?;
+      let session_id = payload.get("sessionId").and_then(|v| v.as_str())
+        .ok_or_else(|| "[session.hash] missing sessionId".to_string())?;
+
+      match state.db.session_content_hash(session_id)
+        .map_err(|e| format!("[session.hash] {}", e))? {
+        Some(hash) => {
+          emit_server_event_app(&app, &json!({
+            "type": "session.hash",
+            "payload": { "sessionId": session_id, "hash": hash }
+          }))?;
+        }
+        None => {
+          emit_server_event_app(&app, &json!({
+            "type": "runner.error",
+            "payload": { "sessionId": session_id, "message": "Session not found" }
+          }))?;
+        }
+      }
+      Ok(())
+    }
+
     // message.edit - enrich with session data and messages from DB for sidecar to restore
     "message.edit" => {
       let payload = event.get("payload").ok_or_else(|| "[message.edit] missing payload".to_string())?;
@@ -2128,14 +6239,28 @@ fn client_event(app: tauri::AppHandle, state: tauri::State<'_, AppState>, event:
         .ok_or_else(|| "[message.edit] missing messageIndex".to_string())? as usize;
       
       eprintln!("[message.edit] Looking up session: {}, truncating after index {}", session_id, message_index);
+
+      if state.db.is_session_locked(session_id).unwrap_or(false) {
+        emit_server_event_app(&app, &json!({
+          "type": "runner.error",
+          "payload": { "sessionId": session_id, "message": "Session is locked; unlock it to edit messages" }
+        }))?;
+        return Ok(());
+      }
+
+      // Checked before truncating: a refused edit must leave the history alone
+      if budget_exceeded(&app, &state.db, Some(session_id))? {
+        return Ok(());
+      }
       
       // Truncate history in DB first (before sending to sidecar)
-      if let Err(e) = state.db.truncate_history_after(session_id, message_index) {
-        eprintln!("[message.edit] Failed to truncate history in DB: {}", e);
+      match state.db.truncate_history_after(session_id, message_index) {
+        Ok(()) => sweep_orphan_blobs(&state.db),
+        Err(e) => eprintln!("[message.edit] Failed to truncate history in DB: {}", e),
       }
       
       // Get session history from DB (after truncation) to provide full context to sidecar
-      match state.db.get_session_history(session_id) {
+      match load_session_history(&state.db, session_id) {
         Ok(Some(history)) => {
           eprintln!("[message.edit] Found session: title='{}', messages={} (after truncation)", 
             history.session.title, history.messages.len());
@@ -2153,7 +6278,9 @@ fn client_event(app: tauri::AppHandle, state: tauri::State<'_, AppState>, event:
                 "cwd": history.session.cwd,
                 "model": history.session.model,
                 "allowedTools": history.session.allowed_tools,
-                "temperature": history.session.temperature
+                "temperature": history.session.temperature,
+                "maxOutputTokens": history.session.max_output_tokens,
+                "permissionMode": history.session.permission_mode
               },
               // Message history for LLM context (already truncated)
               "messages": history.messages,
@@ -2181,6 +6308,19 @@ fn client_event(app: tauri::AppHandle, state: tauri::State<'_, AppState>, event:
       let new_cwd = payload.get("cwd").and_then(|v| v.as_str());
       
       eprintln!("[session.continue] Looking up session: {}", session_id);
+
+      // Replies couldn't be recorded anyway
+      if state.db.is_session_locked(session_id).unwrap_or(false) {
+        emit_server_event_app(&app, &json!({
+          "type": "runner.error",
+          "payload": { "sessionId": session_id, "message": "Session is locked; unlock it to continue" }
+        }))?;
+        return Ok(());
+      }
+
+      if budget_exceeded(&app, &state.db, Some(session_id))? {
+        return Ok(());
+      }
       
       // Update cwd in DB if provided and different from stored
       if let Some(cwd) = new_cwd {
@@ -2193,33 +6333,23 @@ fn client_event(app: tauri::AppHandle, state: tauri::State<'_, AppState>, event:
           eprintln!("[session.continue] Updated cwd to: {}", cwd);
         }
       }
-      
+
       // Get session history from DB to provide full context to sidecar
-      match state.db.get_session_history(session_id) {
-        Ok(Some(history)) => {
+      match load_session_history(&state.db, session_id) {
+        Ok(Some(mut history)) => {
+          if let Some((omitted, max_tokens)) = trim_history_for_context(&state.db, &mut history) {
+            eprintln!("[session.continue] Left out {} old messages to fit {} tokens", omitted, max_tokens);
+            emit_server_event_app(&app, &json!({
+              "type": "session.pruned",
+              "payload": { "sessionId": session_id, "omitted": omitted, "maxTokens": max_tokens }
+            }))?;
+          }
           let final_cwd = new_cwd.or(history.session.cwd.as_deref()).unwrap_or("");
           eprintln!("[session.continue] Found session: title='{}', cwd={:?}, model={:?}, messages={}", 
             history.session.title, final_cwd, history.session.model, history.messages.len());
           
-          // Enrich the event with session data AND message history
-          let enriched_event = json!({
-            "type": "session.continue",
-            "payload": {
-              "sessionId": session_id,
-              "prompt": payload.get("prompt").and_then(|v| v.as_str()).unwrap_or(""),
-              // Session data for restoration in sidecar
-              "sessionData": {
-                "title": history.session.title,
-                "cwd": final_cwd,
-                "model": history.session.model,
-                "allowedTools": history.session.allowed_tools,
-                "temperature": history.session.temperature
-              },
-              // Message history for LLM context
-              "messages": history.messages,
-              "todos": history.todos
-            }
-          });
+          let prompt = payload.get("prompt").and_then(|v| v.as_str()).unwrap_or("");
+          let enriched_event = build_continue_event(session_id, prompt, final_cwd, &history);
           send_to_sidecar(app, state.inner(), &enriched_event)
         }
         Ok(None) => {
@@ -2231,7 +6361,71 @@ fn client_event(app: tauri::AppHandle, state: tauri::State<'_, AppState>, event:
           eprintln!("[session.continue] DB error: {}", e);
           send_to_sidecar(app, state.inner(), &event)
         }
-      }
+      }
+    }
+
+    // Move large inline base64 (images, files) out of the DB into app_data_dir/blobs
+    "session.blobs.externalize" => {
+      let payload = event.get("payload").ok_or_else(|| "[session.blobs.externalize] missing payload".to_string())?;
+      let session_id = payload.get("sessionId").and_then(|v| v.as_str())
+        .ok_or_else(|| "[session.blobs.externalize] missing sessionId".to_string())?;
+      let dir = blobs_dir()?;
+
+      let result = state.db.externalize_blobs(session_id, &dir)
+        .map_err(|e| format!("[session.blobs.externalize] {}", e))?;
+      eprintln!("[session.blobs.externalize] {}: {} blobs, {} bytes saved", session_id, result.blobs, result.bytes_saved);
+      emit_server_event_app(&app, &json!({
+        "type": "session.blobs.externalized",
+        "payload": {
+          "sessionId": session_id,
+          "messagesUpdated": result.messages_updated,
+          "blobs": result.blobs,
+          "bytesSaved": result.bytes_saved
+        }
+      }))?;
+      Ok(())
+    }
+
+    // What session.continue would forward to the sidecar, returned (redacted) instead of sent
+    "session.debug_payload" => {
+      let payload = event.get("payload").ok_or_else(|| "[session.debug_payload] missing payload".to_string())?;
+      let session_id = payload.get("sessionId").and_then(|v| v.as_str())
+        .ok_or_else(|| "[session.debug_payload] missing sessionId".to_string())?;
+      let prompt = payload.get("prompt").and_then(|v| v.as_str()).unwrap_or("");
+
+      let mut history = match load_session_history(&state.db, session_id) {
+        Ok(Some(history)) => history,
+        Ok(None) => {
+          emit_server_event_app(&app, &json!({
+            "type": "runner.error",
+            "payload": { "message": "Session not found" }
+          }))?;
+          return Ok(());
+        }
+        Err(e) => return Err(format!("[session.debug_payload] {}", e)),
+      };
+      trim_history_for_context(&state.db, &mut history);
+
+      let cwd = payload.get("cwd").and_then(|v| v.as_str())
+        .or(history.session.cwd.as_deref())
+        .unwrap_or("")
+        .to_string();
+      let mut sidecar_event = build_continue_event(session_id, prompt, &cwd, &history);
+      let size_bytes = sidecar_event.to_string().len();
+      let patterns = state.db.get_setting(redaction::REDACTION_PATTERNS_SETTING)
+        .map_err(|e| format!("[session.debug_payload] {}", e))?;
+      let redactions = redaction::Redactor::from_setting(patterns.as_deref()).redact_value(&mut sidecar_event);
+
+      emit_server_event_app(&app, &json!({
+        "type": "session.debug_payload",
+        "payload": {
+          "sessionId": session_id,
+          "sizeBytes": size_bytes,
+          "redactions": redactions,
+          "event": sidecar_event
+        }
+      }))?;
+      Ok(())
     }
 
     // session.compact - enrich with session data and messages from DB for sidecar to restore
@@ -2242,11 +6436,15 @@ fn client_event(app: tauri::AppHandle, state: tauri::State<'_, AppState>, event:
 
       eprintln!("[session.compact] Looking up session: {}", session_id);
 
+      if budget_exceeded(&app, &state.db, Some(session_id))? {
+        return Ok(());
+      }
+
       // Also load LLM provider settings so sidecar can resolve the model
       let llm_settings = state.db.get_llm_provider_settings().ok();
       let api_settings = state.db.get_api_settings().ok().flatten();
 
-      match state.db.get_session_history(session_id) {
+      match load_session_history(&state.db, session_id) {
         Ok(Some(history)) => {
           eprintln!("[session.compact] Found session: title='{}', messages={}", 
             history.session.title, history.messages.len());
@@ -2260,7 +6458,9 @@ fn client_event(app: tauri::AppHandle, state: tauri::State<'_, AppState>, event:
                 "cwd": history.session.cwd,
                 "model": history.session.model,
                 "allowedTools": history.session.allowed_tools,
-                "temperature": history.session.temperature
+                "temperature": history.session.temperature,
+                "maxOutputTokens": history.session.max_output_tokens,
+                "permissionMode": history.session.permission_mode
               },
               "messages": history.messages,
               "llmProviderSettings": llm_settings,
@@ -2273,82 +6473,869 @@ fn client_event(app: tauri::AppHandle, state: tauri::State<'_, AppState>, event:
           eprintln!("[session.compact] Session {} NOT FOUND in DB!", session_id);
           send_to_sidecar(app, state.inner(), &event)
         }
-        Err(e) => {
-          eprintln!("[session.compact] DB error: {}", e);
-          send_to_sidecar(app, state.inner(), &event)
+        Err(e) => {
+          eprintln!("[session.compact] DB error: {}", e);
+          send_to_sidecar(app, state.inner(), &event)
+        }
+      }
+    }
+
+    // Re-run a legacy migration on demand (each one refuses to overwrite newer data)
+    "migration.run" => {
+      let payload = event.get("payload")
+        .ok_or_else(|| "[migration.run] missing payload".to_string())?;
+      let source = payload.get("source").and_then(|v| v.as_str())
+        .ok_or_else(|| "[migration.run] missing source".to_string())?;
+
+      let result = match source {
+        "json" => app_data_dir().map(|dir| (migrate_json_to_db(&state.db, &dir), Vec::new())),
+        "old_dir" => migrate_old_dirs_on_demand(&state.db),
+        "memory" => Ok((
+          migrate_old_memory().map(|p| p.display().to_string()).into_iter().collect(),
+          Vec::new(),
+        )),
+        other => Err(format!("Unknown migration source '{other}' (expected json, old_dir or memory)")),
+      };
+
+      match result {
+        Ok((migrated, skipped)) => {
+          emit_server_event_app(&app, &json!({
+            "type": "migration.result",
+            "payload": { "source": source, "migrated": migrated, "skipped": skipped }
+          }))?;
+          if !migrated.is_empty() {
+            let settings = state.db.get_llm_provider_settings()
+              .map_err(|e| format!("[migration.run] {}", e))?;
+            emit_server_event_app(&app, &json!({
+              "type": "llm.providers.loaded",
+              "payload": { "settings": settings }
+            }))?;
+          }
+        }
+        Err(message) => {
+          emit_server_event_app(&app, &json!({
+            "type": "runner.error",
+            "payload": { "message": format!("Migration failed: {}", message) }
+          }))?;
+        }
+      }
+      Ok(())
+    }
+
+    // Settings snapshots for "what changed" debugging
+    "settings.snapshot" => {
+      let label = event.get("payload")
+        .and_then(|p| p.get("label"))
+        .and_then(|v| v.as_str())
+        .filter(|l| !l.trim().is_empty());
+      let snapshot = state.db.create_settings_snapshot(label)
+        .map_err(|e| format!("[settings.snapshot] {}", e))?;
+      let snapshots = state.db.list_settings_snapshots()
+        .map_err(|e| format!("[settings.snapshot] {}", e))?;
+      emit_server_event_app(&app, &json!({
+        "type": "settings.snapshots",
+        "payload": { "created": snapshot, "snapshots": snapshots }
+      }))?;
+      Ok(())
+    }
+
+    "settings.diff" => {
+      let payload = event.get("payload")
+        .ok_or_else(|| "[settings.diff] missing payload".to_string())?;
+      let id_a = payload.get("snapshotIdA").and_then(|v| v.as_i64())
+        .ok_or_else(|| "[settings.diff] missing snapshotIdA".to_string())?;
+      let id_b = payload.get("snapshotIdB").and_then(|v| v.as_i64())
+        .ok_or_else(|| "[settings.diff] missing snapshotIdB".to_string())?;
+
+      let load = |id: i64| state.db.get_settings_snapshot(id)
+        .map_err(|e| format!("[settings.diff] {}", e));
+      match (load(id_a)?, load(id_b)?) {
+        (Some(a), Some(b)) => {
+          emit_server_event_app(&app, &json!({
+            "type": "settings.diff",
+            "payload": {
+              "snapshotIdA": id_a,
+              "snapshotIdB": id_b,
+              "changes": db::diff_settings_snapshots(&a, &b)
+            }
+          }))?;
+        }
+        _ => {
+          emit_server_event_app(&app, &json!({
+            "type": "runner.error",
+            "payload": { "message": "Settings snapshot not found" }
+          }))?;
+        }
+      }
+      Ok(())
+    }
+
+    // Portable settings profile: ApiSettings + providers + models in one JSON file
+    "profile.export" => {
+      let strip_keys = event.get("payload")
+        .and_then(|p| p.get("stripKeys"))
+        .and_then(|v| v.as_bool())
+        .unwrap_or(true);
+      let profile = state.db.export_profile(strip_keys)
+        .map_err(|e| format!("[profile.export] {}", e))?;
+
+      let Some(path) = rfd::FileDialog::new()
+        .add_filter("JSON", &["json"])
+        .set_file_name("valedesk-profile.json")
+        .save_file() else {
+        emit_server_event_app(&app, &json!({
+          "type": "profile.exported",
+          "payload": { "cancelled": true }
+        }))?;
+        return Ok(());
+      };
+      let body = serde_json::to_string_pretty(&profile)
+        .map_err(|e| format!("[profile.export] serialize failed: {}", e))?;
+      fs::write(&path, body)
+        .map_err(|e| format!("[profile.export] write {} failed: {}", path.display(), e))?;
+
+      emit_server_event_app(&app, &json!({
+        "type": "profile.exported",
+        "payload": {
+          "cancelled": false,
+          "path": path.to_string_lossy(),
+          "strippedKeys": strip_keys,
+          "providers": profile.providers.len(),
+          "models": profile.models.len()
+        }
+      }))?;
+      Ok(())
+    }
+
+    "profile.import" => {
+      let payload = event.get("payload")
+        .ok_or_else(|| "[profile.import] missing payload".to_string())?;
+      let mode: db::ProfileImportMode = serde_json::from_value(payload.get("mergeOrReplace").cloned().unwrap_or(Value::Null))
+        .map_err(|_| "[profile.import] mergeOrReplace must be \"merge\" or \"replace\"".to_string())?;
+
+      let Some(path) = rfd::FileDialog::new().add_filter("JSON", &["json"]).pick_file() else {
+        emit_server_event_app(&app, &json!({
+          "type": "profile.imported",
+          "payload": { "cancelled": true }
+        }))?;
+        return Ok(());
+      };
+      let raw = fs::read_to_string(&path)
+        .map_err(|e| format!("[profile.import] read {} failed: {}", path.display(), e))?;
+      let profile: db::SettingsProfile = match serde_json::from_str(&raw) {
+        Ok(profile) => profile,
+        Err(e) => {
+          emit_server_event_app(&app, &json!({
+            "type": "runner.error",
+            "payload": { "message": format!("Invalid profile file: {}", e) }
+          }))?;
+          return Ok(());
+        }
+      };
+      let result = match state.db.import_profile(&profile, mode) {
+        Ok(result) => result,
+        Err(e) => {
+          emit_server_event_app(&app, &json!({
+            "type": "runner.error",
+            "payload": { "message": format!("Profile import failed: {}", e) }
+          }))?;
+          return Ok(());
+        }
+      };
+
+      let settings = state.db.get_api_settings()
+        .map_err(|e| format!("[profile.import] {}", e))?;
+      apply_network_settings(settings.as_ref());
+      let provider_settings = state.db.get_llm_provider_settings()
+        .map_err(|e| format!("[profile.import] {}", e))?;
+
+      emit_server_event_app(&app, &json!({
+        "type": "profile.imported",
+        "payload": { "cancelled": false, "path": path.to_string_lossy(), "result": result }
+      }))?;
+      emit_server_event_app(&app, &json!({
+        "type": "llm.providers.loaded",
+        "payload": { "settings": provider_settings }
+      }))?;
+
+      // Keep the sidecar's in-memory copies in sync
+      send_to_sidecar(app.clone(), state.inner(), &json!({
+        "type": "llm.providers.save",
+        "payload": { "settings": provider_settings }
+      }))?;
+      match settings {
+        Some(settings) => {
+          emit_server_event_app(&app, &json!({
+            "type": "settings.loaded",
+            "payload": { "settings": settings }
+          }))?;
+          send_to_sidecar(app, state.inner(), &json!({
+            "type": "settings.save",
+            "payload": { "settings": settings }
+          }))
+        }
+        None => Ok(()),
+      }
+    }
+
+    // Re-emit recent events after a frontend reload
+    "events.replay" => {
+      let since = event.get("payload")
+        .and_then(|p| p.get("since"))
+        .and_then(|v| v.as_i64())
+        .unwrap_or(0);
+      let events = state.recent_events.lock()
+        .map_err(|_| "[events.replay] event log lock poisoned".to_string())?
+        .since(since);
+
+      for (_, replayed) in &events {
+        let raw = serde_json::to_string(replayed)
+          .map_err(|e| format!("[events.replay] serialize failed: {e}"))?;
+        // Emit directly so replays aren't recorded again
+        app.emit("server-event", raw)
+          .map_err(|e| format!("[events.replay] emit failed: {e}"))?;
+      }
+      emit_server_event_app(&app, &json!({
+        "type": "events.replayed",
+        "payload": { "count": events.len(), "latest": events.last().map(|(at, _)| *at) }
+      }))?;
+      Ok(())
+    }
+
+    // DB diagnostics
+    "db.stats" => {
+      let stats = state.db.stats()
+        .map_err(|e| format!("[db.stats] {}", e))?;
+      emit_server_event_app(&app, &json!({
+        "type": "db.stats",
+        "payload": stats
+      }))?;
+      Ok(())
+    }
+
+    // How long writers wait on a locked DB before failing; applied immediately
+    "db.busy_timeout.get" => {
+      let ms = state.db.get_busy_timeout_ms()
+        .map_err(|e| format!("[db.busy_timeout.get] {}", e))?;
+      emit_server_event_app(&app, &json!({
+        "type": "db.busy_timeout.loaded",
+        "payload": { "ms": ms }
+      }))?;
+      Ok(())
+    }
+
+    "db.busy_timeout.set" => {
+      let payload = event.get("payload")
+        .ok_or_else(|| "[db.busy_timeout.set] missing payload".to_string())?;
+      let ms = payload.get("ms").and_then(|v| v.as_u64())
+        .ok_or_else(|| "[db.busy_timeout.set] missing ms".to_string())?;
+      state.db.set_busy_timeout_ms(ms)
+        .map_err(|e| format!("[db.busy_timeout.set] {}", e))?;
+      emit_server_event_app(&app, &json!({
+        "type": "db.busy_timeout.loaded",
+        "payload": { "ms": ms }
+      }))?;
+      Ok(())
+    }
+
+    "db.checkpoint" => {
+      let result = state.db.checkpoint_wal()
+        .map_err(|e| format!("[db.checkpoint] {}", e))?;
+      emit_server_event_app(&app, &json!({
+        "type": "db.checkpoint",
+        "payload": result
+      }))?;
+      Ok(())
+    }
+
+    // Settings - handled in Rust DB (with fallback to sidecar for migration)
+    "settings.get" => {
+      match state.db.get_api_settings() {
+        Ok(Some(settings)) => {
+          emit_server_event_app(&app, &json!({
+            "type": "settings.loaded",
+            "payload": { "settings": settings }
+          }))?;
+          Ok(())
+        }
+        Ok(None) => {
+          // No settings in DB yet - forward to sidecar (will migrate on save)
+          send_to_sidecar(app, state.inner(), &event)
+        }
+        Err(e) => {
+          eprintln!("[settings.get] DB error: {}, falling back to sidecar", e);
+          send_to_sidecar(app, state.inner(), &event)
+        }
+      }
+    }
+
+    "settings.save" => {
+      let payload = event.get("payload")
+        .ok_or_else(|| "[settings.save] missing payload".to_string())?;
+      let settings: ApiSettings = serde_json::from_value(payload.get("settings").cloned().unwrap_or(Value::Null))
+        .map_err(|e| format!("[settings.save] invalid settings: {}", e))?;
+      
+      let previous_voice = state.db.get_api_settings().ok().flatten().and_then(|s| s.voice_settings);
+      state.db.save_api_settings(&settings)
+        .map_err(|e| format!("[settings.save] {}", e))?;
+      apply_network_settings(Some(&settings));
+
+      if previous_voice != settings.voice_settings {
+        let generation = state.voice.reset();
+        emit_server_event_app(&app, &json!({
+          "type": "voice.reset",
+          "payload": { "generation": generation }
+        }))?;
+      }
+      
+      emit_server_event_app(&app, &json!({
+        "type": "settings.loaded",
+        "payload": { "settings": settings }
+      }))?;
+      
+      // Also forward to sidecar so it has updated settings in memory
+      send_to_sidecar(app, state.inner(), &event)
+    }
+
+    // Proxy + extra headers for every outbound HTTP request (voice, downloads, provider checks)
+    "settings.network.set" => {
+      let payload = event.get("payload")
+        .ok_or_else(|| "[settings.network.set] missing payload".to_string())?;
+      let proxy = payload.get("proxy").and_then(|v| v.as_str()).map(str::trim).filter(|p| !p.is_empty()).map(String::from);
+      let headers: Option<std::collections::BTreeMap<String, String>> = match payload.get("headers") {
+        None | Some(Value::Null) => None,
+        Some(v) => Some(serde_json::from_value(v.clone())
+          .map_err(|e| format!("[settings.network.set] headers must be an object of strings: {}", e))?),
+      };
+
+      let mut settings = state.db.get_api_settings()
+        .map_err(|e| format!("[settings.network.set] {}", e))?
+        .unwrap_or_default();
+      settings.http_proxy = proxy;
+      settings.http_headers = headers.filter(|h| !h.is_empty());
+      NetworkSettings::from_api(Some(&settings)).build_parts()
+        .map_err(|e| format!("[settings.network.set] {}", e))?;
+
+      state.db.save_api_settings(&settings)
+        .map_err(|e| format!("[settings.network.set] {}", e))?;
+      apply_network_settings(Some(&settings));
+
+      emit_server_event_app(&app, &json!({
+        "type": "settings.loaded",
+        "payload": { "settings": settings }
+      }))?;
+
+      // Sidecar makes its own LLM requests, keep its settings in sync
+      send_to_sidecar(app, state.inner(), &json!({
+        "type": "settings.save",
+        "payload": { "settings": settings }
+      }))
+    }
+
+    // LLM Providers - always handled in Rust DB
+    "llm.providers.get" => {
+      let settings = state.db.get_llm_provider_settings()
+        .map_err(|e| format!("[llm.providers.get] {}", e))?;
+      
+      eprintln!("[providers] {} providers, {} models", settings.providers.len(), settings.models.len());
+      
+      emit_server_event_app(&app, &json!({
+        "type": "llm.providers.loaded",
+        "payload": { "settings": settings }
+      }))?;
+
+      // Surface models pointing at missing providers; repair via providers.validate
+      let dangling = state.db.list_dangling_models()
+        .map_err(|e| format!("[llm.providers.get] {}", e))?;
+      if !dangling.is_empty() {
+        eprintln!("[providers] {} models reference missing providers", dangling.len());
+        emit_server_event_app(&app, &json!({
+          "type": "providers.validation",
+          "payload": {
+            "count": dangling.len(),
+            "modelIds": dangling.iter().map(|m| m.id.as_str()).collect::<Vec<_>>(),
+            "repaired": 0
+          }
+        }))?;
+      }
+      Ok(())
+    }
+
+    // Find (and optionally fix) models whose provider no longer exists.
+    // payload.action: "report" (default) | "drop" | "reassign" (with payload.providerId)
+    "providers.validate" => {
+      let payload = event.get("payload").cloned().unwrap_or(Value::Null);
+      let action = payload.get("action").and_then(|v| v.as_str()).unwrap_or("report");
+      let dangling = state.db.list_dangling_models()
+        .map_err(|e| format!("[providers.validate] {}", e))?;
+
+      let repaired = match action {
+        "report" => 0,
+        "drop" => state.db.repair_dangling_models(None)
+          .map_err(|e| format!("[providers.validate] {}", e))?,
+        "reassign" => {
+          let provider_id = payload.get("providerId").and_then(|v| v.as_str())
+            .ok_or_else(|| "[providers.validate] reassign needs providerId".to_string())?;
+          state.db.repair_dangling_models(Some(provider_id))
+            .map_err(|e| format!("[providers.validate] {}", e))?
+        }
+        other => return Err(format!("[providers.validate] unknown action: {}", other)),
+      };
+
+      emit_server_event_app(&app, &json!({
+        "type": "providers.validation",
+        "payload": {
+          "count": dangling.len(),
+          "modelIds": dangling.iter().map(|m| m.id.as_str()).collect::<Vec<_>>(),
+          "action": action,
+          "repaired": repaired
+        }
+      }))?;
+      if repaired > 0 {
+        let settings = state.db.get_llm_provider_settings()
+          .map_err(|e| format!("[providers.validate] {}", e))?;
+        emit_server_event_app(&app, &json!({
+          "type": "llm.providers.loaded",
+          "payload": { "settings": settings }
+        }))?;
+      }
+      Ok(())
+    }
+
+    "llm.providers.save" => {
+      let payload = event.get("payload")
+        .ok_or_else(|| "[llm.providers.save] missing payload".to_string())?;
+      let settings: LLMProviderSettings = serde_json::from_value(payload.get("settings").cloned().unwrap_or(Value::Null))
+        .map_err(|e| format!("[llm.providers.save] invalid settings: {}", e))?;
+      
+      state.db.save_llm_provider_settings(&settings)
+        .map_err(|e| format!("[llm.providers.save] {}", e))?;
+      
+      emit_server_event_app(&app, &json!({
+        "type": "llm.providers.saved",
+        "payload": { "settings": settings }
+      }))?;
+      
+      // Also forward to sidecar so it has updated settings in memory
+      send_to_sidecar(app, state.inner(), &event)
+    }
+
+    // Move a provider's key into the OS keychain; the DB keeps only config.keyRef
+    "provider.key.set" => {
+      let payload = event.get("payload")
+        .ok_or_else(|| "[provider.key.set] missing payload".to_string())?;
+      let provider_id = payload.get("providerId").and_then(|v| v.as_str())
+        .ok_or_else(|| "[provider.key.set] missing providerId".to_string())?;
+      let api_key = payload.get("apiKey").and_then(|v| v.as_str()).map(str::trim).filter(|k| !k.is_empty())
+        .ok_or_else(|| "[provider.key.set] missing apiKey".to_string())?;
+      let mut provider = state.db.list_providers()
+        .map_err(|e| format!("[provider.key.set] {}", e))?
+        .into_iter()
+        .find(|p| p.id == provider_id)
+        .ok_or_else(|| format!("[provider.key.set] provider {} not found", provider_id))?;
+      let key_ref = payload.get("keyRef").and_then(|v| v.as_str()).map(str::trim).filter(|r| !r.is_empty())
+        .map(String::from)
+        .unwrap_or_else(|| format!("provider:{}", provider_id));
+
+      if let Err(e) = keychain_entry(&key_ref).and_then(|entry| entry.set_password(api_key).map_err(|e| e.to_string())) {
+        emit_server_event_app(&app, &json!({
+          "type": "runner.error",
+          "payload": { "message": format!("Failed to store key in the keychain: {}", e) }
+        }))?;
+        return Ok(());
+      }
+      let mut config = provider.config.take().filter(|c| c.is_object()).unwrap_or_else(|| json!({}));
+      config[db::PROVIDER_KEY_REF_FIELD] = json!(key_ref);
+      provider.config = Some(config);
+      provider.api_key = None;
+      state.db.save_provider(&provider)
+        .map_err(|e| format!("[provider.key.set] {}", e))?;
+
+      emit_server_event_app(&app, &json!({
+        "type": "provider.key.updated",
+        "payload": { "providerId": provider_id, "keyRef": key_ref }
+      }))?;
+      let settings = state.db.get_llm_provider_settings()
+        .map_err(|e| format!("[provider.key.set] {}", e))?;
+      emit_server_event_app(&app, &json!({
+        "type": "llm.providers.loaded",
+        "payload": { "settings": settings }
+      }))?;
+      resync_sidecar_providers(&app, state.inner(), &settings)
+    }
+
+    "provider.key.clear" => {
+      let payload = event.get("payload")
+        .ok_or_else(|| "[provider.key.clear] missing payload".to_string())?;
+      let provider_id = payload.get("providerId").and_then(|v| v.as_str())
+        .ok_or_else(|| "[provider.key.clear] missing providerId".to_string())?;
+      let mut provider = state.db.list_providers()
+        .map_err(|e| format!("[provider.key.clear] {}", e))?
+        .into_iter()
+        .find(|p| p.id == provider_id)
+        .ok_or_else(|| format!("[provider.key.clear] provider {} not found", provider_id))?;
+
+      if let Some(key_ref) = provider.key_ref().map(String::from) {
+        let deleted = keychain_entry(&key_ref).and_then(|entry| match entry.delete_credential() {
+          Ok(()) | Err(keyring::Error::NoEntry) => Ok(()),
+          Err(e) => Err(e.to_string()),
+        });
+        if let Err(e) = deleted {
+          emit_server_event_app(&app, &json!({
+            "type": "runner.error",
+            "payload": { "message": format!("Failed to remove key from the keychain: {}", e) }
+          }))?;
+          return Ok(());
+        }
+        if let Some(config) = provider.config.as_mut().and_then(|c| c.as_object_mut()) {
+          config.remove(db::PROVIDER_KEY_REF_FIELD);
         }
+        state.db.save_provider(&provider)
+          .map_err(|e| format!("[provider.key.clear] {}", e))?;
       }
+
+      emit_server_event_app(&app, &json!({
+        "type": "provider.key.updated",
+        "payload": { "providerId": provider_id, "keyRef": null }
+      }))?;
+      let settings = state.db.get_llm_provider_settings()
+        .map_err(|e| format!("[provider.key.clear] {}", e))?;
+      emit_server_event_app(&app, &json!({
+        "type": "llm.providers.loaded",
+        "payload": { "settings": settings }
+      }))?;
+      resync_sidecar_providers(&app, state.inner(), &settings)
     }
 
-    // Settings - handled in Rust DB (with fallback to sidecar for migration)
-    "settings.get" => {
-      match state.db.get_api_settings() {
-        Ok(Some(settings)) => {
+    // Fetch model metadata (pricing, context length) into models' config, cached with a TTL
+    "provider.models.metadata" => {
+      let payload = event.get("payload")
+        .ok_or_else(|| "[provider.models.metadata] missing payload".to_string())?;
+      let provider_id = payload.get("providerId").and_then(|v| v.as_str())
+        .ok_or_else(|| "[provider.models.metadata] missing providerId".to_string())?
+        .to_string();
+      let force = payload.get("force").and_then(|v| v.as_bool()).unwrap_or(false);
+      let now = chrono::Utc::now().timestamp_millis();
+      let cache_key = model_metadata_cache_key(&provider_id);
+
+      if !force {
+        let fetched_at = state.db.get_setting(&cache_key)
+          .map_err(|e| format!("[provider.models.metadata] {}", e))?
+          .and_then(|s| s.parse::<i64>().ok());
+        if matches!(fetched_at, Some(ts) if now - ts < MODEL_METADATA_TTL_MS) {
+          let settings = state.db.get_llm_provider_settings()
+            .map_err(|e| format!("[provider.models.metadata] {}", e))?;
           emit_server_event_app(&app, &json!({
-            "type": "settings.loaded",
+            "type": "llm.providers.loaded",
             "payload": { "settings": settings }
           }))?;
-          Ok(())
+          return Ok(());
         }
-        Ok(None) => {
-          // No settings in DB yet - forward to sidecar (will migrate on save)
-          send_to_sidecar(app, state.inner(), &event)
+      }
+
+      let provider = state.db.list_providers()
+        .map_err(|e| format!("[provider.models.metadata] {}", e))?
+        .into_iter()
+        .find(|p| p.id == provider_id)
+        .ok_or_else(|| format!("[provider.models.metadata] provider {} not found", provider_id))?;
+      let base_url = provider_base_url(&provider)
+        .ok_or_else(|| format!("[provider.models.metadata] provider {} has no baseUrl", provider_id))?;
+
+      let app_handle = app.clone();
+      std::thread::spawn(move || {
+        let state: tauri::State<'_, AppState> = app_handle.state();
+        let result = state.http.blocking_client()
+          .map_err(|e| format!("[provider.models.metadata] failed to build http client: {e}"))
+          .and_then(|client| fetch_provider_models_blocking(&client, &base_url, provider_api_key(&provider).as_deref()))
+          .and_then(|response| apply_model_metadata(&state.db, &provider_id, &response, now));
+
+        match result {
+          Ok(count) => {
+            eprintln!("[provider.models.metadata] {}: updated {} models", provider_id, count);
+            if let Err(e) = state.db.set_setting(&cache_key, &now.to_string()) {
+              eprintln!("[provider.models.metadata] Failed to store fetch time: {}", e);
+            }
+            if let Ok(settings) = state.db.get_llm_provider_settings() {
+              let _ = emit_server_event_app(&app_handle, &json!({
+                "type": "llm.providers.loaded",
+                "payload": { "settings": settings }
+              }));
+            }
+          }
+          Err(message) => {
+            eprintln!("{}", message);
+            let _ = emit_server_event_app(&app_handle, &json!({
+              "type": "runner.error",
+              "payload": { "message": format!("Failed to fetch model metadata: {}", message) }
+            }));
+          }
         }
-        Err(e) => {
-          eprintln!("[settings.get] DB error: {}, falling back to sidecar", e);
-          send_to_sidecar(app, state.inner(), &event)
+      });
+      Ok(())
+    }
+
+    // Refresh a provider's model list from /models, keeping user choices for models that still exist
+    "provider.models.sync" => {
+      let payload = event.get("payload")
+        .ok_or_else(|| "[provider.models.sync] missing payload".to_string())?;
+      let provider_id = payload.get("providerId").and_then(|v| v.as_str())
+        .ok_or_else(|| "[provider.models.sync] missing providerId".to_string())?
+        .to_string();
+      let provider = state.db.list_providers()
+        .map_err(|e| format!("[provider.models.sync] {}", e))?
+        .into_iter()
+        .find(|p| p.id == provider_id)
+        .ok_or_else(|| format!("[provider.models.sync] provider {} not found", provider_id))?;
+      let base_url = provider_base_url(&provider)
+        .ok_or_else(|| format!("[provider.models.sync] provider {} has no baseUrl", provider_id))?;
+
+      let app_handle = app.clone();
+      std::thread::spawn(move || {
+        let state: tauri::State<'_, AppState> = app_handle.state();
+        let result = state.http.blocking_client()
+          .map_err(|e| format!("[provider.models.sync] failed to build http client: {e}"))
+          .and_then(|client| fetch_provider_models_blocking(&client, &base_url, provider_api_key(&provider).as_deref()))
+          .and_then(|response| remote_model_ids(&response))
+          .and_then(|ids| {
+            // An empty list is far more likely a broken endpoint than a provider with no models
+            if ids.is_empty() {
+              return Err("[provider.models.sync] provider returned no models".to_string());
+            }
+            state.db.sync_provider_models(&provider_id, &ids).map_err(|e| format!("[provider.models.sync] {}", e))
+          });
+
+        match result {
+          Ok(report) => {
+            eprintln!("[provider.models.sync] {}: +{} -{} ={}", provider_id, report.added.len(), report.removed.len(), report.kept);
+            let _ = emit_server_event_app(&app_handle, &json!({
+              "type": "provider.models.synced",
+              "payload": { "providerId": provider_id, "added": report.added, "removed": report.removed, "kept": report.kept }
+            }));
+            if let Ok(settings) = state.db.get_llm_provider_settings() {
+              let _ = emit_server_event_app(&app_handle, &json!({
+                "type": "llm.providers.loaded",
+                "payload": { "settings": settings }
+              }));
+            }
+          }
+          Err(message) => {
+            eprintln!("{}", message);
+            let _ = emit_server_event_app(&app_handle, &json!({
+              "type": "runner.error",
+              "payload": { "message": format!("Failed to sync models: {}", message) }
+            }));
+          }
         }
-      }
+      });
+      Ok(())
     }
 
-    "settings.save" => {
+    // Healthcheck all enabled providers at once
+    "providers.test_all" => {
+      let providers: Vec<LLMProvider> = state.db.list_providers()
+        .map_err(|e| format!("[providers.test_all] {}", e))?
+        .into_iter()
+        .filter(|p| p.enabled)
+        .collect();
+
+      let app_handle = app.clone();
+      std::thread::spawn(move || {
+        let client = match app_handle.state::<AppState>().http.blocking_client() {
+          Ok(client) => client,
+          Err(e) => {
+            let _ = emit_server_event_app(&app_handle, &json!({
+              "type": "runner.error",
+              "payload": { "message": format!("[providers.test_all] failed to build http client: {}", e) }
+            }));
+            return;
+          }
+        };
+        let results = check_providers_health_blocking(&client, providers, PROVIDER_HEALTHCHECK_CONCURRENCY);
+        let healthy = results.values().filter(|h| h.ok).count();
+        eprintln!("[providers.test_all] {}/{} healthy", healthy, results.len());
+        let _ = emit_server_event_app(&app_handle, &json!({
+          "type": "providers.health",
+          "payload": { "results": results }
+        }));
+      });
+      Ok(())
+    }
+
+    // Single-model liveness check (tiny chat completion)
+    "model.ping" => {
       let payload = event.get("payload")
-        .ok_or_else(|| "[settings.save] missing payload".to_string())?;
-      let settings: ApiSettings = serde_json::from_value(payload.get("settings").cloned().unwrap_or(Value::Null))
-        .map_err(|e| format!("[settings.save] invalid settings: {}", e))?;
-      
-      state.db.save_api_settings(&settings)
-        .map_err(|e| format!("[settings.save] {}", e))?;
-      
-      emit_server_event_app(&app, &json!({
-        "type": "settings.loaded",
-        "payload": { "settings": settings }
-      }))?;
-      
-      // Also forward to sidecar so it has updated settings in memory
-      send_to_sidecar(app, state.inner(), &event)
+        .ok_or_else(|| "[model.ping] missing payload".to_string())?;
+      let model_id = payload.get("modelId").and_then(|v| v.as_str())
+        .ok_or_else(|| "[model.ping] missing modelId".to_string())?;
+      let model = state.db.get_model(model_id)
+        .map_err(|e| format!("[model.ping] {}", e))?
+        .ok_or_else(|| format!("[model.ping] model {} not found", model_id))?;
+      let provider = state.db.list_providers()
+        .map_err(|e| format!("[model.ping] {}", e))?
+        .into_iter()
+        .find(|p| p.id == model.provider_id)
+        .ok_or_else(|| format!("[model.ping] provider {} not found", model.provider_id))?;
+
+      let app_handle = app.clone();
+      std::thread::spawn(move || {
+        let result = match app_handle.state::<AppState>().http.blocking_client() {
+          Ok(client) => ping_model_blocking(&client, &provider, &model),
+          Err(e) => ModelPing { model_id: model.id.clone(), ok: false, latency_ms: 0, model_missing: false, error: Some(e) },
+        };
+        let _ = emit_server_event_app(&app_handle, &json!({
+          "type": "model.pinged",
+          "payload": result
+        }));
+      });
+      Ok(())
     }
 
-    // LLM Providers - always handled in Rust DB
-    "llm.providers.get" => {
-      let settings = state.db.get_llm_provider_settings()
-        .map_err(|e| format!("[llm.providers.get] {}", e))?;
-      
-      eprintln!("[providers] {} providers, {} models", settings.providers.len(), settings.models.len());
-      
-      emit_server_event_app(&app, &json!({
-        "type": "llm.providers.loaded",
-        "payload": { "settings": settings }
-      }))?;
+    // "Is this key accepted?" without relying on /models
+    "provider.auth_check" => {
+      let payload = event.get("payload")
+        .ok_or_else(|| "[provider.auth_check] missing payload".to_string())?;
+      let provider_id = payload.get("providerId").and_then(|v| v.as_str())
+        .ok_or_else(|| "[provider.auth_check] missing providerId".to_string())?
+        .to_string();
+      let provider = state.db.list_providers()
+        .map_err(|e| format!("[provider.auth_check] {}", e))?
+        .into_iter()
+        .find(|p| p.id == provider_id)
+        .ok_or_else(|| format!("[provider.auth_check] provider {} not found", provider_id))?;
+      // Any model name works for the auth layer; prefer a real one so fewer gateways answer 400
+      let model_name = match payload.get("model").and_then(|v| v.as_str()) {
+        Some(model) => model.to_string(),
+        None => state.db.list_models_by_provider(&provider_id)
+          .map_err(|e| format!("[provider.auth_check] {}", e))?
+          .into_iter()
+          .find(|m| m.enabled)
+          .map(|m| m.name)
+          .unwrap_or_else(|| "auth-check".to_string()),
+      };
+
+      let app_handle = app.clone();
+      std::thread::spawn(move || {
+        let result = match app_handle.state::<AppState>().http.blocking_client() {
+          Ok(client) => check_provider_auth_blocking(&client, &provider, &model_name),
+          Err(e) => ProviderAuth { authorized: None, status: None, error: Some(e) },
+        };
+        let _ = emit_server_event_app(&app_handle, &json!({
+          "type": "provider.auth_checked",
+          "payload": { "providerId": provider_id, "result": result }
+        }));
+      });
       Ok(())
     }
 
-    "llm.providers.save" => {
+    // Ping every enabled model of a provider and disable (not delete) the ones the provider says
+    // don't exist; outages, rate limits and auth errors are only reported
+    "models.prune_unreachable" => {
       let payload = event.get("payload")
-        .ok_or_else(|| "[llm.providers.save] missing payload".to_string())?;
-      let settings: LLMProviderSettings = serde_json::from_value(payload.get("settings").cloned().unwrap_or(Value::Null))
-        .map_err(|e| format!("[llm.providers.save] invalid settings: {}", e))?;
-      
-      state.db.save_llm_provider_settings(&settings)
-        .map_err(|e| format!("[llm.providers.save] {}", e))?;
-      
+        .ok_or_else(|| "[models.prune_unreachable] missing payload".to_string())?;
+      let provider_id = payload.get("providerId").and_then(|v| v.as_str())
+        .ok_or_else(|| "[models.prune_unreachable] missing providerId".to_string())?
+        .to_string();
+      let provider = state.db.list_providers()
+        .map_err(|e| format!("[models.prune_unreachable] {}", e))?
+        .into_iter()
+        .find(|p| p.id == provider_id)
+        .ok_or_else(|| format!("[models.prune_unreachable] provider {} not found", provider_id))?;
+      let models: Vec<LLMModel> = state.db.list_models_by_provider(&provider_id)
+        .map_err(|e| format!("[models.prune_unreachable] {}", e))?
+        .into_iter()
+        .filter(|m| m.enabled)
+        .collect();
+
+      let app_handle = app.clone();
+      std::thread::spawn(move || {
+        let state: tauri::State<'_, AppState> = app_handle.state();
+        let client = match state.http.blocking_client() {
+          Ok(client) => client,
+          Err(e) => {
+            let _ = emit_server_event_app(&app_handle, &json!({
+              "type": "runner.error",
+              "payload": { "message": format!("[models.prune_unreachable] failed to build http client: {}", e) }
+            }));
+            return;
+          }
+        };
+
+        let tested = models.len();
+        let provider_for_ping = provider.clone();
+        let results = run_bounded(models.clone(), MODEL_PING_CONCURRENCY, move |model: LLMModel| {
+          ping_model_blocking(&client, &provider_for_ping, &model)
+        });
+
+        let mut disabled = Vec::new();
+        for result in results.iter().filter(|r| r.model_missing) {
+          let Some(model) = models.iter().find(|m| m.id == result.model_id) else { continue };
+          match state.db.save_model(&LLMModel { enabled: false, ..model.clone() }) {
+            Ok(()) => disabled.push(model.id.clone()),
+            Err(e) => eprintln!("[models.prune_unreachable] Failed to disable {}: {}", model.id, e),
+          }
+        }
+        let failed = results.iter().filter(|r| !r.ok && !r.model_missing).count();
+        eprintln!("[models.prune_unreachable] {}: disabled {}/{}, {} failed for other reasons", provider_id, disabled.len(), tested, failed);
+
+        let _ = emit_server_event_app(&app_handle, &json!({
+          "type": "models.pruned",
+          "payload": { "providerId": provider_id, "tested": tested, "disabled": disabled, "failed": failed, "results": results }
+        }));
+        if let Ok(settings) = state.db.get_llm_provider_settings() {
+          let _ = emit_server_event_app(&app_handle, &json!({
+            "type": "llm.providers.loaded",
+            "payload": { "settings": settings }
+          }));
+        }
+      });
+      Ok(())
+    }
+
+    "models.import_text" => {
+      let payload = event.get("payload")
+        .ok_or_else(|| "[models.import_text] missing payload".to_string())?;
+      let provider_id = payload.get("providerId").and_then(|v| v.as_str())
+        .ok_or_else(|| "[models.import_text] missing providerId".to_string())?;
+      let text = payload.get("text").and_then(|v| v.as_str()).unwrap_or("");
+      if !state.db.list_providers()
+        .map_err(|e| format!("[models.import_text] {}", e))?
+        .iter()
+        .any(|p| p.id == provider_id)
+      {
+        return Err(format!("[models.import_text] provider {} not found", provider_id));
+      }
+
+      // Existing models keep their name/config; the import only (re-)enables them
+      let existing: HashMap<String, LLMModel> = state.db.list_models_by_provider(provider_id)
+        .map_err(|e| format!("[models.import_text] {}", e))?
+        .into_iter()
+        .map(|m| (m.id.clone(), m))
+        .collect();
+      let models: Vec<LLMModel> = parse_model_list(text)
+        .into_iter()
+        .map(|name| {
+          let id = format!("{}::{}", provider_id, name);
+          match existing.get(&id) {
+            Some(model) => LLMModel { enabled: true, ..model.clone() },
+            None => LLMModel { id, provider_id: provider_id.to_string(), name, enabled: true, config: None },
+          }
+        })
+        .collect();
+      state.db.save_models_bulk(&models)
+        .map_err(|e| format!("[models.import_text] {}", e))?;
+
+      let added = models.iter().filter(|m| !existing.contains_key(&m.id)).count();
+      let ids: Vec<&str> = models.iter().map(|m| m.id.as_str()).collect();
       emit_server_event_app(&app, &json!({
-        "type": "llm.providers.saved",
-        "payload": { "settings": settings }
+        "type": "models.imported",
+        "payload": { "providerId": provider_id, "modelIds": ids, "added": added }
       }))?;
-      
-      // Also forward to sidecar so it has updated settings in memory
-      send_to_sidecar(app, state.inner(), &event)
+      let settings = state.db.get_llm_provider_settings()
+        .map_err(|e| format!("[models.import_text] {}", e))?;
+      emit_server_event_app(&app, &json!({
+        "type": "llm.providers.loaded",
+        "payload": { "settings": settings }
+      }))
     }
 
     // Forward other LLM-related events to sidecar
@@ -2376,8 +7363,17 @@ fn client_event(app: tauri::AppHandle, state: tauri::State<'_, AppState>, event:
       let model_id = payload.get("modelId").and_then(|v| v.as_str())
         .ok_or_else(|| "[scheduler.default_model.set] missing modelId".to_string())?;
 
-      state.db.set_scheduler_default_model(model_id)
-        .map_err(|e| format!("[scheduler.default_model.set] {}", e))?;
+      let model_id = match state.db.set_scheduler_default_model(model_id) {
+        Ok(resolved) => resolved,
+        Err(rusqlite::Error::ToSqlConversionFailure(e)) => {
+          emit_server_event_app(&app, &json!({
+            "type": "runner.error",
+            "payload": { "message": format!("Invalid scheduler default model: {}", e) }
+          }))?;
+          return Ok(());
+        }
+        Err(e) => return Err(format!("[scheduler.default_model.set] {}", e)),
+      };
 
       eprintln!("[scheduler] Default model set: {}", model_id);
 
@@ -2428,6 +7424,137 @@ fn client_event(app: tauri::AppHandle, state: tauri::State<'_, AppState>, event:
       Ok(())
     }
 
+    // Stop the agent run a scheduled task started (see scheduledTaskId in session.start)
+    "scheduler.execution.cancel" => {
+      let payload = event.get("payload")
+        .ok_or_else(|| "[scheduler.execution.cancel] missing payload".to_string())?;
+      let task_id = payload.get("taskId").and_then(|v| v.as_str())
+        .ok_or_else(|| "[scheduler.execution.cancel] missing taskId".to_string())?;
+      let run = state.db.get_active_task_run(task_id)
+        .map_err(|e| format!("[scheduler.execution.cancel] {}", e))?;
+      let Some(run) = run else {
+        emit_server_event_app(&app, &json!({
+          "type": "runner.error",
+          "payload": { "message": format!("No running execution for task {}", task_id) }
+        }))?;
+        return Ok(());
+      };
+
+      // Record first: the sidecar's own status update must not overwrite "cancelled"
+      state.db.finish_task_runs(&run.session_id, "cancelled")
+        .map_err(|e| format!("[scheduler.execution.cancel] {}", e))?;
+      send_to_sidecar(app.clone(), state.inner(), &json!({
+        "type": "session.stop",
+        "payload": { "sessionId": run.session_id }
+      }))?;
+      emit_server_event_app(&app, &json!({
+        "type": "scheduler.execution.cancelled",
+        "payload": { "taskId": task_id, "sessionId": run.session_id, "runId": run.id }
+      }))?;
+      Ok(())
+    }
+
+    // Focused session, restored on next launch
+    "session.last_active.set" => {
+      let payload = event.get("payload")
+        .ok_or_else(|| "[session.last_active.set] missing payload".to_string())?;
+      let session_id = payload.get("sessionId").and_then(|v| v.as_str())
+        .ok_or_else(|| "[session.last_active.set] missing sessionId".to_string())?;
+      if !state.db.set_last_active_session(session_id)
+        .map_err(|e| format!("[session.last_active.set] {}", e))? {
+        eprintln!("[session.last_active.set] Unknown session {}, ignored", session_id);
+      }
+      Ok(())
+    }
+
+    "session.last_active.get" => {
+      let session_id = state.db.get_last_active_session()
+        .map_err(|e| format!("[session.last_active.get] {}", e))?;
+      emit_server_event_app(&app, &json!({
+        "type": "session.last_active",
+        "payload": { "sessionId": session_id }
+      }))?;
+      Ok(())
+    }
+
+    // Unfinished session to offer on launch (also emitted once at startup); null when disabled
+    "session.resume_suggestion.get" => {
+      emit_resume_suggestion(&app, &state.db)
+        .map_err(|e| format!("[session.resume_suggestion.get] {}", e))
+    }
+
+    "session.resume_suggestion.enabled.set" => {
+      let payload = event.get("payload")
+        .ok_or_else(|| "[session.resume_suggestion.enabled.set] missing payload".to_string())?;
+      let enabled = payload.get("enabled").and_then(|v| v.as_bool())
+        .ok_or_else(|| "[session.resume_suggestion.enabled.set] missing enabled".to_string())?;
+      state.db.set_resume_unfinished_on_launch(enabled)
+        .map_err(|e| format!("[session.resume_suggestion.enabled.set] {}", e))?;
+      emit_server_event_app(&app, &json!({
+        "type": "session.resume_suggestion.enabled",
+        "payload": { "enabled": enabled }
+      }))?;
+      Ok(())
+    }
+
+    // Session cap: null/0 = unlimited. Applied the next time a session is created.
+    "sessions.max.get" => {
+      let max = state.db.get_max_sessions()
+        .map_err(|e| format!("[sessions.max.get] {}", e))?;
+      emit_server_event_app(&app, &json!({
+        "type": "sessions.max.loaded",
+        "payload": { "maxSessions": max }
+      }))?;
+      Ok(())
+    }
+
+    "sessions.max.set" => {
+      let payload = event.get("payload")
+        .ok_or_else(|| "[sessions.max.set] missing payload".to_string())?;
+      let max = payload.get("maxSessions").and_then(|v| v.as_u64()).map(|n| n as usize);
+      state.db.set_max_sessions(max)
+        .map_err(|e| format!("[sessions.max.set] {}", e))?;
+      let max = state.db.get_max_sessions()
+        .map_err(|e| format!("[sessions.max.set] {}", e))?;
+      emit_server_event_app(&app, &json!({
+        "type": "sessions.max.loaded",
+        "payload": { "maxSessions": max }
+      }))?;
+      Ok(())
+    }
+
+    "scheduler.pause" => {
+      state.scheduler.pause();
+      emit_server_event_app(&app, &json!({
+        "type": "scheduler.paused",
+        "payload": { "paused": true, "skipped": 0 }
+      }))?;
+      Ok(())
+    }
+
+    "scheduler.resume" => {
+      // Default: overdue tasks fire on the next check, as after any downtime
+      let catch_up = event.get("payload")
+        .and_then(|p| p.get("catchUp"))
+        .and_then(|v| v.as_bool())
+        .unwrap_or(true);
+      let skipped = state.scheduler.resume(catch_up)
+        .map_err(|e| format!("[scheduler.resume] {}", e))?;
+      emit_server_event_app(&app, &json!({
+        "type": "scheduler.paused",
+        "payload": { "paused": false, "skipped": skipped }
+      }))?;
+      Ok(())
+    }
+
+    "scheduler.status" => {
+      emit_server_event_app(&app, &json!({
+        "type": "scheduler.paused",
+        "payload": { "paused": state.scheduler.is_paused(), "skipped": 0 }
+      }))?;
+      Ok(())
+    }
+
     // Scheduled Tasks - handled in Rust
     "task.create" => {
       let payload = event.get("payload")
@@ -2511,6 +7638,40 @@ fn client_event(app: tauri::AppHandle, state: tauri::State<'_, AppState>, event:
       Ok(())
     }
 
+    // Toggle recurrence while keeping the task's timing
+    "task.convert" => {
+      let payload = event.get("payload")
+        .ok_or_else(|| "[task.convert] missing payload".to_string())?;
+      let task_id = payload.get("taskId").and_then(|v| v.as_str())
+        .ok_or_else(|| "[task.convert] missing taskId".to_string())?;
+      let to_recurring = payload.get("toRecurring").and_then(|v| v.as_bool())
+        .ok_or_else(|| "[task.convert] missing toRecurring".to_string())?;
+      let schedule = payload.get("schedule").and_then(|v| v.as_str());
+      let task = state.db.get_scheduled_task(task_id)
+        .map_err(|e| format!("[task.convert] {}", e))?
+        .ok_or_else(|| format!("[task.convert] task {} not found", task_id))?;
+
+      let now = chrono::Utc::now().timestamp_millis();
+      let result = scheduler::convert_task_schedule(&task, to_recurring, schedule, now)
+        .and_then(|params| state.db.update_scheduled_task(task_id, &params).map_err(|e| e.to_string()))
+        .and_then(|updated| Ok((updated, state.db.get_scheduled_task(task_id).map_err(|e| e.to_string())?)));
+      match result {
+        Ok((updated, task)) => {
+          emit_server_event_app(&app, &json!({
+            "type": "task.updated",
+            "payload": { "taskId": task_id, "updated": updated, "task": task }
+          }))?;
+        }
+        Err(e) => {
+          emit_server_event_app(&app, &json!({
+            "type": "runner.error",
+            "payload": { "message": format!("Failed to convert task: {}", e) }
+          }))?;
+        }
+      }
+      Ok(())
+    }
+
     "task.delete" => {
       let payload = event.get("payload")
         .ok_or_else(|| "[task.delete] missing payload".to_string())?;
@@ -2530,7 +7691,58 @@ fn client_event(app: tauri::AppHandle, state: tauri::State<'_, AppState>, event:
             "payload": { "message": format!("Failed to delete task: {}", e) }
           }))?;
         }
-      }
+      }
+      Ok(())
+    }
+
+    "schedule.describe" => {
+      let payload = event.get("payload")
+        .ok_or_else(|| "[schedule.describe] missing payload".to_string())?;
+      let schedule = payload.get("schedule").and_then(|v| v.as_str())
+        .ok_or_else(|| "[schedule.describe] missing schedule".to_string())?;
+
+      let now = chrono::Utc::now().timestamp_millis();
+      let next_run = scheduler::calculate_next_run(schedule, now);
+      let valid = next_run.is_some();
+
+      emit_server_event_app(&app, &json!({
+        "type": "schedule.described",
+        "payload": {
+          "schedule": schedule,
+          "valid": valid,
+          "recurring": valid && scheduler::is_recurring_schedule(schedule),
+          "nextRunMs": next_run,
+          "humanDescription": scheduler::describe_schedule(schedule)
+        }
+      }))?;
+      Ok(())
+    }
+
+    "schedule.parse" => {
+      let payload = event.get("payload")
+        .ok_or_else(|| "[schedule.parse] missing payload".to_string())?;
+      let schedule = payload.get("schedule").and_then(|v| v.as_str())
+        .ok_or_else(|| "[schedule.parse] missing schedule".to_string())?;
+
+      let result = match scheduler::parse_schedule(schedule) {
+        Ok(parsed) => json!({
+          "schedule": schedule,
+          "valid": true,
+          "parsed": parsed,
+          "recurring": parsed.is_recurring(),
+          "nextRunMs": parsed.next_run(chrono::Utc::now().timestamp_millis())
+        }),
+        Err(e) => json!({
+          "schedule": schedule,
+          "valid": false,
+          "error": e,
+          "message": e.to_string()
+        }),
+      };
+      emit_server_event_app(&app, &json!({
+        "type": "schedule.parsed",
+        "payload": result
+      }))?;
       Ok(())
     }
 
@@ -2545,7 +7757,11 @@ fn client_event(app: tauri::AppHandle, state: tauri::State<'_, AppState>, event:
       let session_id = payload.get("sessionId").and_then(|v| v.as_str())
         .ok_or_else(|| "[miniworkflow.distill] missing sessionId".to_string())?;
 
-      match state.db.get_session_history(session_id) {
+      if budget_exceeded(&app, &state.db, Some(session_id))? {
+        return Ok(());
+      }
+
+      match load_session_history(&state.db, session_id) {
         Ok(Some(history)) => {
           eprintln!("[miniworkflow.distill] Found session: {}, messages={}", session_id, history.messages.len());
           let enriched_event = json!({
@@ -2560,7 +7776,9 @@ fn client_event(app: tauri::AppHandle, state: tauri::State<'_, AppState>, event:
                 "cwd": history.session.cwd,
                 "model": history.session.model,
                 "allowedTools": history.session.allowed_tools,
-                "temperature": history.session.temperature
+                "temperature": history.session.temperature,
+                "maxOutputTokens": history.session.max_output_tokens,
+                "permissionMode": history.session.permission_mode
               },
               "messages": history.messages
             }
@@ -2578,6 +7796,19 @@ fn client_event(app: tauri::AppHandle, state: tauri::State<'_, AppState>, event:
       }
     }
 
+    "session.update" => {
+      let mut payload = event.get("payload").and_then(|v| v.as_object()).cloned()
+        .ok_or_else(|| "[session.update] missing payload".to_string())?;
+      let model_id = match payload.get("model").and_then(|v| v.as_str()) {
+        Some(model) => Some(model.to_string()),
+        None => payload.get("sessionId").and_then(|v| v.as_str())
+          .and_then(|id| state.db.get_session(id).ok().flatten())
+          .and_then(|s| s.model),
+      };
+      clamp_payload_temperature(&app, &state.db, &mut payload, model_id.as_deref())?;
+      send_to_sidecar(app, state.inner(), &json!({ "type": "session.update", "payload": payload }))
+    }
+
     _ => {
       // Forward unknown events to sidecar
       send_to_sidecar(app, state.inner(), &event)
@@ -2585,7 +7816,12 @@ fn client_event(app: tauri::AppHandle, state: tauri::State<'_, AppState>, event:
   }
 }
 
-fn migrate_json_to_db(db: &Database, user_data_dir: &PathBuf) {
+/// Import legacy JSON settings from `user_data_dir` into the DB. Each part only runs while the
+/// corresponding DB data is still empty, so it never overwrites newer settings.
+/// Returns a description of what was migrated.
+fn migrate_json_to_db(db: &Database, user_data_dir: &Path) -> Vec<String> {
+  let mut migrated = Vec::new();
+
   // Migrate api-settings.json → DB
   let api_settings_path = user_data_dir.join("api-settings.json");
   if api_settings_path.exists() {
@@ -2597,6 +7833,7 @@ fn migrate_json_to_db(db: &Database, user_data_dir: &PathBuf) {
             eprintln!("[migrate] Failed to save api settings: {}", e);
           } else {
             eprintln!("[migrate] Migrated api-settings.json to DB");
+            migrated.push("api-settings.json".to_string());
           }
         }
       }
@@ -2656,12 +7893,55 @@ fn migrate_json_to_db(db: &Database, user_data_dir: &PathBuf) {
             } else {
               eprintln!("[migrate] Migrated llm-providers.json to DB ({} providers, {} models)", 
                 migrated_settings.providers.len(), migrated_settings.models.len());
+              migrated.push(format!(
+                "llm-providers-settings.json ({} providers, {} models)",
+                migrated_settings.providers.len(), migrated_settings.models.len()
+              ));
             }
           }
         }
       }
     }
   }
+
+  migrated
+}
+
+/// On-demand variant of the old-directory migration for an already running app: copies files
+/// from old install dirs only where the new dir has none (the live sessions.db is never touched)
+/// and imports their JSON settings if the DB has none yet.
+fn migrate_old_dirs_on_demand(db: &Database) -> Result<(Vec<String>, Vec<String>), String> {
+  let new_dir = app_data_dir()?;
+  let mut migrated = Vec::new();
+  let mut skipped = Vec::new();
+
+  for old_dir in get_old_app_dirs().into_iter().filter(|d| d.is_dir()) {
+    let entries = fs::read_dir(&old_dir)
+      .map_err(|e| format!("Failed to read {}: {e}", old_dir.display()))?;
+    for entry in entries.flatten() {
+      let src = entry.path();
+      if !src.is_file() {
+        continue;
+      }
+      let file_name = entry.file_name().to_string_lossy().to_string();
+      let dst = new_dir.join(&file_name);
+      if file_name.starts_with("sessions.db") || dst.exists() {
+        skipped.push(src.display().to_string());
+        continue;
+      }
+      match fs::copy(&src, &dst) {
+        Ok(_) => migrated.push(format!("{} → {}", src.display(), dst.display())),
+        Err(e) => skipped.push(format!("{} ({e})", src.display())),
+      }
+    }
+    migrated.extend(
+      migrate_json_to_db(db, &old_dir)
+        .into_iter()
+        .map(|item| format!("{item} from {}", old_dir.display())),
+    );
+  }
+
+  Ok((migrated, skipped))
 }
 
 /// Migrate data from old app directories to new ValeDesk directory
@@ -2793,24 +8073,18 @@ fn get_old_app_dirs() -> Vec<PathBuf> {
   dirs
 }
 
-/// Migrate old memory locations to app_data_dir
-fn migrate_old_memory() {
-  let app_dir = match app_data_dir() {
-    Ok(d) => d,
-    Err(_) => return,
-  };
+/// Migrate old memory locations to app_data_dir; returns the source that was copied, if any
+fn migrate_old_memory() -> Option<PathBuf> {
+  let app_dir = app_data_dir().ok()?;
   
   let new_memory = app_dir.join("memory.md");
   
   // Skip if app_data_dir already has memory.md
   if new_memory.exists() {
-    return;
+    return None;
   }
   
-  let home = match home_dir() {
-    Ok(h) => h,
-    Err(_) => return,
-  };
+  let home = home_dir().ok()?;
   
   // Try to migrate from old locations in order of priority
   let old_locations = [
@@ -2825,12 +8099,13 @@ fn migrate_old_memory() {
       
       if let Err(e) = fs::copy(&old_memory, &new_memory) {
         eprintln!("[migration] Failed to copy memory.md: {e}");
-      } else {
-        eprintln!("[migration] Memory migration complete!");
+        return None;
       }
-      return; // Stop after first successful migration
+      eprintln!("[migration] Memory migration complete!");
+      return Some(old_memory); // Stop after first successful migration
     }
   }
+  None
 }
 
 /// Recursively copy a directory
@@ -2849,7 +8124,27 @@ fn copy_dir_recursive(src: &Path, dst: &Path) -> std::io::Result<()> {
   Ok(())
 }
 
+/// Emits `session.resume_suggestion` with the most recent unfinished session,
+/// or a null sessionId when the setting is off or nothing was left mid-turn
+fn emit_resume_suggestion(app: &tauri::AppHandle, db: &Database) -> Result<(), String> {
+  let session_id = if db.get_resume_unfinished_on_launch().map_err(|e| e.to_string())? {
+    db.find_unfinished_session().map_err(|e| e.to_string())?
+  } else {
+    None
+  };
+  emit_server_event_app(app, &json!({
+    "type": "session.resume_suggestion",
+    "payload": { "sessionId": session_id }
+  }))
+}
+
 fn main() {
+  // The JS sandbox re-runs this binary as a worker it can kill on timeout
+  if std::env::var_os(sandbox::JS_WORKER_ENV).is_some() {
+    sandbox::run_js_worker();
+    return;
+  }
+
   // Migrate data from old LocalDesk directory if needed
   migrate_from_localdesk();
   
@@ -2869,9 +8164,17 @@ fn main() {
     Err(e) => eprintln!("[startup] Failed to reset running sessions: {}", e),
     _ => {}
   }
+  // Drafts left by a crash are shown as partial messages for a while, not forever
+  let draft_cutoff = chrono::Utc::now().timestamp_millis() - db::STREAM_DRAFT_MAX_AGE_MS;
+  match db.expire_stream_drafts(draft_cutoff) {
+    Ok(count) if count > 0 => eprintln!("[startup] Removed {} expired stream drafts", count),
+    Err(e) => eprintln!("[startup] Failed to expire stream drafts: {}", e),
+    _ => {}
+  }
 
   // Migrate JSON settings to DB on first run
   migrate_json_to_db(&db, &user_data_dir);
+  apply_network_settings(db.get_api_settings().ok().flatten().as_ref());
 
   let db_arc = Arc::new(db);
   let scheduler = SchedulerService::new(db_arc.clone());
@@ -2881,6 +8184,10 @@ fn main() {
     sidecar: SidecarState::default(),
     scheduler,
     voice: VoiceState::default(),
+    recent_events: Mutex::new(EventLog::default()),
+    http: HttpClients::default(),
+    tree_walks: Mutex::new(HashMap::new()),
+    stream_activity: Mutex::new(StreamActivity::default()),
   };
 
   tauri::Builder::default()
@@ -2905,7 +8212,8 @@ fn main() {
           let Some(voice_settings) = voice else { continue; };
           if voice_settings.base_url.trim().is_empty() { continue; }
 
-          let (available, _error) = check_voice_server_status_blocking(&voice_settings.base_url, voice_settings.api_key.as_deref())
+          let (available, _error) = state.http.blocking_client()
+            .and_then(|client| check_voice_server_status_blocking(&client, &voice_settings.base_url, voice_settings.api_key.as_deref()))
             .unwrap_or((false, None));
 
           let mut last_guard = state.voice.last_status.lock().unwrap();
@@ -2918,13 +8226,29 @@ fn main() {
           }
         }
       });
+      if state.db.get_resume_unfinished_on_launch().unwrap_or(false) {
+        if let Err(e) = emit_resume_suggestion(app.handle(), &state.db) {
+          eprintln!("[startup] Failed to check for an unfinished session: {}", e);
+        }
+      }
+      let app_handle = app.handle().clone();
+      std::thread::spawn(move || {
+        loop {
+          std::thread::sleep(std::time::Duration::from_secs(IDLE_CHECK_INTERVAL_SECS));
+          stop_idle_sessions(&app_handle);
+        }
+      });
       Ok(())
     })
     .invoke_handler(tauri::generate_handler![
       client_event,
       list_directory,
+      search_files,
       get_thumbnail,
       get_file_text_preview,
+      inspect_file,
+      check_cwd_writable,
+      detect_project_type,
       read_memory,
       write_memory,
       get_file_old_content,
@@ -2945,6 +8269,7 @@ fn main() {
       sandbox_execute,
       // Voice
       transcribe_voice_stream,
+      translate_voice,
       list_voice_models,
       // Database commands - Sessions
       db_session_list,
@@ -2961,6 +8286,8 @@ fn main() {
       // Database commands - Settings & Providers
       db_get_api_settings,
       db_save_api_settings,
+      db_get_ui_prefs,
+      db_save_ui_prefs,
       db_get_llm_providers,
       db_save_llm_providers,
       db_save_provider,
@@ -3115,4 +8442,801 @@ mod tests {
         assert_eq!(settings.models[0].name, "Display Name");
         assert!(!settings.models[0].enabled);
     }
+
+    #[test]
+    fn model_metadata_maps_openrouter_pricing_and_context() {
+        let db = make_test_db();
+        save_test_provider(&db, "or-1", "OpenRouter", "openrouter");
+        db.save_model(&db::LLMModel {
+            id: "or-1::anthropic/claude-3.5-sonnet".to_string(),
+            provider_id: "or-1".to_string(),
+            name: "anthropic/claude-3.5-sonnet".to_string(),
+            enabled: true,
+            config: Some(serde_json::json!({ "maxTemperature": 1.0 })),
+        }).unwrap();
+
+        let response = serde_json::json!({
+            "data": [{
+                "id": "anthropic/claude-3.5-sonnet",
+                "context_length": 200000,
+                "pricing": { "prompt": "0.000003", "completion": "0.000015" },
+                "top_provider": { "max_completion_tokens": 8192 }
+            }]
+        });
+
+        let updated = apply_model_metadata(&db, "or-1", &response, 1234).unwrap();
+        assert_eq!(updated, 1);
+
+        let model = db.list_models_by_provider("or-1").unwrap().remove(0);
+        let config = db::ModelConfig::from_config(model.config.as_ref());
+        assert_eq!(config.context_length, Some(200000));
+        assert_eq!(config.max_output_tokens, Some(8192));
+        assert!((config.input_price_per_million.unwrap() - 3.0).abs() < 1e-9);
+        assert!((config.output_price_per_million.unwrap() - 15.0).abs() < 1e-9);
+        assert_eq!(config.metadata_fetched_at, Some(1234));
+        // Unrelated config keys survive
+        assert_eq!(config.extra.get("maxTemperature"), Some(&serde_json::json!(1.0)));
+    }
+
+    #[test]
+    fn model_metadata_skips_unknown_models() {
+        let db = make_test_db();
+        save_test_provider(&db, "or-1", "OpenRouter", "openrouter");
+        db.save_model(&db::LLMModel {
+            id: "or-1::local-only".to_string(),
+            provider_id: "or-1".to_string(),
+            name: "local-only".to_string(),
+            enabled: true,
+            config: None,
+        }).unwrap();
+
+        let response = serde_json::json!({ "data": [{ "id": "other", "context_length": 1000 }] });
+        assert_eq!(apply_model_metadata(&db, "or-1", &response, 1).unwrap(), 0);
+        assert!(db.list_models_by_provider("or-1").unwrap()[0].config.is_none());
+    }
+
+    #[test]
+    fn providers_health_reports_every_provider() {
+        let now = chrono::Utc::now().timestamp_millis();
+        let make = |id: &str, base_url: Option<&str>| db::LLMProvider {
+            id: id.to_string(),
+            name: id.to_string(),
+            provider_type: "custom".to_string(),
+            base_url: base_url.map(String::from),
+            api_key: None,
+            enabled: true,
+            config: None,
+            created_at: now,
+            updated_at: now,
+        };
+        let providers = vec![
+            make("no-url", None),
+            make("refused", Some("http://127.0.0.1:9")),
+            make("refused-2", Some("http://127.0.0.1:9/v1")),
+        ];
+
+        let client = HttpClients::default().blocking_client().unwrap();
+        let results = check_providers_health_blocking(&client, providers, 2);
+
+        assert_eq!(results.len(), 3);
+        assert!(results.values().all(|h| !h.ok));
+        assert_eq!(results["no-url"].error.as_deref(), Some("No baseUrl configured"));
+        assert_eq!(results["refused"].error.as_deref(), Some("[providers.test_all] refused is unreachable"));
+    }
+
+    #[test]
+    fn stream_activity_reports_sessions_past_their_timeout() {
+        let mut activity = StreamActivity::default();
+        activity.touch("slow", 0);
+        activity.touch("busy", 0);
+        activity.touch("no-timeout", 0);
+        activity.touch("busy", 9_000);
+        activity.touch("stopped", 0);
+        activity.forget("stopped");
+        activity.touch("asking", 0);
+        activity.set_awaiting_permission("asking", true);
+        let timeout_ms = |id: &str| (id != "no-timeout").then_some(5_000);
+
+        assert!(activity.take_idle(4_000, timeout_ms).is_empty());
+        assert_eq!(activity.take_idle(10_000, timeout_ms), vec![("slow".to_string(), 10_000)]);
+        // Reported once, then dropped until activity resumes
+        assert!(activity.take_idle(12_000, timeout_ms).is_empty());
+        assert_eq!(activity.take_idle(15_000, timeout_ms), vec![("busy".to_string(), 6_000)]);
+        let mut left = activity.tracked_ids();
+        left.sort();
+        assert_eq!(left, vec!["asking", "no-timeout"]);
+    }
+
+    #[test]
+    fn transcript_stream_parses_sse_and_ndjson() {
+        // OpenAI-style SSE, split mid-line across chunks
+        let mut sse = TranscriptStream::default();
+        assert!(sse.push("data: {\"type\":\"transcript.text.delta\",\"delta\":\"Hel").is_empty());
+        assert_eq!(sse.push("lo\"}\n\n"), vec!["Hello"]);
+        assert_eq!(sse.push("event: x\ndata: {\"type\":\"transcript.text.delta\",\"delta\":\" world\"}\n"), vec![" world"]);
+        sse.push(": keepalive\ndata: {\"type\":\"transcript.text.done\",\"text\":\"Hello world.\"}\ndata: [DONE]\n");
+        assert_eq!(sse.finish(), "Hello world.");
+
+        // Whisper-server segments without a final event; last line has no newline
+        let mut ndjson = TranscriptStream::default();
+        assert_eq!(ndjson.push("{\"text\":\"First part.\"}\n{\"text\":\"Second"), vec!["First part."]);
+        assert!(ndjson.push(" part.\"}").is_empty());
+        assert_eq!(ndjson.finish(), "First part. Second part.");
+
+        assert!(is_streamed_transcription("text/event-stream; charset=utf-8"));
+        assert!(is_streamed_transcription("application/x-ndjson"));
+        assert!(!is_streamed_transcription("application/json"));
+    }
+
+    #[test]
+    fn ping_disables_only_models_the_provider_says_are_missing() {
+        use reqwest::StatusCode;
+        assert!(ping_failure_is_missing_model(StatusCode::NOT_FOUND, ""));
+        assert!(ping_failure_is_missing_model(StatusCode::BAD_REQUEST, r#"{"error":{"code":"model_not_found"}}"#));
+        assert!(ping_failure_is_missing_model(StatusCode::BAD_REQUEST, "The model `gpt-x` does not exist"));
+        assert!(!ping_failure_is_missing_model(StatusCode::TOO_MANY_REQUESTS, "model not found"));
+        assert!(!ping_failure_is_missing_model(StatusCode::BAD_GATEWAY, "model not found"));
+        assert!(!ping_failure_is_missing_model(StatusCode::UNAUTHORIZED, "invalid api key"));
+        assert!(!ping_failure_is_missing_model(StatusCode::BAD_REQUEST, "max_tokens is too small"));
+    }
+
+    #[test]
+    fn output_cap_uses_max_completion_tokens_only_on_openai() {
+        assert_eq!(max_tokens_param("https://api.openai.com/v1/chat/completions"), "max_completion_tokens");
+        assert_eq!(max_tokens_param("https://team.openai.azure.com/openai/v1/chat/completions"), "max_completion_tokens");
+        assert_eq!(max_tokens_param("https://openrouter.ai/api/v1/chat/completions"), "max_tokens");
+        assert_eq!(max_tokens_param("http://localhost:11434/v1/chat/completions"), "max_tokens");
+    }
+
+    #[test]
+    fn provider_auth_only_rejects_on_401_and_403() {
+        let unauthorized = provider_auth_from_status(reqwest::StatusCode::UNAUTHORIZED, "invalid api key");
+        assert_eq!(unauthorized.authorized, Some(false));
+        assert_eq!(unauthorized.error.as_deref(), Some("http 401 Unauthorized: invalid api key"));
+        assert_eq!(provider_auth_from_status(reqwest::StatusCode::FORBIDDEN, "").authorized, Some(false));
+
+        for status in [reqwest::StatusCode::OK, reqwest::StatusCode::BAD_REQUEST, reqwest::StatusCode::NOT_FOUND] {
+            let auth = provider_auth_from_status(status, "model not found");
+            assert_eq!(auth.authorized, Some(true));
+            assert_eq!(auth.status, Some(status.as_u16()));
+            assert!(auth.error.is_none());
+        }
+
+        // Outages and rate limits say nothing about the key
+        for status in [reqwest::StatusCode::TOO_MANY_REQUESTS, reqwest::StatusCode::BAD_GATEWAY, reqwest::StatusCode::SERVICE_UNAVAILABLE] {
+            let auth = provider_auth_from_status(status, "try again later");
+            assert_eq!(auth.authorized, None);
+            assert_eq!(auth.status, Some(status.as_u16()));
+            assert!(auth.error.is_some());
+        }
+
+        let now = chrono::Utc::now().timestamp_millis();
+        let provider = db::LLMProvider {
+            id: "refused".to_string(),
+            name: "refused".to_string(),
+            provider_type: "custom".to_string(),
+            base_url: Some("http://127.0.0.1:9".to_string()),
+            api_key: Some("sk-test".to_string()),
+            enabled: true,
+            config: None,
+            created_at: now,
+            updated_at: now,
+        };
+        let client = HttpClients::default().blocking_client().unwrap();
+        let auth = check_provider_auth_blocking(&client, &provider, "m");
+        assert!(auth.authorized.is_none() && auth.status.is_none() && auth.error.is_some());
+    }
+
+    fn stream_payload(session_id: &str, stream_event: Value) -> Value {
+        json!({ "sessionId": session_id, "message": { "type": "stream_event", "event": stream_event } })
+    }
+
+    #[test]
+    fn stream_checkpoint_throttles_saves_and_clears_on_stop() {
+        let db = make_test_db();
+        let session = db.create_session(&CreateSessionParams {
+            title: "t".to_string(),
+            ..Default::default()
+        }).unwrap();
+        let mut drafts = HashMap::new();
+        let delta = |text: &str| stream_payload(&session.id, json!({
+            "type": "content_block_delta", "delta": { "type": "text_delta", "text": text }
+        }));
+
+        checkpoint_stream_event(&db, &mut drafts, &stream_payload(&session.id, json!({ "type": "content_block_start" })), 1000, 0);
+        checkpoint_stream_event(&db, &mut drafts, &delta("Hel"), 1000, 500);
+        assert_eq!(db.get_stream_draft(&session.id).unwrap(), None);
+
+        checkpoint_stream_event(&db, &mut drafts, &delta("lo"), 1000, 1200);
+        assert_eq!(db.get_stream_draft(&session.id).unwrap().as_deref(), Some("Hello"));
+
+        checkpoint_stream_event(&db, &mut drafts, &stream_payload(&session.id, json!({ "type": "content_block_stop" })), 1000, 1300);
+        assert_eq!(db.get_stream_draft(&session.id).unwrap(), None);
+        assert!(drafts.is_empty());
+    }
+
+    #[test]
+    fn inspect_file_flags_binary_and_text() {
+        let dir = std::env::temp_dir().join(format!("inspect_file_test_{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        let text = dir.join("a.txt");
+        let binary = dir.join("b.bin");
+        fs::write(&text, "héllo\nworld").unwrap();
+        fs::write(&binary, [0x89u8, b'P', b'N', b'G', 0, 0, 1]).unwrap();
+
+        let info = inspect_file_at(&text).unwrap();
+        assert_eq!((info.encoding.as_str(), info.is_probably_binary, info.too_large), ("utf-8", false, false));
+        assert_eq!(info.size_bytes, "héllo\nworld".len() as u64);
+
+        let info = inspect_file_at(&binary).unwrap();
+        assert!(info.is_probably_binary);
+        assert!(inspect_file_at(&dir).is_err());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn transcription_ws_url_switches_scheme_and_adds_query() {
+        assert_eq!(
+            build_transcription_ws_url("http://localhost:8000", "whisper/large v3", Some("ru")).unwrap(),
+            "ws://localhost:8000/v1/audio/transcriptions?model=whisper%2Flarge+v3&language=ru"
+        );
+        assert_eq!(
+            build_transcription_ws_url("https://voice.example.com/v1/", "m", Some(" ")).unwrap(),
+            "wss://voice.example.com/v1/audio/transcriptions?model=m"
+        );
+        assert!(build_transcription_ws_url("", "m", None).is_err());
+    }
+
+    #[test]
+    fn embeddings_response_is_parsed_in_input_order() {
+        assert_eq!(build_embeddings_url("https://api.openai.com/v1/").unwrap(), "https://api.openai.com/v1/embeddings");
+        assert_eq!(build_embeddings_url("http://localhost:11434").unwrap(), "http://localhost:11434/v1/embeddings");
+
+        let body = json!({ "data": [
+            { "index": 1, "embedding": [0.5, 0.25] },
+            { "index": 0, "embedding": [1, 0] }
+        ] });
+        assert_eq!(parse_embeddings_response(&body, 2).unwrap(), vec![vec![1.0, 0.0], vec![0.5, 0.25]]);
+        assert!(parse_embeddings_response(&body, 3).is_err());
+        assert!(parse_embeddings_response(&json!({ "error": "nope" }), 1).is_err());
+    }
+
+    #[test]
+    fn translation_url_mirrors_transcription_url() {
+        for base in ["http://localhost:8000", "http://localhost:8000/", "http://localhost:8000/v1", "http://localhost:8000/v1/"] {
+            assert_eq!(build_translation_url(base).unwrap(), "http://localhost:8000/v1/audio/translations");
+            assert_eq!(build_transcription_url(base).unwrap(), "http://localhost:8000/v1/audio/transcriptions");
+        }
+        assert_eq!(build_translation_url(" https://api.example.com/v1/proxy ").unwrap(), "https://api.example.com/v1/proxy/audio/translations");
+        assert!(build_translation_url("  ").is_err());
+    }
+
+    #[test]
+    fn verbose_transcripts_parse_segments_or_words() {
+        let verbose = json!({
+            "text": "hello world",
+            "segments": [
+                { "id": 0, "text": " hello", "start": 0.0, "end": 0.6 },
+                { "id": 1, "text": " world", "start": 0.6, "end": 1.25 }
+            ]
+        });
+        let segments = parse_transcript_segments(&verbose).unwrap();
+        assert_eq!(segments.len(), 2);
+        assert_eq!(segments[1], TranscriptSegment { text: "world".to_string(), start: 0.6, end: 1.25 });
+        assert_eq!(json!(segments[0]), json!({ "text": "hello", "start": 0.0, "end": 0.6 }));
+
+        let words = json!({ "text": "hi", "words": [{ "word": "hi", "start": 0.1, "end": 0.3 }, { "word": "x" }] });
+        assert_eq!(parse_transcript_segments(&words).unwrap(), vec![TranscriptSegment { text: "hi".to_string(), start: 0.1, end: 0.3 }]);
+        // Server ignored response_format
+        assert_eq!(parse_transcript_segments(&json!({ "text": "hi" })), None);
+
+        let settings = |format: Option<&str>| VoiceSettings {
+            base_url: "http://localhost:8000".to_string(),
+            api_key: None,
+            model: "m".to_string(),
+            language: None,
+            streaming: None,
+            voice_partial_interval_ms: None,
+            response_format: format.map(String::from),
+        };
+        assert_eq!(voice_response_format(None), None);
+        assert_eq!(voice_response_format(Some(&settings(Some("verbose_json")))), Some("verbose_json"));
+        assert_eq!(voice_response_format(Some(&settings(Some("srt")))), None);
+    }
+
+    #[test]
+    fn dictation_timing_measures_first_partial_and_final() {
+        let voice = VoiceState::default();
+        voice.start_dictation_timing("s1", "whisper", "multipart", 1_000);
+        // Later chunks don't restart the clock
+        voice.start_dictation_timing("s1", "whisper", "multipart", 1_200);
+        voice.mark_dictation_partial("s1", 1_300);
+        voice.mark_dictation_partial("s1", 1_500);
+        let metrics = voice.finish_dictation_timing("s1", 2_000).unwrap();
+        assert_eq!(metrics.first_partial_ms, Some(300));
+        assert_eq!(metrics.final_latency_ms, 1_000);
+        assert_eq!(metrics.transport, "multipart");
+        assert!(voice.finish_dictation_timing("s1", 2_100).is_none());
+
+        voice.start_dictation_timing("s2", "whisper", "websocket", 0);
+        voice.cancel_dictation_timing("s2");
+        assert!(voice.finish_dictation_timing("s2", 10).is_none());
+        voice.start_dictation_timing("s3", "whisper", "websocket", 0);
+        assert_eq!(voice.finish_dictation_timing("s3", 50).unwrap().first_partial_ms, None);
+    }
+
+    #[test]
+    fn finished_voice_stream_leaves_newer_stream_in_place() {
+        let voice = VoiceState::default();
+        let (tx, _rx) = std::sync::mpsc::channel();
+        voice.streams.lock().unwrap().insert("s1".to_string(), VoiceStream { id: 2, tx });
+        // The thread of the earlier stream finishing must not drop its successor
+        voice.remove_stream("s1", 1);
+        assert!(voice.streams.lock().unwrap().contains_key("s1"));
+        voice.remove_stream("s1", 2);
+        assert!(voice.streams.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn voice_partial_throttle_uses_configured_interval() {
+        let settings = |interval: Option<u64>| VoiceSettings {
+            base_url: "http://localhost:8000".to_string(),
+            api_key: None,
+            model: "m".to_string(),
+            language: None,
+            streaming: None,
+            voice_partial_interval_ms: interval,
+            response_format: None,
+        };
+        assert_eq!(voice_partial_interval_ms(None), 1500);
+        assert_eq!(voice_partial_interval_ms(Some(&settings(None))), 1500);
+        assert_eq!(voice_partial_interval_ms(Some(&settings(Some(400)))), 400);
+        assert_eq!(voice_partial_interval_ms(Some(&settings(Some(10)))), 250);
+        assert_eq!(voice_partial_interval_ms(Some(&settings(Some(60_000)))), 5000);
+
+        let fast = voice_partial_interval_ms(Some(&settings(Some(400))));
+        assert!(!voice_partial_due(10_000, 10_399, fast));
+        assert!(voice_partial_due(10_000, 10_400, fast));
+        assert!(!voice_partial_due(10_000, 10_400, voice_partial_interval_ms(None)));
+    }
+
+    #[test]
+    fn realtime_probe_goes_to_the_http_form_of_the_ws_url() {
+        let ws_url = build_transcription_ws_url("https://voice.example.com/v1/", "m", None).unwrap();
+        assert_eq!(
+            realtime_probe_url(&ws_url).unwrap().as_str(),
+            "https://voice.example.com/v1/audio/transcriptions?model=m"
+        );
+        assert_eq!(
+            realtime_probe_url("ws://localhost:8000/v1/audio/transcriptions").unwrap().as_str(),
+            "http://localhost:8000/v1/audio/transcriptions"
+        );
+        assert!(realtime_probe_url("http://localhost:8000").is_none());
+    }
+
+    #[test]
+    fn voice_reset_bumps_generation_and_clears_buffers() {
+        let voice = VoiceState::default();
+        voice.buffers.lock().unwrap().insert("s".to_string(), VoiceBuffer::default());
+        let before = voice.current_generation();
+
+        assert_eq!(voice.reset(), before + 1);
+        assert_eq!(voice.current_generation(), before + 1);
+        assert!(voice.buffers.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn json_migration_reports_and_never_overwrites() {
+        let db = make_test_db();
+        let dir = std::env::temp_dir().join(format!("migrate_json_test_{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("api-settings.json"), r#"{"model":"legacy"}"#).unwrap();
+
+        assert_eq!(migrate_json_to_db(&db, &dir), vec!["api-settings.json".to_string()]);
+        assert_eq!(db.get_api_settings().unwrap().unwrap().model.as_deref(), Some("legacy"));
+
+        fs::write(dir.join("api-settings.json"), r#"{"model":"older"}"#).unwrap();
+        assert!(migrate_json_to_db(&db, &dir).is_empty());
+        assert_eq!(db.get_api_settings().unwrap().unwrap().model.as_deref(), Some("legacy"));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn network_settings_validate_proxy_and_headers() {
+        let mut api = ApiSettings {
+            http_proxy: Some("  http://proxy.corp:3128 ".to_string()),
+            http_headers: Some([("X-Team".to_string(), "desk".to_string())].into_iter().collect()),
+            ..Default::default()
+        };
+        let network = NetworkSettings::from_api(Some(&api));
+        assert_eq!(network.proxy.as_deref(), Some("http://proxy.corp:3128"));
+        let (proxy, headers) = network.build_parts().unwrap();
+        assert!(proxy.is_some());
+        assert_eq!(headers.get("x-team").unwrap(), "desk");
+
+        api.http_proxy = Some(" ".to_string());
+        assert_eq!(NetworkSettings::from_api(Some(&api)).proxy, None);
+
+        api.http_headers = Some([("Bad Header".to_string(), "x".to_string())].into_iter().collect());
+        assert!(NetworkSettings::from_api(Some(&api)).build_parts().unwrap_err().contains("invalid header name"));
+    }
+
+    #[test]
+    fn chat_completions_url_and_failed_ping() {
+        assert_eq!(build_chat_completions_url("https://api.openai.com/v1/").unwrap(), "https://api.openai.com/v1/chat/completions");
+        assert_eq!(build_chat_completions_url("http://localhost:8080").unwrap(), "http://localhost:8080/v1/chat/completions");
+        assert!(build_chat_completions_url(" ").is_err());
+
+        let provider = LLMProvider {
+            id: "p".to_string(),
+            name: "Local".to_string(),
+            provider_type: "custom".to_string(),
+            base_url: Some("http://127.0.0.1:9".to_string()),
+            api_key: None,
+            enabled: true,
+            config: None,
+            created_at: 0,
+            updated_at: 0,
+        };
+        let model = LLMModel { id: "p::m".to_string(), provider_id: "p".to_string(), name: "m".to_string(), enabled: true, config: None };
+        let client = HttpClients::default().blocking_client().unwrap();
+        let results = run_bounded(vec![model.clone(), model], 2, move |m: LLMModel| ping_model_blocking(&client, &provider, &m));
+        assert_eq!(results.len(), 2);
+        assert!(results.iter().all(|r| !r.ok && r.error.is_some() && r.model_id == "p::m"));
+    }
+
+    #[test]
+    fn search_files_matches_globs_and_respects_gitignore() {
+        let dir = std::env::temp_dir().join(format!("search_files_test_{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(dir.join("src/nested")).unwrap();
+        fs::create_dir_all(dir.join("generated")).unwrap();
+        fs::write(dir.join(".gitignore"), "generated/\n").unwrap();
+        fs::write(dir.join("src/main.rs"), "fn main() {}").unwrap();
+        fs::write(dir.join("src/nested/lib.rs"), "").unwrap();
+        fs::write(dir.join("src/notes.txt"), "").unwrap();
+        fs::write(dir.join("generated/out.rs"), "").unwrap();
+        let root = dir.to_string_lossy().to_string();
+
+        let names = |glob: &str, max: u32| -> Vec<String> {
+            search_files(root.clone(), glob.to_string(), max).unwrap().into_iter().map(|f| f.name).collect()
+        };
+        assert_eq!(names("*.rs", 100), vec!["main.rs", "lib.rs"]);
+        assert_eq!(names("src/*.rs", 100), vec!["main.rs"]);
+        assert_eq!(names("src/**/*.rs", 100), vec!["main.rs", "lib.rs"]);
+        assert_eq!(names("*.rs", 1), vec!["main.rs"]);
+        assert!(names("*.rs", 0).is_empty());
+
+        let Err(err) = search_files(dir.join("missing").to_string_lossy().to_string(), "*".to_string(), 10) else {
+            panic!("missing root should fail");
+        };
+        assert!(err.contains("does not exist"), "{err}");
+
+        #[cfg(unix)]
+        {
+            let outside = std::env::temp_dir().join(format!("search_files_outside_{}.rs", uuid::Uuid::new_v4()));
+            fs::write(&outside, "").unwrap();
+            std::os::unix::fs::symlink(&outside, dir.join("src/escape.rs")).unwrap();
+            std::os::unix::fs::symlink(&dir, dir.join("src/loop")).unwrap();
+            assert_eq!(names("*.rs", 100), vec!["main.rs", "lib.rs"]);
+            fs::remove_file(&outside).unwrap();
+        }
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn tree_walk_batches_ignores_and_caps() {
+        let dir = std::env::temp_dir().join(format!("tree_walk_test_{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(dir.join("src/nested")).unwrap();
+        fs::create_dir_all(dir.join("node_modules/pkg")).unwrap();
+        fs::write(dir.join("src/main.rs"), "fn main() {}").unwrap();
+        fs::write(dir.join("src/nested/lib.rs"), "").unwrap();
+        fs::write(dir.join("debug.log"), "").unwrap();
+        fs::write(dir.join("README.md"), "").unwrap();
+
+        let ignore = vec!["node_modules".to_string(), "*.log".to_string()];
+        let cancel = std::sync::atomic::AtomicBool::new(false);
+        let mut batches: Vec<Vec<TreeNode>> = Vec::new();
+        let summary = walk_tree(&dir, &ignore, 100, 2, &cancel, |nodes| batches.push(nodes));
+
+        let paths: Vec<String> = batches.iter().flatten().map(|n| n.relative_path.clone()).collect();
+        assert_eq!(paths, vec!["README.md", "src", "src/main.rs", "src/nested", "src/nested/lib.rs"]);
+        assert!(batches.iter().all(|b| b.len() <= 2));
+        assert_eq!(summary, TreeWalkSummary { total: 5, truncated: false, aborted: false });
+
+        let capped = walk_tree(&dir, &ignore, 3, 10, &cancel, |_| {});
+        assert_eq!((capped.total, capped.truncated), (3, true));
+
+        cancel.store(true, std::sync::atomic::Ordering::Relaxed);
+        assert!(walk_tree(&dir, &ignore, 100, 10, &cancel, |_| {}).aborted);
+
+        assert!(name_matches_pattern("a.test.ts", "*.test.*"));
+        assert!(!name_matches_pattern("a.ts", "*.test.*"));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn project_type_reads_top_level_markers() {
+        let dir = std::env::temp_dir().join(format!("project_type_test_{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(dir.join(".git")).unwrap();
+        fs::create_dir_all(dir.join("backend")).unwrap();
+        fs::write(dir.join("pyproject.toml"), "").unwrap();
+        fs::write(dir.join("requirements.txt"), "").unwrap();
+        fs::write(dir.join("go.mod"), "").unwrap();
+        fs::write(dir.join("backend/Cargo.toml"), "").unwrap();
+
+        assert_eq!(project_type_at(&dir), ProjectType {
+            ecosystems: vec!["python".to_string(), "go".to_string()],
+            primary: Some("python".to_string()),
+            is_git_repo: true,
+            sandbox_language: Some("python".to_string()),
+        });
+        assert_eq!(project_type_at(&dir.join("backend")).primary.as_deref(), Some("rust"));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn continue_event_matches_sidecar_shape() {
+        let db = make_test_db();
+        let session = db.create_session(&CreateSessionParams { title: "Debug".to_string(), ..Default::default() }).unwrap();
+        db.record_message(&session.id, &json!({ "type": "user_prompt", "prompt": "my key is sk-abcdefghijklmnop1234" })).unwrap();
+        let history = db.get_session_history(&session.id).unwrap().unwrap();
+
+        let mut event = build_continue_event(&session.id, "next", "/tmp/project", &history);
+        assert_eq!(event["type"], "session.continue");
+        assert_eq!(event["payload"]["sessionData"]["cwd"], "/tmp/project");
+        assert_eq!(event["payload"]["messages"].as_array().unwrap().len(), 1);
+
+        let redactor = redaction::Redactor::new(redaction::DEFAULT_REDACTION_PATTERNS);
+        assert_eq!(redactor.redact_value(&mut event), 1);
+        assert_eq!(event["payload"]["messages"][0]["prompt"], "my key is [REDACTED]");
+    }
+
+    #[test]
+    fn session_snapshot_is_redacted_and_versioned() {
+        let db = make_test_db();
+        save_test_provider(&db, "p", "My Provider", "openai");
+        db.save_model(&LLMModel {
+            id: "p::gpt".to_string(),
+            provider_id: "p".to_string(),
+            name: "GPT".to_string(),
+            enabled: true,
+            config: None,
+        }).unwrap();
+        let session = db.create_session(&CreateSessionParams {
+            title: "Bug".to_string(),
+            model: Some("p::gpt".to_string()),
+            ..Default::default()
+        }).unwrap();
+        db.record_message(&session.id, &json!({ "type": "user_prompt", "prompt": "use sk-abcdefghijklmnop1234" })).unwrap();
+        db.update_tokens(&session.id, 120, 30).unwrap();
+
+        let (snapshot, redactions) = build_session_snapshot(&db, &session.id).unwrap().unwrap();
+        assert_eq!(redactions, 1);
+        assert_eq!(snapshot["version"], SESSION_SNAPSHOT_VERSION);
+        assert_eq!(snapshot["app"]["version"], env!("CARGO_PKG_VERSION"));
+        assert_eq!(snapshot["model"]["name"], "GPT");
+        assert_eq!(snapshot["model"]["provider"], "My Provider");
+        assert_eq!((snapshot["tokens"]["input"].as_i64(), snapshot["tokens"]["output"].as_i64()), (Some(120), Some(30)));
+        assert_eq!(snapshot["session"]["messages"][0]["data"]["prompt"], "use [REDACTED]");
+
+        assert!(build_session_snapshot(&db, "missing").unwrap().is_none());
+    }
+
+    #[test]
+    fn cwd_writable_probe_cleans_up() {
+        let dir = std::env::temp_dir().join(format!("cwd_writable_test_{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+
+        let check = cwd_writable(&dir);
+        assert!(check.writable, "{:?}", check.reason);
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 0);
+
+        let missing = cwd_writable(&dir.join("missing"));
+        assert!(!missing.writable);
+        assert!(missing.reason.unwrap().contains("Not a directory"));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn event_log_skips_streaming_and_is_bounded() {
+        let mut log = EventLog::default();
+        log.record(&json!({ "type": "stream.message" }), 1);
+        log.record(&json!({ "type": "fs.tree.chunk", "payload": { "entries": [] } }), 2);
+        log.record(&json!({ "type": "voice.transcription.partial", "payload": {} }), 3);
+        log.record(&json!({ "type": "sandbox.output", "payload": { "line": "tick" } }), 3);
+        log.record(&json!({ "type": "session.list", "payload": "x".repeat(EVENT_REPLAY_MAX_BYTES) }), 4);
+        assert!(log.events.is_empty());
+        for i in 0..(EVENT_REPLAY_CAPACITY as i64 + 5) {
+            log.record(&json!({ "type": "session.list", "n": i }), i + 10);
+        }
+
+        assert_eq!(log.events.len(), EVENT_REPLAY_CAPACITY);
+        assert_eq!(log.events.front().unwrap().1["n"], 5);
+        let recent = log.since(EVENT_REPLAY_CAPACITY as i64 + 12);
+        assert_eq!(recent.len(), 2);
+        assert!(recent.iter().all(|(_, e)| e["type"] == "session.list"));
+    }
+
+    #[test]
+    fn sidecar_line_buffer_reassembles_split_messages() {
+        let mut buffer = SidecarLineBuffer::default();
+        assert!(buffer.push("[debug] starting").is_none());
+        assert!(buffer.push("").is_none());
+
+        assert_eq!(buffer.push(r#"{"type":"log"}"#).unwrap().unwrap()["type"], "log");
+
+        assert!(buffer.push(r#"{"type":"server-event","#).is_none());
+        let joined = buffer.push(r#""event":{"type":"stream.message"}}"#).unwrap().unwrap();
+        assert_eq!(joined["event"]["type"], "stream.message");
+
+        // Garbled fragment followed by a new message keeps the new message
+        assert!(buffer.push(r#"{"type":"broken"#).is_none());
+        assert_eq!(buffer.push(r#"{"type":"ok"}"#).unwrap().unwrap()["type"], "ok");
+        assert!(buffer.pending.is_empty());
+    }
+
+    #[test]
+    fn sidecar_restart_backoff_doubles_and_stops_crash_loops() {
+        let mut policy = SidecarRestartPolicy::default();
+        let delays: Vec<Option<u64>> = (0..6).map(|i| policy.next_delay(1_000 + i * 100)).collect();
+        assert_eq!(delays, vec![Some(500), Some(1_000), Some(2_000), Some(4_000), Some(8_000), None]);
+
+        // Once the oldest restarts age out of the window, restarts resume with a shorter backoff
+        let later = 1_200 + SIDECAR_RESTART_WINDOW_MS + 1;
+        assert_eq!(policy.next_delay(later), Some(2_000));
+
+        let mut quiet = SidecarRestartPolicy::default();
+        assert_eq!(quiet.next_delay(0), Some(500));
+        assert_eq!(quiet.next_delay(SIDECAR_RESTART_WINDOW_MS * 2), Some(500));
+    }
+
+    #[test]
+    fn sidecar_stays_down_after_crash_loop_until_window_passes() {
+        let sidecar = SidecarState::default();
+        for i in 0..SIDECAR_MAX_RESTARTS as u64 {
+            assert!(sidecar.record_crash(1_000 + i).unwrap().is_some());
+            assert!(sidecar.check_crash_loop(1_000 + i).is_ok());
+        }
+        assert_eq!(sidecar.record_crash(2_000).unwrap(), None);
+
+        // A send right after the fatal crash finds no child but must not respawn it
+        assert!(sidecar.child.lock().unwrap().is_none());
+        assert!(sidecar.check_crash_loop(2_001).is_err());
+        assert!(sidecar.check_crash_loop(2_000 + SIDECAR_RESTART_WINDOW_MS - 1).is_err());
+        assert!(sidecar.check_crash_loop(2_000 + SIDECAR_RESTART_WINDOW_MS).is_ok());
+        assert!(sidecar.fatal_until.lock().unwrap().is_none());
+    }
+
+    /// Mock sidecar over a stdin/stdout pipe pair: answers `sidecar-request`s unless `silent`
+    fn mock_sidecar(requests: Arc<SidecarRequests>, silent: bool) -> std::io::PipeWriter {
+        let (stdin_rx, stdin_tx) = std::io::pipe().unwrap();
+        let (stdout_rx, mut stdout_tx) = std::io::pipe().unwrap();
+        std::thread::spawn(move || {
+            for line in BufReader::new(stdin_rx).lines() {
+                let msg: Value = serde_json::from_str(&line.unwrap()).unwrap();
+                if silent {
+                    continue;
+                }
+                let reply = json!({
+                    "type": "sidecar-response",
+                    "requestId": msg["requestId"],
+                    "result": { "success": true, "data": msg["event"]["type"] }
+                });
+                writeln!(stdout_tx, "{reply}").unwrap();
+            }
+        });
+        std::thread::spawn(move || {
+            for line in BufReader::new(stdout_rx).lines() {
+                let msg: Value = serde_json::from_str(&line.unwrap()).unwrap();
+                assert!(requests.route(&msg));
+            }
+        });
+        stdin_tx
+    }
+
+    #[test]
+    fn sidecar_request_correlates_responses_and_times_out() {
+        let requests = Arc::new(SidecarRequests::default());
+        let stdin = Mutex::new(mock_sidecar(Arc::clone(&requests), false));
+        let send = |msg: &Value| writeln!(stdin.lock().unwrap(), "{msg}").map_err(|e| e.to_string());
+
+        for event_type in ["session.list", "session.history"] {
+            let result = requests
+                .request(send, &json!({ "type": event_type }), std::time::Duration::from_secs(5))
+                .unwrap();
+            assert_eq!(result, json!({ "success": true, "data": event_type }));
+        }
+        assert_eq!(requests.len(), 0);
+
+        let silent = Arc::new(SidecarRequests::default());
+        let stdin = Mutex::new(mock_sidecar(Arc::clone(&silent), true));
+        let send = |msg: &Value| writeln!(stdin.lock().unwrap(), "{msg}").map_err(|e| e.to_string());
+        let err = silent
+            .request(send, &json!({ "type": "session.list" }), std::time::Duration::from_millis(50))
+            .unwrap_err();
+        assert!(err.contains("timed out"), "{err}");
+        assert_eq!(silent.len(), 0);
+
+        // A reply after the timeout is dropped rather than delivered to a stale waiter
+        assert!(!silent.resolve("rust-0", json!({})));
+        let err = silent.request(|_| Err("pipe closed".to_string()), &json!({}), std::time::Duration::from_secs(1));
+        assert_eq!(err.unwrap_err(), "pipe closed");
+        assert_eq!(silent.len(), 0);
+    }
+
+    #[test]
+    fn sidecar_ack_tracking_cleans_up_on_ack_or_timeout() {
+        let requests = Arc::new(SidecarRequests::default());
+        requests.track_ack("sched-1", std::time::Duration::from_secs(5));
+        requests.track_ack("sched-2", std::time::Duration::from_millis(20));
+        assert_eq!(requests.len(), 2);
+
+        assert!(requests.route(&json!({ "type": "sidecar-ack", "requestId": "sched-1" })));
+        assert!(!requests.route(&json!({ "type": "log", "requestId": "sched-2" })));
+        std::thread::sleep(std::time::Duration::from_millis(200));
+        assert_eq!(requests.len(), 0);
+    }
+
+    #[test]
+    fn parse_model_list_splits_trims_and_dedupes() {
+        let text = "gpt-4o, gpt-4o-mini\n\n  llama3:8b  \r\ngpt-4o,,\n";
+        assert_eq!(parse_model_list(text), vec!["gpt-4o", "gpt-4o-mini", "llama3:8b"]);
+        assert!(parse_model_list(" \n , ").is_empty());
+    }
+
+    #[test]
+    fn sidecar_protocol_monitor_reports_once_per_run_of_failures() {
+        let mut monitor = SidecarProtocolMonitor::default();
+        assert!(monitor.record_failure("bad 1").is_none());
+        monitor.record_ok();
+        assert!(monitor.record_failure("bad 2").is_none());
+        assert!(monitor.record_failure("bad 3").is_none());
+        assert_eq!(monitor.record_failure("bad 4").unwrap(), vec!["bad 2", "bad 3", "bad 4"]);
+        // Still broken: no repeat report until a message parses again
+        assert!(monitor.record_failure("bad 5").is_none());
+        monitor.record_ok();
+        monitor.record_failure("bad 6");
+        monitor.record_failure("bad 7");
+        assert_eq!(monitor.record_failure("bad 8").unwrap().len(), SIDECAR_PROTOCOL_ERROR_THRESHOLD);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn is_executable_checks_mode_bits() {
+        use std::os::unix::fs::PermissionsExt;
+        let path = std::env::temp_dir().join(format!("sidecar_exec_{}", uuid::Uuid::new_v4()));
+        fs::write(&path, "#!/bin/sh\n").unwrap();
+        assert!(!is_executable(&path));
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+        assert!(is_executable(&path));
+        assert!(!is_executable(&std::env::temp_dir()));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn voice_usage_tally_flushes_on_final() {
+        let db = Database::new(std::path::Path::new(":memory:")).unwrap();
+        let voice = VoiceState::default();
+        assert_eq!(estimate_audio_seconds(32_000, "audio/wav"), 1.0);
+        assert_eq!(estimate_audio_seconds(32_000, "audio/webm;codecs=opus"), 2.0);
+
+        voice.record_usage("s1", 1.0, 1);
+        voice.record_usage("s1", 2.0, 1);
+        assert_eq!(voice.pending_usage("s1").requests, 2);
+
+        voice.flush_usage(&db, "s1");
+        assert_eq!(voice.pending_usage("s1").requests, 0);
+        let saved = db.get_voice_usage("s1").unwrap();
+        assert_eq!((saved.audio_seconds, saved.requests), (3.0, 2));
+
+        voice.record_usage("s2", 1.0, 1);
+        voice.discard_usage("s2");
+        assert!(voice.usage.lock().unwrap().is_empty());
+    }
 }
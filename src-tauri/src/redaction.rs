@@ -0,0 +1,114 @@
+//! Secret scrubbing for sessions that are about to leave the machine (export/sharing).
+
+use regex::Regex;
+use serde_json::Value;
+
+pub const REDACTED: &str = "[REDACTED]";
+
+/// Settings key holding a JSON array of regex strings that replaces the defaults
+pub const REDACTION_PATTERNS_SETTING: &str = "redaction_patterns";
+
+pub const DEFAULT_REDACTION_PATTERNS: &[&str] = &[
+    // OpenAI / Anthropic / OpenRouter style keys
+    r"sk-[A-Za-z0-9_\-]{16,}",
+    r"(?i)bearer\s+[A-Za-z0-9._\-~+/]+=*",
+    r"gh[pousr]_[A-Za-z0-9]{20,}",
+    r"AKIA[0-9A-Z]{16}",
+    r"xox[abprs]-[A-Za-z0-9\-]{10,}",
+    r"tvly-[A-Za-z0-9]{16,}",
+    // Home directories reveal the local user name
+    r"/Users/[^/\s]+",
+    r"/home/[^/\s]+",
+    r"(?i)[A-Z]:\\Users\\[^\\\s]+",
+];
+
+pub struct Redactor {
+    patterns: Vec<Regex>,
+}
+
+impl Redactor {
+    /// Compile patterns, skipping (and logging) any that are invalid
+    pub fn new<S: AsRef<str>>(patterns: &[S]) -> Self {
+        let patterns = patterns
+            .iter()
+            .filter_map(|p| match Regex::new(p.as_ref()) {
+                Ok(re) => Some(re),
+                Err(e) => {
+                    eprintln!("[redaction] Skipping invalid pattern {:?}: {}", p.as_ref(), e);
+                    None
+                }
+            })
+            .collect();
+        Self { patterns }
+    }
+
+    /// Patterns from the settings override when it is a valid JSON array of strings, else the defaults
+    pub fn from_setting(setting: Option<&str>) -> Self {
+        let custom: Option<Vec<String>> = setting.and_then(|raw| serde_json::from_str(raw).ok());
+        match custom {
+            Some(patterns) => Self::new(&patterns),
+            None => Self::new(DEFAULT_REDACTION_PATTERNS),
+        }
+    }
+
+    /// Redact a string, returning the new text and how many matches were replaced
+    pub fn redact_str(&self, text: &str) -> (String, usize) {
+        let mut out = text.to_string();
+        let mut count = 0;
+        for re in &self.patterns {
+            let matches = re.find_iter(&out).count();
+            if matches > 0 {
+                count += matches;
+                out = re.replace_all(&out, REDACTED).into_owned();
+            }
+        }
+        (out, count)
+    }
+
+    /// Redact every string inside a JSON value in place, returning the number of replacements
+    pub fn redact_value(&self, value: &mut Value) -> usize {
+        match value {
+            Value::String(s) => {
+                let (redacted, count) = self.redact_str(s);
+                if count > 0 {
+                    *s = redacted;
+                }
+                count
+            }
+            Value::Array(items) => items.iter_mut().map(|v| self.redact_value(v)).sum(),
+            Value::Object(map) => map.values_mut().map(|v| self.redact_value(v)).sum(),
+            _ => 0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn default_patterns_redact_keys_tokens_and_home_paths() {
+        let redactor = Redactor::new(DEFAULT_REDACTION_PATTERNS);
+        let mut message = json!({
+            "type": "text",
+            "text": "key sk-abcdefghijklmnop1234 in /Users/alice/project",
+            "input": { "headers": ["Authorization: Bearer eyJhbGciOi.abc"] },
+            "count": 3
+        });
+
+        assert_eq!(redactor.redact_value(&mut message), 3);
+        assert_eq!(message["text"], "key [REDACTED] in [REDACTED]/project");
+        assert_eq!(message["input"]["headers"][0], "Authorization: [REDACTED]");
+        assert_eq!(message["count"], 3);
+    }
+
+    #[test]
+    fn setting_overrides_defaults() {
+        let redactor = Redactor::from_setting(Some(r#"["secret\\d+", "("]"#));
+        assert_eq!(redactor.redact_str("secret42 sk-abcdefghijklmnop1234"), ("[REDACTED] sk-abcdefghijklmnop1234".to_string(), 1));
+
+        let fallback = Redactor::from_setting(Some("not json"));
+        assert_eq!(fallback.redact_str("sk-abcdefghijklmnop1234").1, 1);
+    }
+}
@@ -6,8 +6,13 @@
  */
 
 use boa_engine::{Context, Source};
+use regex::Regex;
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io::BufRead;
 use std::process::Command;
+use std::sync::{mpsc, Mutex, OnceLock};
+use std::time::Duration;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
@@ -18,84 +23,144 @@ pub struct SandboxResult {
     pub error: Option<String>,
     pub logs: Vec<String>,
     pub language: String,
+    /// Position of the error within the user's code, when the engine reports one
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error_detail: Option<SandboxErrorDetail>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SandboxErrorDetail {
+    pub message: String,
+    /// 1-based line within the submitted code (None if the error is outside it)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub line: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub column: Option<usize>,
 }
 
 // ============ JavaScript Sandbox (boa_engine) ============
 
-pub fn execute_javascript(
-    code: &str,
-    cwd: &str,
-    _timeout_ms: u64,
-) -> SandboxResult {
-    let mut logs: Vec<String> = Vec::new();
-    
-    // Create JS context
-    let mut context = Context::default();
-    
-    // Wrap code to capture console.log output
-    let wrapped_code = format!(r#"
+// console.* shim collecting output into the global `__logs` array
+const JS_CONSOLE_PRELUDE: &str = r#"
         var __logs = [];
-        var console = {{
-            log: function() {{
+        var console = {
+            log: function() {
                 var args = Array.prototype.slice.call(arguments);
-                var msg = args.map(function(a) {{
+                var msg = args.map(function(a) {
                     if (a === null) return 'null';
                     if (a === undefined) return 'undefined';
-                    if (typeof a === 'object') {{
-                        try {{ return JSON.stringify(a); }} catch(e) {{ return String(a); }}
-                    }}
+                    if (typeof a === 'object') {
+                        try { return JSON.stringify(a); } catch(e) { return String(a); }
+                    }
                     return String(a);
-                }}).join(' ');
+                }).join(' ');
                 __logs.push(msg);
-            }},
-            error: function() {{
+            },
+            error: function() {
                 var args = Array.prototype.slice.call(arguments);
-                var msg = 'ERROR: ' + args.map(function(a) {{
+                var msg = 'ERROR: ' + args.map(function(a) {
                     if (a === null) return 'null';
                     if (a === undefined) return 'undefined';
-                    if (typeof a === 'object') {{
-                        try {{ return JSON.stringify(a); }} catch(e) {{ return String(a); }}
-                    }}
+                    if (typeof a === 'object') {
+                        try { return JSON.stringify(a); } catch(e) { return String(a); }
+                    }
                     return String(a);
-                }}).join(' ');
+                }).join(' ');
                 __logs.push(msg);
-            }},
-            warn: function() {{
+            },
+            warn: function() {
                 var args = Array.prototype.slice.call(arguments);
-                var msg = 'WARN: ' + args.map(function(a) {{
+                var msg = 'WARN: ' + args.map(function(a) {
                     if (a === null) return 'null';
                     if (a === undefined) return 'undefined';
-                    if (typeof a === 'object') {{
-                        try {{ return JSON.stringify(a); }} catch(e) {{ return String(a); }}
-                    }}
+                    if (typeof a === 'object') {
+                        try { return JSON.stringify(a); } catch(e) { return String(a); }
+                    }
                     return String(a);
-                }}).join(' ');
+                }).join(' ');
                 __logs.push(msg);
-            }},
-            info: function() {{ this.log.apply(this, arguments); }}
-        }};
-        
-        var __dirname = "{}";
-        var __result;
+            },
+            info: function() { this.log.apply(this, arguments); }
+        };
+"#;
+
+fn js_dirname_decl(cwd: &str) -> String {
+    format!(
+        "\n        var __dirname = \"{}\";\n",
+        cwd.replace("\\", "\\\\").replace("\"", "\\\"")
+    )
+}
+
+// Per-run wrapper around the user's code: runs it inside a function (so `return`
+// works) and serializes logs + result + any thrown error back as JSON
+const JS_RUN_PREFIX: &str = r#"
+        __logs = [];
+        var __result = undefined;
+        var __error = undefined;
         
-        try {{
-            __result = (function() {{
-                {}
-            }})();
-        }} catch(e) {{
-            __logs.push('ERROR: ' + e.message);
-        }}
+        try {
+            __result = (function() {
+                "#;
+const JS_RUN_SUFFIX: &str = r#"
+            })();
+        } catch(e) {
+            __error = String(e && e.message !== undefined ? e.message : e);
+        }
         
-        JSON.stringify({{ logs: __logs, result: __result }});
-    "#, 
-        cwd.replace("\\", "\\\\").replace("\"", "\\\""), 
-        code
-    );
-    
-    match context.eval(Source::from_bytes(&wrapped_code)) {
+        JSON.stringify({ logs: __logs, result: __result, error: __error });
+    "#;
+
+struct JsScript {
+    source: String,
+    /// 1-based line/column where the user's code starts within `source`
+    code_line: usize,
+    code_column: usize,
+    code_lines: usize,
+}
+
+fn build_js_script(header: &str, code: &str) -> JsScript {
+    let prefix = format!("{}{}", header, JS_RUN_PREFIX);
+    let last_line_start = prefix.rfind('\n').map(|i| i + 1).unwrap_or(0);
+    JsScript {
+        source: format!("{}{}{}", prefix, code, JS_RUN_SUFFIX),
+        code_line: prefix.matches('\n').count() + 1,
+        code_column: prefix.len() - last_line_start + 1,
+        code_lines: code.lines().count().max(1),
+    }
+}
+
+/// Pull "line N, col M" out of a boa error message and map it back onto the user's code
+fn js_error_detail(message: &str, script: &JsScript) -> SandboxErrorDetail {
+    static POSITION: OnceLock<Regex> = OnceLock::new();
+    let position = POSITION
+        .get_or_init(|| Regex::new(r"line:? (\d+), col(?:umn)?:? (\d+)").unwrap())
+        .captures(message)
+        .and_then(|caps| Some((caps[1].parse::<usize>().ok()?, caps[2].parse::<usize>().ok()?)));
+
+    let (line, column) = match position {
+        Some((line, column)) if line >= script.code_line && line < script.code_line + script.code_lines => {
+            let user_line = line - script.code_line + 1;
+            let user_column = if user_line == 1 {
+                column.saturating_sub(script.code_column - 1).max(1)
+            } else {
+                column
+            };
+            (Some(user_line), Some(user_column))
+        }
+        _ => (None, None),
+    };
+
+    SandboxErrorDetail { message: message.to_string(), line, column }
+}
+
+fn eval_wrapped_js(context: &mut Context, script: &JsScript) -> SandboxResult {
+    let mut logs: Vec<String> = Vec::new();
+
+    match context.eval(Source::from_bytes(&script.source)) {
         Ok(result) => {
             // Convert JsValue to string
-            let result_str = result.to_string(&mut context)
+            let result_str = result.to_string(context)
                 .map(|s| s.to_std_string_escaped())
                 .unwrap_or_default();
             
@@ -124,6 +189,18 @@ pub fn execute_javascript(
                         String::new()
                     };
                     
+                    // The wrapper caught a throw from the user's code
+                    if let Some(message) = obj.get("error").and_then(|v| v.as_str()) {
+                        return SandboxResult {
+                            success: false,
+                            output: logs.join("\n"),
+                            error_detail: Some(js_error_detail(message, script)),
+                            error: Some(message.to_string()),
+                            logs,
+                            language: "javascript".to_string(),
+                        };
+                    }
+
                     let output = if !logs.is_empty() {
                         if output_result.is_empty() {
                             logs.join("\n")
@@ -140,6 +217,7 @@ pub fn execute_javascript(
                         error: None,
                         logs,
                         language: "javascript".to_string(),
+                        error_detail: None,
                     };
                 }
             }
@@ -151,13 +229,16 @@ pub fn execute_javascript(
                 error: None,
                 logs,
                 language: "javascript".to_string(),
+                error_detail: None,
             }
         }
         Err(e) => {
+            let message = e.to_string();
             SandboxResult {
                 success: false,
                 output: String::new(),
-                error: Some(e.to_string()),
+                error_detail: Some(js_error_detail(&message, script)),
+                error: Some(message),
                 logs,
                 language: "javascript".to_string(),
             }
@@ -165,12 +246,317 @@ pub fn execute_javascript(
     }
 }
 
+/// boa can't be interrupted from outside, so a timed-out JS session run is abandoned rather than
+/// stopped. These caps make boa throw on runaway loops, deep recursion and stack growth,
+/// letting the abandoned thread exit instead of spinning or overflowing the native stack.
+const JS_LOOP_ITERATION_LIMIT: u64 = 100_000_000;
+const JS_RECURSION_LIMIT: usize = 2_000;
+const JS_STACK_SIZE_LIMIT: usize = 512 * 1024;
+
+fn new_js_context() -> Context {
+    let mut context = Context::default();
+    let limits = context.runtime_limits_mut();
+    limits.set_loop_iteration_limit(JS_LOOP_ITERATION_LIMIT);
+    limits.set_recursion_limit(JS_RECURSION_LIMIT);
+    limits.set_stack_size_limit(JS_STACK_SIZE_LIMIT);
+    context
+}
+
+fn js_failure(error: String) -> SandboxResult {
+    SandboxResult {
+        success: false,
+        output: String::new(),
+        error: Some(error),
+        logs: vec![],
+        language: "javascript".to_string(),
+        error_detail: None,
+    }
+}
+
+/// When set, the app binary runs as a one-shot JS worker instead of starting the UI
+pub const JS_WORKER_ENV: &str = "VALEDESK_JS_WORKER";
+
+/// Marks the worker's result, so unrelated output on its stdout is ignored
+const JS_WORKER_RESULT_PREFIX: &str = "__valedesk_js_result__:";
+
+#[derive(Serialize, Deserialize)]
+struct JsWorkerRequest {
+    code: String,
+    cwd: String,
+}
+
+fn js_script_for(code: &str, cwd: &str) -> JsScript {
+    build_js_script(&format!("{}{}", JS_CONSOLE_PRELUDE, js_dirname_decl(cwd)), code)
+}
+
+/// Worker process entry point: evaluate the request read from stdin in a fresh context and
+/// print the `SandboxResult` as JSON on a line of its own.
+pub fn run_js_worker() {
+    let mut input = String::new();
+    let request = std::io::Read::read_to_string(&mut std::io::stdin(), &mut input)
+        .map_err(|e| e.to_string())
+        .and_then(|_| serde_json::from_str::<JsWorkerRequest>(&input).map_err(|e| e.to_string()));
+    let result = match request {
+        Ok(request) => eval_wrapped_js(&mut new_js_context(), &js_script_for(&request.code, &request.cwd)),
+        Err(e) => js_failure(format!("[sandbox] failed to read JS worker request: {}", e)),
+    };
+    println!("{}{}", JS_WORKER_RESULT_PREFIX, serde_json::to_string(&result).unwrap_or_default());
+}
+
+fn js_worker_command() -> std::io::Result<Command> {
+    let mut command = Command::new(std::env::current_exe()?);
+    // Under `cargo test` the current exe is the test harness: run only the worker test
+    #[cfg(test)]
+    command.args(["sandbox::tests::js_worker_entry", "--exact", "--nocapture", "--test-threads=1"]);
+    command.env(JS_WORKER_ENV, "1");
+    Ok(command)
+}
+
+/// Evaluate `code` in a worker process (this binary, see `run_js_worker`), which is killed
+/// once `timeout_ms` passes. boa can't be interrupted in-process, so this is what actually
+/// stops a runaway script instead of leaving it spinning on a thread.
+pub fn execute_javascript(
+    code: &str,
+    cwd: &str,
+    timeout_ms: u64,
+) -> SandboxResult {
+    let request = serde_json::to_vec(&JsWorkerRequest { code: code.to_string(), cwd: cwd.to_string() })
+        .unwrap_or_default();
+
+    let mut command = match js_worker_command() {
+        Ok(command) => command,
+        Err(e) => return js_failure(format!("[sandbox] failed to locate JS worker: {}", e)),
+    };
+    command
+        .stdin(std::process::Stdio::piped())
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped());
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::CommandExt;
+        command.process_group(0);
+    }
+    let mut child = match command.spawn() {
+        Ok(child) => child,
+        Err(e) => return js_failure(format!("[sandbox] failed to start JS worker: {}", e)),
+    };
+    // Feed the request from its own thread so a large script can't block before the deadline starts
+    if let Some(mut stdin) = child.stdin.take() {
+        std::thread::spawn(move || {
+            let _ = std::io::Write::write_all(&mut stdin, &request);
+        });
+    }
+
+    let (status, stdout, stderr) = match wait_with_deadline(child, timeout_ms, None) {
+        Ok(result) => result,
+        Err(e) => return js_failure(format!("[sandbox] failed to wait for JS worker: {}", e)),
+    };
+    if status.is_none() {
+        return js_failure(format!("execution timed out after {}ms", timeout_ms));
+    }
+    stdout
+        .lines()
+        .find_map(|line| line.split_once(JS_WORKER_RESULT_PREFIX).map(|(_, json)| json))
+        .and_then(|json| serde_json::from_str(json).ok())
+        .unwrap_or_else(|| js_failure(format!("[sandbox] JS worker exited without a result: {}", stderr.trim())))
+}
+
+// ============ JavaScript Self-Test ============
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SelfTestCheck {
+    pub name: String,
+    pub passed: bool,
+    /// What was expected vs. what came back, for failed checks
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SandboxSelfTest {
+    pub passed: bool,
+    pub checks: Vec<SelfTestCheck>,
+    pub duration_ms: u64,
+}
+
+const SELFTEST_SCRIPT: &str = r#"console.log('selftest');
+var data = JSON.parse('{"items":[1,2,3]}');
+console.log(JSON.stringify(data));
+return data.items.reduce(function(a, b) { return a + b; }, 0) * 7;"#;
+
+const SELFTEST_THROW_SCRIPT: &str = "throw new Error('selftest boom');";
+
+fn selftest_check(name: &str, passed: bool, detail: impl FnOnce() -> String) -> SelfTestCheck {
+    SelfTestCheck {
+        name: name.to_string(),
+        passed,
+        detail: (!passed).then(detail),
+    }
+}
+
+/// Run fixed scripts through `execute_javascript` and compare against known results.
+/// Used to confirm the built-in engine works after an update.
+pub fn run_js_selftest() -> SandboxSelfTest {
+    let started = std::time::Instant::now();
+    let ok = execute_javascript(SELFTEST_SCRIPT, "/tmp", 5000);
+    let thrown = execute_javascript(SELFTEST_THROW_SCRIPT, "/tmp", 5000);
+
+    let expected_logs = vec!["selftest".to_string(), r#"{"items":[1,2,3]}"#.to_string()];
+    let checks = vec![
+        selftest_check("execute", ok.success, || format!("expected success, got error {:?}", ok.error)),
+        selftest_check("console.log", ok.logs.first() == expected_logs.first(), || {
+            format!("expected first log {:?}, got {:?}", expected_logs[0], ok.logs)
+        }),
+        selftest_check("json", ok.logs == expected_logs, || {
+            format!("expected logs {:?}, got {:?}", expected_logs, ok.logs)
+        }),
+        selftest_check("arithmetic", ok.output.ends_with("Return value: 42"), || {
+            format!("expected return value 42, got {:?}", ok.output)
+        }),
+        selftest_check(
+            "thrown error",
+            !thrown.success && thrown.error.as_deref() == Some("selftest boom"),
+            || format!("expected error \"selftest boom\", got success={} error={:?}", thrown.success, thrown.error),
+        ),
+    ];
+
+    SandboxSelfTest {
+        passed: checks.iter().all(|c| c.passed),
+        checks,
+        duration_ms: started.elapsed().as_millis() as u64,
+    }
+}
+
+// ============ Persistent JavaScript Sessions ============
+//
+// A JS session keeps one boa `Context` alive across executions so the console
+// prelude is parsed once and globals persist between runs. Isolation caveats:
+// - state set via `globalThis.x = ...` (or undeclared assignment) is visible to
+//   every later run in the same session; `var`/`let` inside a snippet stay local
+//   because each snippet runs inside a function wrapper
+// - prototypes/builtins patched by one run stay patched for the next
+// - `__logs` is reset before every run, so output is per-execution
+// Use `execute_javascript` when runs must not observe each other.
+//
+// boa's `Context` is not `Send`, so each session owns a dedicated thread and
+// receives jobs over a channel.
+
+struct JsJob {
+    code: String,
+    reply: mpsc::Sender<SandboxResult>,
+}
+
+struct JsSession {
+    jobs: mpsc::Sender<JsJob>,
+}
+
+static JS_SESSIONS: OnceLock<Mutex<HashMap<String, JsSession>>> = OnceLock::new();
+
+fn js_sessions() -> &'static Mutex<HashMap<String, JsSession>> {
+    JS_SESSIONS.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+/// Create a persistent JS context under `id`
+pub fn create_js_session(id: &str, cwd: &str) -> Result<(), String> {
+    let mut sessions = js_sessions().lock().map_err(|_| "[sandbox] sessions lock poisoned".to_string())?;
+    if sessions.contains_key(id) {
+        return Err(format!("[sandbox] JS session '{}' already exists", id));
+    }
+
+    let (jobs_tx, jobs_rx) = mpsc::channel::<JsJob>();
+    let prelude = format!("{}{}", JS_CONSOLE_PRELUDE, js_dirname_decl(cwd));
+    let session_id = id.to_string();
+    std::thread::spawn(move || {
+        let mut context = new_js_context();
+        if let Err(e) = context.eval(Source::from_bytes(&prelude)) {
+            eprintln!("[sandbox] JS session {} prelude failed: {}", session_id, e);
+        }
+        // Loop ends once the session is closed (sender dropped)
+        for job in jobs_rx {
+            let result = eval_wrapped_js(&mut context, &build_js_script("", &job.code));
+            let _ = job.reply.send(result);
+        }
+    });
+
+    sessions.insert(id.to_string(), JsSession { jobs: jobs_tx });
+    Ok(())
+}
+
+/// Run code in an existing JS session. A run that exceeds `timeout_ms` discards
+/// the session, since its context thread is still busy with the runaway code.
+pub fn execute_in_js_session(id: &str, code: &str, timeout_ms: u64) -> SandboxResult {
+    let failure = js_failure;
+
+    let jobs = match js_sessions().lock() {
+        Ok(sessions) => match sessions.get(id) {
+            Some(session) => session.jobs.clone(),
+            None => return failure(format!("JS session '{}' not found", id)),
+        },
+        Err(_) => return failure("[sandbox] sessions lock poisoned".to_string()),
+    };
+
+    let (reply_tx, reply_rx) = mpsc::channel();
+    if jobs.send(JsJob { code: code.to_string(), reply: reply_tx }).is_err() {
+        close_js_session(id);
+        return failure(format!("JS session '{}' is no longer running", id));
+    }
+
+    match reply_rx.recv_timeout(Duration::from_millis(timeout_ms)) {
+        Ok(result) => result,
+        Err(_) => {
+            close_js_session(id);
+            failure(format!("execution timed out after {}ms; JS session '{}' was closed", timeout_ms, id))
+        }
+    }
+}
+
+/// Drop a JS session; returns false if it didn't exist
+pub fn close_js_session(id: &str) -> bool {
+    match js_sessions().lock() {
+        Ok(mut sessions) => sessions.remove(id).is_some(),
+        Err(_) => false,
+    }
+}
+
 // ============ Python Sandbox (subprocess) ============
 
+/// Which pipe a streamed output line came from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputStream {
+    Stdout,
+    Stderr,
+}
+
+/// Receives output lines (without the newline) while a sandboxed process runs
+pub type OutputSink = std::sync::Arc<dyn Fn(OutputStream, &str) + Send + Sync>;
+
 pub fn execute_python(
     code: &str,
     cwd: &str,
-    _timeout_ms: u64,
+    timeout_ms: u64,
+) -> SandboxResult {
+    run_python(code, cwd, timeout_ms, None)
+}
+
+/// Like `execute_python`, but every stdout/stderr line also goes to `on_line` as it is printed.
+/// The returned result still carries the aggregated output.
+pub fn execute_python_streaming(
+    code: &str,
+    cwd: &str,
+    timeout_ms: u64,
+    on_line: OutputSink,
+) -> SandboxResult {
+    run_python(code, cwd, timeout_ms, Some(&on_line))
+}
+
+fn run_python(
+    code: &str,
+    cwd: &str,
+    timeout_ms: u64,
+    on_line: Option<&OutputSink>,
 ) -> SandboxResult {
     // Find Python executable
     let python_cmd = find_python();
@@ -182,6 +568,7 @@ pub fn execute_python(
             error: Some("Python not found. Install Python 3: https://www.python.org/downloads/".to_string()),
             logs: vec![],
             language: "python".to_string(),
+            error_detail: None,
         };
     }
     
@@ -198,44 +585,66 @@ pub fn execute_python(
             error: Some(format!("Failed to create temp file: {}", e)),
             logs: vec![],
             language: "python".to_string(),
+            error_detail: None,
         };
     }
     
     // Execute Python
-    let result = Command::new(&python)
+    let mut command = Command::new(&python);
+    command
         .arg(&temp_file)
         .current_dir(cwd)
-        .output();
+        .stdin(std::process::Stdio::null())
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped());
+    if on_line.is_some() {
+        // Piped stdout is block-buffered by default, which would hold lines back until exit
+        command.env("PYTHONUNBUFFERED", "1");
+    }
+    // Own process group, so a timeout also kills subprocesses the script started
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::CommandExt;
+        command.process_group(0);
+    }
+    let result = command.spawn().and_then(|child| wait_with_deadline(child, timeout_ms, on_line));
     
     // Clean up temp file
     let _ = std::fs::remove_file(&temp_file);
     
     match result {
-        Ok(output) => {
-            let stdout = String::from_utf8_lossy(&output.stdout).to_string();
-            let stderr = String::from_utf8_lossy(&output.stderr).to_string();
-            
+        Ok((None, stdout, _)) => SandboxResult {
+            success: false,
+            logs: stdout.lines().map(|s| s.to_string()).collect(),
+            output: stdout,
+            error: Some("python execution timed out".to_string()),
+            language: "python".to_string(),
+            error_detail: None,
+        },
+        Ok((Some(status), stdout, stderr)) => {
             let logs: Vec<String> = stdout.lines().map(|s| s.to_string()).collect();
             
-            if output.status.success() {
+            if status.success() {
                 SandboxResult {
                     success: true,
                     output: stdout.trim().to_string(),
                     error: if stderr.is_empty() { None } else { Some(stderr) },
                     logs,
                     language: "python".to_string(),
+                    error_detail: None,
                 }
             } else {
                 SandboxResult {
                     success: false,
                     output: stdout,
                     error: Some(if stderr.is_empty() { 
-                        format!("Python exited with code {}", output.status.code().unwrap_or(-1))
+                        format!("Python exited with code {}", status.code().unwrap_or(-1))
                     } else { 
                         stderr 
                     }),
                     logs,
                     language: "python".to_string(),
+                    error_detail: None,
                 }
             }
         }
@@ -246,6 +655,7 @@ pub fn execute_python(
                 error: Some(format!("Failed to execute Python: {}", e)),
                 logs: vec![],
                 language: "python".to_string(),
+                error_detail: None,
             }
         }
     }
@@ -253,16 +663,24 @@ pub fn execute_python(
 
 // Find Python 3 executable
 fn find_python() -> Option<String> {
+    find_python_with_version().map(|(cmd, _)| cmd)
+}
+
+// Python 3 executable plus its reported version (e.g. "Python 3.12.1")
+fn find_python_with_version() -> Option<(String, String)> {
     // Try common Python executables
     let candidates = ["python3", "python", "/usr/bin/python3", "/usr/local/bin/python3"];
     
     for cmd in candidates {
         if let Ok(output) = Command::new(cmd).arg("--version").output() {
             if output.status.success() {
-                let version = String::from_utf8_lossy(&output.stdout);
+                // Older Pythons print the version to stderr
+                let stdout = String::from_utf8_lossy(&output.stdout);
+                let stderr = String::from_utf8_lossy(&output.stderr);
+                let version = if stdout.contains("Python 3") { stdout } else { stderr };
                 // Make sure it's Python 3
-                if version.contains("Python 3") || String::from_utf8_lossy(&output.stderr).contains("Python 3") {
-                    return Some(cmd.to_string());
+                if version.contains("Python 3") {
+                    return Some((cmd.to_string(), version.trim().to_string()));
                 }
             }
         }
@@ -271,6 +689,42 @@ fn find_python() -> Option<String> {
     None
 }
 
+// ============ Language Availability ============
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SandboxLanguage {
+    pub id: String,
+    pub name: String,
+    pub available: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub install_hint: Option<String>,
+}
+
+/// Languages `execute_code` accepts, with whether they can run on this machine right now
+pub fn supported_languages() -> Vec<SandboxLanguage> {
+    let python = find_python_with_version();
+    vec![
+        SandboxLanguage {
+            id: "javascript".to_string(),
+            name: "JavaScript".to_string(),
+            available: true,
+            version: Some("boa_engine (built-in)".to_string()),
+            install_hint: None,
+        },
+        SandboxLanguage {
+            id: "python".to_string(),
+            name: "Python".to_string(),
+            available: python.is_some(),
+            install_hint: python.is_none()
+                .then(|| "Install Python 3: https://www.python.org/downloads/".to_string()),
+            version: python.map(|(_, version)| version),
+        },
+    ]
+}
+
 // ============ Unified Execute Function ============
 
 pub fn execute_code(
@@ -288,14 +742,266 @@ pub fn execute_code(
             error: Some(format!("Unsupported language: '{}'. Supported: javascript, python", language)),
             logs: vec![],
             language: language.to_string(),
+            error_detail: None,
+        }
+    }
+}
+
+/// `execute_code` with output lines forwarded to `on_line` while the code runs. JavaScript runs
+/// in-process and only yields its console output at the end, so its logs are forwarded then.
+pub fn execute_code_streaming(
+    code: &str,
+    language: &str,
+    cwd: &str,
+    timeout_ms: u64,
+    on_line: OutputSink,
+) -> SandboxResult {
+    match language.to_lowercase().as_str() {
+        "python" | "py" => execute_python_streaming(code, cwd, timeout_ms, on_line),
+        _ => {
+            let result = execute_code(code, language, cwd, timeout_ms);
+            for line in &result.logs {
+                on_line(OutputStream::Stdout, line);
+            }
+            result
         }
     }
 }
 
+// ============ Session Replay ============
+
+/// Sandbox code a session ran, pulled from one of its stored `tool_use` messages
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReplayStep {
+    pub message_index: usize,
+    pub language: &'static str,
+    pub code: String,
+    pub timeout_ms: u64,
+}
+
+/// `execute_js`/`execute_python` calls in message order, with the timeouts the tools would have
+/// used (their defaults, capped at their maximums)
+pub fn replay_steps(messages: &[serde_json::Value]) -> Vec<ReplayStep> {
+    messages
+        .iter()
+        .enumerate()
+        .filter(|(_, m)| m.get("type").and_then(|v| v.as_str()) == Some("tool_use"))
+        .filter_map(|(message_index, m)| {
+            let (language, default_ms, max_ms) = match m.get("name").and_then(|v| v.as_str())? {
+                "execute_js" => ("javascript", 5_000, 30_000),
+                "execute_python" => ("python", 30_000, 60_000),
+                _ => return None,
+            };
+            let input = m.get("input")?;
+            let code = input.get("code").and_then(|v| v.as_str())?.to_string();
+            let timeout_ms = input
+                .get("timeout")
+                .and_then(|v| v.as_u64())
+                .filter(|t| *t > 0)
+                .unwrap_or(default_ms)
+                .min(max_ms);
+            Some(ReplayStep { message_index, language, code, timeout_ms })
+        })
+        .collect()
+}
+
+// ============ Allowlisted Native Commands ============
+
+/// Setting key: JSON object mapping a tool name to the absolute path of its binary
+pub const NATIVE_TOOLS_SETTING: &str = "native_tools_allowlist";
+pub const NATIVE_DEFAULT_TIMEOUT_MS: u64 = 30_000;
+pub const NATIVE_MAX_TIMEOUT_MS: u64 = 120_000;
+
+/// Parse the allowlist setting. Entries whose binary isn't an absolute path are dropped.
+pub fn parse_native_allowlist(setting: Option<&str>) -> HashMap<String, String> {
+    let parsed: HashMap<String, String> = setting
+        .and_then(|s| serde_json::from_str(s).ok())
+        .unwrap_or_default();
+    parsed
+        .into_iter()
+        .filter(|(name, binary)| !name.trim().is_empty() && std::path::Path::new(binary).is_absolute())
+        .collect()
+}
+
+fn native_failure(error: String) -> SandboxResult {
+    SandboxResult {
+        success: false,
+        output: String::new(),
+        error: Some(error),
+        logs: vec![],
+        language: "native".to_string(),
+        error_detail: None,
+    }
+}
+
+/// Run allowlisted command `name` with `args` (no shell involved) in `cwd`, killing it after
+/// `timeout_ms`. Anything not in `allowlist` is refused before a process is spawned.
+pub fn run_native_command(
+    allowlist: &HashMap<String, String>,
+    name: &str,
+    args: &[String],
+    cwd: &str,
+    timeout_ms: u64,
+) -> SandboxResult {
+    let Some(binary) = allowlist.get(name) else {
+        return native_failure(format!("'{}' is not in the native tools allowlist", name));
+    };
+    if !std::path::Path::new(binary).is_file() {
+        return native_failure(format!("Allowlisted binary for '{}' not found: {}", name, binary));
+    }
+    let cwd = match std::fs::canonicalize(cwd) {
+        Ok(path) if path.is_dir() => path,
+        _ => return native_failure(format!("Working directory does not exist: {}", cwd)),
+    };
+
+    let mut command = Command::new(binary);
+    command
+        .args(args)
+        .current_dir(&cwd)
+        .stdin(std::process::Stdio::null())
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped());
+    // Own process group, so a timeout also kills whatever the command spawned
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::CommandExt;
+        command.process_group(0);
+    }
+    let child = match command.spawn() {
+        Ok(child) => child,
+        Err(e) => return native_failure(format!("Failed to start {}: {}", binary, e)),
+    };
+    let (status, stdout, stderr) = match wait_with_deadline(child, timeout_ms, None) {
+        Ok(result) => result,
+        Err(e) => return native_failure(format!("Failed to wait for {}: {}", name, e)),
+    };
+    let logs: Vec<String> = stdout.lines().map(|s| s.to_string()).collect();
+
+    match status {
+        None => SandboxResult {
+            error: Some(format!("execution timed out after {}ms", timeout_ms)),
+            output: stdout,
+            logs,
+            ..native_failure(String::new())
+        },
+        Some(status) if status.success() => SandboxResult {
+            success: true,
+            output: stdout.trim().to_string(),
+            error: if stderr.is_empty() { None } else { Some(stderr) },
+            logs,
+            language: "native".to_string(),
+            error_detail: None,
+        },
+        Some(status) => SandboxResult {
+            error: Some(if stderr.is_empty() {
+                format!("{} exited with code {}", name, status.code().unwrap_or(-1))
+            } else {
+                stderr
+            }),
+            output: stdout,
+            logs,
+            ..native_failure(String::new())
+        },
+    }
+}
+
+/// Wait for `child` until `timeout_ms`, collecting its output. On timeout the child is killed and
+/// the status is None. Once the child is done its process group (on Unix, when it leads one) is
+/// killed too, and output is collected until the same deadline at most, so a leftover process
+/// holding the pipes open can't stall the caller.
+/// With `on_line`, each output line is also forwarded as soon as it is read.
+fn wait_with_deadline(
+    mut child: std::process::Child,
+    timeout_ms: u64,
+    on_line: Option<&OutputSink>,
+) -> std::io::Result<(Option<std::process::ExitStatus>, String, String)> {
+    // Drain both pipes on their own threads so a chatty process can't block on a full pipe.
+    // Lines land in a shared buffer, so output read so far survives an abandoned reader.
+    let drain = |pipe: Option<Box<dyn std::io::Read + Send>>, stream: OutputStream| {
+        let on_line = on_line.cloned();
+        let collected = std::sync::Arc::new(Mutex::new(Vec::new()));
+        let sink = collected.clone();
+        let handle = std::thread::spawn(move || {
+            let Some(pipe) = pipe else { return };
+            let mut reader = std::io::BufReader::new(pipe);
+            let mut line = Vec::new();
+            loop {
+                line.clear();
+                match reader.read_until(b'\n', &mut line) {
+                    Ok(0) | Err(_) => break,
+                    Ok(_) => {
+                        if let Some(on_line) = &on_line {
+                            let text = String::from_utf8_lossy(&line);
+                            on_line(stream, text.trim_end_matches(['\n', '\r']));
+                        }
+                        if let Ok(mut buf) = sink.lock() {
+                            buf.extend_from_slice(&line);
+                        }
+                    }
+                }
+            }
+        });
+        (handle, collected)
+    };
+    let stdout = drain(child.stdout.take().map(|p| Box::new(p) as Box<dyn std::io::Read + Send>), OutputStream::Stdout);
+    let stderr = drain(child.stderr.take().map(|p| Box::new(p) as Box<dyn std::io::Read + Send>), OutputStream::Stderr);
+
+    let deadline = std::time::Instant::now() + Duration::from_millis(timeout_ms);
+    let status = loop {
+        match child.try_wait()? {
+            Some(status) => break Some(status),
+            None if std::time::Instant::now() >= deadline => {
+                kill_process_group(&child);
+                let _ = child.kill();
+                let _ = child.wait();
+                break None;
+            }
+            None => std::thread::sleep(Duration::from_millis(20)),
+        }
+    };
+    // Pipes close once every process holding them is gone: take down what the child left behind
+    kill_process_group(&child);
+
+    // Readers get until the deadline (plus a moment to flush after a kill); a process outside the
+    // group may still hold the pipes, in which case its reader is abandoned
+    let join_deadline = deadline.max(std::time::Instant::now()) + Duration::from_millis(100);
+    let collect = |(handle, collected): (std::thread::JoinHandle<()>, std::sync::Arc<Mutex<Vec<u8>>>)| {
+        while !handle.is_finished() && std::time::Instant::now() < join_deadline {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        if handle.is_finished() {
+            let _ = handle.join();
+        }
+        let buf = collected.lock().map(|b| b.clone()).unwrap_or_default();
+        String::from_utf8_lossy(&buf).to_string()
+    };
+    Ok((status, collect(stdout), collect(stderr)))
+}
+
+#[cfg(unix)]
+fn kill_process_group(child: &std::process::Child) {
+    // A child spawned with `process_group(0)` leads a group whose id is its pid; for any other
+    // (still unreaped) child no such group exists and this is a no-op
+    unsafe {
+        libc::kill(-(child.id() as libc::pid_t), libc::SIGKILL);
+    }
+}
+
+#[cfg(not(unix))]
+fn kill_process_group(_child: &std::process::Child) {}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    /// Stands in for the app binary's worker mode when `execute_javascript` runs under test
+    #[test]
+    fn js_worker_entry() {
+        if std::env::var_os(JS_WORKER_ENV).is_some() {
+            run_js_worker();
+        }
+    }
+
     #[test]
     fn test_javascript_console_log() {
         let result = execute_javascript(
@@ -335,4 +1041,181 @@ mod tests {
             assert!(err.contains("Python not found") || err.contains("Failed"));
         }
     }
+
+    #[test]
+    fn test_python_sleep_is_killed_at_deadline() {
+        if find_python().is_none() {
+            return;
+        }
+        let started = std::time::Instant::now();
+        // The child subprocess shares the group and must not keep the pipes open
+        let result = execute_python(
+            "import subprocess, sys, time\nsubprocess.Popen([sys.executable, '-c', 'import time; time.sleep(30)'])\ntime.sleep(30)",
+            "/tmp",
+            500,
+        );
+        assert!(started.elapsed() < Duration::from_secs(5), "took {:?}", started.elapsed());
+        assert!(!result.success);
+        assert_eq!(result.error.as_deref(), Some("python execution timed out"));
+    }
+
+    #[test]
+    fn test_python_streaming_forwards_lines_as_printed() {
+        if find_python().is_none() {
+            return;
+        }
+        let lines = std::sync::Arc::new(Mutex::new(Vec::new()));
+        let sink_lines = lines.clone();
+        let sink: OutputSink = std::sync::Arc::new(move |stream, line: &str| {
+            sink_lines.lock().unwrap().push((stream, line.to_string(), std::time::Instant::now()));
+        });
+        let result = execute_python_streaming(
+            "import sys, time\nprint('step 1')\ntime.sleep(0.4)\nprint('oops', file=sys.stderr)\nprint('step 2')",
+            "/tmp",
+            5000,
+            sink,
+        );
+        assert!(result.success, "Error: {:?}", result.error);
+        assert_eq!(result.output, "step 1\nstep 2");
+
+        let lines = lines.lock().unwrap();
+        let stdout: Vec<&str> = lines.iter().filter(|(s, _, _)| *s == OutputStream::Stdout).map(|(_, l, _)| l.as_str()).collect();
+        assert_eq!(stdout, vec!["step 1", "step 2"]);
+        assert!(lines.iter().any(|(s, l, _)| *s == OutputStream::Stderr && l == "oops"));
+        // The first line arrived while the script was still sleeping
+        let first = lines.iter().find(|(_, l, _)| l == "step 1").unwrap().2;
+        let last = lines.iter().find(|(_, l, _)| l == "step 2").unwrap().2;
+        assert!(last.duration_since(first) >= Duration::from_millis(300));
+    }
+
+    #[test]
+    fn test_js_session_keeps_globals_and_resets_logs() {
+        let id = "test-session-globals";
+        create_js_session(id, "/tmp").unwrap();
+
+        let first = execute_in_js_session(id, "globalThis.counter = 41; console.log('first');", 5000);
+        assert!(first.success, "Error: {:?}", first.error);
+        assert_eq!(first.logs, vec!["first".to_string()]);
+
+        let second = execute_in_js_session(id, "console.log('second'); return counter + 1;", 5000);
+        assert!(second.success, "Error: {:?}", second.error);
+        assert_eq!(second.logs, vec!["second".to_string()]);
+        assert!(second.output.contains("42"));
+
+        assert!(close_js_session(id));
+        assert!(!execute_in_js_session(id, "return 1;", 5000).success);
+    }
+
+    #[test]
+    fn test_javascript_syntax_error_reports_user_line() {
+        let result = execute_javascript("var a = 1;\nvar b = ;\nreturn a;", "/tmp", 5000);
+        assert!(!result.success);
+        let detail = result.error_detail.expect("structured error");
+        assert_eq!(detail.line, Some(2), "message: {}", detail.message);
+    }
+
+    #[test]
+    fn test_native_allowlist_drops_relative_binaries() {
+        let allowlist = parse_native_allowlist(Some(r#"{"lint": "/usr/bin/true", "rel": "bin/lint", "": "/bin/sh"}"#));
+        assert_eq!(allowlist.len(), 1);
+        assert_eq!(allowlist.get("lint").map(String::as_str), Some("/usr/bin/true"));
+        assert!(parse_native_allowlist(Some("not json")).is_empty());
+        assert!(parse_native_allowlist(None).is_empty());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_native_command_runs_only_allowlisted() {
+        let allowlist = HashMap::from([
+            ("echo".to_string(), "/bin/echo".to_string()),
+            ("sleep".to_string(), "/bin/sleep".to_string()),
+        ]);
+
+        let result = run_native_command(&allowlist, "echo", &["hello; rm -rf /".to_string()], "/tmp", 5_000);
+        assert!(result.success);
+        assert_eq!(result.output, "hello; rm -rf /");
+
+        let refused = run_native_command(&allowlist, "sh", &["-c".to_string(), "true".to_string()], "/tmp", 5_000);
+        assert!(!refused.success);
+        assert!(refused.error.unwrap().contains("not in the native tools allowlist"));
+
+        let bad_cwd = run_native_command(&allowlist, "echo", &[], "/definitely/not/here", 5_000);
+        assert!(bad_cwd.error.unwrap().contains("does not exist"));
+
+        let started = std::time::Instant::now();
+        let slow = run_native_command(&allowlist, "sleep", &["5".to_string()], "/tmp", 200);
+        assert!(started.elapsed() < Duration::from_secs(2));
+        assert_eq!(slow.error.as_deref(), Some("execution timed out after 200ms"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_native_command_does_not_wait_for_leftover_processes() {
+        let allowlist = HashMap::from([("sh".to_string(), "/bin/sh".to_string())]);
+        let run = |script: &str| {
+            let started = std::time::Instant::now();
+            let result = run_native_command(&allowlist, "sh", &["-c".to_string(), script.to_string()], "/tmp", 1_000);
+            (result, started.elapsed())
+        };
+
+        // Background job in the same group: killed with the group once sh exits
+        let (result, elapsed) = run("sleep 30 & echo done");
+        assert!(result.success, "Error: {:?}", result.error);
+        assert_eq!(result.output, "done");
+        assert!(elapsed < Duration::from_secs(1), "took {:?}", elapsed);
+
+        // Escaped into its own session: the pipe stays open, but the deadline still holds
+        if std::path::Path::new("/usr/bin/setsid").exists() {
+            let (result, elapsed) = run("/usr/bin/setsid sleep 3 & echo done");
+            assert_eq!(result.output, "done");
+            assert!(elapsed < Duration::from_secs(2), "took {:?}", elapsed);
+        }
+    }
+
+    #[test]
+    fn test_replay_steps_extracts_sandbox_tool_calls() {
+        let messages = vec![
+            serde_json::json!({ "type": "user_prompt", "prompt": "compute" }),
+            serde_json::json!({ "type": "tool_use", "name": "execute_js", "input": { "code": "return 1", "explanation": "x" } }),
+            serde_json::json!({ "type": "tool_use", "name": "read_file", "input": { "path": "a.txt" } }),
+            serde_json::json!({ "type": "tool_use", "name": "execute_python", "input": { "code": "print(1)", "timeout": 120000 } }),
+            serde_json::json!({ "type": "tool_use", "name": "execute_js", "input": { "explanation": "no code" } }),
+        ];
+        let steps = replay_steps(&messages);
+        assert_eq!(steps, vec![
+            ReplayStep { message_index: 1, language: "javascript", code: "return 1".to_string(), timeout_ms: 5_000 },
+            ReplayStep { message_index: 3, language: "python", code: "print(1)".to_string(), timeout_ms: 60_000 },
+        ]);
+    }
+
+    #[test]
+    fn test_javascript_infinite_loop_times_out() {
+        let started = std::time::Instant::now();
+        let result = execute_javascript("while (true) {}", "/tmp", 500);
+        assert!(started.elapsed() < Duration::from_millis(750), "took {:?}", started.elapsed());
+        assert!(!result.success);
+        assert_eq!(result.error.as_deref(), Some("execution timed out after 500ms"));
+    }
+
+    #[test]
+    fn test_javascript_selftest_passes() {
+        let report = run_js_selftest();
+        assert!(report.passed, "{:?}", report.checks);
+        assert_eq!(report.checks.len(), 5);
+    }
+
+    #[test]
+    fn js_error_detail_maps_script_position_to_user_code() {
+        let script = build_js_script("// header\n", "let x = 1;\nlet y = ;");
+        let first_line_msg = format!("unexpected token at line {}, col {}", script.code_line, script.code_column + 4);
+        let detail = js_error_detail(&first_line_msg, &script);
+        assert_eq!((detail.line, detail.column), (Some(1), Some(5)));
+
+        let second_line_msg = format!("expected token at line {}, col 9", script.code_line + 1);
+        let detail = js_error_detail(&second_line_msg, &script);
+        assert_eq!((detail.line, detail.column), (Some(2), Some(9)));
+
+        let wrapper_msg = "unexpected end of input at line 1, col 1";
+        assert_eq!(js_error_detail(wrapper_msg, &script).line, None);
+    }
 }
@@ -1,5 +1,5 @@
 use crate::db::{Database, ScheduledTask, UpdateScheduledTaskParams};
-use std::collections::HashSet;
+use std::collections::{HashSet, VecDeque};
 use std::sync::{Arc, Mutex};
 use std::thread;
 use std::time::Duration;
@@ -7,11 +7,14 @@ use tauri::{AppHandle, Emitter};
 use tauri_plugin_notification::NotificationExt;
 use serde_json::json;
 use regex::Regex;
-use chrono::{Local, NaiveTime, TimeZone};
+use chrono::{Local, NaiveTime, TimeZone, Timelike};
+use serde::Serialize;
 
 pub struct SchedulerService {
     db: Arc<Database>,
     running: Arc<Mutex<bool>>,
+    /// While set, the loop keeps ticking but no notifications or executions fire
+    paused: Arc<Mutex<bool>>,
     notified_tasks: Arc<Mutex<HashSet<String>>>,
 }
 
@@ -20,6 +23,7 @@ impl SchedulerService {
         Self {
             db,
             running: Arc::new(Mutex::new(false)),
+            paused: Arc::new(Mutex::new(false)),
             notified_tasks: Arc::new(Mutex::new(HashSet::new())),
         }
     }
@@ -36,6 +40,7 @@ impl SchedulerService {
 
         let db = self.db.clone();
         let running_flag = self.running.clone();
+        let paused_flag = self.paused.clone();
         let notified_tasks = self.notified_tasks.clone();
 
         thread::spawn(move || {
@@ -45,7 +50,7 @@ impl SchedulerService {
             thread::sleep(Duration::from_secs(3));
             
             // Check immediately after delay
-            check_tasks(&db, &app, &notified_tasks);
+            check_tasks(&db, &app, &notified_tasks, *paused_flag.lock().unwrap());
             
             // Then check every 30 seconds
             loop {
@@ -57,7 +62,7 @@ impl SchedulerService {
                     break;
                 }
                 
-                check_tasks(&db, &app, &notified_tasks);
+                check_tasks(&db, &app, &notified_tasks, *paused_flag.lock().unwrap());
             }
         });
     }
@@ -67,15 +72,59 @@ impl SchedulerService {
         let mut running = self.running.lock().unwrap();
         *running = false;
     }
+
+    /// Silence all tasks without stopping the background thread
+    pub fn pause(&self) {
+        *self.paused.lock().unwrap() = true;
+        eprintln!("[Scheduler] Paused");
+    }
+
+    /// Resume after `pause`. With `catch_up`, tasks that came due while paused run on the
+    /// next check; otherwise they are advanced past now (one-time tasks are disabled).
+    /// Returns how many overdue tasks were skipped.
+    pub fn resume(&self, catch_up: bool) -> Result<usize, String> {
+        let mut paused = self.paused.lock().unwrap();
+        let skipped = if catch_up {
+            0
+        } else {
+            skip_overdue(&self.db, chrono::Utc::now().timestamp_millis())?
+        };
+        *paused = false;
+        eprintln!("[Scheduler] Resumed (catch up: {}, skipped: {})", catch_up, skipped);
+        Ok(skipped)
+    }
+
+    pub fn is_paused(&self) -> bool {
+        *self.paused.lock().unwrap()
+    }
 }
 
-fn check_tasks(db: &Arc<Database>, app: &AppHandle, notified_tasks: &Arc<Mutex<HashSet<String>>>) {
+fn check_tasks(db: &Arc<Database>, app: &AppHandle, notified_tasks: &Arc<Mutex<HashSet<String>>>, paused: bool) {
     let now = chrono::Utc::now().timestamp_millis();
     
-    // Check for tasks that need notifications
-    check_notifications(db, app, notified_tasks, now);
-    
-    // Check for tasks due to execute
+    // While paused, due tasks stay due; resume decides whether they catch up or get skipped
+    if !paused {
+        // Check for tasks that need notifications
+        check_notifications(db, app, notified_tasks, now);
+        
+        // Check for tasks due to execute
+        execute_due_tasks(db, app, notified_tasks, now);
+    }
+
+    // Let the UI know the loop is alive (after rescheduling, so nextRun is current)
+    match db.list_scheduled_tasks(false) {
+        Ok(tasks) => {
+            if let Err(e) = emit_heartbeat(app, &tasks, now, paused) {
+                eprintln!("[Scheduler] Error emitting heartbeat: {}", e);
+            }
+        }
+        Err(e) => {
+            eprintln!("[Scheduler] Error listing tasks for heartbeat: {}", e);
+        }
+    }
+}
+
+fn execute_due_tasks(db: &Arc<Database>, app: &AppHandle, notified_tasks: &Arc<Mutex<HashSet<String>>>, now: i64) {
     match db.get_tasks_due_now(now) {
         Ok(due_tasks) => {
             if !due_tasks.is_empty() {
@@ -92,6 +141,16 @@ fn check_tasks(db: &Arc<Database>, app: &AppHandle, notified_tasks: &Arc<Mutex<H
     }
 }
 
+/// Move every overdue task past `now` without running it
+fn skip_overdue(db: &Database, now: i64) -> Result<usize, String> {
+    let due_tasks = db.get_tasks_due_now(now).map_err(|e| e.to_string())?;
+    for task in &due_tasks {
+        eprintln!("[Scheduler] Skipping overdue task: {} ({})", task.title, task.id);
+        advance_task(db, task, now);
+    }
+    Ok(due_tasks.len())
+}
+
 fn check_notifications(db: &Arc<Database>, app: &AppHandle, notified_tasks: &Arc<Mutex<HashSet<String>>>, now: i64) {
     match db.list_scheduled_tasks(false) {
         Ok(tasks) => {
@@ -122,7 +181,8 @@ fn check_notifications(db: &Arc<Database>, app: &AppHandle, notified_tasks: &Arc
 }
 
 fn execute_task(db: &Arc<Database>, app: &AppHandle, notified_tasks: &Arc<Mutex<HashSet<String>>>, task: &ScheduledTask, now: i64) {
-    eprintln!("[Scheduler] Executing task: {} ({})", task.title, task.id);
+    let pending = pending_catch_up_slots(task, now);
+    eprintln!("[Scheduler] Executing task: {} ({}), {} missed run(s) left", task.title, task.id, pending.len());
     
     // Show reminder notification
     send_notification(app, "Reminder", &task.title);
@@ -140,7 +200,50 @@ fn execute_task(db: &Arc<Database>, app: &AppHandle, notified_tasks: &Arc<Mutex<
         notified.remove(&task.id);
     }
     
-    // Update next run time if recurring, otherwise disable
+    // Missed runs replay one per check rather than all at once
+    match pending.front() {
+        Some(&slot) => {
+            let params = UpdateScheduledTaskParams { next_run: Some(slot), ..Default::default() };
+            if let Err(e) = db.update_scheduled_task(&task.id, &params) {
+                eprintln!("[Scheduler] Error queueing catch-up run for task {}: {}", task.id, e);
+            }
+        }
+        None => advance_task(db, task, now),
+    }
+}
+
+/// Upper bound on runs replayed for a task with the "each" catch-up policy
+pub const CATCH_UP_MAX_RUNS: usize = 10;
+/// Schedule steps examined when looking for missed slots
+const CATCH_UP_SCAN_LIMIT: usize = 100_000;
+
+/// Missed slots of a due recurring "each" task still to replay after the run firing now,
+/// oldest first. Only the newest `CATCH_UP_MAX_RUNS - 1` are kept; older ones are coalesced.
+/// Empty for every other task, which fires once and is then moved past `now`.
+fn pending_catch_up_slots(task: &ScheduledTask, now: i64) -> VecDeque<i64> {
+    let mut pending = VecDeque::new();
+    if !task.is_recurring || task.catch_up_policy != "each" {
+        return pending;
+    }
+    let mut slot = task.next_run;
+    for _ in 0..CATCH_UP_SCAN_LIMIT {
+        match calculate_next_run(&task.schedule, slot) {
+            // A zero-length interval would never move past `now`
+            Some(next) if next > slot && next <= now => {
+                if pending.len() == CATCH_UP_MAX_RUNS - 1 {
+                    pending.pop_front();
+                }
+                pending.push_back(next);
+                slot = next;
+            }
+            _ => break,
+        }
+    }
+    pending
+}
+
+/// Update next run time if recurring, otherwise disable
+fn advance_task(db: &Database, task: &ScheduledTask, now: i64) {
     if task.is_recurring {
         if let Some(next_run) = calculate_next_run(&task.schedule, now) {
             let params = UpdateScheduledTaskParams {
@@ -203,73 +306,174 @@ fn emit_task_execute(app: &AppHandle, task: &ScheduledTask) -> Result<(), String
         .map_err(|e| format!("Failed to emit: {}", e))
 }
 
-/// Calculate the next run time for a schedule
+/// Payload for `scheduler.heartbeat`: enabled task count and the soonest upcoming run.
+fn heartbeat_payload(tasks: &[ScheduledTask], now: i64, paused: bool) -> serde_json::Value {
+    let enabled: Vec<&ScheduledTask> = tasks.iter().filter(|t| t.enabled).collect();
+    let next = enabled.iter().min_by_key(|t| t.next_run);
+    json!({
+        "now": now,
+        "paused": paused,
+        "enabledTasks": enabled.len(),
+        "nextRun": next.map(|t| t.next_run),
+        "nextTaskId": next.map(|t| t.id.clone()),
+        "nextTaskTitle": next.map(|t| t.title.clone())
+    })
+}
+
+fn emit_heartbeat(app: &AppHandle, tasks: &[ScheduledTask], now: i64, paused: bool) -> Result<(), String> {
+    let event_json = serde_json::to_string(&json!({
+        "type": "scheduler.heartbeat",
+        "payload": heartbeat_payload(tasks, now, paused)
+    })).map_err(|e| format!("Failed to serialize: {}", e))?;
+
+    app.emit("server-event", event_json)
+        .map_err(|e| format!("Failed to emit: {}", e))
+}
+
+/// A schedule string, parsed. `parse_schedule` is the only place schedule syntax is interpreted.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "kind", rename_all = "camelCase")]
+pub enum ParsedSchedule {
+    /// One-time delay: "1m", "5m", "1h", "2h", "1d", "7d"
+    Delay { amount: u64, unit: char },
+    /// Repeating interval: "every 10m", "every 1h", "every 1d"
+    Every { amount: u64, unit: char },
+    /// Daily at a local time: "daily 09:00"
+    Daily { hour: u32, minute: u32 },
+    /// Specific local datetime: "2026-01-20 15:30"
+    At {
+        #[serde(serialize_with = "serialize_schedule_datetime")]
+        datetime: chrono::NaiveDateTime,
+    },
+}
+
+fn serialize_schedule_datetime<S: serde::Serializer>(datetime: &chrono::NaiveDateTime, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_str(&datetime.format("%Y-%m-%d %H:%M"))
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "kind", rename_all = "camelCase")]
+pub enum ScheduleError {
+    /// Matches none of the supported formats
+    UnknownFormat,
+    /// Well-formed but the interval can't be represented
+    BadInterval { amount: String },
+    /// "every 0m" would fire on every scheduler tick
+    ZeroInterval,
+    /// Hour/minute out of range
+    BadTime { hour: u32, minute: u32 },
+    /// Not a real calendar date, e.g. "2026-02-30"
+    BadDate,
+}
+
+impl std::fmt::Display for ScheduleError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ScheduleError::UnknownFormat => write!(f, "Unknown schedule format; use e.g. \"30m\", \"every 1h\", \"daily 09:00\" or \"2026-01-20 15:30\""),
+            ScheduleError::BadInterval { amount } => write!(f, "Interval {} is too large", amount),
+            ScheduleError::ZeroInterval => write!(f, "Repeat interval must be greater than zero"),
+            ScheduleError::BadTime { hour, minute } => write!(f, "{:02}:{:02} is not a valid time", hour, minute),
+            ScheduleError::BadDate => write!(f, "Not a valid calendar date"),
+        }
+    }
+}
+
+fn unit_millis(unit: char) -> i64 {
+    match unit {
+        'm' => 60 * 1000,
+        'h' => 60 * 60 * 1000,
+        _ => 24 * 60 * 60 * 1000,
+    }
+}
+
+fn parse_interval(amount: &str, unit: &str) -> Result<(u64, char), ScheduleError> {
+    let bad = || ScheduleError::BadInterval { amount: amount.to_string() };
+    let unit = unit.chars().next().ok_or_else(bad)?;
+    let value: u64 = amount.parse().map_err(|_| bad())?;
+    // Must fit in i64 millis when added to a timestamp
+    i64::try_from(value).ok().and_then(|v| v.checked_mul(unit_millis(unit))).ok_or_else(bad)?;
+    Ok((value, unit))
+}
+
+fn parse_time(hour: &str, minute: &str) -> Result<NaiveTime, ScheduleError> {
+    // The regexes only let digits through, so these parses can't fail
+    let hour: u32 = hour.parse().unwrap_or(u32::MAX);
+    let minute: u32 = minute.parse().unwrap_or(u32::MAX);
+    NaiveTime::from_hms_opt(hour, minute, 0).ok_or(ScheduleError::BadTime { hour, minute })
+}
+
+/// Parse a schedule string.
 /// Supports: "1m", "5m", "1h", "1d", "every 10m", "every 1h", "daily 09:00", "2026-01-20 15:30"
-pub fn calculate_next_run(schedule: &str, from: i64) -> Option<i64> {
-    // One-time delays: "1m", "5m", "1h", "2h", "1d", "7d"
-    let once_re = Regex::new(r"^(\d+)([mhd])$").ok()?;
+pub fn parse_schedule(schedule: &str) -> Result<ParsedSchedule, ScheduleError> {
+    let once_re = Regex::new(r"^(\d+)([mhd])$").unwrap();
     if let Some(caps) = once_re.captures(schedule) {
-        let amount: i64 = caps.get(1)?.as_str().parse().ok()?;
-        let unit = caps.get(2)?.as_str();
-        let multiplier: i64 = match unit {
-            "m" => 60 * 1000,
-            "h" => 60 * 60 * 1000,
-            "d" => 24 * 60 * 60 * 1000,
-            _ => return None,
-        };
-        return Some(from + amount * multiplier);
+        let (amount, unit) = parse_interval(&caps[1], &caps[2])?;
+        return Ok(ParsedSchedule::Delay { amount, unit });
     }
-    
-    // Repeating intervals: "every 10m", "every 1h", "every 1d"
-    let every_re = Regex::new(r"^every (\d+)([mhd])$").ok()?;
+
+    let every_re = Regex::new(r"^every (\d+)([mhd])$").unwrap();
     if let Some(caps) = every_re.captures(schedule) {
-        let amount: i64 = caps.get(1)?.as_str().parse().ok()?;
-        let unit = caps.get(2)?.as_str();
-        let multiplier: i64 = match unit {
-            "m" => 60 * 1000,
-            "h" => 60 * 60 * 1000,
-            "d" => 24 * 60 * 60 * 1000,
-            _ => return None,
-        };
-        return Some(from + amount * multiplier);
+        let (amount, unit) = parse_interval(&caps[1], &caps[2])?;
+        if amount == 0 {
+            return Err(ScheduleError::ZeroInterval);
+        }
+        return Ok(ParsedSchedule::Every { amount, unit });
     }
-    
-    // Daily at specific time: "daily 09:00", "daily 14:30"
-    let daily_re = Regex::new(r"^daily (\d{2}):(\d{2})$").ok()?;
+
+    let daily_re = Regex::new(r"^daily (\d{2}):(\d{2})$").unwrap();
     if let Some(caps) = daily_re.captures(schedule) {
-        let hours: u32 = caps.get(1)?.as_str().parse().ok()?;
-        let minutes: u32 = caps.get(2)?.as_str().parse().ok()?;
-        
-        let from_dt = chrono::DateTime::from_timestamp_millis(from)?;
-        let local_dt = from_dt.with_timezone(&Local);
-        
-        let target_time = NaiveTime::from_hms_opt(hours, minutes, 0)?;
-        let mut target = local_dt.date_naive().and_time(target_time);
-        
-        // If the time has passed today, schedule for tomorrow
-        if Local.from_local_datetime(&target).single()?.timestamp_millis() <= from {
-            target = target + chrono::Duration::days(1);
-        }
-        
-        return Some(Local.from_local_datetime(&target).single()?.timestamp_millis());
+        let time = parse_time(&caps[1], &caps[2])?;
+        return Ok(ParsedSchedule::Daily { hour: time.hour(), minute: time.minute() });
     }
-    
-    // Specific datetime: "2026-01-20 15:30"
-    let datetime_re = Regex::new(r"^(\d{4})-(\d{2})-(\d{2}) (\d{2}):(\d{2})$").ok()?;
+
+    let datetime_re = Regex::new(r"^(\d{4})-(\d{2})-(\d{2}) (\d{2}):(\d{2})$").unwrap();
     if let Some(caps) = datetime_re.captures(schedule) {
-        let year: i32 = caps.get(1)?.as_str().parse().ok()?;
-        let month: u32 = caps.get(2)?.as_str().parse().ok()?;
-        let day: u32 = caps.get(3)?.as_str().parse().ok()?;
-        let hours: u32 = caps.get(4)?.as_str().parse().ok()?;
-        let minutes: u32 = caps.get(5)?.as_str().parse().ok()?;
-        
-        let target = chrono::NaiveDate::from_ymd_opt(year, month, day)?
-            .and_hms_opt(hours, minutes, 0)?;
-        
-        return Some(Local.from_local_datetime(&target).single()?.timestamp_millis());
+        let time = parse_time(&caps[4], &caps[5])?;
+        let date = chrono::NaiveDate::from_ymd_opt(
+            caps[1].parse().unwrap_or(0),
+            caps[2].parse().unwrap_or(0),
+            caps[3].parse().unwrap_or(0),
+        )
+        .ok_or(ScheduleError::BadDate)?;
+        return Ok(ParsedSchedule::At { datetime: date.and_time(time) });
     }
-    
-    None
+
+    Err(ScheduleError::UnknownFormat)
+}
+
+impl ParsedSchedule {
+    pub fn is_recurring(&self) -> bool {
+        matches!(self, ParsedSchedule::Every { .. } | ParsedSchedule::Daily { .. })
+    }
+
+    /// None when the local time doesn't exist or is ambiguous (DST transitions)
+    pub fn next_run(&self, from: i64) -> Option<i64> {
+        match self {
+            ParsedSchedule::Delay { amount, unit } | ParsedSchedule::Every { amount, unit } => {
+                from.checked_add(*amount as i64 * unit_millis(*unit))
+            }
+            ParsedSchedule::Daily { hour, minute } => {
+                let from_dt = chrono::DateTime::from_timestamp_millis(from)?;
+                let local_dt = from_dt.with_timezone(&Local);
+
+                let target_time = NaiveTime::from_hms_opt(*hour, *minute, 0)?;
+                let mut target = local_dt.date_naive().and_time(target_time);
+
+                // If the time has passed today, schedule for tomorrow
+                if Local.from_local_datetime(&target).single()?.timestamp_millis() <= from {
+                    target += chrono::Duration::days(1);
+                }
+
+                Some(Local.from_local_datetime(&target).single()?.timestamp_millis())
+            }
+            ParsedSchedule::At { datetime } => Some(Local.from_local_datetime(datetime).single()?.timestamp_millis()),
+        }
+    }
+}
+
+/// Calculate the next run time for a schedule
+pub fn calculate_next_run(schedule: &str, from: i64) -> Option<i64> {
+    parse_schedule(schedule).ok()?.next_run(from)
 }
 
 /// Check if a schedule format is valid
@@ -279,5 +483,248 @@ pub fn is_valid_schedule(schedule: &str) -> bool {
 
 /// Check if a schedule is recurring
 pub fn is_recurring_schedule(schedule: &str) -> bool {
-    schedule.starts_with("every") || schedule.starts_with("daily")
+    parse_schedule(schedule).map(|p| p.is_recurring()).unwrap_or(false)
+}
+
+fn describe_interval(amount: u64, unit: char) -> String {
+    let noun = match unit {
+        'm' => "minute",
+        'h' => "hour",
+        _ => "day",
+    };
+    if amount == 1 {
+        format!("1 {}", noun)
+    } else {
+        format!("{} {}s", amount, noun)
+    }
+}
+
+/// Human-readable description of a schedule string, e.g. "Every 10 minutes" or "Daily at 09:00".
+/// Returns None for formats `calculate_next_run` doesn't accept.
+pub fn describe_schedule(schedule: &str) -> Option<String> {
+    if !is_valid_schedule(schedule) {
+        return None;
+    }
+
+    Some(match parse_schedule(schedule).ok()? {
+        ParsedSchedule::Delay { amount, unit } => format!("Once in {}", describe_interval(amount, unit)),
+        ParsedSchedule::Every { amount, unit } => format!("Every {}", describe_interval(amount, unit)),
+        ParsedSchedule::Daily { hour, minute } => format!("Daily at {:02}:{:02}", hour, minute),
+        ParsedSchedule::At { datetime } => format!("Once at {}", datetime.format("%Y-%m-%d %H:%M")),
+    })
+}
+
+/// Schedule/next_run/is_recurring update for toggling a task's recurrence.
+/// To recurring: `schedule` must be a recurring format; next_run is recomputed from `now`.
+/// To one-time: the upcoming occurrence (or `schedule`, if given and one-time) becomes a fixed datetime.
+pub fn convert_task_schedule(
+    task: &ScheduledTask,
+    to_recurring: bool,
+    schedule: Option<&str>,
+    now: i64,
+) -> Result<UpdateScheduledTaskParams, String> {
+    let schedule = schedule.map(str::trim).filter(|s| !s.is_empty());
+
+    if to_recurring {
+        let schedule = schedule.ok_or("A recurring schedule is required, e.g. \"every 1h\" or \"daily 09:00\"")?;
+        let parsed = parse_schedule(schedule).map_err(|e| e.to_string())?;
+        if !parsed.is_recurring() {
+            return Err(format!("\"{}\" is not a recurring schedule", schedule));
+        }
+        let next_run = parsed.next_run(now).ok_or_else(|| format!("\"{}\" has no next run", schedule))?;
+        return Ok(UpdateScheduledTaskParams {
+            schedule: Some(schedule.to_string()),
+            next_run: Some(next_run),
+            is_recurring: Some(true),
+            ..Default::default()
+        });
+    }
+
+    let next_run = match schedule {
+        Some(schedule) => {
+            let parsed = parse_schedule(schedule).map_err(|e| e.to_string())?;
+            if parsed.is_recurring() {
+                return Err(format!("\"{}\" is a recurring schedule", schedule));
+            }
+            parsed.next_run(now).ok_or_else(|| format!("\"{}\" has no next run", schedule))?
+        }
+        // A stale next_run (task overdue or paused) is moved to the schedule's next slot
+        None if task.next_run > now => task.next_run,
+        None => calculate_next_run(&task.schedule, now).unwrap_or(task.next_run),
+    };
+
+    // Freeze as an absolute datetime so the schedule string and next_run agree
+    let local = chrono::DateTime::from_timestamp_millis(next_run)
+        .ok_or("Next run is out of range")?
+        .with_timezone(&Local);
+    let frozen = local.format("%Y-%m-%d %H:%M").to_string();
+    let next_run = calculate_next_run(&frozen, now)
+        .ok_or_else(|| format!("{} is not a valid local time", frozen))?;
+
+    Ok(UpdateScheduledTaskParams {
+        schedule: Some(frozen),
+        next_run: Some(next_run),
+        is_recurring: Some(false),
+        ..Default::default()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn describe_schedule_covers_supported_formats() {
+        assert_eq!(describe_schedule("1m").as_deref(), Some("Once in 1 minute"));
+        assert_eq!(describe_schedule("every 10m").as_deref(), Some("Every 10 minutes"));
+        assert_eq!(describe_schedule("every 1d").as_deref(), Some("Every 1 day"));
+        assert_eq!(describe_schedule("daily 09:00").as_deref(), Some("Daily at 09:00"));
+        assert_eq!(describe_schedule("2099-01-20 15:30").as_deref(), Some("Once at 2099-01-20 15:30"));
+        assert_eq!(describe_schedule("sometimes"), None);
+    }
+
+    #[test]
+    fn parse_schedule_reports_typed_errors() {
+        assert_eq!(parse_schedule("every 10m"), Ok(ParsedSchedule::Every { amount: 10, unit: 'm' }));
+        assert_eq!(parse_schedule("daily 09:05"), Ok(ParsedSchedule::Daily { hour: 9, minute: 5 }));
+        assert!(parse_schedule("daily 09:05").unwrap().is_recurring());
+        assert!(!parse_schedule("2h").unwrap().is_recurring());
+
+        assert_eq!(parse_schedule("daily 25:00"), Err(ScheduleError::BadTime { hour: 25, minute: 0 }));
+        assert_eq!(parse_schedule("2026-01-20 12:60"), Err(ScheduleError::BadTime { hour: 12, minute: 60 }));
+        assert_eq!(parse_schedule("2026-02-30 12:00"), Err(ScheduleError::BadDate));
+        assert!(matches!(parse_schedule("99999999999999999999d"), Err(ScheduleError::BadInterval { .. })));
+        assert_eq!(parse_schedule("0 9 * * *"), Err(ScheduleError::UnknownFormat));
+        assert_eq!(parse_schedule("every day"), Err(ScheduleError::UnknownFormat));
+        assert_eq!(parse_schedule("every 0m"), Err(ScheduleError::ZeroInterval));
+
+        assert_eq!(calculate_next_run("5m", 1_000), Some(1_000 + 5 * 60 * 1000));
+        assert_eq!(calculate_next_run("daily 24:00", 1_000), None);
+    }
+
+    fn task(id: &str, next_run: i64, enabled: bool) -> ScheduledTask {
+        ScheduledTask {
+            id: id.to_string(),
+            title: format!("Task {}", id),
+            prompt: None,
+            schedule: "every 10m".to_string(),
+            next_run,
+            is_recurring: true,
+            notify_before: None,
+            enabled,
+            catch_up_policy: "coalesce".to_string(),
+            created_at: 0,
+            updated_at: 0,
+        }
+    }
+
+    #[test]
+    fn heartbeat_reports_soonest_enabled_task() {
+        let tasks = vec![task("a", 5_000, true), task("b", 2_000, false), task("c", 3_000, true)];
+        let payload = heartbeat_payload(&tasks, 1_000, false);
+        assert_eq!(payload["now"], 1_000);
+        assert_eq!(payload["enabledTasks"], 2);
+        assert_eq!(payload["nextRun"], 3_000);
+        assert_eq!(payload["nextTaskId"], "c");
+
+        let empty = heartbeat_payload(&[], 1_000, true);
+        assert_eq!(empty["enabledTasks"], 0);
+        assert!(empty["nextRun"].is_null());
+        assert_eq!(empty["paused"], true);
+    }
+
+    #[test]
+    fn skip_overdue_advances_recurring_and_disables_one_time() {
+        let db = Database::new(std::path::Path::new(":memory:")).unwrap();
+        let params = |title: &str, schedule: &str| crate::db::CreateScheduledTaskParams {
+            id: None,
+            title: title.to_string(),
+            prompt: None,
+            schedule: schedule.to_string(),
+            notify_before: None,
+            catch_up_policy: None,
+        };
+        let recurring = db.create_scheduled_task(&params("standup", "every 10m"), 1_000, true).unwrap();
+        let once = db.create_scheduled_task(&params("call", "5m"), 2_000, false).unwrap();
+
+        let now = 10_000;
+        assert_eq!(skip_overdue(&db, now).unwrap(), 2);
+        assert!(db.get_tasks_due_now(now).unwrap().is_empty());
+        assert!(db.get_scheduled_task(&recurring.id).unwrap().unwrap().next_run > now);
+        assert!(!db.get_scheduled_task(&once.id).unwrap().unwrap().enabled);
+    }
+
+    #[test]
+    fn catch_up_policy_controls_missed_runs() {
+        let interval = 10 * 60 * 1000;
+        let now = 100 * interval;
+        // next_run was 5 intervals ago, so slots at -5..-1 intervals are all missed
+        let mut overdue = task("late", now - 4 * interval - 1, true);
+
+        assert!(pending_catch_up_slots(&overdue, now).is_empty());
+
+        overdue.catch_up_policy = "each".to_string();
+        let pending = pending_catch_up_slots(&overdue, now);
+        assert_eq!(pending.len(), 4);
+        assert_eq!(pending.front(), Some(&(overdue.next_run + interval)));
+
+        // A long backlog keeps only the newest slots
+        overdue.next_run = now - 50 * interval;
+        let pending = pending_catch_up_slots(&overdue, now);
+        assert_eq!(pending.len(), CATCH_UP_MAX_RUNS - 1);
+        assert_eq!(pending.back(), Some(&now));
+
+        overdue.is_recurring = false;
+        assert!(pending_catch_up_slots(&overdue, now).is_empty());
+
+        // Both policies leave the task scheduled past now
+        let db = Database::new(std::path::Path::new(":memory:")).unwrap();
+        for policy in ["coalesce", "each"] {
+            let created = db.create_scheduled_task(&crate::db::CreateScheduledTaskParams {
+                id: None,
+                title: policy.to_string(),
+                prompt: None,
+                schedule: "every 10m".to_string(),
+                notify_before: None,
+                catch_up_policy: Some(policy.to_string()),
+            }, now - 4 * interval - 1, true).unwrap();
+            assert_eq!(created.catch_up_policy, policy);
+            advance_task(&db, &created, now);
+            assert!(db.get_scheduled_task(&created.id).unwrap().unwrap().next_run > now);
+        }
+        assert!(db.update_scheduled_task("x", &UpdateScheduledTaskParams {
+            catch_up_policy: Some("sometimes".to_string()),
+            ..Default::default()
+        }).is_err());
+    }
+
+    #[test]
+    fn convert_task_schedule_toggles_recurrence() {
+        let now = chrono::Utc::now().timestamp_millis();
+        let upcoming = now + 10 * 60 * 1000 + 30_000;
+        let recurring = task("r", upcoming, true);
+
+        // One-time keeps the upcoming occurrence, to minute precision
+        let once = convert_task_schedule(&recurring, false, None, now).unwrap();
+        assert_eq!(once.is_recurring, Some(false));
+        let frozen = once.next_run.unwrap();
+        assert!(frozen <= upcoming && upcoming - frozen < 60_000);
+        assert!(!is_recurring_schedule(once.schedule.as_deref().unwrap()));
+
+        // An explicit one-time schedule wins; a recurring one is rejected
+        let later = convert_task_schedule(&recurring, false, Some("2h"), now).unwrap();
+        assert_eq!(later.next_run, calculate_next_run(later.schedule.as_deref().unwrap(), now));
+        assert!(convert_task_schedule(&recurring, false, Some("every 1h"), now).is_err());
+
+        let mut one_time = task("o", upcoming, true);
+        one_time.schedule = once.schedule.clone().unwrap();
+        one_time.is_recurring = false;
+        let back = convert_task_schedule(&one_time, true, Some("daily 09:00"), now).unwrap();
+        assert_eq!(back.schedule.as_deref(), Some("daily 09:00"));
+        assert_eq!(back.is_recurring, Some(true));
+        assert_eq!(back.next_run, calculate_next_run("daily 09:00", now));
+        assert!(convert_task_schedule(&one_time, true, None, now).is_err());
+        assert!(convert_task_schedule(&one_time, true, Some("30m"), now).is_err());
+        assert!(convert_task_schedule(&one_time, true, Some("daily 25:00"), now).is_err());
+    }
 }
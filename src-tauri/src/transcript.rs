@@ -0,0 +1,265 @@
+//! Human-readable session transcripts (shared message walk + HTML rendering).
+
+use serde_json::Value;
+
+/// Tool output longer than this is cut in transcripts
+const TOOL_OUTPUT_MAX_CHARS: usize = 4000;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TranscriptRole {
+    User,
+    Assistant,
+    Tool,
+}
+
+impl TranscriptRole {
+    fn css_class(self) -> &'static str {
+        match self {
+            TranscriptRole::User => "user",
+            TranscriptRole::Assistant => "assistant",
+            TranscriptRole::Tool => "tool",
+        }
+    }
+
+    fn label(self) -> &'static str {
+        match self {
+            TranscriptRole::User => "You",
+            TranscriptRole::Assistant => "Assistant",
+            TranscriptRole::Tool => "Tool",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TranscriptEntry {
+    pub role: TranscriptRole,
+    /// Tool name for tool calls/results
+    pub title: Option<String>,
+    pub text: String,
+}
+
+/// Walk stored session messages and keep what a reader cares about: prompts, replies, tool calls.
+/// Stream events, results and system messages are dropped.
+pub fn collect_entries(messages: &[Value]) -> Vec<TranscriptEntry> {
+    let mut entries = Vec::new();
+    for message in messages {
+        match message.get("type").and_then(|v| v.as_str()) {
+            Some("user_prompt") => push_text(&mut entries, TranscriptRole::User, message.get("prompt")),
+            Some("text") => push_text(&mut entries, TranscriptRole::Assistant, message.get("text")),
+            Some("assistant") => {
+                for block in content_blocks(message) {
+                    match block.get("type").and_then(|v| v.as_str()) {
+                        Some("text") => push_text(&mut entries, TranscriptRole::Assistant, block.get("text")),
+                        Some("tool_use") => entries.push(TranscriptEntry {
+                            role: TranscriptRole::Tool,
+                            title: block.get("name").and_then(|v| v.as_str()).map(String::from),
+                            text: block
+                                .get("input")
+                                .map(|input| serde_json::to_string_pretty(input).unwrap_or_default())
+                                .unwrap_or_default(),
+                        }),
+                        _ => {}
+                    }
+                }
+            }
+            Some("user") => {
+                for block in content_blocks(message) {
+                    if block.get("type").and_then(|v| v.as_str()) != Some("tool_result") {
+                        continue;
+                    }
+                    let text = match block.get("content") {
+                        Some(Value::String(s)) => s.clone(),
+                        Some(Value::Array(parts)) => parts
+                            .iter()
+                            .filter_map(|p| p.get("text").and_then(|v| v.as_str()))
+                            .collect::<Vec<_>>()
+                            .join("\n"),
+                        _ => continue,
+                    };
+                    entries.push(TranscriptEntry {
+                        role: TranscriptRole::Tool,
+                        title: Some("Result".to_string()),
+                        text: truncate_chars(&text, TOOL_OUTPUT_MAX_CHARS),
+                    });
+                }
+            }
+            _ => {}
+        }
+    }
+    entries
+}
+
+fn content_blocks(message: &Value) -> &[Value] {
+    message
+        .pointer("/message/content")
+        .and_then(|v| v.as_array())
+        .map(|v| v.as_slice())
+        .unwrap_or(&[])
+}
+
+fn push_text(entries: &mut Vec<TranscriptEntry>, role: TranscriptRole, text: Option<&Value>) {
+    let Some(text) = text.and_then(|v| v.as_str()) else { return };
+    if text.trim().is_empty() {
+        return;
+    }
+    entries.push(TranscriptEntry { role, title: None, text: text.to_string() });
+}
+
+fn truncate_chars(text: &str, max: usize) -> String {
+    match text.char_indices().nth(max) {
+        Some((idx, _)) => format!("{}\n… (truncated)", &text[..idx]),
+        None => text.to_string(),
+    }
+}
+
+pub fn escape_html(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+/// Fenced code blocks become `<pre><code class="language-x">`, inline `code` becomes `<code>`,
+/// everything else is escaped paragraphs.
+fn render_body(text: &str) -> String {
+    let mut html = String::new();
+    let mut paragraph: Vec<&str> = Vec::new();
+    let mut code: Option<(String, Vec<&str>)> = None;
+
+    let flush_paragraph = |html: &mut String, paragraph: &mut Vec<&str>| {
+        if !paragraph.is_empty() {
+            let lines: Vec<String> = paragraph.iter().map(|l| render_inline(l)).collect();
+            html.push_str(&format!("<p>{}</p>\n", lines.join("<br>")));
+            paragraph.clear();
+        }
+    };
+
+    for line in text.lines() {
+        let fence = line.trim_start().strip_prefix("```");
+        match (&mut code, fence) {
+            (Some((lang, lines)), Some(_)) => {
+                let class = if lang.is_empty() { String::new() } else { format!(" class=\"language-{}\"", escape_html(lang)) };
+                html.push_str(&format!("<pre><code{}>{}</code></pre>\n", class, escape_html(&lines.join("\n"))));
+                code = None;
+            }
+            (Some((_, lines)), None) => lines.push(line),
+            (None, Some(lang)) => {
+                flush_paragraph(&mut html, &mut paragraph);
+                code = Some((lang.trim().to_string(), Vec::new()));
+            }
+            (None, None) if line.trim().is_empty() => flush_paragraph(&mut html, &mut paragraph),
+            (None, None) => paragraph.push(line),
+        }
+    }
+    // Unterminated fence: still show it as code
+    if let Some((_, lines)) = code {
+        html.push_str(&format!("<pre><code>{}</code></pre>\n", escape_html(&lines.join("\n"))));
+    }
+    flush_paragraph(&mut html, &mut paragraph);
+    html
+}
+
+fn render_inline(line: &str) -> String {
+    let mut out = String::new();
+    for (i, part) in line.split('`').enumerate() {
+        if i % 2 == 1 {
+            out.push_str(&format!("<code>{}</code>", escape_html(part)));
+        } else {
+            out.push_str(&escape_html(part));
+        }
+    }
+    out
+}
+
+const TRANSCRIPT_CSS: &str = "\
+body{font-family:-apple-system,BlinkMacSystemFont,'Segoe UI',Roboto,sans-serif;max-width:860px;margin:2rem auto;padding:0 1rem;color:#1f2328;background:#fff;line-height:1.55}\
+h1{font-size:1.5rem;margin-bottom:.25rem}.meta{color:#656d76;font-size:.85rem;margin-bottom:2rem}\
+.msg{border-radius:10px;padding:.75rem 1rem;margin:0 0 1rem}.role{font-size:.75rem;font-weight:600;text-transform:uppercase;letter-spacing:.04em;color:#656d76;margin-bottom:.35rem}\
+.user{background:#eef4ff;border:1px solid #c8dafc}.assistant{background:#f6f8fa;border:1px solid #d8dee4}.tool{background:#fff8e6;border:1px solid #f0dca4;font-size:.85rem}\
+p{margin:.4rem 0}code{font-family:ui-monospace,SFMono-Regular,Menlo,Consolas,monospace;background:rgba(175,184,193,.2);padding:.1em .3em;border-radius:4px;font-size:.9em}\
+pre{background:#0d1117;color:#e6edf3;padding:.75rem;border-radius:8px;overflow-x:auto}pre code{background:none;padding:0;color:inherit}";
+
+/// Self-contained HTML page (inline CSS, no scripts). All session content is escaped.
+pub fn render_html(title: &str, meta: &str, entries: &[TranscriptEntry]) -> String {
+    let mut body = String::new();
+    for entry in entries {
+        let label = match &entry.title {
+            Some(name) => format!("{} · {}", entry.role.label(), escape_html(name)),
+            None => entry.role.label().to_string(),
+        };
+        let content = if entry.role == TranscriptRole::Tool {
+            format!("<pre><code>{}</code></pre>\n", escape_html(&entry.text))
+        } else {
+            render_body(&entry.text)
+        };
+        body.push_str(&format!(
+            "<div class=\"msg {}\"><div class=\"role\">{}</div>\n{}</div>\n",
+            entry.role.css_class(),
+            label,
+            content
+        ));
+    }
+
+    format!(
+        "<!DOCTYPE html>\n<html lang=\"en\">\n<head>\n<meta charset=\"utf-8\">\n<meta name=\"viewport\" content=\"width=device-width, initial-scale=1\">\n<title>{title}</title>\n<style>{css}</style>\n</head>\n<body>\n<h1>{title}</h1>\n<div class=\"meta\">{meta}</div>\n{body}</body>\n</html>\n",
+        title = escape_html(title),
+        meta = escape_html(meta),
+        css = TRANSCRIPT_CSS,
+        body = body
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn collects_prompts_replies_and_tools() {
+        let messages = vec![
+            json!({ "type": "user_prompt", "prompt": "List files" }),
+            json!({ "type": "stream_event", "event": {} }),
+            json!({ "type": "assistant", "message": { "content": [
+                { "type": "tool_use", "id": "t1", "name": "bash", "input": { "command": "ls" } }
+            ] } }),
+            json!({ "type": "user", "message": { "content": [
+                { "type": "tool_result", "tool_use_id": "t1", "content": "a.txt" }
+            ] } }),
+            json!({ "type": "text", "text": "Found `a.txt`" }),
+            json!({ "type": "result", "subtype": "success" }),
+        ];
+
+        let entries = collect_entries(&messages);
+        let roles: Vec<TranscriptRole> = entries.iter().map(|e| e.role).collect();
+        assert_eq!(roles, vec![TranscriptRole::User, TranscriptRole::Tool, TranscriptRole::Tool, TranscriptRole::Assistant]);
+        assert_eq!(entries[1].title.as_deref(), Some("bash"));
+        assert_eq!(entries[2].text, "a.txt");
+    }
+
+    #[test]
+    fn html_escapes_content_and_marks_code() {
+        let entries = vec![
+            TranscriptEntry { role: TranscriptRole::User, title: None, text: "<script>alert(1)</script>".to_string() },
+            TranscriptEntry {
+                role: TranscriptRole::Assistant,
+                title: None,
+                text: "Use `a<b`:\n\n```rust\nfn main() { println!(\"<hi>\"); }\n```".to_string(),
+            },
+        ];
+        let html = render_html("A & B", "2 messages", &entries);
+
+        assert!(!html.contains("<script>"));
+        assert!(html.contains("&lt;script&gt;alert(1)&lt;/script&gt;"));
+        assert!(html.contains("<title>A &amp; B</title>"));
+        assert!(html.contains("<code>a&lt;b</code>"));
+        assert!(html.contains("<pre><code class=\"language-rust\">fn main() { println!(&quot;&lt;hi&gt;&quot;); }</code></pre>"));
+    }
+}
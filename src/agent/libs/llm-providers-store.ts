@@ -27,6 +27,36 @@ function getSettingsPath(): string {
   return join(getUserDataDir(), SETTINGS_FILE);
 }
 
+// Keys of keychain-backed providers (config.keyRef), pushed by Rust. Memory only: never written to disk.
+const runtimeProviderKeys = new Map<string, string>();
+
+export function setRuntimeProviderKeys(keys: Record<string, string>): void {
+  runtimeProviderKeys.clear();
+  for (const [providerId, key] of Object.entries(keys)) {
+    if (key) runtimeProviderKeys.set(providerId, key);
+  }
+}
+
+function withRuntimeKeys(settings: LLMProviderSettings): LLMProviderSettings {
+  if (runtimeProviderKeys.size === 0) return settings;
+  return {
+    ...settings,
+    providers: settings.providers.map((p) =>
+      !p.apiKey && runtimeProviderKeys.has(p.id) ? { ...p, apiKey: runtimeProviderKeys.get(p.id)! } : p
+    ),
+  };
+}
+
+function withoutRuntimeKeys(settings: LLMProviderSettings): LLMProviderSettings {
+  if (runtimeProviderKeys.size === 0) return settings;
+  return {
+    ...settings,
+    providers: settings.providers.map((p) =>
+      p.apiKey && p.apiKey === runtimeProviderKeys.get(p.id) ? { ...p, apiKey: "" } : p
+    ),
+  };
+}
+
 export function loadLLMProviderSettings(): LLMProviderSettings | null {
   try {
     const settingsPath = getSettingsPath();
@@ -52,7 +82,7 @@ export function loadLLMProviderSettings(): LLMProviderSettings | null {
       return { providers: settings.providers || [], models: [] };
     }
 
-    return settings;
+    return withRuntimeKeys(settings);
   } catch (error) {
     console.error("[LLM Providers] Failed to load settings:", error);
     return { providers: [], models: [] };
@@ -69,8 +99,7 @@ export function saveLLMProviderSettings(settings: LLMProviderSettings): void {
       mkdirSync(dir, { recursive: true });
     }
 
-    writeFileSync(settingsPath, JSON.stringify(settings, null, 2), "utf8");
-    writeFileSync(settingsPath, JSON.stringify(settings, null, 2), "utf8");
+    writeFileSync(settingsPath, JSON.stringify(withoutRuntimeKeys(settings), null, 2), "utf8");
   } catch (error) {
     console.error("[LLM Providers] Failed to save settings:", error);
     throw new Error("Failed to save LLM provider settings");
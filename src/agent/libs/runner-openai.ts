@@ -91,6 +91,17 @@ const redactMessagesForLog = (messages: ChatMessage[]) => {
   });
 };
 
+// OpenAI's own API (and Azure OpenAI) reject `max_tokens` for reasoning models and want
+// `max_completion_tokens`; OpenAI-compatible servers generally only know `max_tokens`.
+const maxTokensParam = (baseURL: string): 'max_tokens' | 'max_completion_tokens' => {
+  try {
+    const host = new URL(baseURL).hostname;
+    return host === 'api.openai.com' || host.endsWith('.openai.azure.com') ? 'max_completion_tokens' : 'max_tokens';
+  } catch {
+    return 'max_tokens';
+  }
+};
+
 
 export async function runClaude(options: RunnerOptions): Promise<RunnerHandle> {
   const { prompt, session, onEvent, onSessionUpdate } = options;
@@ -668,7 +679,7 @@ export async function runClaude(options: RunnerOptions): Promise<RunnerHandle> {
         session_id: session.id,
         tools: activeTools.map(t => t.function.name),
         model: modelName,
-        permissionMode: currentGuiSettings?.permissionMode || 'ask',
+        permissionMode: session.permissionMode || currentGuiSettings?.permissionMode || 'ask',
         memoryEnabled: currentGuiSettings?.enableMemory || false
       });
 
@@ -875,7 +886,8 @@ export async function runClaude(options: RunnerOptions): Promise<RunnerHandle> {
                 stream: true,
                 parallel_tool_calls: true,
                 stream_options: { include_usage: true },
-                ...(temperature !== undefined ? { temperature } : {})
+                ...(temperature !== undefined ? { temperature } : {}),
+                ...(session.maxOutputTokens ? { [maxTokensParam(baseURL)]: session.maxOutputTokens } : {})
               }, { signal: abortController.signal });
 
               for await (const chunk of stream) {
@@ -1250,9 +1262,9 @@ export async function runClaude(options: RunnerOptions): Promise<RunnerHandle> {
 
           // Request permission
           const toolUseId = toolCall.id;
-          // Reload settings to get latest permissionMode
+          // Reload settings to get latest permissionMode; the session's own mode wins
           const currentSettings = loadApiSettings();
-          const permissionMode = currentSettings?.permissionMode || 'ask';
+          const permissionMode = session.permissionMode || currentSettings?.permissionMode || 'ask';
           
           console.log(`[tool] ${toolName}`);
           
@@ -19,6 +19,9 @@ export type Session = {
   lastPrompt?: string;
   model?: string;
   temperature?: number;
+  maxOutputTokens?: number | null;
+  /** Overrides the global permissionMode setting for this session */
+  permissionMode?: 'default' | 'ask';
   threadId?: string; // Thread ID for multi-thread sessions
   fileChanges?: FileChange[];
   pendingPermissions: Map<string, PendingPermission>;
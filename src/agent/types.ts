@@ -127,6 +127,7 @@ export type SessionInfo = {
   claudeSessionId?: string;
   cwd?: string;
   isPinned?: boolean;
+  isLocked?: boolean;
   createdAt: number;
   updatedAt: number;
   inputTokens?: number;
@@ -319,13 +320,15 @@ export type CreateTaskPayload = {
 
 // Client -> Server events
 export type ClientEvent =
-  | { type: "session.start"; payload: { title: string; prompt: string; cwd?: string; allowedTools?: string; model?: string; temperature?: number } }
+  | { type: "session.start"; payload: { title: string; prompt: string; cwd?: string; allowedTools?: string; model?: string; temperature?: number; maxOutputTokens?: number; permissionMode?: 'default' | 'ask'; sessionId?: string } }
   | { type: "session.continue"; payload: { sessionId: string; prompt: string; cwd?: string; retry?: boolean; retryReason?: string } }
   | { type: "session.stop"; payload: { sessionId: string } }
   | { type: "session.delete"; payload: { sessionId: string } }
   | { type: "session.pin"; payload: { sessionId: string; isPinned: boolean } }
+  | { type: "session.lock"; payload: { sessionId: string } }
+  | { type: "session.unlock"; payload: { sessionId: string } }
   | { type: "session.update-cwd"; payload: { sessionId: string; cwd: string } }
-  | { type: "session.update"; payload: { sessionId: string; model?: string; temperature?: number; sendTemperature?: boolean; title?: string } }
+  | { type: "session.update"; payload: { sessionId: string; model?: string; temperature?: number; maxOutputTokens?: number | null; permissionMode?: 'default' | 'ask'; sendTemperature?: boolean; title?: string } }
   | { type: "session.compact"; payload: { sessionId: string } }
   | { type: "session.list" }
   | { type: "session.history"; payload: { sessionId: string; threadId?: string; limit?: number; before?: number } }
@@ -10,7 +10,7 @@ import { runClaude as runClaudeSDK } from "../agent/libs/runner.js";
 import { runClaude as runOpenAI } from "../agent/libs/runner-openai.js";
 import { loadApiSettings, saveApiSettings } from "../agent/libs/settings-store.js";
 import { generateSessionTitle } from "../agent/libs/util.js";
-import { loadLLMProviderSettings, saveLLMProviderSettings } from "../agent/libs/llm-providers-store.js";
+import { loadLLMProviderSettings, saveLLMProviderSettings, setRuntimeProviderKeys } from "../agent/libs/llm-providers-store.js";
 import { fetchModelsFromProvider, checkModelsAvailability } from "../agent/libs/llm-providers.js";
 import { loadSkillsSettings, toggleSkill, setMarketplaceUrl, addRepository, updateRepository, removeRepository, toggleRepository } from "../agent/libs/skills-store.js";
 import { fetchSkillsFromMarketplace } from "../agent/libs/skills-loader.js";
@@ -461,6 +461,8 @@ async function performCompact(sessionId: string, nextPrompt?: string, llmProvide
     allowedTools: session.allowedTools,
     model: session.model,
     temperature: session.temperature,
+    maxOutputTokens: session.maxOutputTokens,
+    permissionMode: session.permissionMode,
   });
 
   // Record the summary as the first user message (context carrier)
@@ -627,6 +629,10 @@ function handleSessionStart(event: Extract<ClientEvent, { type: "session.start"
     model: event.payload.model,
     threadId: event.payload.threadId,
     temperature: event.payload.temperature,
+    maxOutputTokens: event.payload.maxOutputTokens,
+    permissionMode: event.payload.permissionMode,
+    // Preassigned by Rust for scheduler-started runs
+    id: event.payload.sessionId,
   });
 
   if (!event.payload.prompt || event.payload.prompt.trim() === "") {
@@ -680,6 +686,8 @@ function handleSessionContinue(event: Extract<ClientEvent, { type: "session.cont
       model: sessionData.model,
       allowedTools: sessionData.allowedTools,
       temperature: sessionData.temperature,
+      maxOutputTokens: sessionData.maxOutputTokens,
+      permissionMode: sessionData.permissionMode,
     });
     
     // Restore message history from DB
@@ -801,10 +809,12 @@ function handleSessionUpdateCwd(event: Extract<ClientEvent, { type: "session.upd
 }
 
 function handleSessionUpdate(event: Extract<ClientEvent, { type: "session.update" }>) {
-  const { sessionId, model, temperature, title } = event.payload;
+  const { sessionId, model, temperature, maxOutputTokens, permissionMode, title } = event.payload;
   const updates: any = {};
   if (model !== undefined) updates.model = model;
   if (temperature !== undefined) updates.temperature = temperature;
+  if (maxOutputTokens !== undefined) updates.maxOutputTokens = maxOutputTokens;
+  if (permissionMode !== undefined) updates.permissionMode = permissionMode;
   if (title !== undefined) updates.title = title;
   sessions.updateSession(sessionId, updates);
   const session = sessions.getSession(sessionId);
@@ -839,6 +849,8 @@ function handleMessageEdit(event: Extract<ClientEvent, { type: "message.edit" }>
       model: sessionData.model,
       allowedTools: sessionData.allowedTools,
       temperature: sessionData.temperature,
+      maxOutputTokens: sessionData.maxOutputTokens,
+      permissionMode: sessionData.permissionMode,
     });
     
     // Restore message history from DB
@@ -2093,6 +2105,9 @@ async function handleClientEvent(event: ClientEvent) {
     case "llm.providers.save":
       handleLlmProvidersSave(event);
       return;
+    case "provider.keys.sync":
+      setRuntimeProviderKeys(event.payload.keys);
+      return;
     case "llm.models.test":
       handleLlmModelsTest(event);
       return;
@@ -2229,6 +2244,8 @@ async function handleClientEvent(event: ClientEvent) {
           model: sessionData.model,
           allowedTools: sessionData.allowedTools,
           temperature: sessionData.temperature,
+          maxOutputTokens: sessionData.maxOutputTokens,
+          permissionMode: sessionData.permissionMode,
         });
         if (historyMessages && Array.isArray(historyMessages)) {
           for (const msg of historyMessages) {
@@ -3087,6 +3104,8 @@ async function handleClientEvent(event: ClientEvent) {
           model: sessionData.model,
           allowedTools: sessionData.allowedTools,
           temperature: sessionData.temperature,
+          maxOutputTokens: sessionData.maxOutputTokens,
+          permissionMode: sessionData.permissionMode,
         });
         if (historyMessages && Array.isArray(historyMessages)) {
           for (const msg of historyMessages) {
@@ -3127,6 +3146,16 @@ rl.on("line", (line) => {
     if (resolve) {
       resolve(result);
     }
+    writeOut({ type: "sidecar-ack", requestId });
+    return;
+  }
+
+  if (msg.type === "sidecar-request") {
+    // Request from Rust that waits for a correlated reply
+    const { requestId } = msg;
+    void handleClientEvent(msg.event)
+      .then(() => writeOut({ type: "sidecar-response", requestId, result: { success: true } }))
+      .catch((error) => writeOut({ type: "sidecar-response", requestId, result: { success: false, error: String(error) } }));
     return;
   }
   
@@ -13,9 +13,12 @@ export type SchedulerResponse = {
 
 export type SidecarInboundMessage =
   | { type: "client-event"; event: ClientEvent }
-  | { type: "scheduler-response"; payload: SchedulerResponse };
+  | { type: "scheduler-response"; payload: SchedulerResponse }
+  | { type: "sidecar-request"; requestId: string; event: ClientEvent };
 
 export type SidecarOutboundMessage =
   | { type: "server-event"; event: ServerEvent }
-  | { type: "log"; level: "info" | "error"; message: string; context?: Record<string, unknown> };
+  | { type: "log"; level: "info" | "error"; message: string; context?: Record<string, unknown> }
+  | { type: "sidecar-ack"; requestId: string }
+  | { type: "sidecar-response"; requestId: string; result: SchedulerResponse["result"] };
 
@@ -24,6 +24,8 @@ export type Session = {
   lastPrompt?: string;
   model?: string;
   temperature?: number;
+  maxOutputTokens?: number | null;
+  permissionMode?: 'default' | 'ask';
   threadId?: string;
   fileChanges?: FileChange[];
   pendingPermissions: Map<string, PendingPermission>;
@@ -96,6 +98,8 @@ export class MemorySessionStore {
     model?: string;
     threadId?: string;
     temperature?: number;
+    maxOutputTokens?: number | null;
+    permissionMode?: 'default' | 'ask';
     id?: string; // Allow external ID
     ephemeral?: boolean; // Skip sync to Rust DB (used by distillation sub-sessions)
   }): Session {
@@ -109,6 +113,8 @@ export class MemorySessionStore {
       lastPrompt: options.prompt,
       model: options.model,
       temperature: options.temperature,
+      maxOutputTokens: options.maxOutputTokens,
+      permissionMode: options.permissionMode,
       threadId: options.threadId,
       pendingPermissions: new Map()
     };
@@ -126,7 +132,9 @@ export class MemorySessionStore {
         cwd: session.cwd,
         allowedTools: session.allowedTools,
         model: session.model,
-        threadId: session.threadId
+        threadId: session.threadId,
+        maxOutputTokens: session.maxOutputTokens,
+        permissionMode: session.permissionMode
       });
     }
 
@@ -144,6 +152,8 @@ export class MemorySessionStore {
     model?: string;
     allowedTools?: string;
     temperature?: number;
+    maxOutputTokens?: number | null;
+    permissionMode?: 'default' | 'ask';
     threadId?: string;
   }): Session {
     const existing = this.sessions.get(options.id);
@@ -158,6 +168,8 @@ export class MemorySessionStore {
       model: options.model,
       threadId: options.threadId,
       temperature: options.temperature,
+      maxOutputTokens: options.maxOutputTokens,
+      permissionMode: options.permissionMode,
       pendingPermissions: new Map(),
     };
     this.sessions.set(options.id, session);
@@ -287,6 +299,8 @@ export class MemorySessionStore {
     if (updates.status !== undefined) syncUpdates.status = updates.status;
     if (updates.cwd !== undefined) syncUpdates.cwd = updates.cwd;
     if (updates.model !== undefined) syncUpdates.model = updates.model;
+    if (updates.maxOutputTokens !== undefined) syncUpdates.maxOutputTokens = updates.maxOutputTokens;
+    if (updates.permissionMode !== undefined) syncUpdates.permissionMode = updates.permissionMode;
     if (updates.inputTokens !== undefined) syncUpdates.inputTokens = session.inputTokens;
     if (updates.outputTokens !== undefined) syncUpdates.outputTokens = session.outputTokens;
     
@@ -116,6 +116,12 @@ export function createTauriPlatform(): PlatformAdapter {
           const path = String(args[0] ?? "");
           return tauriInvoke("list_directory", { path });
         }
+        case "search-files": {
+          const root = String(args[0] ?? "");
+          const glob = String(args[1] ?? "");
+          const maxResults = args[2] !== undefined ? Number(args[2]) : 200;
+          return tauriInvoke("search_files", { root, glob, maxResults });
+        }
         case "get-thumbnail": {
           const path = String(args[0] ?? "");
           const size = args[1] !== undefined ? Number(args[1]) : 128;
@@ -146,6 +152,22 @@ export function createTauriPlatform(): PlatformAdapter {
             isFinal
           });
         }
+        case "translate-voice": {
+          const audioB64 = String(args[0] ?? "");
+          const audioMime = String(args[1] ?? "");
+          const sessionId = String(args[2] ?? "");
+          const baseUrl = String(args[3] ?? "");
+          const apiKey = args[4] as string | undefined;
+          const model = String(args[5] ?? "whisper-1");
+          return tauriInvoke("translate_voice", {
+            audioB64,
+            audioMime,
+            sessionId,
+            baseUrl,
+            apiKey,
+            model
+          });
+        }
         case "voice-models": {
           const baseUrl = String(args[0] ?? "");
           const apiKey = args[1] as string | undefined;
@@ -877,7 +877,7 @@ export const useAppStore = create<AppState>((set, get) => ({
 
       // Scheduler task execution - auto-start session with prompt
       case "scheduler.task_execute": {
-        const { title, prompt } = event.payload as any;
+        const { taskId, title, prompt } = event.payload as any;
         if (prompt) {
           // Use scheduler default model, or fallback to first enabled model
           const { schedulerDefaultModel, llmModels } = get();
@@ -901,6 +901,7 @@ export const useAppStore = create<AppState>((set, get) => ({
               prompt: prompt,
               model: model,
               cwd: undefined,
+              scheduledTaskId: taskId,
             }
           });
         }
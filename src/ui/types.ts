@@ -102,6 +102,8 @@ export type SessionInfo = {
   cwd?: string;
   model?: string;
   isPinned?: boolean;
+  isLocked?: boolean;
+  isArchived?: boolean;
   createdAt: number;
   updatedAt: number;
   inputTokens?: number;
@@ -209,6 +211,34 @@ export type VoiceSettings = {
   apiKey?: string;
   model: string;
   language?: string;
+  /** Minimum gap between partial transcriptions, 250-5000ms (default 1500) */
+  voicePartialIntervalMs?: number;
+  /** "verbose_json" adds segment timestamps to final transcripts */
+  responseFormat?: "json" | "verbose_json";
+};
+
+/** Latency of one dictation, measured from its first audio chunk */
+export type DictationMetrics = {
+  sessionId: string;
+  model: string | null;
+  transport: "websocket" | "multipart";
+  firstPartialMs: number | null;
+  finalLatencyMs: number;
+};
+
+export type DictationMetricsSummary = {
+  model: string | null;
+  transport: "websocket" | "multipart";
+  dictations: number;
+  avgFirstPartialMs: number | null;
+  avgFinalLatencyMs: number;
+  maxFinalLatencyMs: number;
+};
+
+export type TranscriptSegment = {
+  text: string;
+  start: number;
+  end: number;
 };
 
 export type VoiceServerStatus = {
@@ -217,6 +247,106 @@ export type VoiceServerStatus = {
   checkedAt?: number;
 };
 
+/** Result of one `model.ping`; `modelMissing` means the provider said the model doesn't exist */
+export type ModelPing = {
+  modelId: string;
+  ok: boolean;
+  latencyMs: number;
+  modelMissing: boolean;
+  error?: string;
+};
+
+/** One entry of a streamed `fs.tree.start` walk */
+export type TreeNode = {
+  path: string;
+  relativePath: string;
+  name: string;
+  isDirectory: boolean;
+  size?: number;
+  depth: number;
+};
+
+export type SandboxSelfTest = {
+  passed: boolean;
+  checks: { name: string; passed: boolean; detail?: string }[];
+  durationMs: number;
+};
+
+export type SidecarConfig = {
+  entry: string | null;
+  entryError: string | null;
+  entryExists: boolean;
+  entryExecutable: boolean;
+  usesNode: boolean;
+  nodeBin: string;
+  nodeVersion: string | null;
+  nodeError: string | null;
+  envSidecarEntry: string | null;
+  envNodeBin: string | null;
+};
+
+export type BudgetStatus = {
+  capUsd: number | null;
+  spentUsd: number;
+  exceeded: boolean;
+  overridden: boolean;
+};
+
+export type SemanticHit = {
+  sessionId: string;
+  sessionTitle: string;
+  messageId: string;
+  snippet: string;
+  createdAt: number;
+  score: number;
+};
+
+export type EmbeddingSettings = {
+  providerId: string;
+  model: string;
+};
+
+export type ModelUsage = {
+  model: string | null;
+  inputTokens: number;
+  outputTokens: number;
+  updates: number;
+  estimatedCost: number | null;
+};
+
+export type MessageHit = {
+  sessionId: string;
+  sessionTitle: string;
+  messageId: string;
+  /** Matched text with <mark> around the hits */
+  snippet: string;
+  createdAt: number;
+};
+
+export type SettingsChange = {
+  /** Dotted path, e.g. `providers.openrouter.baseUrl`; secrets show as "[REDACTED]" */
+  path: string;
+  before?: unknown;
+  after?: unknown;
+};
+
+export type DuplicateCluster = {
+  prompt: string;
+  sessions: { id: string; title: string; updatedAt: number }[];
+};
+
+export type ParsedSchedule =
+  | { kind: "delay" | "every"; amount: number; unit: "m" | "h" | "d" }
+  | { kind: "daily"; hour: number; minute: number }
+  | { kind: "at"; datetime: string };
+
+export type ScheduleError =
+  | { kind: "unknownFormat" }
+  | { kind: "badInterval"; amount: string }
+  | { kind: "zeroInterval" }
+  | { kind: "badTime"; hour: number; minute: number }
+  | { kind: "badDate" };
+
 export type ApiSettings = {
   apiKey: string;
   baseUrl: string;
@@ -244,6 +374,8 @@ export type ApiSettings = {
   requestTimeoutMs?: number; // API request timeout in ms (default: 300000 = 5 min)
   locale?: string; // UI language (e.g. 'en', 'ru')
   voiceSettings?: VoiceSettings; // Voice transcription settings
+  httpProxy?: string; // Proxy URL for all outbound HTTP (falls back to HTTPS_PROXY env)
+  httpHeaders?: Record<string, string>; // Extra headers sent with every outbound HTTP request
 };
 
 export type ModelInfo = {
@@ -266,6 +398,7 @@ export interface LLMProvider {
   zaiApiPrefix?: ZaiApiUrlPrefix; // Only for zai provider
   proxyUrl?: string; // HTTP/HTTPS/SOCKS5 proxy URL
   enabled: boolean;
+  config?: { keyRef?: string; [key: string]: unknown }; // keyRef: API key lives in the OS keychain
 }
 
 export interface LLMModel {
@@ -289,6 +422,7 @@ export type ServerEvent =
   | { type: "stream.user_prompt"; payload: { sessionId: string; prompt: string; threadId?: string } }
   | { type: "session.status"; payload: { sessionId: string; status: SessionStatus; title?: string; cwd?: string; error?: string; model?: string; temperature?: number; threadId?: string } }
   | { type: "session.list"; payload: { sessions: SessionInfo[] } }
+  | { type: "session.list.for_cwd"; payload: { cwd: string; includeSubfolders: boolean; sessions: SessionInfo[] } }
   | { type: "session.history"; payload: { sessionId: string; status: SessionStatus; messages: StreamMessage[]; inputTokens?: number; outputTokens?: number; todos?: TodoItem[]; model?: string; fileChanges?: FileChange[]; hasMore?: boolean; nextCursor?: number; page?: "initial" | "prepend" } }
   | { type: "session.deleted"; payload: { sessionId: string } }
   | { type: "permission.request"; payload: { sessionId: string; toolUseId: string; toolName: string; input: unknown; explanation?: string } }
@@ -311,11 +445,64 @@ export type ServerEvent =
   | { type: "llm.models.fetched"; payload: { providerId: string; models: LLMModel[] } }
   | { type: "llm.models.error"; payload: { providerId: string; message: string } }
   | { type: "llm.models.checked"; payload: { unavailableModels: string[] } }
+  | { type: "provider.models.synced"; payload: { providerId: string; added: string[]; removed: string[]; kept: number } }
+  | { type: "provider.key.updated"; payload: { providerId: string; keyRef: string | null } }
+  | { type: "models.imported"; payload: { providerId: string; modelIds: string[]; added: number } }
+  | { type: "session.hash"; payload: { sessionId: string; hash: string } }
+  | { type: "provider.auth_checked"; payload: { providerId: string; result: { authorized: boolean | null; status?: number; error?: string } } }
+  | { type: "model.pinged"; payload: ModelPing }
+  | { type: "models.pruned"; payload: { providerId: string; tested: number; disabled: string[]; failed: number; results: ModelPing[] } }
+  | { type: "providers.validation"; payload: { count: number; modelIds: string[]; action?: "report" | "drop" | "reassign"; repaired: number } }
   // Voice events
   | { type: "voice.server.status"; payload: VoiceServerStatus }
+  | { type: "voice.usage"; payload: { sessionId: string; audioSeconds: number; requests: number } }
+  | { type: "voice.dictation.metrics"; payload: DictationMetrics }
+  | { type: "voice.dictation.metrics.summary"; payload: { since: number; summary: DictationMetricsSummary[] } }
+  | { type: "budget.status"; payload: { sessionId: string | null; status: BudgetStatus } }
+  | { type: "usage.summary"; payload: { since: number; models: ModelUsage[]; totalCost: number } }
+  | { type: "session.idle_timeout"; payload: { sessionId: string; idleSeconds: number } }
+  | { type: "session.snapshot.saved"; payload: { sessionId: string; cancelled: true } | { sessionId: string; cancelled: false; path: string; redactions: number } }
+  | { type: "session.idle_timeout.updated"; payload: { sessionId: string; seconds: number | null } }
+  | { type: "budget.exceeded"; payload: { sessionId: string | null; capUsd: number | null; spentUsd: number } }
+  | { type: "stats.exported"; payload: { format: "csv"; cancelled: true } | { format: "csv"; cancelled: false; path: string; rows: number } }
+  | { type: "session.exported"; payload: { sessionId: string; format: "bundle"; cancelled: true } | { sessionId: string; format: "bundle"; cancelled: false; path: string } }
+  | { type: "session.imported"; payload: { source: "bundle"; cancelled: true } | { source: "bundle"; cancelled: false; sessionId: string } }
+  | { type: "session.evicted"; payload: { sessionIds: string[]; maxSessions: number } }
+  | { type: "sessions.max.loaded"; payload: { maxSessions: number | null } }
+  | { type: "sessions.temperature_updated"; payload: { temperature: number; count: number } }
+  | { type: "session.temperature.warning"; payload: { sessionId: string | null; requested: number; temperature: number | null; min: number; max: number } }
+  | { type: "sidecar.config"; payload: SidecarConfig }
+  | { type: "sidecar.protocol_error"; payload: { message: string; consecutiveFailures: number; samples: string[] } }
+  | { type: "sidecar.restarted"; payload: { exit: string; delayMs: number } }
+  | { type: "sidecar.fatal"; payload: { message: string; exit: string; maxRestarts: number; windowMs: number } }
+  | { type: "session.last_active"; payload: { sessionId: string | null } }
+  | { type: "message.raw"; payload: { sessionId: string; uuidOrIndex: string | number; raw: string } }
+  | { type: "session.resume_suggestion"; payload: { sessionId: string | null } }
+  | { type: "session.resume_suggestion.enabled"; payload: { enabled: boolean } }
+  | { type: "session.pruned"; payload: { sessionId: string; omitted: number; maxTokens: number } }
+  | { type: "session.meta"; payload: { sessionId: string; key: string; value: string | null } | { sessionId: string; metadata: Record<string, string> } }
+  | { type: "session.debug_payload"; payload: { sessionId: string; sizeBytes: number; redactions: number; event: unknown } }
+  | { type: "session.blobs.externalized"; payload: { sessionId: string; messagesUpdated: number; blobs: number; bytesSaved: number } }
+  | { type: "fs.tree.chunk"; payload: { walkId: string; nodes: TreeNode[] } }
+  | { type: "fs.tree.done"; payload: { walkId: string; root: string; total: number; truncated: boolean; aborted: boolean } }
+  | { type: "session.duplicates"; payload: { clusters: DuplicateCluster[] } }
+  | { type: "session.titles_deduped"; payload: { renamed: number } }
+  | { type: "search.messages.results"; payload: { query: string; groups: { sessionId: string; sessionTitle: string; hits: MessageHit[] }[] } }
+  | { type: "search.semantic.configured"; payload: { settings: EmbeddingSettings | null } }
+  | { type: "search.semantic.results"; payload: { query: string; hits: SemanticHit[] } }
+  | { type: "search.semantic.backfilled"; payload: { embedded: number } }
+  | { type: "profile.exported"; payload: { cancelled: true } | { cancelled: false; path: string; strippedKeys: boolean; providers: number; models: number } }
+  | { type: "profile.imported"; payload: { cancelled: true } | { cancelled: false; path: string; result: { mode: "merge" | "replace"; snapshotId: number; changes: SettingsChange[] } } }
+  | { type: "schedule.parsed"; payload: { schedule: string; valid: boolean; parsed?: ParsedSchedule; recurring?: boolean; nextRunMs?: number | null; error?: ScheduleError; message?: string } }
   | { type: "voice.transcription.partial"; payload: { sessionId: string; text: string } }
-  | { type: "voice.transcription.final"; payload: { sessionId: string; text: string } }
+  | { type: "voice.transcription.segment"; payload: { sessionId: string; index: number; text: string } }
+  | { type: "voice.transcription.final"; payload: { sessionId: string; text: string; segments?: TranscriptSegment[] } }
   | { type: "voice.transcription.error"; payload: { sessionId: string; message: string } }
+  | { type: "voice.translation.final"; payload: { sessionId: string; text: string } }
+  | { type: "voice.translation.error"; payload: { sessionId: string; message: string } }
+  // Sandbox events
+  | { type: "sandbox.output"; payload: { requestId: string | null; stream: "stdout" | "stderr"; line: string } }
+  | { type: "sandbox.selftest"; payload: SandboxSelfTest }
   // Skills events
   | { type: "skills.loaded"; payload: { skills: Skill[]; repositories: SkillRepository[]; lastFetched?: number } }
   | { type: "skills.error"; payload: { message: string } }
@@ -334,6 +521,9 @@ export type ServerEvent =
   // Scheduler events
   | { type: "scheduler.notification"; payload: { title: string; body: string } }
   | { type: "scheduler.task_execute"; payload: { taskId: string; title: string; prompt?: string } }
+  | { type: "scheduler.heartbeat"; payload: { now: number; paused: boolean; enabledTasks: number; nextRun: number | null; nextTaskId: string | null; nextTaskTitle: string | null } }
+  | { type: "scheduler.paused"; payload: { paused: boolean; skipped: number } }
+  | { type: "scheduler.execution.cancelled"; payload: { taskId: string; sessionId: string; runId: string } }
   | { type: "scheduler.default_model.loaded"; payload: { modelId: string | null } }
   | { type: "scheduler.default_temperature.loaded"; payload: { temperature: number; sendTemperature: boolean } }
   // OAuth events
@@ -344,17 +534,22 @@ export type ServerEvent =
 
 // Client -> Server events
 export type ClientEvent =
-  | { type: "session.start"; payload: { title: string; prompt: string; cwd?: string; model?: string; allowedTools?: string; threadId?: string; temperature?: number } }
+  | { type: "session.start"; payload: { title: string; prompt: string; cwd?: string; model?: string; allowedTools?: string; threadId?: string; temperature?: number; maxOutputTokens?: number; permissionMode?: 'default' | 'ask'; sessionId?: string; scheduledTaskId?: string } }
   | { type: "session.continue"; payload: { sessionId: string; prompt: string; cwd?: string; retry?: boolean; retryReason?: string } }
   | { type: "session.stop"; payload: { sessionId: string; } }
   | { type: "session.delete"; payload: { sessionId: string; } }
   | { type: "session.pin"; payload: { sessionId: string; isPinned: boolean; } }
+  | { type: "session.archive"; payload: { sessionId: string; archived?: boolean } }
+  | { type: "session.lock"; payload: { sessionId: string } }
+  | { type: "session.unlock"; payload: { sessionId: string } }
   | { type: "session.update-cwd"; payload: { sessionId: string; cwd: string; } }
-  | { type: "session.update"; payload: { sessionId: string; model?: string; temperature?: number; sendTemperature?: boolean; title?: string; } }
-  | { type: "session.list" }
+  | { type: "session.update"; payload: { sessionId: string; model?: string; temperature?: number; maxOutputTokens?: number | null; permissionMode?: 'default' | 'ask'; sendTemperature?: boolean; title?: string; } }
+  | { type: "session.list"; payload?: { includeArchived?: boolean } }
+  | { type: "session.list.for_cwd"; payload: { cwd: string; includeSubfolders?: boolean } }
   | { type: "session.history"; payload: { sessionId: string; limit?: number; before?: number } }
   | { type: "permission.response"; payload: { sessionId: string; toolUseId: string; result: PermissionResult; } }
   | { type: "message.edit"; payload: { sessionId: string; messageIndex: number; newPrompt: string; } }
+  | { type: "message.raw"; payload: { sessionId: string; uuidOrIndex: string | number } }
   | { type: "settings.get" }
   | { type: "settings.save"; payload: { settings: ApiSettings; } }
   | { type: "open.external"; payload: { url: string; } }
@@ -369,12 +564,49 @@ export type ClientEvent =
   | { type: "task.stop"; payload: { sessionId: string } }
   | { type: "llm.providers.get" }
   | { type: "llm.providers.save"; payload: { settings: LLMProviderSettings } }
+  /** Rust → sidecar only: resolved keys of keychain-backed providers */
+  | { type: "provider.keys.sync"; payload: { keys: Record<string, string> } }
   | { type: "llm.models.fetch"; payload: { providerId: string } }
   | { type: "llm.models.test"; payload: { provider: LLMProvider } }
   | { type: "llm.models.check" }
+  | { type: "provider.models.sync"; payload: { providerId: string } }
+  | { type: "provider.auth_check"; payload: { providerId: string; model?: string } }
+  | { type: "provider.key.set"; payload: { providerId: string; apiKey: string; keyRef?: string } }
+  | { type: "models.import_text"; payload: { providerId: string; text: string } }
+  | { type: "session.hash"; payload: { sessionId: string } }
+  | { type: "provider.key.clear"; payload: { providerId: string } }
   // Voice events
   | { type: "voice.check"; payload: { baseUrl: string; apiKey?: string; model: string; language?: string } }
+  | { type: "voice.usage"; payload: { sessionId: string } }
+  | { type: "voice.dictation.metrics.summary"; payload?: { since?: number } }
+  | { type: "budget.get"; payload?: { sessionId?: string } }
+  | { type: "usage.summary"; payload?: { since?: number } }
+  | { type: "session.idle_timeout.set"; payload: { sessionId: string; seconds: number | null } }
+  | { type: "session.snapshot"; payload: { sessionId: string } }
+  | { type: "budget.cap.set"; payload: { capUsd: number | null } }
+  | { type: "budget.override"; payload: { sessionId: string; enabled?: boolean } }
+  | { type: "stats.export_csv"; payload: { from?: number; to?: number } }
+  | { type: "session.export"; payload: { sessionId: string } }
+  | { type: "session.import" }
+  | { type: "sidecar.config" }
+  | { type: "session.last_active.set"; payload: { sessionId: string } }
+  | { type: "session.last_active.get" }
+  | { type: "session.resume_suggestion.get" }
+  | { type: "session.resume_suggestion.enabled.set"; payload: { enabled: boolean } }
+  | { type: "session.find_duplicates" }
+  | { type: "session.dedupe_titles" }
+  | { type: "search.messages"; payload: { query: string; limit?: number } }
+  | { type: "search.semantic.configure"; payload: { providerId: string; model: string } | { providerId: null } }
+  | { type: "search.semantic"; payload: { query: string; topK?: number } }
+  | { type: "search.semantic.backfill" }
+  | { type: "schedule.parse"; payload: { schedule: string } }
+  | { type: "profile.export"; payload?: { stripKeys?: boolean } }
+  | { type: "profile.import"; payload: { mergeOrReplace: "merge" | "replace" } }
+  | { type: "sessions.max.get" }
+  | { type: "sessions.max.set"; payload: { maxSessions: number | null } }
+  | { type: "sessions.set_temperature"; payload: { temperature: number; sessionIds?: string[] } }
   | { type: "voice.preload"; payload: { baseUrl: string; apiKey?: string; model: string } }
+  | { type: "voice.translate"; payload: { sessionId: string; audioB64: string; audioMime?: string; baseUrl?: string; apiKey?: string; model?: string } }
   // Skills events
   | { type: "skills.get" }
   | { type: "skills.refresh" }
@@ -402,10 +634,15 @@ export type ClientEvent =
   // Compact events
   | { type: "session.compact"; payload: { sessionId: string } }
   // Scheduler events
+  | { type: "scheduler.pause" }
+  | { type: "scheduler.execution.cancel"; payload: { taskId: string } }
+  | { type: "scheduler.resume"; payload?: { catchUp?: boolean } }
+  | { type: "scheduler.status" }
   | { type: "scheduler.default_model.get" }
   | { type: "scheduler.default_model.set"; payload: { modelId: string } }
   | { type: "scheduler.default_temperature.get" }
   | { type: "scheduler.default_temperature.set"; payload: { temperature: number; sendTemperature: boolean } }
+  | { type: "task.convert"; payload: { taskId: string; toRecurring: boolean; schedule?: string } }
   // OAuth events
   | { type: "oauth.login"; payload: { provider: string; method?: 'browser' | 'device_code' | 'token'; token?: string } }
   | { type: "oauth.logout"; payload: { provider: string } }